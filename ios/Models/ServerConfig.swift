@@ -8,6 +8,10 @@ struct ServerConfig: Codable {
     var host: String
     var port: Int
     var useHTTPS: Bool
+    /// Server user whose data this device shows (nil = everyone)
+    var userId: Int? = nil
+    /// API token for servers with authentication enabled (nil = none)
+    var apiToken: String? = nil
 
     static let `default` = ServerConfig(
         host: "localhost",
@@ -18,6 +18,28 @@ actor APIClient {
         return TimeZone.current.secondsFromGMT()
     }
 
+    // Time zone plus the configured user filter. The server prefers the zone
+    // name (DST-aware); the offset is a fallback for older servers.
+    private var filterQueryItems: [URLQueryItem] {
+        var items = [
+            URLQueryItem(name: "tz", value: TimeZone.current.identifier),
+            URLQueryItem(name: "tz_offset", value: "\(timezoneOffsetSeconds)")
+        ]
+        if let userId = config.userId {
+            items.append(URLQueryItem(name: "user_id", value: "\(userId)"))
+        }
+        return items
+    }
+
+    // Sends the configured token, which servers with authentication need
+    private func authorizedRequest(_ url: URL) -> URLRequest {
+        var request = URLRequest(url: url)
+        if let token = config.apiToken {
+            request.setValue("Bearer \(token)", forHTTPHeaderField: "Authorization")
+        }
+        return request
+    }
+
     // MARK: - Health Check
 
     func checkConnection() async throws -> Bool {
@@ -26,13 +48,18 @@ actor APIClient {
         }
 
         do {
-            let (_, response) = try await session.data(from: url)
+            let (_, response) = try await session.data(for: authorizedRequest(url))
 
-            guard let httpResponse = response as? HTTPURLResponse else {
+            guard let httpResponse = response as? HTTPURLResponse,
+                  httpResponse.statusCode == 200 else {
                 return false
             }
 
-            return httpResponse.statusCode == 200
+            // The health check is public, so try the token on a read endpoint
+            if config.apiToken != nil {
+                _ = try await fetchActivityDates()
+            }
+            return true
         } catch let error as URLError {
             throw APIError.fromURLError(error)
         }
@@ -46,16 +73,14 @@ actor APIClient {
         }
 
         // Add timezone offset query parameter
-        urlComponents.queryItems = [
-            URLQueryItem(name: "tz_offset", value: "\(timezoneOffsetSeconds)")
-        ]
+        urlComponents.queryItems = filterQueryItems
 
         guard let url = urlComponents.url else {
             throw APIError.invalidURL
         }
 
         do {
-            let (data, response) = try await session.data(from: url)
+            let (data, response) = try await session.data(for: authorizedRequest(url))
 
             guard let httpResponse = response as? HTTPURLResponse else {
                 throw APIError.serverError(0)
@@ -85,16 +110,14 @@ actor APIClient {
             throw APIError.invalidURL
         }
 
-        urlComponents.queryItems = [
-            URLQueryItem(name: "tz_offset", value: "\(timezoneOffsetSeconds)")
-        ]
+        urlComponents.queryItems = filterQueryItems
 
         guard let url = urlComponents.url else {
             throw APIError.invalidURL
         }
 
         do {
-            let (data, response) = try await session.data(from: url)
+            let (data, response) = try await session.data(for: authorizedRequest(url))
 
             guard let httpResponse = response as? HTTPURLResponse else {
                 throw APIError.serverError(0)
@@ -125,16 +148,14 @@ actor APIClient {
         }
 
         // Add timezone offset query parameter
-        urlComponents.queryItems = [
-            URLQueryItem(name: "tz_offset", value: "\(timezoneOffsetSeconds)")
-        ]
+        urlComponents.queryItems = filterQueryItems
 
         guard let url = urlComponents.url else {
             throw APIError.invalidURL
         }
 
         do {
-            let (data, response) = try await session.data(from: url)
+            let (data, response) = try await session.data(for: authorizedRequest(url))
 
             guard let httpResponse = response as? HTTPURLResponse else {
                 throw APIError.serverError(0)
@@ -164,16 +185,14 @@ actor APIClient {
         }
 
         // Add timezone offset query parameter
-        urlComponents.queryItems = [
-            URLQueryItem(name: "tz_offset", value: "\(timezoneOffsetSeconds)")
-        ]
+        urlComponents.queryItems = filterQueryItems
 
         guard let url = urlComponents.url else {
             throw APIError.invalidURL
         }
 
         do {
-            let (data, response) = try await session.data(from: url)
+            let (data, response) = try await session.data(for: authorizedRequest(url))
 
             guard let httpResponse = response as? HTTPURLResponse else {
                 throw APIError.serverError(0)
@@ -215,7 +234,11 @@ enum APIError: Error, LocalizedError {
         case .invalidURL:
             return "Invalid server URL. Check settings."
         case .serverError(let code):
-            if code == 404 {
+            if code == 401 {
+                return "Missing or invalid API token. Check settings."
+            } else if code == 403 {
+                return "The API token doesn't allow this (403)"
+            } else if code == 404 {
                 return "Server endpoint not found (404)"
             } else if code >= 500 {
                 return "Server error (\(code)). Is the server running?"
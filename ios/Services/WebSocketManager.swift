@@ -57,9 +57,13 @@ actor WebSocketManager {
 
         connectionStatusSubject.send(.connecting)
 
-        // Create WebSocket task
+        // Create WebSocket task, with the token in a header rather than the URL
+        var request = URLRequest(url: wsURL)
+        if let token = config.apiToken {
+            request.setValue("Bearer \(token)", forHTTPHeaderField: "Authorization")
+        }
         let session = URLSession(configuration: .default)
-        webSocketTask = session.webSocketTask(with: wsURL)
+        webSocketTask = session.webSocketTask(with: request)
         webSocketTask?.resume()
 
         // Note: Connection status will be set to .connected when we receive the first message
@@ -139,6 +143,10 @@ actor WebSocketManager {
 
             switch message {
             case .newSample(let sample):
+                // Only show samples for the configured user (if any)
+                if let userId = config.userId, sample.userId != userId {
+                    return
+                }
                 print("📨 Received new sample via WebSocket: steps=\(sample.stepsDelta ?? 0)")
                 sampleSubject.send(sample)
 
@@ -216,6 +224,7 @@ struct WebSocketSample: Codable {
     let distanceDelta: Int64?
     let caloriesDelta: Int64?
     let stepsDelta: Int64?
+    let userId: Int?
 
     enum CodingKeys: String, CodingKey {
         case timestamp
@@ -223,6 +232,7 @@ struct WebSocketSample: Codable {
         case distanceDelta = "distance_delta"
         case caloriesDelta = "calories_delta"
         case stepsDelta = "steps_delta"
+        case userId = "user_id"
     }
 
     var timestampDate: Date {
@@ -4,6 +4,8 @@ struct SettingsView: View {
     @State private var host: String
     @State private var port: String
     @State private var useHTTPS: Bool
+    @State private var userId: String
+    @State private var apiToken: String
     @State private var isTestingConnection = false
     @State private var connectionTestResult: ConnectionTestResult?
     @AppStorage(UnitPreference.storageKey) private var unitPreferenceRaw: String = UnitPreference.imperial.rawValue
@@ -17,6 +19,8 @@ struct SettingsView: View {
         _host = State(initialValue: config.host)
         _port = State(initialValue: String(config.port))
         _useHTTPS = State(initialValue: config.useHTTPS)
+        _userId = State(initialValue: config.userId.map(String.init) ?? "")
+        _apiToken = State(initialValue: config.apiToken ?? "")
     }
 
     private var hasUnsavedChanges: Bool {
@@ -26,7 +30,18 @@ struct SettingsView: View {
 
         return trimmedHost != saved.host ||
                currentPort != saved.port ||
-               useHTTPS != saved.useHTTPS
+               useHTTPS != saved.useHTTPS ||
+               parsedUserId != saved.userId ||
+               parsedToken != saved.apiToken
+    }
+
+    private var parsedUserId: Int? {
+        Int(userId.trimmingCharacters(in: .whitespaces))
+    }
+
+    private var parsedToken: String? {
+        let trimmed = apiToken.trimmingCharacters(in: .whitespaces)
+        return trimmed.isEmpty ? nil : trimmed
     }
 
     var body: some View {
@@ -61,10 +76,23 @@ struct SettingsView: View {
                     }
 
                     Toggle("Use HTTPS", isOn: $useHTTPS)
+
+                    HStack {
+                        Text("User ID")
+                        Spacer()
+                        TextField("All users", text: $userId)
+                            .keyboardType(.numberPad)
+                            .multilineTextAlignment(.trailing)
+                            .frame(width: 100)
+                    }
+
+                    SecureField("API Token (if required)", text: $apiToken)
+                        .textInputAutocapitalization(.never)
+                        .autocorrectionDisabled()
                 } header: {
                     Text("Server Configuration")
                 } footer: {
-                    Text("Changes apply after successful connection test. Example: myserver.local or 192.168.1.100")
+                    Text("Changes apply after successful connection test. Example: myserver.local or 192.168.1.100. Set a User ID to only see that person's walks on a shared treadmill. Servers with authentication need a token (walkpad-server token create phone --scope control).")
                 }
 
                 // Test Connection
@@ -132,6 +160,8 @@ struct SettingsView: View {
         host = config.host
         port = String(config.port)
         useHTTPS = config.useHTTPS
+        userId = config.userId.map(String.init) ?? ""
+        apiToken = config.apiToken ?? ""
         connectionTestResult = nil
     }
 
@@ -148,7 +178,7 @@ struct SettingsView: View {
             return
         }
 
-        let testConfig = ServerConfig(host: trimmedHost, port: portNum, useHTTPS: useHTTPS)
+        let testConfig = ServerConfig(host: trimmedHost, port: portNum, useHTTPS: useHTTPS, userId: parsedUserId, apiToken: parsedToken)
         let testClient = APIClient(config: testConfig)
 
         isTestingConnection = true
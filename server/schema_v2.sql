@@ -10,7 +10,12 @@ CREATE TABLE IF NOT EXISTS treadmill_samples (
     steps_total INTEGER,            -- cumulative steps from treadmill (raw, for debugging)
     distance_delta INTEGER,         -- meters walked since last sample
     calories_delta INTEGER,         -- kcal burned since last sample
-    steps_delta INTEGER             -- steps taken since last sample
+    steps_delta INTEGER,            -- steps taken since last sample
+    excluded INTEGER NOT NULL DEFAULT 0, -- 1 = hidden from summaries (manual exclusion)
+    user_id INTEGER,                -- who was walking (NULL = unassigned)
+    incline REAL,                   -- percent grade (NULL if not reported)
+    heart_rate INTEGER,             -- bpm (NULL if not reported)
+    calories_estimated REAL         -- kcal since last sample, estimated from body weight
 );
 
 -- Index for time-range queries (critical for Grafana and iOS app)
@@ -19,3 +24,170 @@ CREATE INDEX IF NOT EXISTS idx_timestamp ON treadmill_samples(timestamp);
 -- Partial index for active samples (speed > 0) - speeds up daily summary queries
 -- This covers the common WHERE speed > 0.0 filter used in most aggregation queries
 CREATE INDEX IF NOT EXISTS idx_timestamp_active ON treadmill_samples(timestamp) WHERE speed > 0.0;
+
+-- Per-user time-range queries
+CREATE INDEX IF NOT EXISTS idx_user_timestamp ON treadmill_samples(user_id, timestamp);
+
+-- People who share the treadmill
+CREATE TABLE IF NOT EXISTS users (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    created_at INTEGER NOT NULL,    -- Unix epoch (seconds)
+    weight_kg REAL                  -- for calorie estimation (NULL = configured default)
+);
+
+-- Time-of-day windows during which a user becomes active automatically
+CREATE TABLE IF NOT EXISTS user_schedules (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    start_minute INTEGER NOT NULL,  -- minutes after local midnight (0-1439)
+    end_minute INTEGER NOT NULL     -- exclusive, may be less than start_minute to wrap midnight
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_schedules_user ON user_schedules(user_id);
+
+-- Daily or weekly targets per metric
+CREATE TABLE IF NOT EXISTS goals (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER,                -- NULL = everyone's walking combined
+    metric TEXT NOT NULL,           -- steps, distance_meters, active_minutes or calories
+    period TEXT NOT NULL,           -- daily or weekly (Monday to Sunday)
+    target REAL NOT NULL,
+    created_at INTEGER NOT NULL     -- Unix epoch (seconds)
+);
+
+CREATE INDEX IF NOT EXISTS idx_goals_user ON goals(user_id);
+
+-- Best value per record kind, for everyone combined (user_id NULL) and per user
+CREATE TABLE IF NOT EXISTS personal_records (
+    user_id INTEGER,
+    kind TEXT NOT NULL,             -- longest_session, most_steps_day, fastest_mile, ...
+    value REAL NOT NULL,            -- seconds, steps, days or meters depending on kind
+    date TEXT NOT NULL,             -- YYYY-MM-DD (local day it was set)
+    achieved_at INTEGER NOT NULL    -- Unix epoch (seconds)
+);
+
+-- Lifetime milestones reached
+CREATE TABLE IF NOT EXISTS achievements (
+    user_id INTEGER,
+    milestone TEXT NOT NULL,        -- lifetime_100_miles, ...
+    date TEXT NOT NULL,             -- YYYY-MM-DD (local day it was unlocked)
+    achieved_at INTEGER NOT NULL    -- Unix epoch (seconds)
+);
+
+-- Running totals records are computed from, so new samples don't need a rescan
+CREATE TABLE IF NOT EXISTS record_counters (
+    user_id INTEGER,
+    counters TEXT NOT NULL          -- JSON
+);
+
+-- Small key/value store for server state (e.g. the active user)
+CREATE TABLE IF NOT EXISTS settings (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+
+-- Manual adjustments to a day's totals (amounts are added to the sample sums)
+CREATE TABLE IF NOT EXISTS daily_corrections (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    date TEXT NOT NULL,                          -- YYYY-MM-DD (local date)
+    user_id INTEGER,                             -- NULL = applies to unassigned samples
+    distance_meters INTEGER NOT NULL DEFAULT 0,
+    calories INTEGER NOT NULL DEFAULT 0,
+    steps INTEGER NOT NULL DEFAULT 0,
+    duration_seconds INTEGER NOT NULL DEFAULT 0,
+    reason TEXT,
+    actor TEXT NOT NULL,
+    created_at INTEGER NOT NULL                  -- Unix epoch (seconds)
+);
+
+CREATE INDEX IF NOT EXISTS idx_daily_corrections_date ON daily_corrections(date);
+
+-- Samples the live quality checks rejected (never recorded) or flagged (recorded but suspicious)
+CREATE TABLE IF NOT EXISTS quarantined_samples (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp INTEGER NOT NULL,     -- Unix epoch (seconds)
+    user_id INTEGER,                -- active user when the sample arrived
+    status TEXT NOT NULL,           -- 'rejected' or 'flagged'
+    issues TEXT NOT NULL,           -- JSON array of {reason, message, value}
+    sample TEXT NOT NULL            -- JSON of the sample as parsed
+);
+
+CREATE INDEX IF NOT EXISTS idx_quarantined_timestamp ON quarantined_samples(timestamp);
+
+-- Audit trail of manual data changes (deletions, exclusions, corrections, imports)
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at INTEGER NOT NULL,    -- Unix epoch (seconds)
+    actor TEXT NOT NULL,
+    action TEXT NOT NULL,
+    details TEXT NOT NULL           -- JSON
+);
+
+-- Devices that export sessions elsewhere (e.g. phones writing to Apple Health)
+CREATE TABLE IF NOT EXISTS sync_clients (
+    client_id TEXT PRIMARY KEY,     -- chosen by the client, kept across reinstalls
+    name TEXT,
+    destination TEXT NOT NULL,      -- clients exporting to the same place share one
+    user_id INTEGER,                -- whose sessions it exports (NULL = everyone)
+    cursor INTEGER NOT NULL,        -- sessions starting earlier are settled (Unix epoch)
+    created_at INTEGER NOT NULL,
+    last_seen_at INTEGER NOT NULL
+);
+
+-- Which sessions were exported to which destination, by whom and in what state
+CREATE TABLE IF NOT EXISTS sync_ledger (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    destination TEXT NOT NULL,
+    item_id TEXT NOT NULL,          -- export token (stable per session and user)
+    user_id INTEGER,
+    session_start INTEGER NOT NULL,
+    session_end INTEGER NOT NULL,
+    fingerprint TEXT NOT NULL,      -- session contents when exported
+    version INTEGER NOT NULL,       -- bumped on each re-export
+    client_id TEXT NOT NULL,
+    external_id TEXT,               -- the destination's ID (e.g. HealthKit UUID)
+    exported_at INTEGER NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_sync_ledger_item ON sync_ledger(destination, item_id);
+CREATE INDEX IF NOT EXISTS idx_sync_ledger_session ON sync_ledger(destination, session_start);
+
+-- Feed of sample and correction changes for incremental sync (see storage/changes.rs)
+CREATE TABLE IF NOT EXISTS change_log (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,  -- increases with every committed change
+    changed_at INTEGER NOT NULL,            -- Unix epoch (seconds)
+    kind TEXT NOT NULL,                     -- e.g. 'sample_upserted', 'samples_deleted'
+    timestamp INTEGER,                      -- the sample, or the start of a range
+    end_timestamp INTEGER,                  -- end of a range (exclusive)
+    user_id INTEGER,
+    data TEXT                               -- JSON of a correction
+);
+
+-- Bearer tokens for the API and WebSocket (managed with `walkpad-server token`)
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    token_hash TEXT NOT NULL UNIQUE,    -- SHA-256 of the token, hex (the token itself isn't kept)
+    scope TEXT NOT NULL,                -- 'read', 'control' or 'write'
+    user_id INTEGER,                    -- only this user's data (NULL = every user's)
+    created_at INTEGER NOT NULL,
+    last_used_at INTEGER
+);
+
+-- Webhook deliveries, kept for inspection and redelivery (see webhooks.rs)
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    webhook TEXT NOT NULL,              -- name from [[webhooks]] in the config
+    event TEXT NOT NULL,                -- e.g. 'session_ended'
+    payload TEXT NOT NULL,              -- request body
+    status TEXT NOT NULL,               -- 'pending', 'delivered' or 'failed'
+    attempts INTEGER NOT NULL,
+    response_status INTEGER,            -- HTTP status of the last attempt
+    error TEXT,                         -- why the last attempt failed
+    redelivery_of INTEGER,              -- the delivery this repeats
+    created_at INTEGER NOT NULL,        -- Unix epoch (seconds)
+    last_attempt_at INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_webhook ON webhook_deliveries(webhook, id);
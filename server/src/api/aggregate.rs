@@ -0,0 +1,116 @@
+//! Totals grouped by local hour, day, week, month or year.
+
+use axum::{
+    extract::{Extension, Query, State},
+    Json,
+};
+use chrono::Weekday;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{
+    auth, resolve_zone, validate_date, ApiError, AppState, ErrorBody, ValidationError,
+    MAX_DATE_RANGE_DAYS,
+};
+use crate::energy::CaloriesSource;
+use crate::storage::{ApiToken, GroupBy, PeriodSummary};
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct AggregateQuery {
+    group_by: GroupBy,
+    #[serde(default)]
+    start_date: Option<String>, // YYYY-MM-DD (required for group_by=hour)
+    #[serde(default)]
+    end_date: Option<String>, // YYYY-MM-DD (inclusive)
+    #[serde(default)]
+    tz: Option<String>,
+    #[serde(default)]
+    tz_offset: Option<i32>,
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    week_start: Option<Weekday>, // e.g. "sunday" (default: from config)
+    #[serde(default)]
+    user_id: Option<i64>,
+    #[serde(default)]
+    calories: Option<CaloriesSource>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct AggregateResponse {
+    group_by: GroupBy,
+    timezone: String,
+    #[schema(value_type = String, example = "Mon")]
+    week_start: Weekday,
+    periods: Vec<PeriodSummary>,
+}
+
+// Totals per period, oldest first
+#[utoipa::path(
+    get,
+    path = "/api/aggregate",
+    tag = "activity",
+    params(AggregateQuery),
+    responses(
+        (status = 200, body = AggregateResponse),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub(super) async fn get_aggregate(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Query(mut query): Query<AggregateQuery>,
+) -> Result<Json<AggregateResponse>, ApiError> {
+    query.user_id = auth::limit_user(token.as_deref(), query.user_id)?;
+    let range = match (&query.start_date, &query.end_date) {
+        (Some(start), Some(end)) => {
+            let (start, end) = (validate_date(start)?, validate_date(end)?);
+            let days_diff = (end - start).num_days();
+            if days_diff < 0 {
+                return Err(ApiError::Validation(ValidationError::new(
+                    "start_date must be before end_date",
+                )));
+            }
+            if query.group_by == GroupBy::Hour && days_diff > MAX_DATE_RANGE_DAYS {
+                return Err(ApiError::Validation(ValidationError::new(format!(
+                    "Date range too large for hourly totals (max {} days)",
+                    MAX_DATE_RANGE_DAYS
+                ))));
+            }
+            Some((start, end))
+        }
+        (None, None) if query.group_by == GroupBy::Hour => {
+            return Err(ApiError::Validation(ValidationError::new(
+                "group_by=hour requires start_date and end_date",
+            )));
+        }
+        (None, None) => None,
+        _ => {
+            return Err(ApiError::Validation(ValidationError::new(
+                "start_date and end_date must be given together",
+            )));
+        }
+    };
+
+    let zone = resolve_zone(query.tz.as_deref(), query.tz_offset, &state)?;
+    let week_start = query.week_start.unwrap_or(state.week_start);
+    info!(
+        "Getting {:?} totals (range={:?}, tz={}, week_start={}, user_id={:?})",
+        query.group_by, range, zone, week_start, query.user_id
+    );
+
+    let mut periods = state
+        .storage
+        .get_period_summaries(query.group_by, range, &zone, week_start, query.user_id)
+        .await?;
+    let source = query.calories.unwrap_or(state.energy.calories_source);
+    periods.iter_mut().for_each(|p| p.report_calories(source));
+
+    Ok(Json(AggregateResponse {
+        group_by: query.group_by,
+        timezone: zone.to_string(),
+        week_start,
+        periods,
+    }))
+}
@@ -0,0 +1,126 @@
+//! Bearer-token check in front of every route (when `[auth] enabled`).
+//!
+//! Browsers can't set headers on a WebSocket, so `/ws/live` also accepts the
+//! token as `?access_token=`. Either way it's checked before the upgrade.
+//!
+//! A token that belongs to a user only reaches the routes allowed by
+//! [`allows_user_token`]; their handlers call [`limit_user`] or
+//! [`check_owner`] to keep to that user's data.
+
+use axum::{
+    extract::{Query, Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use tracing::warn;
+
+use super::{ApiError, AppState};
+use crate::auth::{allows_user_token, hash_token, required_scope};
+use crate::storage::ApiToken;
+
+// Don't write `last_used_at` on every request
+const TOUCH_INTERVAL_SECS: i64 = 60;
+
+#[derive(Debug, Deserialize)]
+struct AccessTokenQuery {
+    access_token: Option<String>,
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| token.trim())
+        .filter(|t| !t.is_empty())
+}
+
+pub(super) async fn require_token(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if !state.auth_enabled {
+        return Ok(next.run(request).await);
+    }
+    let Some(required) = required_scope(request.method(), request.uri().path()) else {
+        return Ok(next.run(request).await);
+    };
+
+    let token = match bearer_token(request.headers()) {
+        Some(token) => Some(token.to_string()),
+        None if request.uri().path() == "/ws/live" => {
+            Query::<AccessTokenQuery>::try_from_uri(request.uri())
+                .ok()
+                .and_then(|Query(q)| q.access_token)
+        }
+        None => None,
+    };
+    let Some(token) = token else {
+        return Err(ApiError::Unauthorized("Missing bearer token".to_string()));
+    };
+
+    let Some(api_token) = state.storage.find_api_token(&hash_token(&token)).await? else {
+        warn!(
+            "Rejected unknown token for {} {}",
+            request.method(),
+            request.uri().path()
+        );
+        return Err(ApiError::Unauthorized("Invalid token".to_string()));
+    };
+    if !api_token.scope.allows(required) {
+        return Err(ApiError::Forbidden(format!(
+            "Token '{}' has {} scope; this needs {}",
+            api_token.name, api_token.scope, required
+        )));
+    }
+    if let Some(user_id) = api_token.user_id {
+        if !allows_user_token(request.method(), request.uri().path()) {
+            return Err(ApiError::Forbidden(format!(
+                "Token '{}' belongs to user {}; this covers every user",
+                api_token.name, user_id
+            )));
+        }
+    }
+
+    let now = Utc::now().timestamp();
+    if api_token
+        .last_used_at
+        .is_none_or(|t| now - t >= TOUCH_INTERVAL_SECS)
+    {
+        state.storage.touch_api_token(api_token.id).await?;
+    }
+
+    request.extensions_mut().insert::<ApiToken>(api_token);
+    Ok(next.run(request).await)
+}
+
+/// The user a request is limited to. A token that belongs to a user only sees
+/// them: without a `user_id` filter the request is theirs, and anyone else's
+/// is refused.
+pub(super) fn limit_user(
+    token: Option<&ApiToken>,
+    user_id: Option<i64>,
+) -> Result<Option<i64>, ApiError> {
+    match token.and_then(|t| t.user_id) {
+        None => Ok(user_id),
+        Some(own) if user_id.is_none_or(|id| id == own) => Ok(Some(own)),
+        Some(own) => Err(not_yours(own)),
+    }
+}
+
+/// Refuse a user's token something that belongs to someone else (or to
+/// everyone, when `owner` is None)
+pub(super) fn check_owner(token: Option<&ApiToken>, owner: Option<i64>) -> Result<(), ApiError> {
+    match token.and_then(|t| t.user_id) {
+        Some(own) if owner != Some(own) => Err(not_yours(own)),
+        _ => Ok(()),
+    }
+}
+
+fn not_yours(user_id: i64) -> ApiError {
+    ApiError::Forbidden(format!("This token only covers user {}", user_id))
+}
@@ -0,0 +1,76 @@
+//! Change feed for incremental sync: clients keep the `next_cursor` of each
+//! response and pass it as `since` next time.
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{ApiError, AppState, ErrorBody, ValidationError};
+use crate::storage::Change;
+
+const DEFAULT_CHANGES_LIMIT: i64 = 1000;
+const MAX_CHANGES_LIMIT: i64 = 10_000;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct ChangesQuery {
+    #[serde(default)]
+    since: i64, // cursor from a previous response (0 = from the beginning)
+    #[serde(default)]
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct ChangesResponse {
+    changes: Vec<Change>,
+    next_cursor: i64, // pass as `since` to continue
+    more: bool,       // true if more changes are waiting beyond `limit`
+    latest: i64,      // newest change overall (start here after a full download)
+}
+
+// Changes after a cursor, oldest first
+#[utoipa::path(
+    get,
+    path = "/api/changes",
+    tag = "changes",
+    params(ChangesQuery),
+    responses(
+        (status = 200, body = ChangesResponse),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub(super) async fn get_changes(
+    State(state): State<AppState>,
+    Query(query): Query<ChangesQuery>,
+) -> Result<Json<ChangesResponse>, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_CHANGES_LIMIT);
+    if !(1..=MAX_CHANGES_LIMIT).contains(&limit) {
+        return Err(ApiError::Validation(ValidationError::new(format!(
+            "limit must be between 1 and {}",
+            MAX_CHANGES_LIMIT
+        ))));
+    }
+    if query.since < 0 {
+        return Err(ApiError::Validation(ValidationError::new(
+            "since must not be negative",
+        )));
+    }
+    info!("Getting changes since {} (limit {})", query.since, limit);
+
+    let mut changes = state.storage.get_changes(query.since, limit + 1).await?;
+    let more = changes.len() as i64 > limit;
+    changes.truncate(limit as usize);
+    let next_cursor = changes.last().map_or(query.since, |c| c.seq);
+    let latest = state.storage.get_latest_change_seq().await?;
+
+    Ok(Json(ChangesResponse {
+        changes,
+        next_cursor,
+        more,
+        latest,
+    }))
+}
@@ -0,0 +1,326 @@
+//! Endpoints for fixing recorded data: deleting or excluding sample ranges,
+//! per-day corrections, and the audit log.
+
+use axum::{
+    extract::{Extension, Path, Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{validate_date, ApiError, AppState, ErrorBody, ValidationError};
+use crate::storage::{ApiToken, AuditEntry, ChangeAuthor, CorrectionAmounts, DailyCorrection};
+
+const ANONYMOUS_ACTOR: &str = "api"; // authentication is off
+const DEFAULT_AUDIT_LIMIT: i64 = 100;
+const MAX_AUDIT_LIMIT: i64 = 1000;
+
+/// Why a change is made (optional on every mutation)
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub(super) struct AuthorFields {
+    #[serde(default)]
+    reason: Option<String>, // free text for the audit log
+}
+
+impl AuthorFields {
+    pub(super) fn author(self, token: Option<&ApiToken>) -> ChangeAuthor {
+        change_author(token, self.reason)
+    }
+}
+
+/// The audit log's author: the token making the change (by name), never
+/// something the client claims
+pub(super) fn change_author(token: Option<&ApiToken>, reason: Option<String>) -> ChangeAuthor {
+    ChangeAuthor {
+        actor: token.map_or_else(|| ANONYMOUS_ACTOR.to_string(), |t| t.name.clone()),
+        reason: reason.filter(|r| !r.trim().is_empty()),
+    }
+}
+
+/// A half-open range of samples, `[start, end)` in Unix seconds
+#[derive(Debug, Deserialize, ToSchema)]
+pub(super) struct SampleRangeRequest {
+    start: i64,
+    end: i64,
+    #[serde(flatten)]
+    author: AuthorFields,
+}
+
+impl SampleRangeRequest {
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.end <= self.start {
+            return Err(ValidationError::new("end must be after start"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct SampleRangeResponse {
+    start: i64,
+    end: i64,
+    affected: u64,
+}
+
+// Permanently delete samples in a time range
+#[utoipa::path(
+    delete,
+    path = "/api/samples",
+    tag = "corrections",
+    request_body = SampleRangeRequest,
+    responses(
+        (status = 200, body = SampleRangeResponse),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub(super) async fn delete_samples(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Json(request): Json<SampleRangeRequest>,
+) -> Result<Json<SampleRangeResponse>, ApiError> {
+    request.validate()?;
+    let (start, end) = (request.start, request.end);
+    let author = request.author.author(token.as_deref());
+    info!(
+        "Deleting samples {}..{} (actor={})",
+        start, end, author.actor
+    );
+
+    let affected = state.storage.delete_samples(start, end, &author).await?;
+    // The range may have held a record
+    if affected > 0 {
+        state.records_rebuild.request();
+    }
+
+    Ok(Json(SampleRangeResponse {
+        start,
+        end,
+        affected,
+    }))
+}
+
+// Hide samples in a time range from summaries (reversible)
+#[utoipa::path(
+    post,
+    path = "/api/samples/exclude",
+    tag = "corrections",
+    request_body = SampleRangeRequest,
+    responses(
+        (status = 200, body = SampleRangeResponse),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub(super) async fn exclude_samples(
+    state: State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    request: Json<SampleRangeRequest>,
+) -> Result<Json<SampleRangeResponse>, ApiError> {
+    set_excluded(state, token, request, true).await
+}
+
+// Undo an exclusion
+#[utoipa::path(
+    post,
+    path = "/api/samples/include",
+    tag = "corrections",
+    request_body = SampleRangeRequest,
+    responses(
+        (status = 200, body = SampleRangeResponse),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub(super) async fn include_samples(
+    state: State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    request: Json<SampleRangeRequest>,
+) -> Result<Json<SampleRangeResponse>, ApiError> {
+    set_excluded(state, token, request, false).await
+}
+
+async fn set_excluded(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Json(request): Json<SampleRangeRequest>,
+    excluded: bool,
+) -> Result<Json<SampleRangeResponse>, ApiError> {
+    request.validate()?;
+    let (start, end) = (request.start, request.end);
+    let author = request.author.author(token.as_deref());
+    info!(
+        "Setting excluded={} for samples {}..{} (actor={})",
+        excluded, start, end, author.actor
+    );
+
+    let affected = state
+        .storage
+        .set_samples_excluded(start, end, excluded, &author)
+        .await?;
+    if affected > 0 {
+        state.records_rebuild.request();
+    }
+
+    Ok(Json(SampleRangeResponse {
+        start,
+        end,
+        affected,
+    }))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct CorrectionsResponse {
+    date: String,
+    corrections: Vec<DailyCorrection>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct UserQuery {
+    #[serde(default)]
+    user_id: Option<i64>,
+}
+
+// List corrections recorded for a date
+#[utoipa::path(
+    get,
+    path = "/api/dates/{date}/corrections",
+    tag = "corrections",
+    params(("date" = String, Path, description = "Local date (YYYY-MM-DD)"), UserQuery),
+    responses(
+        (status = 200, body = CorrectionsResponse),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub(super) async fn get_date_corrections(
+    State(state): State<AppState>,
+    Path(date_str): Path<String>,
+    Query(query): Query<UserQuery>,
+) -> Result<Json<CorrectionsResponse>, ApiError> {
+    validate_date(&date_str)?;
+
+    let corrections = state
+        .storage
+        .get_corrections_for_date(&date_str, query.user_id)
+        .await?;
+
+    Ok(Json(CorrectionsResponse {
+        date: date_str,
+        corrections,
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(super) struct CorrectionRequest {
+    #[serde(default)]
+    user_id: Option<i64>,
+    #[serde(flatten)]
+    amounts: CorrectionAmounts,
+    #[serde(flatten)]
+    author: AuthorFields,
+}
+
+// Adjust a day's totals (amounts are added; use negative values to subtract)
+#[utoipa::path(
+    post,
+    path = "/api/dates/{date}/corrections",
+    tag = "corrections",
+    params(("date" = String, Path, description = "Local date (YYYY-MM-DD)")),
+    request_body = CorrectionRequest,
+    responses(
+        (status = 200, body = DailyCorrection),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub(super) async fn add_date_correction(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Path(date_str): Path<String>,
+    Json(request): Json<CorrectionRequest>,
+) -> Result<Json<DailyCorrection>, ApiError> {
+    validate_date(&date_str)?;
+    let a = &request.amounts;
+    if a.distance_meters == 0 && a.calories == 0 && a.steps == 0 && a.duration_seconds == 0 {
+        return Err(ApiError::Validation(ValidationError::new(
+            "Correction must change at least one of distance_meters, calories, steps, duration_seconds",
+        )));
+    }
+
+    let author = request.author.author(token.as_deref());
+    info!(
+        "Adding correction for {} (actor={})",
+        date_str, author.actor
+    );
+
+    let correction = state
+        .storage
+        .add_correction(&date_str, request.user_id, &request.amounts, &author)
+        .await?;
+
+    Ok(Json(correction))
+}
+
+// Remove a correction
+#[utoipa::path(
+    delete,
+    path = "/api/corrections/{id}",
+    tag = "corrections",
+    params(("id" = i64, Path)),
+    request_body(content = Option<AuthorFields>, description = "Optional, for the audit log"),
+    responses(
+        (status = 200, description = "The deleted correction", body = DailyCorrection),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub(super) async fn delete_correction(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Path(id): Path<i64>,
+    body: Option<Json<AuthorFields>>,
+) -> Result<Json<DailyCorrection>, ApiError> {
+    let author = body
+        .map(|Json(b)| b)
+        .unwrap_or_default()
+        .author(token.as_deref());
+    info!("Deleting correction {} (actor={})", id, author.actor);
+
+    state
+        .storage
+        .delete_correction(id, &author)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("No correction with id {}", id)))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct AuditQuery {
+    #[serde(default)]
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct AuditResponse {
+    entries: Vec<AuditEntry>,
+}
+
+// Most recent manual changes, newest first
+#[utoipa::path(
+    get,
+    path = "/api/audit",
+    tag = "corrections",
+    params(AuditQuery),
+    responses((status = 200, body = AuditResponse))
+)]
+pub(super) async fn get_audit_log(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<AuditResponse>, ApiError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_AUDIT_LIMIT)
+        .clamp(1, MAX_AUDIT_LIMIT);
+
+    let entries = state.storage.get_audit_log(limit).await?;
+
+    Ok(Json(AuditResponse { entries }))
+}
@@ -0,0 +1,155 @@
+//! Goal endpoints: setting daily/weekly targets and reading streaks.
+//! Progress towards goals is included in the summary endpoints.
+
+use axum::{
+    extract::{Extension, Path, Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{auth, ApiError, AppState, ErrorBody, TimezoneQuery, ValidationError};
+use crate::goals::{self, GoalMetric, GoalPeriod, Streak};
+use crate::storage::{ApiToken, Goal};
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct GoalsQuery {
+    #[serde(default)]
+    user_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct GoalsResponse {
+    goals: Vec<Goal>,
+}
+
+// List goals (all of them, or one user's)
+#[utoipa::path(
+    get,
+    path = "/api/goals",
+    tag = "goals",
+    params(GoalsQuery),
+    responses((status = 200, body = GoalsResponse))
+)]
+pub(super) async fn list_goals(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Query(mut query): Query<GoalsQuery>,
+) -> Result<Json<GoalsResponse>, ApiError> {
+    query.user_id = auth::limit_user(token.as_deref(), query.user_id)?;
+    let goals = state.storage.get_goals(query.user_id).await?;
+
+    Ok(Json(GoalsResponse { goals }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(super) struct GoalRequest {
+    #[serde(default)]
+    user_id: Option<i64>, // None = everyone's walking combined
+    metric: GoalMetric,
+    period: GoalPeriod,
+    target: f64,
+}
+
+// Set a target, replacing any existing goal for the same user, metric and period
+#[utoipa::path(
+    post,
+    path = "/api/goals",
+    tag = "goals",
+    request_body = GoalRequest,
+    responses(
+        (status = 200, body = Goal),
+        (status = 400, body = ErrorBody),
+        (status = 404, description = "Unknown user", body = ErrorBody),
+    )
+)]
+pub(super) async fn set_goal(
+    State(state): State<AppState>,
+    Json(request): Json<GoalRequest>,
+) -> Result<Json<Goal>, ApiError> {
+    if !request.target.is_finite() || request.target <= 0.0 {
+        return Err(ApiError::Validation(ValidationError::new(
+            "target must be greater than zero",
+        )));
+    }
+    if let Some(id) = request.user_id {
+        if state.storage.get_user(id).await?.is_none() {
+            return Err(ApiError::NotFound(format!("No user with id {}", id)));
+        }
+    }
+
+    info!(
+        "Setting {:?} {:?} goal to {} (user_id={:?})",
+        request.period, request.metric, request.target, request.user_id
+    );
+    let goal = state
+        .storage
+        .set_goal(
+            request.user_id,
+            request.metric,
+            request.period,
+            request.target,
+        )
+        .await?;
+
+    Ok(Json(goal))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/goals/{id}",
+    tag = "goals",
+    params(("id" = i64, Path)),
+    responses(
+        (status = 200, description = "The deleted goal", body = Goal),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub(super) async fn delete_goal(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<Goal>, ApiError> {
+    state
+        .storage
+        .delete_goal(id)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("No goal with id {}", id)))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct StreaksResponse {
+    streaks: Vec<Streak>,
+}
+
+// Current and longest streaks for the goals of one scope (a user, or everyone)
+#[utoipa::path(
+    get,
+    path = "/api/goals/streaks",
+    tag = "goals",
+    params(TimezoneQuery),
+    responses(
+        (status = 200, body = StreaksResponse),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub(super) async fn get_streaks(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Query(mut query): Query<TimezoneQuery>,
+) -> Result<Json<StreaksResponse>, ApiError> {
+    query.user_id = auth::limit_user(token.as_deref(), query.user_id)?;
+    let zone = query.zone(&state)?;
+    info!(
+        "Getting goal streaks (tz={}, user_id={:?})",
+        zone, query.user_id
+    );
+
+    let goals = state.storage.get_goals_for_scope(query.user_id).await?;
+    let streaks =
+        goals::streaks(&state.storage, &goals, &zone, query.calories_source(&state)).await?;
+
+    Ok(Json(StreaksResponse { streaks }))
+}
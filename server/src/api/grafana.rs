@@ -0,0 +1,443 @@
+//! Grafana JSON datasource (the SimpleJSON contract), so walks can be charted
+//! without giving Grafana the database. Point a JSON/SimpleJSON datasource at
+//! `http://<server>/grafana`.
+//!
+//! Sample targets (`speed`, `steps`, ...) are summed or averaged in SQL into
+//! buckets of `range / maxDataPoints`; daily targets (`daily_steps`, ...) come
+//! from the summaries and roll up to weeks, months or years when a range has
+//! more days than points. Each target may carry `{"user_id": 1, "tz": "..."}`
+//! in its `data` (or `payload`) to pick a user and the zone that defines days.
+
+use axum::{extract::State, Json};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::ToSchema;
+
+use super::{resolve_zone, ApiError, AppState, ErrorBody, ValidationError};
+use crate::energy::CaloriesSource;
+use crate::sessions::sessions_overlapping;
+use crate::storage::{GroupBy, PeriodSummary, SeriesPoint};
+
+const DEFAULT_MAX_DATA_POINTS: i64 = 1000;
+
+/// Everything `/grafana/search` offers
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Metric {
+    Speed,
+    MaxSpeed,
+    Distance,
+    Steps,
+    Calories,
+    CaloriesEstimated,
+    HeartRate,
+    Incline,
+    DailyDistance,
+    DailySteps,
+    DailyCalories,
+    DailyDuration,
+    DailyAvgSpeed,
+}
+
+impl Metric {
+    const ALL: [Metric; 13] = [
+        Metric::Speed,
+        Metric::MaxSpeed,
+        Metric::Distance,
+        Metric::Steps,
+        Metric::Calories,
+        Metric::CaloriesEstimated,
+        Metric::HeartRate,
+        Metric::Incline,
+        Metric::DailyDistance,
+        Metric::DailySteps,
+        Metric::DailyCalories,
+        Metric::DailyDuration,
+        Metric::DailyAvgSpeed,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Metric::Speed => "speed",
+            Metric::MaxSpeed => "max_speed",
+            Metric::Distance => "distance",
+            Metric::Steps => "steps",
+            Metric::Calories => "calories",
+            Metric::CaloriesEstimated => "calories_estimated",
+            Metric::HeartRate => "heart_rate",
+            Metric::Incline => "incline",
+            Metric::DailyDistance => "daily_distance",
+            Metric::DailySteps => "daily_steps",
+            Metric::DailyCalories => "daily_calories",
+            Metric::DailyDuration => "daily_duration",
+            Metric::DailyAvgSpeed => "daily_avg_speed",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.name() == name)
+    }
+
+    /// The bucket's value, if it has one (heart rate and incline may be missing)
+    fn sample_value(self, point: &SeriesPoint) -> Option<f64> {
+        match self {
+            Metric::Speed => Some(point.avg_speed),
+            Metric::MaxSpeed => Some(point.max_speed),
+            Metric::Distance => Some(point.distance_meters as f64),
+            Metric::Steps => Some(point.steps as f64),
+            Metric::Calories => Some(point.calories as f64),
+            Metric::CaloriesEstimated => Some(point.calories_estimated),
+            Metric::HeartRate => point.heart_rate,
+            Metric::Incline => point.incline,
+            _ => None,
+        }
+    }
+
+    /// `None` for sample metrics
+    fn period_value(self, period: &PeriodSummary) -> Option<f64> {
+        match self {
+            Metric::DailyDistance => Some(period.distance_meters as f64),
+            Metric::DailySteps => Some(period.steps as f64),
+            Metric::DailyCalories => Some(period.calories as f64),
+            Metric::DailyDuration => Some(period.duration_seconds as f64),
+            Metric::DailyAvgSpeed => Some(period.avg_speed),
+            _ => None,
+        }
+    }
+
+    fn is_daily(self) -> bool {
+        self.name().starts_with("daily_")
+    }
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub(super) struct SearchRequest {
+    #[serde(default)]
+    target: String, // text typed so far (matches any target containing it)
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(super) struct TimeRange {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct QueryRequest {
+    range: TimeRange,
+    #[serde(default)]
+    max_data_points: Option<i64>, // default 1000
+    targets: Vec<QueryTarget>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum TargetType {
+    #[default]
+    Timeserie,
+    Table,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(super) struct QueryTarget {
+    target: String,
+    #[serde(default, rename = "type")]
+    kind: TargetType,
+    #[serde(default)]
+    hide: bool,
+    #[serde(default, alias = "payload")]
+    #[schema(value_type = Option<TargetOptions>)]
+    data: Option<serde_json::Value>, // TargetOptions (anything else is ignored)
+}
+
+/// Per-target settings, from the target's additional JSON
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub(super) struct TargetOptions {
+    #[serde(default)]
+    user_id: Option<i64>, // only this user's walks (default: everyone)
+    #[serde(default)]
+    tz: Option<String>, // IANA zone for daily targets (default: from config)
+    #[serde(default)]
+    calories: Option<CaloriesSource>, // for daily_calories (default: from config)
+}
+
+impl QueryTarget {
+    fn options(&self) -> Result<TargetOptions, ApiError> {
+        match &self.data {
+            Some(data @ serde_json::Value::Object(_)) => serde_json::from_value(data.clone())
+                .map_err(|e| {
+                    ApiError::Validation(ValidationError::new(format!(
+                        "Invalid data for target {}: {}",
+                        self.target, e
+                    )))
+                }),
+            _ => Ok(TargetOptions::default()),
+        }
+    }
+}
+
+/// A time series (`[value, unix_ms]` pairs) or, for table targets, a table
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(untagged)]
+pub(super) enum QueryResult {
+    TimeSeries {
+        target: String,
+        datapoints: Vec<[f64; 2]>,
+    },
+    Table {
+        #[serde(rename = "type")]
+        kind: String, // always "table"
+        columns: Vec<TableColumn>,
+        rows: Vec<[f64; 2]>,
+    },
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct TableColumn {
+    text: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(super) struct AnnotationRequest {
+    range: TimeRange,
+    #[schema(value_type = Object)]
+    annotation: serde_json::Value, // `query` may hold a user id (default: everyone)
+}
+
+/// A walking session, as a region
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Annotation {
+    #[schema(value_type = Object)]
+    annotation: serde_json::Value, // the request's annotation, echoed back
+    time: i64,     // Unix ms
+    time_end: i64, // Unix ms
+    is_region: bool,
+    title: String,
+    text: String,
+    tags: Vec<String>,
+}
+
+// Grafana's "Save & test"
+#[utoipa::path(
+    get,
+    path = "/grafana/",
+    tag = "grafana",
+    responses((status = 200, description = "The datasource is reachable", body = String))
+)]
+pub(super) async fn test_connection() -> &'static str {
+    "OK"
+}
+
+// Target names for the query editor
+#[utoipa::path(
+    post,
+    path = "/grafana/search",
+    tag = "grafana",
+    request_body = SearchRequest,
+    responses((status = 200, body = Vec<String>))
+)]
+pub(super) async fn search(request: Option<Json<SearchRequest>>) -> Json<Vec<&'static str>> {
+    let Json(request) = request.unwrap_or_default();
+    let needle = request.target.to_lowercase();
+    Json(
+        Metric::ALL
+            .into_iter()
+            .map(Metric::name)
+            .filter(|name| name.contains(&needle))
+            .collect(),
+    )
+}
+
+// Data points per target, bucketed to at most `maxDataPoints`
+#[utoipa::path(
+    post,
+    path = "/grafana/query",
+    tag = "grafana",
+    request_body = QueryRequest,
+    responses(
+        (status = 200, body = Vec<QueryResult>),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub(super) async fn query(
+    State(state): State<AppState>,
+    Json(request): Json<QueryRequest>,
+) -> Result<Json<Vec<QueryResult>>, ApiError> {
+    let (from, to) = (request.range.from.timestamp(), request.range.to.timestamp());
+    if to <= from {
+        return Err(ApiError::Validation(ValidationError::new(
+            "range.from must be before range.to",
+        )));
+    }
+    let max_points = request
+        .max_data_points
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_MAX_DATA_POINTS);
+
+    let mut results = Vec::new();
+    for target in request.targets.iter().filter(|t| !t.hide) {
+        let metric = Metric::from_name(&target.target).ok_or_else(|| {
+            ApiError::Validation(ValidationError::new(format!(
+                "Unknown target: {}",
+                target.target
+            )))
+        })?;
+        let options = target.options()?;
+        info!(
+            "Grafana query: {} from {} to {} ({} points)",
+            metric.name(),
+            from,
+            to,
+            max_points
+        );
+
+        let datapoints = if metric.is_daily() {
+            daily_points(&state, metric, &options, (from, to), max_points).await?
+        } else {
+            let bucket_secs = (to - from + max_points - 1) / max_points;
+            state
+                .storage
+                .get_sample_series((from, to), bucket_secs, options.user_id)
+                .await?
+                .iter()
+                .filter_map(|point| {
+                    let value = metric.sample_value(point)?;
+                    Some([value, (point.start * 1000) as f64])
+                })
+                .collect()
+        };
+
+        results.push(match target.kind {
+            TargetType::Timeserie => QueryResult::TimeSeries {
+                target: target.target.clone(),
+                datapoints,
+            },
+            TargetType::Table => QueryResult::Table {
+                kind: "table".to_string(),
+                columns: vec![
+                    TableColumn {
+                        text: "Time".to_string(),
+                        kind: "time".to_string(),
+                    },
+                    TableColumn {
+                        text: target.target.clone(),
+                        kind: "number".to_string(),
+                    },
+                ],
+                rows: datapoints.into_iter().map(|[v, t]| [t, v]).collect(),
+            },
+        });
+    }
+
+    Ok(Json(results))
+}
+
+/// One point per local day, or per week, month or year when there are more
+/// days than points. Each is placed at the start of its period.
+async fn daily_points(
+    state: &AppState,
+    metric: Metric,
+    options: &TargetOptions,
+    (from, to): (i64, i64),
+    max_points: i64,
+) -> Result<Vec<[f64; 2]>, ApiError> {
+    let zone = resolve_zone(options.tz.as_deref(), None, state)?;
+    let (first, last) = (zone.local_date(from), zone.local_date(to - 1));
+    let group_by = period_for(first, last, max_points);
+
+    let mut periods = state
+        .storage
+        .get_period_summaries(
+            group_by,
+            Some((first, last)),
+            &zone,
+            state.week_start,
+            options.user_id,
+        )
+        .await?;
+
+    let source = options.calories.unwrap_or(state.energy.calories_source);
+    Ok(periods
+        .iter_mut()
+        .filter_map(|period| {
+            period.report_calories(source);
+            let value = metric.period_value(period)?;
+            Some([value, (period.start * 1000) as f64])
+        })
+        .collect())
+}
+
+/// The shortest period that keeps `first..=last` within `max_points`
+fn period_for(first: NaiveDate, last: NaiveDate, max_points: i64) -> GroupBy {
+    let days = (last - first).num_days() + 1;
+    [(GroupBy::Day, 1), (GroupBy::Week, 7), (GroupBy::Month, 31)]
+        .into_iter()
+        .find(|&(_, length)| days <= max_points * length)
+        .map_or(GroupBy::Year, |(group_by, _)| group_by)
+}
+
+// Walking sessions in the range, as regions
+#[utoipa::path(
+    post,
+    path = "/grafana/annotations",
+    tag = "grafana",
+    request_body = AnnotationRequest,
+    responses(
+        (status = 200, body = Vec<Annotation>),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub(super) async fn annotations(
+    State(state): State<AppState>,
+    Json(request): Json<AnnotationRequest>,
+) -> Result<Json<Vec<Annotation>>, ApiError> {
+    let user_id = match request.annotation["query"].as_str().map(str::trim) {
+        None | Some("") => None,
+        Some(query) => Some(query.parse::<i64>().map_err(|_| {
+            ApiError::Validation(ValidationError::new(
+                "Annotation query must be empty or a user id",
+            ))
+        })?),
+    };
+    let (from, to) = (request.range.from.timestamp(), request.range.to.timestamp());
+
+    let sessions = sessions_overlapping(&state.storage, from, to, user_id).await?;
+    Ok(Json(
+        sessions
+            .into_iter()
+            .map(|s| Annotation {
+                annotation: request.annotation.clone(),
+                time: s.start * 1000,
+                time_end: s.end * 1000,
+                is_region: true,
+                title: "Walk".to_string(),
+                text: format!(
+                    "{:.2} km, {} steps, {} min",
+                    s.distance_meters as f64 / 1000.0,
+                    s.steps,
+                    s.duration_seconds / 60
+                ),
+                tags: vec!["walk".to_string()],
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_period_grows_with_the_range() {
+        let first = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+        let days = |n: u64| first + chrono::Days::new(n - 1);
+        assert_eq!(period_for(first, days(100), 100), GroupBy::Day);
+        assert_eq!(period_for(first, days(101), 100), GroupBy::Week);
+        assert_eq!(period_for(first, days(3100), 100), GroupBy::Month);
+        assert_eq!(period_for(first, days(3101), 100), GroupBy::Year);
+    }
+}
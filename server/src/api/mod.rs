@@ -1,44 +1,181 @@
+mod aggregate;
+mod auth;
+mod changes;
+mod corrections;
+mod goals;
+mod grafana;
+mod openapi;
+mod quality;
+mod records;
+mod sync;
+mod users;
+mod webhooks;
+mod workouts;
+
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, Extension, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware,
     response::{Html, IntoResponse},
-    routing::get,
+    routing::{delete, get, post, put},
     Json, Router,
 };
-use chrono::{NaiveDate, Utc};
+use chrono::{NaiveDate, Utc, Weekday};
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::{broadcast, Mutex, RwLock};
+use tower_http::cors::CorsLayer;
 use tracing::{error, info, warn};
+use utoipa::{IntoParams, ToSchema};
 
 use crate::bluetooth::ConnectionStatus;
-use crate::storage::{DailySummary, Storage, TreadmillSample};
+use crate::config::EnergyConfig;
+use crate::energy::CaloriesSource;
+use crate::export::{self, Units};
+use crate::goals::{attach_progress, attach_progress_for_date};
+use crate::import::{self, ImportFormat, ImportOptions, ImportSummary};
+use crate::metrics::{self, Metrics};
+use crate::records::{RebuildRequests, RecordsEngine};
+use crate::storage::{ApiToken, DailySummary, Storage, TreadmillSample};
+use crate::timezone::Zone;
+use crate::users::ActiveUser;
+use crate::webhooks::Webhooks;
 use crate::websocket::WsMessage;
+use crate::writer::{SampleWriter, WriterStats};
 
 // Validation constants
-const MAX_DATE_RANGE_DAYS: i64 = 365;
+const MAX_DATE_RANGE_DAYS: i64 = 365; // for a single, unpaginated JSON response
+const MAX_PAGE_LIMIT: i64 = 10_000;
+const MAX_IMPORT_BYTES: usize = 64 * 1024 * 1024;
 
 #[derive(Clone)]
 pub struct AppState {
     pub storage: Arc<Storage>,
     pub ws_tx: broadcast::Sender<WsMessage>,
     pub bluetooth_status: Arc<RwLock<ConnectionStatus>>,
+    pub active_user: Arc<ActiveUser>,
+    pub energy: EnergyConfig,
+    pub timezone: Zone,      // default zone for local days
+    pub week_start: Weekday, // default first day of the week for aggregation
+    pub records: Arc<Mutex<RecordsEngine>>,
+    pub records_rebuild: RebuildRequests, // rebuilds records in the background
+    pub writer: SampleWriter,
+    pub metrics: Arc<Metrics>,
+    pub webhooks: Arc<Webhooks>,
+    pub auth_enabled: bool,      // require API tokens (see `auth`)
+    pub cors: Option<CorsLayer>, // policy for browser clients on other origins
 }
 
 pub fn create_router(state: AppState) -> Router {
-    Router::new()
+    let cors = state.cors.clone();
+    let router = Router::new()
         .route("/", get(serve_dashboard))
         .route("/dashboard", get(serve_dashboard))
         .route("/api/health", get(health_check))
+        .route("/api/openapi.json", get(openapi::get_openapi))
         .route("/api/bluetooth/status", get(get_bluetooth_status))
         .route("/api/dates", get(get_activity_dates))
         .route("/api/dates/summaries", get(get_all_summaries))
         .route("/api/dates/:date/summary", get(get_date_summary))
         .route("/api/dates/:date/samples", get(get_date_samples))
-        .route("/api/samples", get(get_samples_by_range))
+        .route("/api/dates/:date/export.fit", get(workouts::get_date_fit))
+        .route("/api/dates/:date/export.tcx", get(workouts::get_date_tcx))
+        .route("/api/dates/:date/export.gpx", get(workouts::get_date_gpx))
+        .route(
+            "/api/sessions/:id/export.fit",
+            get(workouts::get_session_fit),
+        )
+        .route(
+            "/api/sessions/:id/export.tcx",
+            get(workouts::get_session_tcx),
+        )
+        .route(
+            "/api/sessions/:id/export.gpx",
+            get(workouts::get_session_gpx),
+        )
+        .route("/api/aggregate", get(aggregate::get_aggregate))
+        .route(
+            "/api/samples",
+            get(get_samples_by_range).delete(corrections::delete_samples),
+        )
+        .route("/api/samples/exclude", post(corrections::exclude_samples))
+        .route("/api/samples/include", post(corrections::include_samples))
+        .route(
+            "/api/dates/:date/corrections",
+            get(corrections::get_date_corrections).post(corrections::add_date_correction),
+        )
+        .route(
+            "/api/corrections/:id",
+            delete(corrections::delete_correction),
+        )
+        .route("/api/audit", get(corrections::get_audit_log))
+        .route("/api/changes", get(changes::get_changes))
+        .route("/api/goals", get(goals::list_goals).post(goals::set_goal))
+        .route("/api/goals/streaks", get(goals::get_streaks))
+        .route("/api/goals/:id", delete(goals::delete_goal))
+        .route("/api/records", get(records::get_records))
+        .route("/api/records/rebuild", post(records::rebuild_records))
+        .route("/api/quality", get(quality::get_quality_reports))
+        .route("/api/quality/:date", get(quality::get_date_quality))
+        .route("/api/sync/clients", get(sync::list_clients))
+        .route(
+            "/api/sync/clients/:client_id",
+            put(sync::put_client).delete(sync::delete_client),
+        )
+        .route(
+            "/api/sync/clients/:client_id/pending",
+            get(sync::get_pending),
+        )
+        .route("/api/sync/clients/:client_id/ack", post(sync::acknowledge))
+        .route("/api/sync/status", get(sync::get_status))
+        .route("/api/sync/ledger", get(sync::get_ledger))
+        .route(
+            "/api/users",
+            get(users::list_users).post(users::create_user),
+        )
+        .route(
+            "/api/users/:id",
+            delete(users::delete_user).patch(users::update_user),
+        )
+        .route(
+            "/api/users/active",
+            get(users::get_active_user).put(users::set_active_user),
+        )
+        .route(
+            "/api/users/:id/schedules",
+            get(users::get_user_schedules).post(users::add_user_schedule),
+        )
+        .route("/api/schedules/:id", delete(users::delete_schedule))
+        .route("/api/samples/assign", post(users::assign_samples))
         .route("/api/stats", get(get_stats))
+        .route("/api/webhooks/deliveries", get(webhooks::list_deliveries))
+        .route(
+            "/api/webhooks/deliveries/:id/redeliver",
+            post(webhooks::redeliver),
+        )
+        .route(
+            "/api/import",
+            post(import_samples).layer(DefaultBodyLimit::max(MAX_IMPORT_BYTES)),
+        )
         .route("/ws/live", get(crate::websocket::ws_handler))
-        .with_state(state)
+        .route("/metrics", get(get_metrics))
+        .route("/grafana/", get(grafana::test_connection))
+        .route("/grafana/search", post(grafana::search))
+        .route("/grafana/query", post(grafana::query))
+        .route("/grafana/annotations", post(grafana::annotations))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_token,
+        ))
+        .with_state(state);
+
+    // Outside authentication, so preflight requests (which carry no token) are answered
+    match cors {
+        Some(cors) => router.layer(cors),
+        None => router,
+    }
 }
 
 // Serve embedded dashboard
@@ -47,105 +184,234 @@ async fn serve_dashboard() -> Html<&'static str> {
 }
 
 // Health check endpoint
-async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
-    let bt_status = state.bluetooth_status.read().await;
-    let (bt_connected, bt_status_str) = match *bt_status {
-        ConnectionStatus::Connected => (true, "connected"),
-        ConnectionStatus::Connecting => (false, "connecting"),
-        ConnectionStatus::Scanning => (false, "scanning"),
-        ConnectionStatus::Disconnected => (false, "disconnected"),
-        ConnectionStatus::Error => (false, "error"),
-    };
+#[derive(Debug, Serialize, ToSchema)]
+struct HealthResponse {
+    status: String,
+    server_time: String, // RFC 3339
+    bluetooth: BluetoothStatusResponse,
+    writer: WriterStats,
+}
 
-    Json(serde_json::json!({
-        "status": "ok",
-        "server_time": Utc::now().to_rfc3339(),
-        "bluetooth": {
-            "connected": bt_connected,
-            "status": bt_status_str
-        }
-    }))
+#[utoipa::path(
+    get,
+    path = "/api/health",
+    tag = "system",
+    responses((status = 200, body = HealthResponse))
+)]
+async fn health_check(State(state): State<AppState>) -> Json<HealthResponse> {
+    Json(HealthResponse {
+        status: "ok".to_string(),
+        server_time: Utc::now().to_rfc3339(),
+        bluetooth: bluetooth_status(&state).await,
+        writer: state.writer.stats(),
+    })
 }
 
 // Bluetooth status endpoint
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct BluetoothStatusResponse {
     connected: bool,
     status: String,
 }
 
-async fn get_bluetooth_status(State(state): State<AppState>) -> impl IntoResponse {
+async fn bluetooth_status(state: &AppState) -> BluetoothStatusResponse {
     let bt_status = state.bluetooth_status.read().await;
-    let (connected, status) = match *bt_status {
-        ConnectionStatus::Connected => (true, "connected".to_string()),
-        ConnectionStatus::Connecting => (false, "connecting".to_string()),
-        ConnectionStatus::Scanning => (false, "scanning".to_string()),
-        ConnectionStatus::Disconnected => (false, "disconnected".to_string()),
-        ConnectionStatus::Error => (false, "error".to_string()),
-    };
+    let connected = matches!(*bt_status, ConnectionStatus::Connected);
+    let status = bt_status.as_str().to_string();
+
+    BluetoothStatusResponse { connected, status }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/bluetooth/status",
+    tag = "system",
+    responses((status = 200, body = BluetoothStatusResponse))
+)]
+async fn get_bluetooth_status(State(state): State<AppState>) -> Json<BluetoothStatusResponse> {
+    Json(bluetooth_status(&state).await)
+}
 
-    Json(BluetoothStatusResponse { connected, status })
+// Prometheus scrape target (see `crate::metrics`)
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "system",
+    responses((status = 200, description = "Prometheus text format", content_type = "text/plain", body = String))
+)]
+async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let status = state.bluetooth_status.read().await.clone();
+    (
+        [(header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+        state.metrics.render(&status, &state.writer),
+    )
 }
 
 // Get all dates with activity
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct ActivityDatesResponse {
     dates: Vec<String>, // YYYY-MM-DD format
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct TimezoneQuery {
     #[serde(default)]
-    tz_offset: Option<i32>, // Timezone offset in seconds (e.g., -28800 for PST/UTC-8)
+    tz: Option<String>, // IANA zone name (e.g., America/Los_Angeles) - DST-aware
+    #[serde(default)]
+    tz_offset: Option<i32>, // Timezone offset in seconds, a multiple of 900 (e.g., -28800 for PST/UTC-8)
+    #[serde(default)]
+    user_id: Option<i64>, // Only this user's data (default: everyone)
+    #[serde(default)]
+    calories: Option<CaloriesSource>, // Which calories summaries report (default: from config)
+}
+
+impl TimezoneQuery {
+    fn zone(&self, state: &AppState) -> Result<Zone, ValidationError> {
+        resolve_zone(self.tz.as_deref(), self.tz_offset, state)
+    }
+
+    fn calories_source(&self, state: &AppState) -> CaloriesSource {
+        self.calories.unwrap_or(state.energy.calories_source)
+    }
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/dates",
+    tag = "activity",
+    params(TimezoneQuery),
+    responses(
+        (status = 200, body = ActivityDatesResponse),
+        (status = 400, body = ErrorBody),
+    )
+)]
 async fn get_activity_dates(
     State(state): State<AppState>,
-    Query(query): Query<TimezoneQuery>,
+    token: Option<Extension<ApiToken>>,
+    Query(mut query): Query<TimezoneQuery>,
 ) -> Result<Json<ActivityDatesResponse>, ApiError> {
-    let tz_offset = query.tz_offset.unwrap_or(0); // Default to UTC
-    info!("Getting all activity dates (tz_offset={})", tz_offset);
+    query.user_id = auth::limit_user(token.as_deref(), query.user_id)?;
+    let zone = query.zone(&state)?;
+    info!(
+        "Getting all activity dates (tz={}, user_id={:?})",
+        zone, query.user_id
+    );
 
-    let dates = state.storage.get_activity_dates(tz_offset).await?;
+    let dates = state
+        .storage
+        .get_activity_dates(&zone, query.user_id)
+        .await?;
 
     Ok(Json(ActivityDatesResponse { dates }))
 }
 
 // Get all summaries at once (single query instead of N+1)
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct AllSummariesResponse {
     summaries: Vec<DailySummary>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/dates/summaries",
+    tag = "activity",
+    params(TimezoneQuery, ExportQuery),
+    responses(
+        (status = 200, content(
+            (AllSummariesResponse = "application/json"),
+            (DailySummary = "application/x-ndjson"),
+            (String = "text/csv"),
+        )),
+        (status = 400, body = ErrorBody),
+    )
+)]
 async fn get_all_summaries(
     State(state): State<AppState>,
-    Query(query): Query<TimezoneQuery>,
-) -> Result<Json<AllSummariesResponse>, ApiError> {
-    let tz_offset = query.tz_offset.unwrap_or(0);
-    info!("Getting all daily summaries (tz_offset={})", tz_offset);
+    token: Option<Extension<ApiToken>>,
+    Query(mut query): Query<TimezoneQuery>,
+    Query(export): Query<ExportQuery>,
+    headers: HeaderMap,
+) -> Result<axum::response::Response, ApiError> {
+    query.user_id = auth::limit_user(token.as_deref(), query.user_id)?;
+    let zone = query.zone(&state)?;
+    info!(
+        "Getting all daily summaries (tz={}, user_id={:?})",
+        zone, query.user_id
+    );
+
+    let mut summaries = state
+        .storage
+        .get_all_daily_summaries(&zone, query.user_id)
+        .await?;
+    let source = query.calories_source(&state);
+    summaries.iter_mut().for_each(|s| s.report_calories(source));
 
-    let summaries = state.storage.get_all_daily_summaries(tz_offset).await?;
+    let goals = state.storage.get_goals_for_scope(query.user_id).await?;
+    attach_progress(&goals, &mut summaries);
 
-    Ok(Json(AllSummariesResponse { summaries }))
+    // One row per day is small enough to hold, but is still sent as it's encoded
+    match export.format(&headers) {
+        ResponseFormat::Json => Ok(Json(AllSummariesResponse { summaries }).into_response()),
+        ResponseFormat::Ndjson => {
+            let lines = stream::iter(summaries).map(|summary| {
+                let mut line = serde_json::to_vec(&summary)?;
+                line.push(b'\n');
+                Ok(line)
+            });
+            Ok(streamed_response("application/x-ndjson", None, lines))
+        }
+        ResponseFormat::Csv => {
+            let units = export.units.unwrap_or_default();
+            let header = stream::once(async move { export::summary_header(units) });
+            let rows = stream::iter(summaries).map(move |s| export::summary_row(&s, units));
+            Ok(streamed_response(
+                "text/csv; charset=utf-8",
+                Some("summaries.csv".to_string()),
+                header.chain(rows),
+            ))
+        }
+    }
 }
 
 // Get daily summary for a specific date
+#[utoipa::path(
+    get,
+    path = "/api/dates/{date}/summary",
+    tag = "activity",
+    params(("date" = String, Path, description = "Local date (YYYY-MM-DD)"), TimezoneQuery),
+    responses(
+        (status = 200, body = DailySummary),
+        (status = 400, body = ErrorBody),
+        (status = 404, description = "No activity that day", body = ErrorBody),
+    )
+)]
 async fn get_date_summary(
     State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
     axum::extract::Path(date_str): axum::extract::Path<String>,
-    Query(query): Query<TimezoneQuery>,
+    Query(mut query): Query<TimezoneQuery>,
 ) -> Result<Json<DailySummary>, ApiError> {
+    query.user_id = auth::limit_user(token.as_deref(), query.user_id)?;
     let date = validate_date(&date_str)?;
-    let tz_offset = query.tz_offset.unwrap_or(0); // Default to UTC
-    info!(
-        "Getting summary for date: {} (tz_offset={})",
-        date_str, tz_offset
-    );
+    let zone = query.zone(&state)?;
+    info!("Getting summary for date: {} (tz={})", date_str, zone);
 
-    let summary = state.storage.get_daily_summary(date, tz_offset).await?;
+    let summary = state
+        .storage
+        .get_daily_summary(date, &zone, query.user_id)
+        .await?;
 
     match summary {
-        Some(s) => Ok(Json(s)),
+        Some(mut s) => {
+            let source = query.calories_source(&state);
+            s.report_calories(source);
+
+            let goals = state.storage.get_goals_for_scope(query.user_id).await?;
+            attach_progress_for_date(&state.storage, &goals, &mut s, &zone, query.user_id, source)
+                .await?;
+            Ok(Json(s))
+        }
         None => Err(ApiError::NotFound(format!(
             "No activity found for date: {}",
             date_str
@@ -154,22 +420,28 @@ async fn get_date_summary(
 }
 
 // Get all samples for a specific date
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct SamplesResponse {
     date: String,
     samples: Vec<SampleResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_after: Option<i64>, // Pass as `after` for the next page (absent on the last)
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct SampleResponse {
-    timestamp: i64,              // Unix epoch
-    speed: Option<f64>,          // m/s
-    distance_total: Option<i64>, // Cumulative (for debugging)
-    calories_total: Option<i64>, // Cumulative (for debugging)
-    steps_total: Option<i64>,    // Cumulative (for debugging)
-    distance_delta: Option<i64>, // Delta since last sample (USE THIS!)
-    calories_delta: Option<i64>, // Delta since last sample (USE THIS!)
-    steps_delta: Option<i64>,    // Delta since last sample (USE THIS!)
+    timestamp: i64,                  // Unix epoch
+    speed: Option<f64>,              // m/s
+    distance_total: Option<i64>,     // Cumulative (for debugging)
+    calories_total: Option<i64>,     // Cumulative (for debugging)
+    steps_total: Option<i64>,        // Cumulative (for debugging)
+    distance_delta: Option<i64>,     // Delta since last sample (USE THIS!)
+    calories_delta: Option<i64>,     // Delta since last sample (USE THIS!)
+    steps_delta: Option<i64>,        // Delta since last sample (USE THIS!)
+    user_id: Option<i64>,            // Who was walking (None = unassigned)
+    incline: Option<f64>,            // Percent grade
+    heart_rate: Option<i64>,         // bpm
+    calories_estimated: Option<f64>, // Estimated kcal since last sample
 }
 
 impl From<TreadmillSample> for SampleResponse {
@@ -183,52 +455,274 @@ impl From<TreadmillSample> for SampleResponse {
             distance_delta: s.distance_delta,
             calories_delta: s.calories_delta,
             steps_delta: s.steps_delta,
+            user_id: s.user_id,
+            incline: s.incline,
+            heart_rate: s.heart_rate,
+            calories_estimated: s.calories_estimated,
+        }
+    }
+}
+
+/// How lists of samples or summaries are returned
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum ResponseFormat {
+    /// One JSON document
+    Json,
+    /// One JSON object per line, streamed
+    Ndjson,
+    /// A header row and one row per item, streamed
+    Csv,
+}
+
+impl ResponseFormat {
+    /// `?format=` wins over the Accept header; JSON by default
+    fn negotiate(format: Option<Self>, headers: &HeaderMap) -> Self {
+        let accept = headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        format.unwrap_or(if accept.contains("text/csv") {
+            ResponseFormat::Csv
+        } else if accept.contains("ndjson") {
+            ResponseFormat::Ndjson
+        } else {
+            ResponseFormat::Json
+        })
+    }
+}
+
+// Output format for sample and summary lists
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ExportQuery {
+    #[serde(default)]
+    format: Option<ResponseFormat>, // `json`, `ndjson` or `csv` (default: from the Accept header)
+    #[serde(default)]
+    units: Option<Units>, // CSV units: `metric` (default) or `imperial`
+}
+
+impl ExportQuery {
+    fn format(&self, headers: &HeaderMap) -> ResponseFormat {
+        ResponseFormat::negotiate(self.format, headers)
+    }
+}
+
+// Cursor pagination for sample lists
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PageQuery {
+    #[serde(default)]
+    limit: Option<i64>, // At most this many samples (1 to MAX_PAGE_LIMIT; default: all)
+    #[serde(default)]
+    after: Option<i64>, // Only samples after this timestamp (the previous page's `next_after`)
+}
+
+impl PageQuery {
+    fn validate(&self) -> Result<(), ValidationError> {
+        match self.limit {
+            Some(limit) if !(1..=MAX_PAGE_LIMIT).contains(&limit) => Err(ValidationError::new(
+                format!("limit must be between 1 and {}", MAX_PAGE_LIMIT),
+            )),
+            _ => Ok(()),
         }
     }
 }
 
+/// A streamed body of lines, ending early (and logged) if a line fails
+fn streamed_response(
+    content_type: &'static str,
+    filename: Option<String>,
+    lines: impl Stream<Item = anyhow::Result<Vec<u8>>> + Send + 'static,
+) -> axum::response::Response {
+    let lines = lines.inspect_err(|e| error!("Export stream failed: {:#}", e));
+    file_response(content_type, filename, Body::from_stream(lines))
+}
+
+/// A body of the given type, offered as a download when `filename` is set
+fn file_response(
+    content_type: &'static str,
+    filename: Option<String>,
+    body: impl Into<Body>,
+) -> axum::response::Response {
+    let mut response = ([(header::CONTENT_TYPE, content_type)], body.into()).into_response();
+    if let Some(filename) = filename {
+        let disposition = format!("attachment; filename=\"{}\"", filename);
+        if let Ok(value) = header::HeaderValue::from_str(&disposition) {
+            response
+                .headers_mut()
+                .insert(header::CONTENT_DISPOSITION, value);
+        }
+    }
+    response
+}
+
+/// Samples in `range` as a JSON page, or an NDJSON or CSV stream with
+/// times local to `zone`
+#[allow(clippy::too_many_arguments)]
+async fn samples_response(
+    state: &AppState,
+    label: String,
+    range: (i64, i64),
+    zone: Zone,
+    user_id: Option<i64>,
+    page: &PageQuery,
+    format: ResponseFormat,
+    units: Units,
+) -> Result<axum::response::Response, ApiError> {
+    let samples = || {
+        state
+            .storage
+            .clone()
+            .stream_samples(range, user_id, page.after, page.limit)
+    };
+    match format {
+        ResponseFormat::Ndjson => {
+            let lines = samples().map(|sample| {
+                let mut line = serde_json::to_vec(&SampleResponse::from(sample?))?;
+                line.push(b'\n');
+                Ok(line)
+            });
+            return Ok(streamed_response("application/x-ndjson", None, lines));
+        }
+        ResponseFormat::Csv => {
+            let header = stream::once(async move { export::sample_header(units) });
+            let rows = samples().map(move |sample| export::sample_row(&sample?, &zone, units));
+            let filename = format!("samples-{}.csv", label.replace(" to ", "_"));
+            return Ok(streamed_response(
+                "text/csv; charset=utf-8",
+                Some(filename),
+                header.chain(rows),
+            ));
+        }
+        ResponseFormat::Json => {}
+    }
+
+    // One extra row tells whether there's another page
+    let mut samples = state
+        .storage
+        .get_samples_page(range, user_id, page.after, page.limit.map(|l| l + 1))
+        .await?;
+    let next_after = match page.limit {
+        Some(limit) if samples.len() as i64 > limit => {
+            samples.truncate(limit as usize);
+            samples.last().map(|s| s.timestamp)
+        }
+        _ => None,
+    };
+
+    Ok(Json(SamplesResponse {
+        date: label,
+        samples: samples.into_iter().map(SampleResponse::from).collect(),
+        next_after,
+    })
+    .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/dates/{date}/samples",
+    tag = "samples",
+    params(
+        ("date" = String, Path, description = "Local date (YYYY-MM-DD)"),
+        TimezoneQuery,
+        PageQuery,
+        ExportQuery
+    ),
+    responses(
+        (status = 200, content(
+            (SamplesResponse = "application/json"),
+            (SampleResponse = "application/x-ndjson"),
+            (String = "text/csv"),
+        )),
+        (status = 400, body = ErrorBody),
+        (status = 404, description = "No samples that day", body = ErrorBody),
+    )
+)]
 async fn get_date_samples(
     State(state): State<AppState>,
-    Query(query): Query<TimezoneQuery>,
+    token: Option<Extension<ApiToken>>,
+    Query(mut query): Query<TimezoneQuery>,
+    Query(page): Query<PageQuery>,
+    Query(export): Query<ExportQuery>,
+    headers: HeaderMap,
     axum::extract::Path(date_str): axum::extract::Path<String>,
-) -> Result<Json<SamplesResponse>, ApiError> {
+) -> Result<axum::response::Response, ApiError> {
+    query.user_id = auth::limit_user(token.as_deref(), query.user_id)?;
     let date = validate_date(&date_str)?;
-    let tz_offset = query.tz_offset.unwrap_or(0);
-    info!(
-        "Getting samples for date: {} with tz_offset: {}",
-        date_str, tz_offset
-    );
-
-    let samples = state.storage.get_samples_for_date(date, tz_offset).await?;
+    let zone = query.zone(&state)?;
+    page.validate()?;
+    let format = export.format(&headers);
+    info!("Getting samples for date: {} with tz: {}", date_str, zone);
 
-    if samples.is_empty() {
-        return Err(ApiError::NotFound(format!(
-            "No samples found for date: {}",
-            date_str
-        )));
+    let range = zone.day_bounds(date);
+    // A later page may be empty, but the first one only when the day is
+    if format == ResponseFormat::Json && page.after.is_none() {
+        let first = state
+            .storage
+            .get_samples_page(range, query.user_id, None, Some(1))
+            .await?;
+        if first.is_empty() {
+            return Err(ApiError::NotFound(format!(
+                "No samples found for date: {}",
+                date_str
+            )));
+        }
     }
 
-    let samples: Vec<SampleResponse> = samples.into_iter().map(SampleResponse::from).collect();
-
-    Ok(Json(SamplesResponse {
-        date: date_str,
-        samples,
-    }))
+    let units = export.units.unwrap_or_default();
+    samples_response(
+        &state,
+        date_str,
+        range,
+        zone,
+        query.user_id,
+        &page,
+        format,
+        units,
+    )
+    .await
 }
 
 // Get samples by date range (for bulk queries)
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct SamplesRangeQuery {
     start_date: String, // YYYY-MM-DD
-    end_date: String,   // YYYY-MM-DD
+    end_date: String,   // YYYY-MM-DD (inclusive)
+    #[serde(default)]
+    user_id: Option<i64>,
+    #[serde(default)]
+    tz: Option<String>, // Zone the dates are local to (default: server zone)
+    #[serde(default)]
+    tz_offset: Option<i32>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/samples",
+    tag = "samples",
+    params(SamplesRangeQuery, PageQuery, ExportQuery),
+    responses(
+        (status = 200, content(
+            (SamplesResponse = "application/json"),
+            (SampleResponse = "application/x-ndjson"),
+            (String = "text/csv"),
+        )),
+        (status = 400, body = ErrorBody),
+    )
+)]
 async fn get_samples_by_range(
     State(state): State<AppState>,
     Query(query): Query<SamplesRangeQuery>,
-) -> Result<Json<SamplesResponse>, ApiError> {
+    Query(page): Query<PageQuery>,
+    Query(export): Query<ExportQuery>,
+    headers: HeaderMap,
+) -> Result<axum::response::Response, ApiError> {
     let start_date = validate_date(&query.start_date)?;
     let end_date = validate_date(&query.end_date)?;
+    page.validate()?;
+    let format = export.format(&headers);
 
     // Validate range
     let days_diff = (end_date - start_date).num_days();
@@ -237,44 +731,53 @@ async fn get_samples_by_range(
             "start_date must be before end_date",
         )));
     }
-    if days_diff > MAX_DATE_RANGE_DAYS {
+    // Only a single JSON document has to fit in memory
+    if days_diff > MAX_DATE_RANGE_DAYS && format == ResponseFormat::Json && page.limit.is_none() {
         return Err(ApiError::Validation(ValidationError::new(format!(
-            "Date range too large (max {} days)",
+            "Date range too large (max {} days without limit, NDJSON or CSV)",
             MAX_DATE_RANGE_DAYS
         ))));
     }
 
+    let zone = resolve_zone(query.tz.as_deref(), query.tz_offset, &state)?;
     info!(
-        "Getting samples from {} to {}",
-        query.start_date, query.end_date
+        "Getting samples from {} to {} (tz={})",
+        query.start_date, query.end_date, zone
     );
 
-    let start = start_date
-        .and_hms_opt(0, 0, 0)
-        .ok_or_else(|| ApiError::Validation(ValidationError::new("Invalid start date time")))?
-        .and_utc();
-    let end = end_date
-        .and_hms_opt(23, 59, 59)
-        .ok_or_else(|| ApiError::Validation(ValidationError::new("Invalid end date time")))?
-        .and_utc();
-
-    let samples = state.storage.get_samples_by_date_range(start, end).await?;
-    let samples: Vec<SampleResponse> = samples.into_iter().map(SampleResponse::from).collect();
+    // Local midnight at the start of start_date to local midnight after end_date
+    let (start, _) = zone.day_bounds(start_date);
+    let (_, end) = zone.day_bounds(end_date);
 
-    Ok(Json(SamplesResponse {
-        date: format!("{} to {}", query.start_date, query.end_date),
-        samples,
-    }))
+    let label = format!("{} to {}", query.start_date, query.end_date);
+    let units = export.units.unwrap_or_default();
+    samples_response(
+        &state,
+        label,
+        (start, end),
+        zone,
+        query.user_id,
+        &page,
+        format,
+        units,
+    )
+    .await
 }
 
 // Get general stats
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct StatsResponse {
     total_samples: i64,
     latest_sample_time: Option<String>,
     server_time: String,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/stats",
+    tag = "system",
+    responses((status = 200, body = StatsResponse))
+)]
 async fn get_stats(State(state): State<AppState>) -> Result<Json<StatsResponse>, ApiError> {
     info!("Getting stats");
 
@@ -292,7 +795,73 @@ async fn get_stats(State(state): State<AppState>) -> Result<Json<StatsResponse>,
     }))
 }
 
+// Import historical samples from a CSV or JSON export (request body)
+#[utoipa::path(
+    post,
+    path = "/api/import",
+    tag = "samples",
+    params(ImportOptions),
+    request_body(
+        description = "A CSV or JSON export",
+        content((String = "text/csv"), (String = "application/json"))
+    ),
+    responses(
+        (status = 200, body = ImportSummary),
+        (status = 400, body = ErrorBody),
+    )
+)]
+async fn import_samples(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Query(mut options): Query<ImportOptions>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<ImportSummary>, ApiError> {
+    if options.format.is_none() {
+        options.format = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(ImportFormat::detect);
+    }
+    info!(
+        "Importing {} bytes ({:?}, {:?} counters)",
+        body.len(),
+        options.format,
+        options.mode
+    );
+
+    let parsed = import::parse(&body, &options)
+        .map_err(|e| ApiError::Validation(ValidationError::new(e.to_string())))?;
+    let summary = import::insert(&state.storage, parsed, &state.energy).await?;
+
+    let author = corrections::change_author(token.as_deref(), None);
+    state
+        .storage
+        .record_audit(&author, "import", serde_json::to_value(&summary)?)
+        .await?;
+
+    info!(
+        "Import finished: {} inserted, {} duplicates, {} invalid",
+        summary.inserted, summary.duplicates, summary.invalid
+    );
+
+    // Imported history may hold older records
+    if summary.inserted > 0 {
+        state.records_rebuild.request();
+    }
+
+    Ok(Json(summary))
+}
+
 // Validation helpers
+fn resolve_zone(
+    tz: Option<&str>,
+    tz_offset: Option<i32>,
+    state: &AppState,
+) -> Result<Zone, ValidationError> {
+    Zone::resolve(tz, tz_offset, state.timezone).map_err(|e| ValidationError::new(e.to_string()))
+}
+
 fn validate_date(date_str: &str) -> Result<NaiveDate, ValidationError> {
     NaiveDate::parse_from_str(date_str, "%Y-%m-%d")
         .map_err(|_| ValidationError::new("Invalid date format (expected YYYY-MM-DD)"))
@@ -316,39 +885,54 @@ impl ValidationError {
 enum ApiError {
     Validation(ValidationError),
     NotFound(String),
+    Unauthorized(String),
+    Forbidden(String),
     Internal(anyhow::Error),
 }
 
+/// Body of every error response
+#[derive(Debug, Serialize, ToSchema)]
+struct ErrorBody {
+    error: String,
+}
+
+impl ErrorBody {
+    fn new(error: impl Into<String>) -> Json<Self> {
+        Json(Self {
+            error: error.into(),
+        })
+    }
+}
+
 impl IntoResponse for ApiError {
     fn into_response(self) -> axum::response::Response {
         match self {
             ApiError::Validation(e) => {
                 warn!("Validation error: {}", e.message);
-                (
-                    StatusCode::BAD_REQUEST,
-                    Json(serde_json::json!({
-                        "error": e.message
-                    })),
-                )
-                    .into_response()
+                (StatusCode::BAD_REQUEST, ErrorBody::new(e.message)).into_response()
             }
             ApiError::NotFound(msg) => {
                 warn!("Not found: {}", msg);
+                (StatusCode::NOT_FOUND, ErrorBody::new(msg)).into_response()
+            }
+            ApiError::Unauthorized(msg) => {
+                warn!("Unauthorized: {}", msg);
                 (
-                    StatusCode::NOT_FOUND,
-                    Json(serde_json::json!({
-                        "error": msg
-                    })),
+                    StatusCode::UNAUTHORIZED,
+                    [(header::WWW_AUTHENTICATE, "Bearer")],
+                    ErrorBody::new(msg),
                 )
                     .into_response()
             }
+            ApiError::Forbidden(msg) => {
+                warn!("Forbidden: {}", msg);
+                (StatusCode::FORBIDDEN, ErrorBody::new(msg)).into_response()
+            }
             ApiError::Internal(e) => {
                 error!("Internal server error: {}", e);
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(serde_json::json!({
-                        "error": "Internal server error"
-                    })),
+                    ErrorBody::new("Internal server error"),
                 )
                     .into_response()
             }
@@ -370,3 +954,899 @@ where
         ApiError::Internal(err.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{generate_token, hash_token, Scope};
+    use crate::config::{Config, ServerConfig, WriterConfig};
+    use crate::storage::test_support::{test_sample, test_storage_in};
+    use axum::body::Body;
+    use axum::http::{Method, Request};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    pub(super) async fn test_state(dir: &tempfile::TempDir, auth_enabled: bool) -> AppState {
+        let storage = Arc::new(test_storage_in(dir).await);
+        let (ws_tx, _) = broadcast::channel(16);
+        let active_user = ActiveUser::load(Arc::clone(&storage), ws_tx.clone())
+            .await
+            .unwrap();
+        let records = RecordsEngine::load(Arc::clone(&storage), ws_tx.clone(), Zone::default())
+            .await
+            .unwrap();
+        let (writer, _) = SampleWriter::start(
+            Arc::clone(&storage),
+            &WriterConfig::default(),
+            dir.path().join("test.db.spill"),
+        );
+        let records = Arc::new(Mutex::new(records));
+        let records_rebuild = RebuildRequests::default();
+        tokio::spawn(crate::records::run_live(
+            Arc::clone(&records),
+            writer.committed(),
+            records_rebuild.clone(),
+        ));
+
+        let webhooks =
+            Webhooks::new(Vec::new(), Arc::clone(&storage), Duration::from_millis(10)).unwrap();
+
+        AppState {
+            storage,
+            ws_tx,
+            bluetooth_status: Arc::new(RwLock::new(ConnectionStatus::Disconnected)),
+            active_user: Arc::new(active_user),
+            energy: EnergyConfig::default(),
+            timezone: Zone::default(),
+            week_start: Weekday::Mon,
+            records,
+            records_rebuild,
+            writer,
+            metrics: Arc::default(),
+            webhooks: Arc::new(webhooks),
+            auth_enabled,
+            cors: None,
+        }
+    }
+
+    async fn create_token(
+        state: &AppState,
+        name: &str,
+        scope: Scope,
+        user_id: Option<i64>,
+    ) -> String {
+        let token = generate_token();
+        state
+            .storage
+            .create_api_token(name, scope, user_id, &hash_token(&token))
+            .await
+            .unwrap();
+        token
+    }
+
+    async fn status(router: &Router, method: Method, uri: &str, token: Option<&str>) -> StatusCode {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let response = router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        response.status()
+    }
+
+    #[tokio::test]
+    async fn test_bearer_token_scopes() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(&dir, true).await;
+        let read = create_token(&state, "dashboard", Scope::Read, None).await;
+        let control = create_token(&state, "remote", Scope::Control, None).await;
+        let write = create_token(&state, "admin", Scope::Write, None).await;
+        let router = create_router(state.clone());
+
+        // Public routes need nothing
+        assert_eq!(
+            status(&router, Method::GET, "/api/health", None).await,
+            StatusCode::OK
+        );
+
+        let response = router
+            .clone()
+            .oneshot(Request::get("/api/dates").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        assert_eq!(
+            status(&router, Method::GET, "/api/dates", Some("wpk_nope")).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        assert_eq!(
+            status(&router, Method::GET, "/api/dates", Some(&read)).await,
+            StatusCode::OK
+        );
+        let delete = "/api/samples?start=0&end=1";
+        assert_eq!(
+            status(&router, Method::DELETE, delete, Some(&read)).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(&router, Method::DELETE, delete, Some(&control)).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_ne!(
+            status(&router, Method::DELETE, delete, Some(&write)).await,
+            StatusCode::FORBIDDEN
+        );
+
+        // Switching the active user is a control action
+        let set_active = |token: &str| {
+            Request::put("/api/users/active")
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"user_id":null}"#))
+                .unwrap()
+        };
+        let response = router.clone().oneshot(set_active(&read)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = router.clone().oneshot(set_active(&control)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The WebSocket is checked before the upgrade; a query token gets past it
+        assert_eq!(
+            status(&router, Method::GET, "/ws/live", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let uri = format!("/ws/live?access_token={}", read);
+        assert_ne!(
+            status(&router, Method::GET, &uri, None).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Revoked tokens stop working
+        state.storage.delete_api_token("dashboard").await.unwrap();
+        assert_eq!(
+            status(&router, Method::GET, "/api/dates", Some(&read)).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    async fn send(
+        router: &Router,
+        method: Method,
+        uri: &str,
+        body: &str,
+        token: &str,
+    ) -> (StatusCode, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_user_tokens_only_reach_their_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(&dir, true).await;
+        let alice = state.storage.create_user("Alice", None).await.unwrap().id;
+        let bob = state.storage.create_user("Bob", None).await.unwrap().id;
+        // Alice walked on the 15th, Bob on the 16th
+        let samples = [
+            TreadmillSample {
+                user_id: Some(alice),
+                ..test_sample(1736935200)
+            },
+            TreadmillSample {
+                user_id: Some(bob),
+                ..test_sample(1737021600)
+            },
+        ];
+        state.storage.add_samples(&samples).await.unwrap();
+        let admin = create_token(&state, "admin", Scope::Write, None).await;
+        let token = create_token(&state, "alice", Scope::Write, Some(alice)).await;
+        let router = create_router(state.clone());
+        let get = |uri: &'static str| send(&router, Method::GET, uri, "", &token);
+
+        // Reads default to Alice and refuse anyone else
+        let (status, body) = get("/api/dates").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("2025-01-15") && !body.contains("2025-01-16"));
+        let (status, body) = get("/api/records").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["user_id"], alice);
+        let uri = format!("/api/dates/summaries?user_id={}", bob);
+        let (status, _) = send(&router, Method::GET, &uri, "", &token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        // Routes covering every user are off limits, whatever the scope
+        for uri in [
+            "/api/samples?start=0&end=2000000000",
+            "/api/users",
+            "/api/audit",
+        ] {
+            assert_eq!(get(uri).await.0, StatusCode::FORBIDDEN, "{}", uri);
+        }
+
+        // Writes too
+        let delete = send(
+            &router,
+            Method::DELETE,
+            "/api/samples?start=0&end=1",
+            "",
+            &token,
+        );
+        assert_eq!(delete.await.0, StatusCode::FORBIDDEN);
+        let user_body = |id: i64| format!(r#"{{"user_id":{}}}"#, id);
+        let (status, _) = send(
+            &router,
+            Method::PUT,
+            "/api/users/active",
+            &user_body(bob),
+            &token,
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(
+            &router,
+            Method::PUT,
+            "/api/users/active",
+            &user_body(alice),
+            &token,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Sync clients are registered for Alice, and Bob's stay out of reach
+        let uri = "/api/sync/clients/alice-phone";
+        let (status, _) = send(&router, Method::PUT, uri, &user_body(bob), &token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = send(&router, Method::PUT, uri, "{}", &token).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["user_id"], alice);
+
+        let uri = "/api/sync/clients/bob-phone";
+        let (status, _) = send(&router, Method::PUT, uri, &user_body(bob), &admin).await;
+        assert_eq!(status, StatusCode::OK);
+        for (method, uri, body) in [
+            (Method::PUT, uri, "{}"),
+            (Method::GET, "/api/sync/clients/bob-phone/pending", ""),
+            (
+                Method::POST,
+                "/api/sync/clients/bob-phone/ack",
+                r#"{"items":[]}"#,
+            ),
+        ] {
+            let (status, _) = send(&router, method, uri, body, &token).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_audit_actor_is_the_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(&dir, true).await;
+        let token = create_token(&state, "kitchen-tablet", Scope::Write, None).await;
+        let router = create_router(state);
+
+        // A claimed actor is ignored; the reason is kept
+        let body = r#"{"start": 0, "end": 10, "actor": "someone-else", "reason": "guest"}"#;
+        let (status, _) = send(&router, Method::POST, "/api/samples/exclude", body, &token).await;
+        assert_eq!(status, StatusCode::OK);
+        let body = serde_json::json!({"samples": [{"timestamp": 1736899200, "speed": 1.2}]});
+        let (status, _) = send(
+            &router,
+            Method::POST,
+            "/api/import",
+            &body.to_string(),
+            &token,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(&router, Method::GET, "/api/audit", "", &token).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let entries = body["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["action"], "import");
+        for entry in entries {
+            assert_eq!(entry["actor"], "kitchen-tablet");
+        }
+        assert!(entries[1]["details"].as_str().unwrap().contains("guest"));
+    }
+
+    async fn get(router: &Router, uri: &str, accept: &str) -> (StatusCode, String) {
+        let request = Request::get(uri)
+            .header(header::ACCEPT, accept)
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    /// One sample a minute from 2025-01-15 00:00:00 UTC; returns the first timestamp
+    async fn insert_minutely_samples(state: &AppState, count: i64) -> i64 {
+        let start = 1736899200;
+        let samples: Vec<_> = (0..count)
+            .map(|i| TreadmillSample {
+                calories_delta: None,
+                ..test_sample(start + i * 60)
+            })
+            .collect();
+        state
+            .storage
+            .insert_samples_if_absent(&samples)
+            .await
+            .unwrap();
+        start
+    }
+
+    #[tokio::test]
+    async fn test_sample_pagination_and_ndjson() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(&dir, false).await;
+        let start = insert_minutely_samples(&state, 25).await;
+        let router = create_router(state);
+
+        // Follow `next_after` until it's gone
+        let mut seen = Vec::new();
+        let mut uri = "/api/samples?start_date=2025-01-15&end_date=2025-01-15&limit=10".to_string();
+        loop {
+            let (status, body) = get(&router, &uri, "application/json").await;
+            assert_eq!(status, StatusCode::OK);
+            let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+            for sample in page["samples"].as_array().unwrap() {
+                seen.push(sample["timestamp"].as_i64().unwrap());
+            }
+            let Some(next) = page.get("next_after") else {
+                break;
+            };
+            uri = format!("/api/dates/2025-01-15/samples?limit=10&after={}", next);
+        }
+        assert_eq!(seen, (0..25).map(|i| start + i * 60).collect::<Vec<_>>());
+
+        // NDJSON from the Accept header or `format`, honouring the cursor
+        let (status, body) = get(
+            &router,
+            &format!("/api/dates/2025-01-15/samples?after={}", start + 20 * 60),
+            "application/x-ndjson",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let lines: Vec<serde_json::Value> = body
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["timestamp"], start + 21 * 60);
+
+        // Streams aren't limited to a year; whole documents are
+        let long = "/api/samples?start_date=2020-01-01&end_date=2025-12-31";
+        let (status, body) = get(&router, &format!("{}&format=ndjson", long), "*/*").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.lines().count(), 25);
+        let (status, _) = get(&router, long, "application/json").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = get(&router, &format!("{}&limit=0", long), "*/*").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_estimates_follow_the_samples_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(&dir, false).await;
+        let storage = Arc::clone(&state.storage);
+        let light = storage.create_user("light", Some(50.0)).await.unwrap();
+        let heavy = storage.create_user("heavy", Some(120.0)).await.unwrap();
+        let router = create_router(state);
+        let start = 1736899200;
+        let estimated = || async {
+            let samples = storage
+                .get_samples_page((start, start + 10), None, None, None)
+                .await
+                .unwrap();
+            assert_eq!(samples.len(), 5);
+            samples
+                .iter()
+                .map(|s| s.calories_estimated.expect("not estimated"))
+                .sum::<f64>()
+        };
+
+        let rows: Vec<_> = (0..5)
+            .map(|i| serde_json::json!({"timestamp": start + i, "speed": 1.2, "steps": 2}))
+            .collect();
+        let body = serde_json::json!({ "samples": rows }).to_string();
+        let uri = format!("/api/import?mode=delta&user_id={}", light.id);
+        let (status, _) = post_json(&router, &uri, &body).await;
+        assert_eq!(status, StatusCode::OK);
+        let light_kcal = estimated().await;
+        assert!(light_kcal > 0.0);
+
+        let body = serde_json::json!({"start": start, "end": start + 10, "user_id": heavy.id});
+        let (status, _) = post_json(&router, "/api/samples/assign", &body.to_string()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(estimated().await > light_kcal * 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_corrections_rebuild_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(&dir, false).await;
+        let start = 1736899200;
+        // A 100 s session, then a 20 s one an hour later
+        let samples: Vec<_> = (0..100)
+            .chain(3600..3620)
+            .map(|i| test_sample(start + i))
+            .collect();
+        state.storage.add_samples(&samples).await.unwrap();
+        state.records.lock().await.rebuild().await.unwrap();
+        let router = create_router(state);
+        let longest_session = || async {
+            let (status, body) = get(&router, "/api/records", "application/json").await;
+            assert_eq!(status, StatusCode::OK);
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            body["records"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|r| r["kind"] == "longest_session")
+                .map(|r| r["value"].as_f64().unwrap())
+        };
+        assert_eq!(longest_session().await, Some(99.0));
+
+        let range = serde_json::json!({"start": start, "end": start + 100}).to_string();
+        let request = Request::delete("/api/samples")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(range))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // Rebuilt in the background
+        for _ in 0..100 {
+            if longest_session().await == Some(19.0) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("records weren't rebuilt");
+    }
+
+    #[tokio::test]
+    async fn test_zone_parameters() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(&dir, false).await;
+        insert_minutely_samples(&state, 3).await;
+        let router = create_router(state);
+
+        // Midnight UTC falls on the 14th five hours west
+        let uri = "/api/dates?tz=Nowhere/Special&tz_offset=-18000";
+        let (status, body) = get(&router, uri, "*/*").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("2025-01-14"), "{}", body);
+
+        for uri in ["/api/dates?tz=Nowhere/Special", "/api/dates?tz_offset=123"] {
+            let (status, _) = get(&router, uri, "*/*").await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_csv_exports() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(&dir, false).await;
+        insert_minutely_samples(&state, 3).await;
+        let router = create_router(state);
+
+        // Times in the requested zone; the first samples fall on the 14th there
+        let (status, body) = get(
+            &router,
+            "/api/dates/2025-01-14/samples?tz=America/New_York&units=imperial",
+            "text/csv",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let lines: Vec<_> = body.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("time,timestamp,speed_mph,distance_ft,"));
+        assert!(lines[1].starts_with("2025-01-14T19:00:00-05:00,1736899200,2.24,3.28,2,"));
+
+        let (status, body) = get(
+            &router,
+            "/api/samples?start_date=2025-01-15&end_date=2025-01-15&format=csv&limit=2",
+            "application/json",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.lines().count(), 3);
+        assert!(body
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("2025-01-15T00:00:00+00:00,"));
+
+        let (status, body) = get(&router, "/api/dates/summaries?format=csv", "*/*").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            "date,distance_km,steps,calories,calories_source,duration_seconds,\
+             avg_speed_kmh,max_speed_kmh,samples,corrected\n\
+             2025-01-15,0.003,6,0,device,0,3.6,3.6,3,false\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_workout_exports() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(&dir, false).await;
+        let start = insert_minutely_samples(&state, 5).await;
+        let router = create_router(state);
+
+        let request = Request::get(format!("/api/sessions/{}/export.tcx", start))
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/vnd.garmin.tcx+xml"
+        );
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"walk-2025-01-15-0000.tcx\""
+        );
+
+        let (status, body) = get(
+            &router,
+            "/api/dates/2025-01-15/export.gpx?lat=40&lon=-74",
+            "*/*",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.matches("<trkpt ").count(), 5);
+        assert!(body.contains("<trkpt lat=\"39.9996"));
+
+        // Only a session's first sample identifies it
+        let uri = format!("/api/sessions/{}/export.gpx", start + 60);
+        assert_eq!(get(&router, &uri, "*/*").await.0, StatusCode::NOT_FOUND);
+        let uri = "/api/dates/2025-01-16/export.tcx";
+        assert_eq!(get(&router, uri, "*/*").await.0, StatusCode::NOT_FOUND);
+
+        let request = Request::get("/api/dates/2025-01-15/export.fit")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/vnd.ant.fit"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[8..12], b".FIT");
+    }
+
+    async fn post_json(router: &Router, uri: &str, body: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn test_grafana_datasource() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(&dir, false).await;
+        let start = insert_minutely_samples(&state, 3).await;
+        let router = create_router(state);
+        let ms = |ts: i64| serde_json::json!((ts * 1000) as f64);
+
+        assert_eq!(get(&router, "/grafana/", "*/*").await.0, StatusCode::OK);
+        let (_, found) = post_json(&router, "/grafana/search", r#"{"target": "daily_"}"#).await;
+        assert_eq!(found.as_array().unwrap().len(), 5);
+
+        // Ten minutes in five points: two-minute buckets
+        let (status, results) = post_json(
+            &router,
+            "/grafana/query",
+            r#"{
+                "range": {"from": "2025-01-15T00:00:00Z", "to": "2025-01-15T00:10:00Z"},
+                "maxDataPoints": 5,
+                "targets": [
+                    {"target": "steps", "refId": "A"},
+                    {"target": "daily_steps", "refId": "B", "data": {"tz": "UTC"}},
+                    {"target": "distance", "refId": "C", "type": "table"},
+                    {"target": "speed", "refId": "D", "hide": true}
+                ]
+            }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(results.as_array().unwrap().len(), 3);
+        assert_eq!(results[0]["target"], "steps");
+        assert_eq!(
+            results[0]["datapoints"],
+            serde_json::json!([[4.0, ms(start)], [2.0, ms(start + 120)]])
+        );
+        assert_eq!(
+            results[1]["datapoints"],
+            serde_json::json!([[6.0, ms(start)]])
+        );
+        assert_eq!(results[2]["type"], "table");
+        assert_eq!(results[2]["rows"][0], serde_json::json!([ms(start), 2.0]));
+
+        let (status, _) = post_json(
+            &router,
+            "/grafana/query",
+            r#"{"range": {"from": "2025-01-15T00:00:00Z", "to": "2025-01-16T00:00:00Z"},
+                "targets": [{"target": "nope"}]}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // The walk as one region
+        let (status, annotations) = post_json(
+            &router,
+            "/grafana/annotations",
+            r#"{"range": {"from": "2025-01-15T00:00:00Z", "to": "2025-01-16T00:00:00Z"},
+                "annotation": {"name": "Walks", "enable": true, "query": ""}}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(annotations.as_array().unwrap().len(), 1);
+        assert_eq!(annotations[0]["time"], start * 1000);
+        assert_eq!(annotations[0]["timeEnd"], (start + 120) * 1000);
+        assert_eq!(annotations[0]["annotation"]["name"], "Walks");
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(&dir, false).await;
+        *state.bluetooth_status.write().await = ConnectionStatus::Connected;
+        let sample = TreadmillSample {
+            speed: Some(1.25),
+            distance_total: Some(500),
+            calories_total: Some(20),
+            steps_total: Some(700),
+            distance_delta: Some(2),
+            calories_delta: Some(1),
+            steps_delta: Some(3),
+            ..test_sample(1736899200)
+        };
+        state.metrics.observe_reading(sample.speed);
+        state.metrics.observe_sample(&sample);
+        state.metrics.observe_sample(&sample);
+        state.metrics.parse_failure();
+        let router = create_router(state);
+
+        let request = Request::get("/metrics").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            metrics::CONTENT_TYPE
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        for line in [
+            "# TYPE walkpad_distance_meters_total counter",
+            "walkpad_speed_meters_per_second 1.25",
+            "walkpad_distance_meters_total 4",
+            "walkpad_steps_total 6",
+            "walkpad_calories_total 2",
+            "walkpad_samples_recorded_total 2",
+            "walkpad_last_sample_timestamp_seconds 1736899200",
+            "walkpad_bluetooth_status{state=\"connected\"} 1",
+            "walkpad_bluetooth_status{state=\"disconnected\"} 0",
+            "walkpad_bluetooth_parse_failures_total 1",
+            "walkpad_db_write_seconds_count 0",
+            "walkpad_websocket_clients 0",
+        ] {
+            assert!(body.lines().any(|l| l == line), "{} missing", line);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_webhook_redelivery() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = test_state(&dir, false).await;
+        let hook = crate::config::WebhookConfig {
+            name: "slack".to_string(),
+            url: "http://127.0.0.1:9/unreachable".to_string(),
+            events: None,
+            secret: None,
+            template: None,
+            max_retries: 0,
+        };
+        state.webhooks = Arc::new(
+            Webhooks::new(vec![hook], Arc::clone(&state.storage), Duration::ZERO).unwrap(),
+        );
+        let event = crate::webhooks::WebhookEvent::SessionEnded;
+        let storage = Arc::clone(&state.storage);
+        let original = storage
+            .create_webhook_delivery("slack", event, r#"{"text":"hi"}"#, None)
+            .await
+            .unwrap();
+        let removed = storage
+            .create_webhook_delivery("old", event, "{}", None)
+            .await
+            .unwrap();
+        let router = create_router(state);
+
+        let uri = format!("/api/webhooks/deliveries/{}/redeliver", original.id);
+        let (status, again) = post_json(&router, &uri, "").await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(again["webhook"], "slack");
+        assert_eq!(again["event"], "session_ended");
+        assert_eq!(again["payload"], r#"{"text":"hi"}"#);
+        assert_eq!(again["redelivery_of"], original.id);
+
+        let uri = format!("/api/webhooks/deliveries/{}/redeliver", removed.id);
+        assert_eq!(post_json(&router, &uri, "").await.0, StatusCode::NOT_FOUND);
+        let uri = "/api/webhooks/deliveries/999/redeliver";
+        assert_eq!(post_json(&router, uri, "").await.0, StatusCode::NOT_FOUND);
+
+        let (status, body) = get(&router, "/api/webhooks/deliveries?webhook=slack", "*/*").await;
+        assert_eq!(status, StatusCode::OK);
+        let deliveries: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let ids: Vec<_> = deliveries["deliveries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d["id"].as_i64().unwrap())
+            .collect();
+        assert_eq!(ids, [again["id"].as_i64().unwrap(), original.id]);
+    }
+
+    fn cors_config(origins: &[&str], credentials: bool) -> ServerConfig {
+        ServerConfig {
+            cors_origins: origins.iter().map(|o| o.to_string()).collect(),
+            cors_credentials: credentials,
+            cors_max_age_secs: 600,
+            ..Config::default().server
+        }
+    }
+
+    async fn from_origin(
+        router: &Router,
+        method: Method,
+        uri: &str,
+        origin: &str,
+    ) -> axum::response::Response {
+        let request = Request::builder()
+            .method(method.clone())
+            .uri(uri)
+            .header(header::ORIGIN, origin);
+        let request = if method == Method::OPTIONS {
+            request
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "DELETE")
+                .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+        } else {
+            request
+        };
+        router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_cors_config() {
+        assert!(cors_config(&[], true).cors().unwrap().is_none());
+        assert!(cors_config(&["*"], false).cors().unwrap().is_some());
+        assert!(cors_config(&["*"], true).cors().is_err());
+        assert!(cors_config(&["https://a.example", "bad\norigin"], false)
+            .cors()
+            .is_err());
+        let mut config = cors_config(&["https://a.example"], false);
+        config.cors_methods = vec!["get".to_string(), "not a method".to_string()];
+        assert!(config.cors().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = "https://walkpad.example.com";
+        let mut state = test_state(&dir, true).await;
+        // Configured with a trailing slash, as copied from an address bar
+        state.cors = cors_config(&["https://walkpad.example.com/"], true)
+            .cors()
+            .unwrap();
+        let router = create_router(state);
+
+        // Preflight is answered without a token
+        let response = from_origin(&router, Method::OPTIONS, "/api/users/1", origin).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+        let methods = headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+            .to_str()
+            .unwrap();
+        assert!(methods.contains("DELETE") && methods.contains("PATCH"));
+        let allowed = headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(allowed.contains("authorization"));
+
+        // Other origins get no permission
+        let response = from_origin(
+            &router,
+            Method::OPTIONS,
+            "/api/users/1",
+            "https://other.example",
+        )
+        .await;
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        // The request itself still needs a token, but the browser can read the error
+        let response = from_origin(&router, Method::GET, "/api/dates", origin).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            origin
+        );
+        let exposed = response.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(exposed.contains("www-authenticate"));
+
+        // WebSocket route too
+        let response = from_origin(&router, Method::GET, "/ws/live", origin).await;
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            origin
+        );
+
+        // Without a policy there are no CORS headers, and preflight needs a token
+        let dir = tempfile::tempdir().unwrap();
+        let router = create_router(test_state(&dir, true).await);
+        let response = from_origin(&router, Method::OPTIONS, "/api/users/1", origin).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn test_auth_disabled_allows_anonymous_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = create_router(test_state(&dir, false).await);
+        assert_eq!(
+            status(&router, Method::GET, "/api/dates", None).await,
+            StatusCode::OK
+        );
+    }
+}
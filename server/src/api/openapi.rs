@@ -0,0 +1,297 @@
+//! OpenAPI description of the REST and WebSocket API, generated from the
+//! handlers' `#[utoipa::path]` attributes and their request/response types.
+//! Each operation's security requirement comes from `auth::required_scope`,
+//! so the document always matches what the server enforces.
+
+use axum::Json;
+use std::sync::LazyLock;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{self, SecurityRequirement};
+use utoipa::{Modify, OpenApi};
+
+use super::{
+    __path_get_activity_dates, __path_get_all_summaries, __path_get_bluetooth_status,
+    __path_get_date_samples, __path_get_date_summary, __path_get_metrics,
+    __path_get_samples_by_range, __path_get_stats, __path_health_check, __path_import_samples,
+    aggregate, changes, corrections, goals, grafana, quality, records, sync, users, webhooks,
+    workouts, ErrorBody,
+};
+use crate::auth::required_scope;
+use crate::websocket::{WsCommand, WsMessage};
+
+const BEARER: &str = "bearer";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "WalkPad Sync Server",
+        description = "Treadmill samples, daily summaries and live updates. When \
+            authentication is enabled, send `Authorization: Bearer <token>`; each \
+            operation lists the token scope it needs."
+    ),
+    paths(
+        health_check,
+        get_openapi,
+        get_bluetooth_status,
+        get_stats,
+        get_metrics,
+        get_activity_dates,
+        get_all_summaries,
+        get_date_summary,
+        get_date_samples,
+        get_samples_by_range,
+        import_samples,
+        aggregate::get_aggregate,
+        corrections::delete_samples,
+        corrections::exclude_samples,
+        corrections::include_samples,
+        corrections::get_date_corrections,
+        corrections::add_date_correction,
+        corrections::delete_correction,
+        corrections::get_audit_log,
+        changes::get_changes,
+        goals::list_goals,
+        goals::set_goal,
+        goals::get_streaks,
+        goals::delete_goal,
+        records::get_records,
+        records::rebuild_records,
+        quality::get_quality_reports,
+        quality::get_date_quality,
+        sync::list_clients,
+        sync::put_client,
+        sync::delete_client,
+        sync::get_pending,
+        sync::acknowledge,
+        sync::get_status,
+        sync::get_ledger,
+        users::list_users,
+        users::create_user,
+        users::update_user,
+        users::delete_user,
+        users::get_active_user,
+        users::set_active_user,
+        users::get_user_schedules,
+        users::add_user_schedule,
+        users::delete_schedule,
+        users::assign_samples,
+        workouts::get_session_fit,
+        workouts::get_session_tcx,
+        workouts::get_session_gpx,
+        workouts::get_date_fit,
+        workouts::get_date_tcx,
+        workouts::get_date_gpx,
+        grafana::test_connection,
+        grafana::search,
+        grafana::query,
+        grafana::annotations,
+        webhooks::list_deliveries,
+        webhooks::redeliver,
+        crate::websocket::ws_handler,
+    ),
+    components(schemas(ErrorBody, WsMessage, WsCommand)),
+    modifiers(&Security),
+    tags(
+        (name = "activity", description = "Daily summaries and aggregates"),
+        (name = "samples", description = "Raw samples and imports"),
+        (name = "corrections", description = "Fixing recorded data, with an audit log"),
+        (name = "changes", description = "Incremental change feed"),
+        (name = "goals", description = "Daily and weekly targets"),
+        (name = "records", description = "Personal records and achievements"),
+        (name = "quality", description = "Quarantined and flagged samples"),
+        (name = "sync", description = "Export ledger for Apple Health and similar"),
+        (name = "users", description = "Profiles, the active user and schedules"),
+        (name = "export", description = "Workout files for other apps"),
+        (name = "grafana", description = "JSON datasource for Grafana"),
+        (name = "webhooks", description = "Outgoing webhook deliveries"),
+        (name = "live", description = "Live samples and events over a WebSocket"),
+        (name = "system", description = "Health and server status"),
+    )
+)]
+pub(super) struct ApiDoc;
+
+/// Bearer auth, with each operation's scope from the same rules the
+/// middleware applies
+struct Security;
+
+impl Modify for Security {
+    fn modify(&self, doc: &mut openapi::OpenApi) {
+        doc.components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                BEARER,
+                SecurityScheme::Http(
+                    HttpBuilder::new()
+                        .scheme(HttpAuthScheme::Bearer)
+                        .description(Some(
+                            "An API token from `walkpad-server token create`. Scopes: read, \
+                         control (also switches the active user), write (everything).",
+                        ))
+                        .build(),
+                ),
+            );
+
+        for (path, item) in doc.paths.paths.iter_mut() {
+            let operations = [
+                (axum::http::Method::GET, &mut item.get),
+                (axum::http::Method::PUT, &mut item.put),
+                (axum::http::Method::POST, &mut item.post),
+                (axum::http::Method::DELETE, &mut item.delete),
+                (axum::http::Method::PATCH, &mut item.patch),
+            ];
+            for (method, operation) in operations {
+                let Some(operation) = operation else {
+                    continue;
+                };
+                operation.security = Some(match required_scope(&method, path) {
+                    Some(scope) => vec![SecurityRequirement::new(BEARER, [scope.to_string()])],
+                    None => Vec::new(),
+                });
+            }
+        }
+    }
+}
+
+static DOCUMENT: LazyLock<openapi::OpenApi> = LazyLock::new(ApiDoc::openapi);
+
+#[utoipa::path(
+    get,
+    path = "/api/openapi.json",
+    tag = "system",
+    responses((status = 200, description = "This document", content_type = "application/json"))
+)]
+pub(super) async fn get_openapi() -> Json<openapi::OpenApi> {
+    Json(DOCUMENT.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::create_router;
+    use crate::api::tests::test_state;
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Method, Request, StatusCode};
+    use std::collections::BTreeSet;
+    use tower::ServiceExt;
+
+    /// (method, path) for every route registered in `create_router`, read from
+    /// its source since axum can't list a router's routes
+    fn router_routes() -> BTreeSet<(String, String)> {
+        let source = include_str!("mod.rs");
+        let start = source.find("pub fn create_router").unwrap();
+        let body = &source[start..];
+        let body = &body[..body.find("\n}\n").unwrap()];
+
+        let mut routes = BTreeSet::new();
+        for route in body.split(".route(").skip(1) {
+            let path = route.split('"').nth(1).unwrap();
+            let handlers = &route[route.find(',').unwrap()..];
+            for method in ["get", "put", "post", "delete", "patch"] {
+                let called = handlers.match_indices(method).any(|(i, _)| {
+                    let before = handlers[..i].chars().last();
+                    handlers[i + method.len()..].starts_with('(')
+                        && matches!(before, Some(' ' | '.' | '\n' | ','))
+                });
+                if called {
+                    // `:param` in axum, `{param}` in OpenAPI
+                    let path = path
+                        .split('/')
+                        .map(|s| match s.strip_prefix(':') {
+                            Some(param) => format!("{{{}}}", param),
+                            None => s.to_string(),
+                        })
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    routes.insert((method.to_uppercase(), path));
+                }
+            }
+        }
+        routes
+    }
+
+    fn spec_routes() -> BTreeSet<(String, String)> {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let mut routes = BTreeSet::new();
+        for (path, item) in doc["paths"].as_object().unwrap() {
+            for method in item.as_object().unwrap().keys() {
+                routes.insert((method.to_uppercase(), path.clone()));
+            }
+        }
+        routes
+    }
+
+    #[test]
+    fn test_spec_covers_every_route() {
+        // The dashboard pages aren't part of the API
+        let routes: BTreeSet<_> = router_routes()
+            .into_iter()
+            .filter(|(_, path)| path != "/" && path != "/dashboard")
+            .collect();
+        let spec = spec_routes();
+        assert!(routes.len() > 40, "failed to read routes: {:?}", routes);
+
+        let undocumented: Vec<_> = routes.difference(&spec).collect();
+        let unrouted: Vec<_> = spec.difference(&routes).collect();
+        assert!(
+            undocumented.is_empty() && unrouted.is_empty(),
+            "routes missing from the OpenAPI document: {:?}; documented but not routed: {:?}",
+            undocumented,
+            unrouted
+        );
+    }
+
+    #[tokio::test]
+    async fn test_documented_operations_are_served() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = create_router(test_state(&dir, false).await);
+
+        for (method, path) in spec_routes() {
+            let uri = path
+                .replace("{date}", "2025-01-15")
+                .replace("{client_id}", "phone")
+                .replace("{id}", "1");
+            let request = Request::builder()
+                .method(Method::from_bytes(method.as_bytes()).unwrap())
+                .uri(&uri)
+                .body(Body::empty())
+                .unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+            // Unrouted requests get an empty 404 or 405; handlers' errors have a body
+            assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{} {}", method, uri);
+            assert!(
+                status != StatusCode::NOT_FOUND || !body.is_empty(),
+                "{} {} is not routed",
+                method,
+                uri
+            );
+        }
+    }
+
+    #[test]
+    fn test_security_follows_route_scopes() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let paths = &doc["paths"];
+
+        assert_eq!(
+            paths["/api/health"]["get"]["security"],
+            serde_json::json!([])
+        );
+        assert_eq!(
+            paths["/api/dates"]["get"]["security"],
+            serde_json::json!([{ "bearer": ["read"] }])
+        );
+        assert_eq!(
+            paths["/api/users/active"]["put"]["security"],
+            serde_json::json!([{ "bearer": ["control"] }])
+        );
+        assert_eq!(
+            paths["/api/samples"]["delete"]["security"],
+            serde_json::json!([{ "bearer": ["write"] }])
+        );
+        assert!(doc["components"]["schemas"]["DailySummary"].is_object());
+        assert!(doc["components"]["schemas"]["WsMessage"].is_object());
+    }
+}
@@ -0,0 +1,81 @@
+//! Data quality reports: per-day counts of recorded, rejected and flagged
+//! samples, and the quarantine entries behind them.
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::Serialize;
+use tracing::info;
+use utoipa::ToSchema;
+
+use super::{validate_date, ApiError, AppState, ErrorBody, TimezoneQuery};
+use crate::quality::{self, QualityReport};
+use crate::storage::QuarantinedSample;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct QualityReportsResponse {
+    days: Vec<QualityReport>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct DateQualityResponse {
+    #[serde(flatten)]
+    report: QualityReport,
+    quarantined: Vec<QuarantinedSample>,
+}
+
+// Quality reports for every day with data, newest first
+#[utoipa::path(
+    get,
+    path = "/api/quality",
+    tag = "quality",
+    params(TimezoneQuery),
+    responses(
+        (status = 200, body = QualityReportsResponse),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub(super) async fn get_quality_reports(
+    State(state): State<AppState>,
+    Query(query): Query<TimezoneQuery>,
+) -> Result<Json<QualityReportsResponse>, ApiError> {
+    let zone = query.zone(&state)?;
+    info!(
+        "Getting quality reports (tz={}, user_id={:?})",
+        zone, query.user_id
+    );
+
+    let days = quality::daily_reports(&state.storage, &zone, query.user_id).await?;
+
+    Ok(Json(QualityReportsResponse { days }))
+}
+
+// One day's quality report with its quarantined samples
+#[utoipa::path(
+    get,
+    path = "/api/quality/{date}",
+    tag = "quality",
+    params(("date" = String, Path, description = "Local date (YYYY-MM-DD)"), TimezoneQuery),
+    responses(
+        (status = 200, body = DateQualityResponse),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub(super) async fn get_date_quality(
+    State(state): State<AppState>,
+    Path(date_str): Path<String>,
+    Query(query): Query<TimezoneQuery>,
+) -> Result<Json<DateQualityResponse>, ApiError> {
+    let date = validate_date(&date_str)?;
+    let zone = query.zone(&state)?;
+    info!("Getting quality report for {} (tz={})", date_str, zone);
+
+    let (report, quarantined) =
+        quality::report_for_date(&state.storage, date, &zone, query.user_id).await?;
+
+    Ok(Json(DateQualityResponse {
+        report,
+        quarantined,
+    }))
+}
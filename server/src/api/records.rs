@@ -0,0 +1,67 @@
+//! Personal records and achievements (kept up to date by `crate::records`).
+
+use axum::{extract::Extension, extract::Query, extract::State, Json};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{auth, ApiError, AppState};
+use crate::storage::{Achievement, ApiToken, PersonalRecord};
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct RecordsQuery {
+    #[serde(default)]
+    user_id: Option<i64>, // default: everyone combined
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct RecordsResponse {
+    user_id: Option<i64>,
+    records: Vec<PersonalRecord>,
+    achievements: Vec<Achievement>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/records",
+    tag = "records",
+    params(RecordsQuery),
+    responses((status = 200, body = RecordsResponse))
+)]
+pub(super) async fn get_records(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Query(mut query): Query<RecordsQuery>,
+) -> Result<Json<RecordsResponse>, ApiError> {
+    query.user_id = auth::limit_user(token.as_deref(), query.user_id)?;
+    let records = state.storage.get_personal_records(query.user_id).await?;
+    let achievements = state.storage.get_achievements(query.user_id).await?;
+
+    Ok(Json(RecordsResponse {
+        user_id: query.user_id,
+        records,
+        achievements,
+    }))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct RebuildResponse {
+    samples: u64,
+}
+
+// Recompute records from all stored samples (after imports, deletions or reassignments)
+#[utoipa::path(
+    post,
+    path = "/api/records/rebuild",
+    tag = "records",
+    responses((status = 200, body = RebuildResponse))
+)]
+pub(super) async fn rebuild_records(
+    State(state): State<AppState>,
+) -> Result<Json<RebuildResponse>, ApiError> {
+    info!("Rebuilding personal records");
+    let samples = state.records.lock().await.rebuild().await?;
+
+    Ok(Json(RebuildResponse { samples }))
+}
@@ -0,0 +1,329 @@
+//! Sync ledger endpoints: clients fetch sessions their destination (e.g.
+//! Apple Health) doesn't have yet, acknowledge what they exported, and ask
+//! whether a day is already there.
+
+use axum::{
+    extract::{Extension, Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{auth, resolve_zone, validate_date, ApiError, AppState, ErrorBody, ValidationError};
+use crate::storage::{ApiToken, LedgerEntry, SyncClient};
+use crate::sync::{self, Ack, AckResult, DayStatus, SyncItem, DEFAULT_DESTINATION};
+
+const MAX_CLIENT_ID_LEN: usize = 128;
+const DEFAULT_PENDING_LIMIT: usize = 100;
+const DEFAULT_LEDGER_LIMIT: i64 = 100;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct ClientsResponse {
+    clients: Vec<SyncClient>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/sync/clients",
+    tag = "sync",
+    responses((status = 200, body = ClientsResponse))
+)]
+pub(super) async fn list_clients(
+    State(state): State<AppState>,
+) -> Result<Json<ClientsResponse>, ApiError> {
+    let clients = state.storage.get_sync_clients().await?;
+
+    Ok(Json(ClientsResponse { clients }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(super) struct ClientRequest {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    destination: Option<String>, // default: apple_health
+    #[serde(default)]
+    user_id: Option<i64>, // only this user's sessions (default: everyone's)
+    #[serde(default)]
+    since: Option<i64>, // reset the cursor (Unix epoch seconds)
+}
+
+// Register a client (or update it). The client ID is chosen by the client
+// and should survive reinstalls (e.g. kept in the keychain).
+#[utoipa::path(
+    put,
+    path = "/api/sync/clients/{client_id}",
+    tag = "sync",
+    params(("client_id" = String, Path, description = "Chosen by the client; should survive reinstalls")),
+    request_body = ClientRequest,
+    responses(
+        (status = 200, body = SyncClient),
+        (status = 400, body = ErrorBody),
+        (status = 404, description = "Unknown user", body = ErrorBody),
+    )
+)]
+pub(super) async fn put_client(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Path(client_id): Path<String>,
+    Json(mut request): Json<ClientRequest>,
+) -> Result<Json<SyncClient>, ApiError> {
+    let token = token.as_deref();
+    request.user_id = auth::limit_user(token, request.user_id)?;
+    if client_id.trim().is_empty() || client_id.len() > MAX_CLIENT_ID_LEN {
+        return Err(ApiError::Validation(ValidationError::new(format!(
+            "client_id must be 1-{} characters",
+            MAX_CLIENT_ID_LEN
+        ))));
+    }
+    let destination = request
+        .destination
+        .as_deref()
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .unwrap_or(DEFAULT_DESTINATION);
+    if let Some(id) = request.user_id {
+        if state.storage.get_user(id).await?.is_none() {
+            return Err(ApiError::NotFound(format!("No user with id {}", id)));
+        }
+    }
+    // A user's token can't take over someone else's client
+    if let Some(existing) = state.storage.get_sync_client(&client_id).await? {
+        auth::check_owner(token, existing.user_id)?;
+    }
+
+    info!(
+        "Registering sync client {} (destination={}, user_id={:?})",
+        client_id, destination, request.user_id
+    );
+    let client = state
+        .storage
+        .upsert_sync_client(
+            &client_id,
+            request.name.as_deref(),
+            destination,
+            request.user_id,
+            request.since,
+        )
+        .await?;
+
+    Ok(Json(client))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/sync/clients/{client_id}",
+    tag = "sync",
+    params(("client_id" = String, Path, description = "Chosen by the client; should survive reinstalls")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub(super) async fn delete_client(
+    State(state): State<AppState>,
+    Path(client_id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    if state.storage.delete_sync_client(&client_id).await? {
+        info!("Deleted sync client {}", client_id);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound(format!("No sync client {}", client_id)))
+    }
+}
+
+async fn get_client(
+    state: &AppState,
+    token: Option<&ApiToken>,
+    client_id: &str,
+) -> Result<SyncClient, ApiError> {
+    let client = state
+        .storage
+        .get_sync_client(client_id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("No sync client {}", client_id)))?;
+    auth::check_owner(token, client.user_id)?;
+    Ok(client)
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct PendingQuery {
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct PendingResponse {
+    client_id: String,
+    destination: String,
+    cursor: i64,
+    items: Vec<SyncItem>,
+    more: bool, // more items beyond `limit`
+}
+
+// Completed sessions to export, oldest first
+#[utoipa::path(
+    get,
+    path = "/api/sync/clients/{client_id}/pending",
+    tag = "sync",
+    params(("client_id" = String, Path, description = "Chosen by the client; should survive reinstalls"), PendingQuery),
+    responses(
+        (status = 200, body = PendingResponse),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub(super) async fn get_pending(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Path(client_id): Path<String>,
+    Query(query): Query<PendingQuery>,
+) -> Result<Json<PendingResponse>, ApiError> {
+    let client = get_client(&state, token.as_deref(), &client_id).await?;
+    let mut items = sync::pending(&state.storage, &client, Utc::now().timestamp()).await?;
+
+    let limit = query.limit.unwrap_or(DEFAULT_PENDING_LIMIT).max(1);
+    let more = items.len() > limit;
+    items.truncate(limit);
+
+    Ok(Json(PendingResponse {
+        client_id: client.client_id,
+        destination: client.destination,
+        cursor: client.cursor,
+        items,
+        more,
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(super) struct AckRequest {
+    items: Vec<Ack>,
+}
+
+// Record exported sessions and advance the client's cursor
+#[utoipa::path(
+    post,
+    path = "/api/sync/clients/{client_id}/ack",
+    tag = "sync",
+    params(("client_id" = String, Path, description = "Chosen by the client; should survive reinstalls")),
+    request_body = AckRequest,
+    responses(
+        (status = 200, body = AckResult),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub(super) async fn acknowledge(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Path(client_id): Path<String>,
+    Json(request): Json<AckRequest>,
+) -> Result<Json<AckResult>, ApiError> {
+    let client = get_client(&state, token.as_deref(), &client_id).await?;
+    let result = sync::acknowledge(
+        &state.storage,
+        &client,
+        &request.items,
+        Utc::now().timestamp(),
+    )
+    .await?;
+
+    info!(
+        "Sync client {} exported {} sessions to {} ({} unknown, cursor {})",
+        client_id,
+        result.recorded.len(),
+        client.destination,
+        result.unknown.len(),
+        result.cursor
+    );
+
+    Ok(Json(result))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct StatusQuery {
+    #[serde(default)]
+    date: Option<String>, // YYYY-MM-DD (default: today)
+    #[serde(default)]
+    destination: Option<String>,
+    #[serde(default)]
+    tz: Option<String>,
+    #[serde(default)]
+    tz_offset: Option<i32>,
+    #[serde(default)]
+    user_id: Option<i64>,
+}
+
+// Is a day's walking already in the destination?
+#[utoipa::path(
+    get,
+    path = "/api/sync/status",
+    tag = "sync",
+    params(StatusQuery),
+    responses(
+        (status = 200, body = DayStatus),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub(super) async fn get_status(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Query(mut query): Query<StatusQuery>,
+) -> Result<Json<DayStatus>, ApiError> {
+    query.user_id = auth::limit_user(token.as_deref(), query.user_id)?;
+    let zone = resolve_zone(query.tz.as_deref(), query.tz_offset, &state)?;
+    let now = Utc::now().timestamp();
+    let date = match &query.date {
+        Some(date) => validate_date(date)?,
+        None => zone.local_date(now),
+    };
+    let destination = query.destination.as_deref().unwrap_or(DEFAULT_DESTINATION);
+
+    let status =
+        sync::day_status(&state.storage, date, &zone, destination, query.user_id, now).await?;
+
+    Ok(Json(status))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct LedgerQuery {
+    #[serde(default)]
+    destination: Option<String>,
+    #[serde(default)]
+    client_id: Option<String>,
+    #[serde(default)]
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct LedgerResponse {
+    entries: Vec<LedgerEntry>,
+}
+
+// What was exported where, most recent first
+#[utoipa::path(
+    get,
+    path = "/api/sync/ledger",
+    tag = "sync",
+    params(LedgerQuery),
+    responses((status = 200, body = LedgerResponse))
+)]
+pub(super) async fn get_ledger(
+    State(state): State<AppState>,
+    Query(query): Query<LedgerQuery>,
+) -> Result<Json<LedgerResponse>, ApiError> {
+    let entries = state
+        .storage
+        .get_ledger(
+            query.destination.as_deref(),
+            query.client_id.as_deref(),
+            query.limit.unwrap_or(DEFAULT_LEDGER_LIMIT).max(1),
+        )
+        .await?;
+
+    Ok(Json(LedgerResponse { entries }))
+}
@@ -0,0 +1,394 @@
+//! Endpoints for user profiles: creating users, switching the active user,
+//! time-of-day schedules, and attributing past samples to a user.
+
+use axum::{
+    extract::{Extension, Path, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::ToSchema;
+
+use super::corrections::AuthorFields;
+use super::{auth, ApiError, AppState, ErrorBody, ValidationError};
+use crate::energy;
+use crate::storage::{ApiToken, User, UserSchedule};
+use crate::users::ChangeSource;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct UsersResponse {
+    users: Vec<User>,
+    active_user_id: Option<i64>,
+}
+
+// List all users and which one is active
+#[utoipa::path(
+    get,
+    path = "/api/users",
+    tag = "users",
+    responses((status = 200, body = UsersResponse))
+)]
+pub(super) async fn list_users(
+    State(state): State<AppState>,
+) -> Result<Json<UsersResponse>, ApiError> {
+    let users = state.storage.get_users().await?;
+    let active_user_id = state.active_user.get().await;
+
+    Ok(Json(UsersResponse {
+        users,
+        active_user_id,
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(super) struct CreateUserRequest {
+    name: String,
+    #[serde(default)]
+    weight_kg: Option<f64>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/users",
+    tag = "users",
+    request_body = CreateUserRequest,
+    responses(
+        (status = 200, body = User),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub(super) async fn create_user(
+    State(state): State<AppState>,
+    Json(request): Json<CreateUserRequest>,
+) -> Result<Json<User>, ApiError> {
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::Validation(ValidationError::new(
+            "name must not be empty",
+        )));
+    }
+    if state
+        .storage
+        .get_users()
+        .await?
+        .iter()
+        .any(|u| u.name == name)
+    {
+        return Err(ApiError::Validation(ValidationError::new(format!(
+            "A user named {} already exists",
+            name
+        ))));
+    }
+
+    validate_weight(request.weight_kg)?;
+
+    info!("Creating user {}", name);
+    let user = state.storage.create_user(name, request.weight_kg).await?;
+
+    Ok(Json(user))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(super) struct UpdateUserRequest {
+    weight_kg: Option<f64>,
+}
+
+// Change a user's body weight (null = use the configured default)
+#[utoipa::path(
+    patch,
+    path = "/api/users/{id}",
+    tag = "users",
+    params(("id" = i64, Path)),
+    request_body = UpdateUserRequest,
+    responses(
+        (status = 200, body = User),
+        (status = 400, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub(super) async fn update_user(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(request): Json<UpdateUserRequest>,
+) -> Result<Json<User>, ApiError> {
+    validate_weight(request.weight_kg)?;
+    info!("Setting weight for user {} to {:?}", id, request.weight_kg);
+
+    state
+        .storage
+        .set_user_weight(id, request.weight_kg)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("No user with id {}", id)))
+}
+
+// Delete a user; their samples are kept but become unassigned
+#[utoipa::path(
+    delete,
+    path = "/api/users/{id}",
+    tag = "users",
+    params(("id" = i64, Path)),
+    request_body(content = Option<AuthorFields>, description = "Optional, for the audit log"),
+    responses(
+        (status = 200, description = "The deleted user", body = User),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub(super) async fn delete_user(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Path(id): Path<i64>,
+    body: Option<Json<AuthorFields>>,
+) -> Result<Json<User>, ApiError> {
+    let author = body
+        .map(|Json(b)| b)
+        .unwrap_or_default()
+        .author(token.as_deref());
+    info!("Deleting user {} (actor={})", id, author.actor);
+
+    let user = state
+        .storage
+        .delete_user(id, &author)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("No user with id {}", id)))?;
+    state.active_user.clear_if(id).await;
+    // Their samples (and records) now count as unassigned
+    state.records_rebuild.request();
+
+    Ok(Json(user))
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub(super) struct ActiveUserBody {
+    user_id: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/active",
+    tag = "users",
+    responses((status = 200, body = ActiveUserBody))
+)]
+pub(super) async fn get_active_user(State(state): State<AppState>) -> Json<ActiveUserBody> {
+    Json(ActiveUserBody {
+        user_id: state.active_user.get().await,
+    })
+}
+
+// Switch who new samples are attributed to (null = unassigned)
+#[utoipa::path(
+    put,
+    path = "/api/users/active",
+    tag = "users",
+    request_body = ActiveUserBody,
+    responses(
+        (status = 200, body = ActiveUserBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub(super) async fn set_active_user(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Json(request): Json<ActiveUserBody>,
+) -> Result<Json<ActiveUserBody>, ApiError> {
+    // A user's token can only make them the walker
+    auth::check_owner(token.as_deref(), request.user_id)?;
+    if !state
+        .active_user
+        .set(request.user_id, ChangeSource::Api)
+        .await?
+    {
+        return Err(ApiError::NotFound(format!(
+            "No user with id {}",
+            request.user_id.unwrap_or_default()
+        )));
+    }
+
+    Ok(Json(request))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct SchedulesResponse {
+    user_id: i64,
+    schedules: Vec<UserSchedule>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/{id}/schedules",
+    tag = "users",
+    params(("id" = i64, Path)),
+    responses((status = 200, body = SchedulesResponse))
+)]
+pub(super) async fn get_user_schedules(
+    State(state): State<AppState>,
+    Path(user_id): Path<i64>,
+) -> Result<Json<SchedulesResponse>, ApiError> {
+    let schedules = state.storage.get_schedules(Some(user_id)).await?;
+
+    Ok(Json(SchedulesResponse { user_id, schedules }))
+}
+
+/// A daily window, as "HH:MM" local times. `end` before `start` wraps past midnight.
+#[derive(Debug, Deserialize, ToSchema)]
+pub(super) struct ScheduleRequest {
+    start: String,
+    end: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/users/{id}/schedules",
+    tag = "users",
+    params(("id" = i64, Path)),
+    request_body = ScheduleRequest,
+    responses(
+        (status = 200, body = UserSchedule),
+        (status = 400, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub(super) async fn add_user_schedule(
+    State(state): State<AppState>,
+    Path(user_id): Path<i64>,
+    Json(request): Json<ScheduleRequest>,
+) -> Result<Json<UserSchedule>, ApiError> {
+    let start_minute = parse_time_of_day(&request.start)?;
+    let end_minute = parse_time_of_day(&request.end)?;
+    if start_minute == end_minute {
+        return Err(ApiError::Validation(ValidationError::new(
+            "start and end must differ",
+        )));
+    }
+    if state.storage.get_user(user_id).await?.is_none() {
+        return Err(ApiError::NotFound(format!("No user with id {}", user_id)));
+    }
+
+    info!(
+        "Adding schedule {}-{} for user {}",
+        request.start, request.end, user_id
+    );
+    let schedule = state
+        .storage
+        .add_schedule(user_id, start_minute, end_minute)
+        .await?;
+
+    Ok(Json(schedule))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/schedules/{id}",
+    tag = "users",
+    params(("id" = i64, Path)),
+    responses(
+        (status = 200, description = "The deleted schedule", body = UserSchedule),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub(super) async fn delete_schedule(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<UserSchedule>, ApiError> {
+    state
+        .storage
+        .delete_schedule(id)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("No schedule with id {}", id)))
+}
+
+/// Samples in `[start, end)` (Unix seconds) to attribute to `user_id` (null = unassign)
+#[derive(Debug, Deserialize, ToSchema)]
+pub(super) struct AssignRequest {
+    start: i64,
+    end: i64,
+    user_id: Option<i64>,
+    #[serde(flatten)]
+    author: AuthorFields,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct AssignResponse {
+    start: i64,
+    end: i64,
+    user_id: Option<i64>,
+    affected: u64,
+}
+
+// Attribute past samples to a user (e.g. after forgetting to switch)
+#[utoipa::path(
+    post,
+    path = "/api/samples/assign",
+    tag = "users",
+    request_body = AssignRequest,
+    responses(
+        (status = 200, body = AssignResponse),
+        (status = 400, body = ErrorBody),
+        (status = 404, description = "Unknown user", body = ErrorBody),
+    )
+)]
+pub(super) async fn assign_samples(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Json(request): Json<AssignRequest>,
+) -> Result<Json<AssignResponse>, ApiError> {
+    if request.end <= request.start {
+        return Err(ApiError::Validation(ValidationError::new(
+            "end must be after start",
+        )));
+    }
+    if let Some(id) = request.user_id {
+        if state.storage.get_user(id).await?.is_none() {
+            return Err(ApiError::NotFound(format!("No user with id {}", id)));
+        }
+    }
+
+    let (start, end, user_id) = (request.start, request.end, request.user_id);
+    let author = request.author.author(token.as_deref());
+    info!(
+        "Assigning samples {}..{} to user {:?} (actor={})",
+        start, end, user_id, author.actor
+    );
+
+    let affected = state
+        .storage
+        .assign_samples(start, end, user_id, &author)
+        .await?;
+    if affected > 0 {
+        // Estimates follow the new walker's weight
+        energy::recalculate(&state.storage, &state.energy, Some((start, end)), false).await?;
+        // Records move with the samples
+        state.records_rebuild.request();
+    }
+
+    Ok(Json(AssignResponse {
+        start,
+        end,
+        user_id,
+        affected,
+    }))
+}
+
+fn validate_weight(weight_kg: Option<f64>) -> Result<(), ValidationError> {
+    match weight_kg {
+        Some(w) if !(20.0..=300.0).contains(&w) => {
+            Err(ValidationError::new("weight_kg must be between 20 and 300"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Parse "HH:MM" into minutes after midnight
+fn parse_time_of_day(value: &str) -> Result<i64, ValidationError> {
+    let invalid = || ValidationError::new(format!("Invalid time {} (expected HH:MM)", value));
+    let (h, m) = value.split_once(':').ok_or_else(invalid)?;
+    let (h, m): (i64, i64) = (
+        h.parse().map_err(|_| invalid())?,
+        m.parse().map_err(|_| invalid())?,
+    );
+    if !(0..24).contains(&h) || !(0..60).contains(&m) {
+        return Err(invalid());
+    }
+    Ok(h * 60 + m)
+}
@@ -0,0 +1,90 @@
+//! Webhook delivery log and redelivery (see `crate::webhooks`)
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{ApiError, AppState, ErrorBody};
+use crate::storage::{DeliveryStatus, WebhookDelivery};
+
+const DEFAULT_DELIVERIES_LIMIT: i64 = 100;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct DeliveriesQuery {
+    /// Only this webhook's deliveries (its name in the config)
+    #[serde(default)]
+    webhook: Option<String>,
+    #[serde(default)]
+    status: Option<DeliveryStatus>,
+    #[serde(default)]
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct DeliveriesResponse {
+    deliveries: Vec<WebhookDelivery>,
+}
+
+// Webhook deliveries, most recent first
+#[utoipa::path(
+    get,
+    path = "/api/webhooks/deliveries",
+    tag = "webhooks",
+    params(DeliveriesQuery),
+    responses((status = 200, body = DeliveriesResponse))
+)]
+pub(super) async fn list_deliveries(
+    State(state): State<AppState>,
+    Query(query): Query<DeliveriesQuery>,
+) -> Result<Json<DeliveriesResponse>, ApiError> {
+    let deliveries = state
+        .storage
+        .get_webhook_deliveries(
+            query.webhook.as_deref(),
+            query.status,
+            query.limit.unwrap_or(DEFAULT_DELIVERIES_LIMIT).max(1),
+        )
+        .await?;
+
+    Ok(Json(DeliveriesResponse { deliveries }))
+}
+
+// Send a delivery's payload again, as a new delivery (sent in the background)
+#[utoipa::path(
+    post,
+    path = "/api/webhooks/deliveries/{id}/redeliver",
+    tag = "webhooks",
+    params(("id" = i64, Path)),
+    responses(
+        (status = 202, description = "The new delivery", body = WebhookDelivery),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub(super) async fn redeliver(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<(StatusCode, Json<WebhookDelivery>), ApiError> {
+    let original = state
+        .storage
+        .get_webhook_delivery(id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("No webhook delivery with id {}", id)))?;
+    let delivery = state.webhooks.redeliver(&original).await?.ok_or_else(|| {
+        ApiError::NotFound(format!(
+            "Webhook {} is no longer configured",
+            original.webhook
+        ))
+    })?;
+    info!(
+        "Redelivering webhook delivery {} as {}",
+        original.id, delivery.id
+    );
+
+    Ok((StatusCode::ACCEPTED, Json(delivery)))
+}
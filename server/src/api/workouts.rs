@@ -0,0 +1,296 @@
+//! Workout files (FIT, TCX, GPX) for a walking session or a whole day, for apps
+//! that only take uploads (see `crate::export`).
+
+use axum::{
+    extract::{Extension, Path, Query, State},
+    response::Response,
+};
+use serde::Deserialize;
+use tracing::info;
+use utoipa::IntoParams;
+
+use super::{auth, file_response, validate_date, ApiError, AppState, ErrorBody, TimezoneQuery};
+use crate::export::gpx::{self, TrackOrigin};
+use crate::export::{fit, tcx, Workout};
+use crate::sessions::{find_session, split_with_samples};
+use crate::storage::ApiToken;
+
+const FIT_CONTENT_TYPE: &str = "application/vnd.ant.fit";
+const TCX_CONTENT_TYPE: &str = "application/vnd.garmin.tcx+xml";
+const GPX_CONTENT_TYPE: &str = "application/gpx+xml";
+
+#[derive(Debug, Clone, Copy)]
+enum WorkoutFormat {
+    Fit,
+    Tcx,
+    Gpx,
+}
+
+// Where GPX tracks are drawn
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct TrackQuery {
+    #[serde(default)]
+    lat: Option<f64>, // Centre of the synthetic 400 m track (default: 0)
+    #[serde(default)]
+    lon: Option<f64>,
+}
+
+fn workout_response(
+    workout: &Workout,
+    format: WorkoutFormat,
+    track: &TrackQuery,
+    filename: &str,
+) -> Response {
+    match format {
+        WorkoutFormat::Fit => file_response(
+            FIT_CONTENT_TYPE,
+            Some(format!("{}.fit", filename)),
+            fit::encode(workout),
+        ),
+        WorkoutFormat::Tcx => file_response(
+            TCX_CONTENT_TYPE,
+            Some(format!("{}.tcx", filename)),
+            tcx::encode(workout),
+        ),
+        WorkoutFormat::Gpx => {
+            let origin = TrackOrigin {
+                lat: track.lat.unwrap_or_default(),
+                lon: track.lon.unwrap_or_default(),
+            };
+            file_response(
+                GPX_CONTENT_TYPE,
+                Some(format!("{}.gpx", filename)),
+                gpx::encode(workout, origin),
+            )
+        }
+    }
+}
+
+async fn session_workout(
+    state: &AppState,
+    id: i64,
+    query: &TimezoneQuery,
+    track: &TrackQuery,
+    format: WorkoutFormat,
+) -> Result<Response, ApiError> {
+    let zone = query.zone(state)?;
+    info!("Exporting session {} as {:?}", id, format);
+
+    let session = find_session(&state.storage, id, query.user_id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("No session started at {}", id)))?;
+
+    let local_start = zone.local_datetime(id);
+    let workout = Workout::new(
+        format!("Treadmill walk {}", local_start.format("%Y-%m-%d %H:%M")),
+        vec![session],
+        query.calories_source(state),
+    );
+    let filename = format!("walk-{}", local_start.format("%Y-%m-%d-%H%M"));
+    Ok(workout_response(&workout, format, track, &filename))
+}
+
+async fn date_workout(
+    state: &AppState,
+    date_str: &str,
+    query: &TimezoneQuery,
+    track: &TrackQuery,
+    format: WorkoutFormat,
+) -> Result<Response, ApiError> {
+    let date = validate_date(date_str)?;
+    let zone = query.zone(state)?;
+    info!("Exporting {} (tz={}) as {:?}", date_str, zone, format);
+
+    // Sessions running over midnight are cut at the day's edges
+    let (start, end) = zone.day_bounds(date);
+    let samples = state
+        .storage
+        .get_active_samples_between(start, end, query.user_id)
+        .await?;
+    if samples.is_empty() {
+        return Err(ApiError::NotFound(format!(
+            "No activity found for date: {}",
+            date_str
+        )));
+    }
+
+    let workout = Workout::new(
+        format!("Treadmill walks {}", date_str),
+        split_with_samples(samples, query.user_id),
+        query.calories_source(state),
+    );
+    Ok(workout_response(
+        &workout,
+        format,
+        track,
+        &format!("walk-{}", date_str),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/sessions/{id}/export.fit",
+    tag = "export",
+    params(
+        ("id" = i64, Path, description = "Session id (timestamp of its first sample)"),
+        TimezoneQuery
+    ),
+    responses(
+        (status = 200, description = "One lap", content_type = "application/vnd.ant.fit"),
+        (status = 404, description = "No session starts then", body = ErrorBody),
+    )
+)]
+pub(super) async fn get_session_fit(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Path(id): Path<i64>,
+    Query(mut query): Query<TimezoneQuery>,
+) -> Result<Response, ApiError> {
+    query.user_id = auth::limit_user(token.as_deref(), query.user_id)?;
+    session_workout(
+        &state,
+        id,
+        &query,
+        &TrackQuery::default(),
+        WorkoutFormat::Fit,
+    )
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/sessions/{id}/export.tcx",
+    tag = "export",
+    params(
+        ("id" = i64, Path, description = "Session id (timestamp of its first sample)"),
+        TimezoneQuery
+    ),
+    responses(
+        (status = 200, description = "One lap", content_type = "application/vnd.garmin.tcx+xml", body = String),
+        (status = 404, description = "No session starts then", body = ErrorBody),
+    )
+)]
+pub(super) async fn get_session_tcx(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Path(id): Path<i64>,
+    Query(mut query): Query<TimezoneQuery>,
+) -> Result<Response, ApiError> {
+    query.user_id = auth::limit_user(token.as_deref(), query.user_id)?;
+    session_workout(
+        &state,
+        id,
+        &query,
+        &TrackQuery::default(),
+        WorkoutFormat::Tcx,
+    )
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/sessions/{id}/export.gpx",
+    tag = "export",
+    params(
+        ("id" = i64, Path, description = "Session id (timestamp of its first sample)"),
+        TimezoneQuery,
+        TrackQuery
+    ),
+    responses(
+        (status = 200, description = "One track segment", content_type = "application/gpx+xml", body = String),
+        (status = 404, description = "No session starts then", body = ErrorBody),
+    )
+)]
+pub(super) async fn get_session_gpx(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Path(id): Path<i64>,
+    Query(mut query): Query<TimezoneQuery>,
+    Query(track): Query<TrackQuery>,
+) -> Result<Response, ApiError> {
+    query.user_id = auth::limit_user(token.as_deref(), query.user_id)?;
+    session_workout(&state, id, &query, &track, WorkoutFormat::Gpx).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/dates/{date}/export.fit",
+    tag = "export",
+    params(("date" = String, Path, description = "Local date (YYYY-MM-DD)"), TimezoneQuery),
+    responses(
+        (status = 200, description = "A lap per session", content_type = "application/vnd.ant.fit"),
+        (status = 400, body = ErrorBody),
+        (status = 404, description = "No activity that day", body = ErrorBody),
+    )
+)]
+pub(super) async fn get_date_fit(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Path(date_str): Path<String>,
+    Query(mut query): Query<TimezoneQuery>,
+) -> Result<Response, ApiError> {
+    query.user_id = auth::limit_user(token.as_deref(), query.user_id)?;
+    date_workout(
+        &state,
+        &date_str,
+        &query,
+        &TrackQuery::default(),
+        WorkoutFormat::Fit,
+    )
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/dates/{date}/export.tcx",
+    tag = "export",
+    params(("date" = String, Path, description = "Local date (YYYY-MM-DD)"), TimezoneQuery),
+    responses(
+        (status = 200, description = "A lap per session", content_type = "application/vnd.garmin.tcx+xml", body = String),
+        (status = 400, body = ErrorBody),
+        (status = 404, description = "No activity that day", body = ErrorBody),
+    )
+)]
+pub(super) async fn get_date_tcx(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Path(date_str): Path<String>,
+    Query(mut query): Query<TimezoneQuery>,
+) -> Result<Response, ApiError> {
+    query.user_id = auth::limit_user(token.as_deref(), query.user_id)?;
+    date_workout(
+        &state,
+        &date_str,
+        &query,
+        &TrackQuery::default(),
+        WorkoutFormat::Tcx,
+    )
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/dates/{date}/export.gpx",
+    tag = "export",
+    params(
+        ("date" = String, Path, description = "Local date (YYYY-MM-DD)"),
+        TimezoneQuery,
+        TrackQuery
+    ),
+    responses(
+        (status = 200, description = "A track segment per session", content_type = "application/gpx+xml", body = String),
+        (status = 400, body = ErrorBody),
+        (status = 404, description = "No activity that day", body = ErrorBody),
+    )
+)]
+pub(super) async fn get_date_gpx(
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+    Path(date_str): Path<String>,
+    Query(mut query): Query<TimezoneQuery>,
+    Query(track): Query<TrackQuery>,
+) -> Result<Response, ApiError> {
+    query.user_id = auth::limit_user(token.as_deref(), query.user_id)?;
+    date_workout(&state, &date_str, &query, &track, WorkoutFormat::Gpx).await
+}
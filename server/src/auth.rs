@@ -0,0 +1,203 @@
+//! Bearer-token authentication.
+//!
+//! Tokens are random strings shown once when created (`walkpad-server token
+//! create`); the database keeps only their SHA-256. Each token has one scope,
+//! and each scope includes the ones below it:
+//!
+//! - `read`: every GET endpoint and the live WebSocket
+//! - `control`: also switching the active user
+//! - `write`: everything, including editing and deleting data (admin)
+//!
+//! A token can also belong to one user (`--user`). It then only sees and
+//! changes that user's data, on the routes in [`allows_user_token`].
+
+use anyhow::{anyhow, Result};
+use axum::http::Method;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+const TOKEN_PREFIX: &str = "wpk_";
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Control,
+    Write,
+}
+
+impl Scope {
+    /// Whether a token with this scope may do something requiring `required`
+    pub fn allows(self, required: Scope) -> bool {
+        self >= required
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scope::Read => "read",
+            Scope::Control => "control",
+            Scope::Write => "write",
+        })
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "read" => Ok(Scope::Read),
+            "control" => Ok(Scope::Control),
+            "write" | "admin" => Ok(Scope::Write),
+            other => Err(anyhow!(
+                "Unknown scope: {} (expected read, control or write)",
+                other
+            )),
+        }
+    }
+}
+
+/// A new random token (32 bytes from the OS, hex encoded)
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", TOKEN_PREFIX, hex::encode(bytes))
+}
+
+/// What the database stores for a token
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// The scope a request needs, or None for public routes (the dashboard page,
+/// health check and API description)
+pub fn required_scope(method: &Method, path: &str) -> Option<Scope> {
+    if matches!(
+        path,
+        "/" | "/dashboard" | "/api/health" | "/api/openapi.json"
+    ) {
+        return None;
+    }
+    // Grafana only reads, but posts its queries
+    if *method == Method::GET || *method == Method::HEAD || path.starts_with("/grafana/") {
+        return Some(Scope::Read);
+    }
+    if *method == Method::PUT && path == "/api/users/active" {
+        return Some(Scope::Control);
+    }
+    // A phone exporting to Health registers itself and acknowledges what it
+    // saved, without being an admin
+    let segments: Vec<&str> = path.split('/').collect();
+    match (method, segments.as_slice()) {
+        (&Method::PUT, ["", "api", "sync", "clients", _])
+        | (&Method::POST, ["", "api", "sync", "clients", _, "ack"]) => Some(Scope::Control),
+        _ => Some(Scope::Write),
+    }
+}
+
+/// Whether a token that belongs to a user may use a route. These handlers
+/// limit what they read or change to the token's user; the rest cover every
+/// user (or the server itself), so such tokens are refused there.
+pub fn allows_user_token(method: &Method, path: &str) -> bool {
+    let segments: Vec<&str> = path.split('/').collect();
+    match (method, segments.as_slice()) {
+        (&Method::GET | &Method::HEAD, ["", "api", rest @ ..]) => matches!(
+            rest,
+            ["dates"]
+                | ["dates", "summaries"]
+                | ["dates", _, "summary" | "samples"]
+                | [
+                    "dates" | "sessions",
+                    _,
+                    "export.fit" | "export.tcx" | "export.gpx"
+                ]
+                | ["aggregate"]
+                | ["records"]
+                | ["goals"]
+                | ["goals", "streaks"]
+                | ["users", "active"]
+                | ["sync", "status"]
+                | ["sync", "clients", _, "pending"]
+        ),
+        (&Method::PUT, ["", "api", "users", "active"])
+        | (&Method::PUT, ["", "api", "sync", "clients", _])
+        | (&Method::POST, ["", "api", "sync", "clients", _, "ack"]) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scopes_by_route() {
+        assert_eq!(required_scope(&Method::GET, "/api/health"), None);
+        assert_eq!(required_scope(&Method::GET, "/ws/live"), Some(Scope::Read));
+        assert_eq!(
+            required_scope(&Method::POST, "/grafana/query"),
+            Some(Scope::Read)
+        );
+        assert_eq!(
+            required_scope(&Method::PUT, "/api/users/active"),
+            Some(Scope::Control)
+        );
+        assert_eq!(
+            required_scope(&Method::DELETE, "/api/samples"),
+            Some(Scope::Write)
+        );
+        assert_eq!(
+            required_scope(&Method::PUT, "/api/sync/clients/phone"),
+            Some(Scope::Control)
+        );
+        assert_eq!(
+            required_scope(&Method::POST, "/api/sync/clients/phone/ack"),
+            Some(Scope::Control)
+        );
+        assert_eq!(
+            required_scope(&Method::DELETE, "/api/sync/clients/phone"),
+            Some(Scope::Write)
+        );
+
+        assert!(Scope::Write.allows(Scope::Control));
+        assert!(Scope::Control.allows(Scope::Read));
+        assert!(!Scope::Control.allows(Scope::Write));
+        assert!(!Scope::Read.allows(Scope::Control));
+    }
+
+    #[test]
+    fn test_user_token_routes() {
+        assert!(allows_user_token(&Method::GET, "/api/dates/summaries"));
+        assert!(allows_user_token(
+            &Method::GET,
+            "/api/sessions/1736899200/export.fit"
+        ));
+        assert!(allows_user_token(
+            &Method::POST,
+            "/api/sync/clients/phone/ack"
+        ));
+        assert!(!allows_user_token(&Method::GET, "/api/samples"));
+        assert!(!allows_user_token(&Method::GET, "/ws/live"));
+        assert!(!allows_user_token(&Method::GET, "/api/sync/clients"));
+        assert!(!allows_user_token(&Method::POST, "/api/goals"));
+        assert!(!allows_user_token(&Method::DELETE, "/api/samples"));
+    }
+
+    #[test]
+    fn test_tokens_are_random_and_hashed() {
+        let (a, b) = (generate_token(), generate_token());
+        assert_ne!(a, b);
+        assert!(a.starts_with(TOKEN_PREFIX));
+        assert_eq!(hash_token(&a), hash_token(&a));
+        assert_eq!(hash_token(&a).len(), 64);
+        assert!(!hash_token(&a).contains(&a[TOKEN_PREFIX.len()..]));
+    }
+}
@@ -123,8 +123,11 @@ pub fn parse_lifespan_response(data: &[u8], query: LifeSpanQuery) -> Result<Trea
                 result.speed = Some(speed_ms);
                 debug!("LifeSpan speed: {:.2} mph = {:.2} m/s", speed_mph, speed_ms);
             } else if speed_mph > 5.0 {
-                warn!("Walking pad speed {:.2} mph exceeds max (5 mph) - possible data corruption, but recording anyway", speed_mph);
-                // Still record it - don't silently discard potentially valid data
+                warn!(
+                    "Walking pad speed {:.2} mph exceeds max (5 mph) - possible data corruption",
+                    speed_mph
+                );
+                // Pass it on - the quality checks quarantine it rather than silently dropping it
                 result.speed = Some(speed_ms);
             }
         }
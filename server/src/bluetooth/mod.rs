@@ -8,13 +8,18 @@ use chrono::Utc;
 use futures_util::stream::StreamExt;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio::sync::{broadcast, mpsc, Notify, RwLock};
 use tokio::time::{sleep, timeout};
 use tracing::{debug, error, info, warn};
 
-use crate::config::BluetoothConfig;
-use crate::storage::Storage;
+use crate::config::{BluetoothConfig, EnergyConfig, QualityConfig};
+use crate::energy;
+use crate::metrics::Metrics;
+use crate::quality::{QualityMonitor, QuarantineStatus, Reason};
+use crate::storage::{Storage, TreadmillSample};
+use crate::users::ActiveUser;
 use crate::websocket::{broadcast_sample, WsMessage};
+use crate::writer::SampleWriter;
 
 // Use the protocol abstraction instead of direct ftms imports
 use ftms::TreadmillData;
@@ -22,6 +27,42 @@ use protocol::{
     detect_protocol, supported_protocol_uuids, ProtocolMode, QueryType, TreadmillProtocol,
 };
 
+/// Consecutive samples rejected for a counter jump before the jump is believed
+const MAX_REJECTED_JUMPS: u32 = 3;
+
+/// Cumulative counter readings from the treadmill
+#[derive(Debug, Clone, Copy, Default)]
+struct Counters {
+    distance: Option<i64>, // meters
+    calories: Option<i64>, // kcal
+    steps: Option<i64>,
+    rejected_jumps: u32, // samples rejected in a row for a counter jump
+}
+
+impl Counters {
+    /// Take a sample's readings as the baselines (counters it lacks keep theirs)
+    fn advance(&mut self, current: &Counters) {
+        self.distance = current.distance.or(self.distance);
+        self.calories = current.calories.or(self.calories);
+        self.steps = current.steps.or(self.steps);
+        self.rejected_jumps = 0;
+    }
+}
+
+/// Increase of a cumulative counter since the last accepted reading: zero for
+/// the first reading and after a reset, None if the reading is missing
+fn counter_delta(name: &str, current: Option<i64>, last: Option<i64>) -> Option<i64> {
+    let current = current?;
+    Some(match last {
+        Some(last) if current >= last => current - last,
+        Some(last) => {
+            debug!("{} reset detected: {} -> {}", name, last, current);
+            0
+        }
+        None => 0,
+    })
+}
+
 #[derive(Debug, Clone)]
 pub enum ConnectionStatus {
     Disconnected,
@@ -31,39 +72,92 @@ pub enum ConnectionStatus {
     Error,
 }
 
+impl ConnectionStatus {
+    pub const ALL: [ConnectionStatus; 5] = [
+        ConnectionStatus::Disconnected,
+        ConnectionStatus::Scanning,
+        ConnectionStatus::Connecting,
+        ConnectionStatus::Connected,
+        ConnectionStatus::Error,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionStatus::Disconnected => "disconnected",
+            ConnectionStatus::Scanning => "scanning",
+            ConnectionStatus::Connecting => "connecting",
+            ConnectionStatus::Connected => "connected",
+            ConnectionStatus::Error => "error",
+        }
+    }
+}
+
 pub struct BluetoothManager {
     storage: Arc<Storage>,
+    active_user: Arc<ActiveUser>,
     config: BluetoothConfig,
+    energy: EnergyConfig,
     status_tx: broadcast::Sender<ConnectionStatus>,
     ws_tx: broadcast::Sender<WsMessage>,
-    // Track last seen cumulative values for delta calculation
-    last_distance: Arc<RwLock<Option<i64>>>,
-    last_calories: Arc<RwLock<Option<i64>>>,
-    last_steps: Arc<RwLock<Option<i64>>>,
+    // Last accepted cumulative values, for delta calculation
+    counters: Arc<RwLock<Counters>>,
+    // Timestamp of the previous sample, for calorie estimation
+    last_timestamp: Arc<RwLock<Option<i64>>>,
+    // Validation between parsing and recording
+    quality: Arc<RwLock<QualityMonitor>>,
+    // Buffered, batched sample inserts
+    writer: SampleWriter,
+    metrics: Arc<Metrics>,
+    // Woken to drop the current connection and scan again
+    rescan: Notify,
 }
 
 impl BluetoothManager {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         storage: Arc<Storage>,
+        active_user: Arc<ActiveUser>,
         config: BluetoothConfig,
+        energy: EnergyConfig,
+        quality: QualityConfig,
+        writer: SampleWriter,
         ws_tx: broadcast::Sender<WsMessage>,
+        metrics: Arc<Metrics>,
     ) -> (Self, broadcast::Receiver<ConnectionStatus>) {
         let (status_tx, status_rx) = broadcast::channel(16);
 
         (
             Self {
                 storage,
+                active_user,
                 config,
+                energy,
                 status_tx,
                 ws_tx,
-                last_distance: Arc::new(RwLock::new(None)),
-                last_calories: Arc::new(RwLock::new(None)),
-                last_steps: Arc::new(RwLock::new(None)),
+                counters: Arc::default(),
+                last_timestamp: Arc::new(RwLock::new(None)),
+                quality: Arc::new(RwLock::new(QualityMonitor::new(quality))),
+                writer,
+                metrics,
+                rescan: Notify::new(),
             },
             status_rx,
         )
     }
 
+    /// Connection status changes from now on
+    pub fn subscribe_status(&self) -> broadcast::Receiver<ConnectionStatus> {
+        self.status_tx.subscribe()
+    }
+
+    /// Drop the current connection and scan again (after the usual reconnect
+    /// delay). Asked while connecting or waiting to reconnect, it applies to
+    /// the next connection.
+    pub fn rescan(&self) {
+        // Stores a permit if nothing is waiting yet, unlike `notify_waiters`
+        self.rescan.notify_one();
+    }
+
     pub async fn run(&self) -> Result<()> {
         info!(
             "Starting Bluetooth manager (scan_timeout={}s, reconnect_delay={}s)",
@@ -88,6 +182,8 @@ impl BluetoothManager {
 
             // Broadcast disconnected status before waiting
             let _ = self.status_tx.send(ConnectionStatus::Disconnected);
+            self.metrics.disconnected();
+            self.metrics.reconnect();
 
             // Wait before reconnecting
             info!(
@@ -279,6 +375,7 @@ impl BluetoothManager {
             let char = char.clone();
             let pending_queries = pending_queries.clone();
             let error_tx = poll_error_tx.clone();
+            let metrics = Arc::clone(&self.metrics);
             let queries = protocol.polling_queries();
 
             // Get polling interval from protocol mode
@@ -302,6 +399,7 @@ impl BluetoothManager {
                         {
                             let error_msg = format!("Failed to write query {:?}: {}", query, e);
                             error!("{}", error_msg);
+                            metrics.poll_write_error();
                             let _ = error_tx.send(error_msg).await;
                             return;
                         }
@@ -344,6 +442,14 @@ impl BluetoothManager {
                     }
                     return Err(anyhow!("Poll task failed: {}", error_msg));
                 }
+                _ = self.rescan.notified() => {
+                    info!("Rescan requested, disconnecting");
+                    if let Some(task) = poll_task.take() {
+                        task.abort();
+                    }
+                    let _ = peripheral.disconnect().await;
+                    return Ok(());
+                }
                 // Receive notification with timeout
                 result = timeout(notification_timeout, notification_stream.next()) => {
                     match result {
@@ -360,6 +466,7 @@ impl BluetoothManager {
                             // Timeout - no notifications received
                             warn!("No notifications received for {} seconds, assuming connection lost",
                                   notification_timeout.as_secs());
+                            self.metrics.notification_timeout();
                             if let Some(task) = poll_task.take() {
                                 task.abort();
                             }
@@ -419,6 +526,7 @@ impl BluetoothManager {
                         }
                         Err(e) => {
                             debug!("Failed to parse response for {:?}: {}", query, e);
+                            self.metrics.parse_failure();
                             continue;
                         }
                     }
@@ -432,11 +540,14 @@ impl BluetoothManager {
                     Ok(data) => data,
                     Err(e) => {
                         warn!("Failed to parse {} data: {}", protocol.name(), e);
+                        self.metrics.parse_failure();
                         continue;
                     }
                 }
             };
 
+            self.metrics.observe_reading(data.speed);
+
             // Record the raw sample to database (only when moving)
             if data.speed.unwrap_or(0.0) > 0.0 {
                 if let Err(e) = self.record_sample(&data).await {
@@ -454,6 +565,12 @@ impl BluetoothManager {
                               data.total_energy);
                     }
                 }
+            } else {
+                // Lets the quality checks tell a pause from lost data
+                self.quality
+                    .write()
+                    .await
+                    .observe_idle(Utc::now().timestamp());
             }
 
             // Check if we're still connected
@@ -473,76 +590,18 @@ impl BluetoothManager {
     async fn record_sample(&self, data: &TreadmillData) -> Result<()> {
         let timestamp = Utc::now();
 
-        // Compute deltas from last seen values
-        let (distance_delta, calories_delta, steps_delta) = {
-            let mut last_distance = self.last_distance.write().await;
-            let mut last_calories = self.last_calories.write().await;
-            let mut last_steps = self.last_steps.write().await;
-
-            // Convert current values to i64
-            let current_distance = data.distance.map(|d| d as i64);
-            let current_calories = data.total_energy.map(|e| e as i64);
-            let current_steps = data.steps.map(|s| s as i64);
-
-            // Compute distance delta
-            let distance_delta = if let Some(curr) = current_distance {
-                let delta = if let Some(last) = *last_distance {
-                    if curr >= last {
-                        // Normal increment
-                        curr - last
-                    } else {
-                        // Reset detected - ignore this sample for delta
-                        debug!("Distance reset detected: {} -> {}", last, curr);
-                        0
-                    }
-                } else {
-                    // First sample - no delta yet
-                    0
-                };
-                *last_distance = Some(curr);
-                Some(delta)
-            } else {
-                None
-            };
-
-            // Compute calories delta
-            let calories_delta = if let Some(curr) = current_calories {
-                let delta = if let Some(last) = *last_calories {
-                    if curr >= last {
-                        curr - last
-                    } else {
-                        debug!("Calories reset detected: {} -> {}", last, curr);
-                        0
-                    }
-                } else {
-                    0
-                };
-                *last_calories = Some(curr);
-                Some(delta)
-            } else {
-                None
-            };
-
-            // Compute steps delta
-            let steps_delta = if let Some(curr) = current_steps {
-                let delta = if let Some(last) = *last_steps {
-                    if curr >= last {
-                        curr - last
-                    } else {
-                        debug!("Steps reset detected: {} -> {}", last, curr);
-                        0
-                    }
-                } else {
-                    0
-                };
-                *last_steps = Some(curr);
-                Some(delta)
-            } else {
-                None
-            };
-
-            (distance_delta, calories_delta, steps_delta)
+        // Deltas against the last accepted readings; they only move on once
+        // the sample passes validation
+        let current = Counters {
+            distance: data.distance.map(i64::from),
+            calories: data.total_energy.map(i64::from),
+            steps: data.steps.map(i64::from),
+            rejected_jumps: 0,
         };
+        let last = *self.counters.read().await;
+        let distance_delta = counter_delta("Distance", current.distance, last.distance);
+        let calories_delta = counter_delta("Calories", current.calories, last.calories);
+        let steps_delta = counter_delta("Steps", current.steps, last.steps);
 
         // Log deltas for debugging
         if let Some(steps) = steps_delta {
@@ -552,32 +611,155 @@ impl BluetoothManager {
         }
 
         // Store both raw cumulative values (for debugging) and deltas (for queries)
-        self.storage
-            .add_sample(
-                timestamp,
-                data.speed,
-                data.distance.map(|d| d as i64),
-                data.total_energy.map(|e| e as i64),
-                data.steps.map(|s| s as i64),
-                distance_delta,
-                calories_delta,
-                steps_delta,
-            )
-            .await?;
-
-        // Broadcast to WebSocket clients
-        let sample = crate::storage::TreadmillSample {
+        let mut sample = TreadmillSample {
             timestamp: timestamp.timestamp(),
             speed: data.speed,
-            distance_total: data.distance.map(|d| d as i64),
-            calories_total: data.total_energy.map(|e| e as i64),
-            steps_total: data.steps.map(|s| s as i64),
+            distance_total: current.distance,
+            calories_total: current.calories,
+            steps_total: current.steps,
             distance_delta,
             calories_delta,
             steps_delta,
+            user_id: self.active_user.get().await,
+            incline: data.incline,
+            heart_rate: data.heart_rate.map(i64::from),
+            calories_estimated: None,
         };
+
+        // Quarantine implausible samples before they reach the totals
+        let mut quality = self.quality.write().await;
+        let issues = quality.check(&sample);
+        let mut counters = self.counters.write().await;
+        if !issues.is_empty() {
+            let status = QuarantineStatus::for_issues(&issues);
+            for issue in &issues {
+                warn!(
+                    "Sample {} {}: {}",
+                    sample.timestamp,
+                    status.as_str(),
+                    issue.message
+                );
+            }
+            self.storage
+                .quarantine_sample(&sample, status, &issues)
+                .await?;
+            if status == QuarantineStatus::Rejected {
+                // The readings after a one-off spike still count from the old
+                // baselines, but counters that stay at the new level have
+                // really moved (or wrapped oddly); follow them
+                let jumped = issues.iter().any(|i| {
+                    matches!(
+                        i.reason,
+                        Reason::DistanceJump | Reason::StepsJump | Reason::CaloriesJump
+                    )
+                });
+                if !jumped {
+                    counters.rejected_jumps = 0;
+                } else if counters.rejected_jumps + 1 < MAX_REJECTED_JUMPS {
+                    counters.rejected_jumps += 1;
+                } else {
+                    warn!("Counters stayed past the rejected jump; taking them as the baseline");
+                    counters.advance(&current);
+                    quality.rebased(sample.timestamp);
+                }
+                return Ok(());
+            }
+        }
+        counters.advance(&current);
+        drop(counters);
+        drop(quality);
+
+        // Estimate calories from the walker's weight alongside the device counter
+        let elapsed = self
+            .last_timestamp
+            .write()
+            .await
+            .replace(sample.timestamp)
+            .map_or(0, |last| sample.timestamp - last);
+        let profile = energy::profile_for_user(&self.storage, &self.energy, sample.user_id).await?;
+        sample.calories_estimated = Some(profile.estimate_sample(&sample, elapsed));
+
+        // Broadcast to WebSocket clients, then queue for writing (live updates
+        // don't wait on the database)
         broadcast_sample(&self.ws_tx, &sample);
+        self.metrics.observe_sample(&sample);
+        self.writer.enqueue(sample);
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, WriterConfig};
+    use crate::storage::test_support::test_storage;
+
+    fn reading(speed: f64, distance: u32, steps: u16) -> TreadmillData {
+        TreadmillData {
+            speed: Some(speed),
+            distance: Some(distance),
+            steps: Some(steps),
+            total_energy: Some(10),
+            ..TreadmillData::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejected_samples_keep_the_baselines() {
+        let (dir, storage) = test_storage().await;
+        let storage = Arc::new(storage);
+        let (ws_tx, mut rx) = broadcast::channel(64);
+        let active_user = ActiveUser::load(Arc::clone(&storage), ws_tx.clone())
+            .await
+            .unwrap();
+        let (writer, _) = SampleWriter::start(
+            Arc::clone(&storage),
+            &WriterConfig::default(),
+            dir.path().join("test.db.spill"),
+        );
+        let config = Config::default();
+        let (manager, _) = BluetoothManager::new(
+            Arc::clone(&storage),
+            Arc::new(active_user),
+            config.bluetooth,
+            config.energy,
+            config.quality,
+            writer,
+            ws_tx,
+            Arc::new(Metrics::default()),
+        );
+        let readings = [
+            reading(1.0, 100, 1000),
+            reading(1.0, 101, 1002),
+            // A one-off steps spike, then the real count again
+            reading(1.0, 102, 5000),
+            reading(1.0, 103, 1004),
+            // Too fast: rejected, but its steps arrive with the next sample
+            reading(5.0, 104, 1006),
+            reading(1.0, 105, 1008),
+            // A jump the counters stay at is believed on the third sample
+            reading(1.0, 106, 3000),
+            reading(1.0, 107, 3002),
+            reading(1.0, 108, 3004),
+            reading(1.0, 109, 3006),
+        ];
+        for data in &readings {
+            manager.record_sample(data).await.unwrap();
+        }
+
+        let mut deltas = Vec::new();
+        while let Ok(message) = rx.try_recv() {
+            if let WsMessage::NewSample { sample } = message {
+                deltas.push((sample.distance_delta, sample.steps_delta));
+            }
+        }
+        let expected = [(0, 0), (1, 2), (2, 2), (2, 4), (1, 2)];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(distance, steps)| (Some(distance), Some(steps)))
+            .collect();
+        assert_eq!(deltas, expected);
+        assert_eq!(storage.get_quarantined(None, None).await.unwrap().len(), 5);
+    }
+}
@@ -0,0 +1,287 @@
+//! Command-line subcommands.
+//!
+//! Running the binary without arguments starts the server. A subcommand runs
+//! a one-off task against the configured database and exits.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::auth::{self, Scope};
+use crate::config::Config;
+use crate::energy;
+use crate::import::{self, ImportFormat, ImportOptions};
+use crate::influx;
+use crate::records::RecordsEngine;
+use crate::storage::Storage;
+
+const USAGE: &str = "\
+Usage: walkpad-server [COMMAND]
+
+Commands:
+  (none)                 Run the sync server
+  import <FILE>          Import historical samples from a CSV or JSON export
+      --format <csv|json>              (default: from file extension)
+      --mode <cumulative|delta>        How counter columns are recorded (default: cumulative)
+      --speed-unit <mps|kmh|mph>       (default: mps)
+      --distance-unit <m|km|mi>        (default: m)
+      --tz-offset <SECONDS>            Offset for timestamps without a zone (default: 0)
+      --tz <ZONE>                      IANA zone for timestamps without one (overrides --tz-offset)
+      --user <ID>                      Attribute samples to this user (default: unassigned)
+  estimate-calories      Fill in estimated calories for samples that lack them
+      --all                            Recalculate every sample (e.g. after changing weights)
+  rebuild-records        Recompute personal records and achievements from all samples
+  influx-backfill        Send stored samples and daily rollups to InfluxDB ([influx] url)
+      --from <YYYY-MM-DD>              First local day (default: the first sample)
+      --to <YYYY-MM-DD>                Last local day, inclusive (default: the last sample)
+  token create <NAME>    Create an API token (printed once; only its hash is stored)
+      --scope <read|control|write>     (default: read)
+      --user <ID>                      Limit the token to this user's data (default: every user's)
+  token list             List API tokens
+  token revoke <NAME>    Revoke an API token
+  help                   Show this message";
+
+pub enum Command {
+    Import {
+        path: PathBuf,
+        options: ImportOptions,
+    },
+    EstimateCalories {
+        all: bool,
+    },
+    RebuildRecords,
+    InfluxBackfill {
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    },
+    CreateToken {
+        name: String,
+        scope: Scope,
+        user_id: Option<i64>,
+    },
+    ListTokens,
+    RevokeToken {
+        name: String,
+    },
+    Help,
+}
+
+impl Command {
+    /// Parse arguments (excluding the program name). Returns `None` to run the server.
+    pub fn parse(args: &[String]) -> Result<Option<Self>> {
+        let Some((name, rest)) = args.split_first() else {
+            return Ok(None);
+        };
+
+        match name.as_str() {
+            "import" => parse_import(rest).map(Some),
+            "estimate-calories" => match rest {
+                [] => Ok(Some(Command::EstimateCalories { all: false })),
+                [flag] if flag == "--all" => Ok(Some(Command::EstimateCalories { all: true })),
+                _ => Err(anyhow!("Unexpected arguments\n\n{}", USAGE)),
+            },
+            "rebuild-records" if rest.is_empty() => Ok(Some(Command::RebuildRecords)),
+            "influx-backfill" => parse_influx_backfill(rest).map(Some),
+            "token" => parse_token(rest).map(Some),
+            "help" | "--help" | "-h" => Ok(Some(Command::Help)),
+            other => Err(anyhow!("Unknown command: {}\n\n{}", other, USAGE)),
+        }
+    }
+
+    pub async fn run(self, config: &Config) -> Result<()> {
+        match self {
+            Command::Import { path, options } => {
+                let storage = open_storage(config).await?;
+                let data = std::fs::read(&path)?;
+                info!("Importing {}", path.display());
+
+                let summary = import::import(&storage, &data, &options, &config.energy).await?;
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+                Ok(())
+            }
+            Command::EstimateCalories { all } => {
+                let storage = open_storage(config).await?;
+                let updated = energy::recalculate(&storage, &config.energy, None, !all).await?;
+                println!("Updated calorie estimates for {} samples", updated);
+                Ok(())
+            }
+            Command::RebuildRecords => {
+                let storage = Arc::new(open_storage(config).await?);
+                let (ws_tx, _) = broadcast::channel(1);
+                let mut engine = RecordsEngine::load(storage, ws_tx, config.server.zone()?).await?;
+                let samples = engine.rebuild().await?;
+                println!("Rebuilt personal records from {} samples", samples);
+                Ok(())
+            }
+            Command::InfluxBackfill { from, to } => {
+                let storage = Arc::new(open_storage(config).await?);
+                let zone = config.server.zone()?;
+                let start = from.map_or(0, |date| zone.day_bounds(date).0);
+                let end = to.map_or(i64::MAX, |date| zone.day_bounds(date).1);
+                let (samples, days) = influx::backfill(
+                    &config.influx,
+                    storage,
+                    (start, end),
+                    zone,
+                    config.energy.calories_source,
+                )
+                .await?;
+                println!(
+                    "Sent {} samples and {} daily rollups to InfluxDB",
+                    samples, days
+                );
+                Ok(())
+            }
+            Command::CreateToken {
+                name,
+                scope,
+                user_id,
+            } => {
+                let storage = open_storage(config).await?;
+                if let Some(id) = user_id {
+                    if storage.get_user(id).await?.is_none() {
+                        return Err(anyhow!("No user with id {}", id));
+                    }
+                }
+                let token = auth::generate_token();
+                storage
+                    .create_api_token(&name, scope, user_id, &auth::hash_token(&token))
+                    .await?;
+                match user_id {
+                    Some(id) => println!(
+                        "Created {} token '{}' for user {}:\n\n    {}\n",
+                        scope, name, id, token
+                    ),
+                    None => println!("Created {} token '{}':\n\n    {}\n", scope, name, token),
+                }
+                println!("Store it now; it can't be shown again.");
+                if !config.auth.enabled {
+                    println!("Note: authentication is disabled ([auth] enabled = false).");
+                }
+                Ok(())
+            }
+            Command::ListTokens => {
+                let storage = open_storage(config).await?;
+                let tokens = storage.get_api_tokens().await?;
+                if tokens.is_empty() {
+                    println!("No API tokens");
+                }
+                for token in tokens {
+                    let last_used = token
+                        .last_used_at
+                        .and_then(|t| DateTime::from_timestamp(t, 0))
+                        .map_or("never".to_string(), |t| t.to_rfc3339());
+                    let user = token
+                        .user_id
+                        .map_or("all users".to_string(), |id| format!("user {}", id));
+                    println!(
+                        "{:<24} {:<8} {:<10} last used: {}",
+                        token.name, token.scope, user, last_used
+                    );
+                }
+                Ok(())
+            }
+            Command::RevokeToken { name } => {
+                let storage = open_storage(config).await?;
+                match storage.delete_api_token(&name).await? {
+                    Some(_) => println!("Revoked token '{}'", name),
+                    None => return Err(anyhow!("No token named '{}'", name)),
+                }
+                Ok(())
+            }
+            Command::Help => {
+                println!("{}", USAGE);
+                Ok(())
+            }
+        }
+    }
+}
+
+async fn open_storage(config: &Config) -> Result<Storage> {
+    let database_url = format!("sqlite://{}", config.database.path);
+    Storage::new(&database_url).await
+}
+
+fn parse_import(args: &[String]) -> Result<Command> {
+    let mut path = None;
+    let mut options = ImportOptions::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            if path.replace(PathBuf::from(arg)).is_some() {
+                return Err(anyhow!("import takes a single file\n\n{}", USAGE));
+            }
+            continue;
+        }
+
+        let value = iter
+            .next()
+            .ok_or_else(|| anyhow!("Missing value for {}", arg))?;
+        match arg.as_str() {
+            "--format" => options.format = Some(value.parse()?),
+            "--mode" => options.mode = value.parse()?,
+            "--speed-unit" => options.speed_unit = value.parse()?,
+            "--distance-unit" => options.distance_unit = value.parse()?,
+            "--tz-offset" => options.tz_offset = value.parse()?,
+            "--tz" => options.tz = Some(value.clone()),
+            "--user" => options.user_id = Some(value.parse()?),
+            other => return Err(anyhow!("Unknown option: {}\n\n{}", other, USAGE)),
+        }
+    }
+
+    let path = path.ok_or_else(|| anyhow!("import requires a file\n\n{}", USAGE))?;
+    if options.format.is_none() {
+        options.format = ImportFormat::detect(&path.to_string_lossy());
+    }
+
+    Ok(Command::Import { path, options })
+}
+
+fn parse_influx_backfill(args: &[String]) -> Result<Command> {
+    let (mut from, mut to) = (None, None);
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let value = iter
+            .next()
+            .ok_or_else(|| anyhow!("Missing value for {}", arg))?;
+        let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .map_err(|_| anyhow!("Invalid date for {}: {} (use YYYY-MM-DD)", arg, value))?;
+        match arg.as_str() {
+            "--from" => from = Some(date),
+            "--to" => to = Some(date),
+            other => return Err(anyhow!("Unknown option: {}\n\n{}", other, USAGE)),
+        }
+    }
+    Ok(Command::InfluxBackfill { from, to })
+}
+
+fn parse_token(args: &[String]) -> Result<Command> {
+    match args {
+        [action, name, rest @ ..] if action == "create" => {
+            let (mut scope, mut user_id) = (Scope::Read, None);
+            let mut iter = rest.iter();
+            while let Some(arg) = iter.next() {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| anyhow!("Missing value for {}", arg))?;
+                match arg.as_str() {
+                    "--scope" => scope = value.parse()?,
+                    "--user" => user_id = Some(value.parse()?),
+                    other => return Err(anyhow!("Unknown option: {}\n\n{}", other, USAGE)),
+                }
+            }
+            Ok(Command::CreateToken {
+                name: name.clone(),
+                scope,
+                user_id,
+            })
+        }
+        [action] if action == "list" => Ok(Command::ListTokens),
+        [action, name] if action == "revoke" => Ok(Command::RevokeToken { name: name.clone() }),
+        _ => Err(anyhow!("Unknown token command\n\n{}", USAGE)),
+    }
+}
@@ -13,16 +13,51 @@
 //! - `TREADMILL_RECONNECT_DELAY` - Reconnect delay in seconds
 //! - `TREADMILL_HOST` - HTTP server bind address
 //! - `TREADMILL_PORT` - HTTP server port
+//! - `TREADMILL_TIMEZONE` - Default IANA time zone for local days (e.g. `America/Los_Angeles`)
+//! - `TREADMILL_WEEK_START` - First day of the week for weekly aggregation (e.g. `sunday`)
+//! - `TREADMILL_TLS_CERT` / `TREADMILL_TLS_KEY` - PEM certificate chain and key to serve HTTPS
+//! - `TREADMILL_TLS_SELF_SIGNED` - Generate a self-signed certificate if none exists (`true`)
+//! - `TREADMILL_CORS_ORIGINS` - Comma-separated origins allowed to call the API from a browser
+//! - `TREADMILL_BODY_WEIGHT_KG` - Body weight used for calorie estimation
+//! - `TREADMILL_CALORIES_SOURCE` - Calories reported by default (`device` or `estimated`)
+//! - `TREADMILL_MAX_SPEED_MS` - Samples faster than this (m/s) are quarantined
+//! - `TREADMILL_AUTH` - Require API tokens (`true` or `false`)
+//! - `TREADMILL_INFLUX_URL` - InfluxDB write endpoint; enables the InfluxDB sink
+//! - `TREADMILL_INFLUX_TOKEN` - Token for the InfluxDB write endpoint
+//! - `TREADMILL_MQTT_HOST` / `TREADMILL_MQTT_PORT` - MQTT broker; enables the MQTT bridge
+//! - `TREADMILL_MQTT_USERNAME` / `TREADMILL_MQTT_PASSWORD` - MQTT broker credentials
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use axum::http::{header, HeaderValue, Method};
+use chrono::Weekday;
 use serde::{Deserialize, Serialize};
 use std::path::Path;
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::energy::{CaloriesSource, EnergyProfile, Sex};
+use crate::timezone::Zone;
+use crate::webhooks::WebhookEvent;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub database: DatabaseConfig,
     pub bluetooth: BluetoothConfig,
     pub server: ServerConfig,
+    #[serde(default)]
+    pub energy: EnergyConfig,
+    #[serde(default)]
+    pub quality: QualityConfig,
+    #[serde(default)]
+    pub writer: WriterConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub influx: InfluxConfig,
+    #[serde(default)]
+    pub mqtt: MqttConfig,
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -68,6 +103,122 @@ pub struct ServerConfig {
 
     #[serde(default = "default_port")]
     pub port: u16,
+
+    /// IANA zone used for local days when a request doesn't give one (default: UTC)
+    #[serde(default)]
+    pub timezone: Option<String>,
+
+    /// First day of the week for weekly aggregation (default: Monday)
+    #[serde(default = "default_week_start")]
+    pub week_start: Weekday,
+
+    /// PEM certificate chain and private key; with both set the server
+    /// speaks HTTPS/WSS, reloading them when the files change
+    #[serde(default)]
+    pub tls_cert: Option<String>,
+    #[serde(default)]
+    pub tls_key: Option<String>,
+
+    /// Generate a self-signed certificate if there isn't one yet (for LAN
+    /// use). Stored at `tls_cert`/`tls_key`, or next to the database.
+    #[serde(default)]
+    pub tls_self_signed: bool,
+
+    /// Origins allowed to call the API from a browser (such as a dashboard
+    /// hosted elsewhere), or `"*"` for any. Empty sends no CORS headers.
+    #[serde(default)]
+    pub cors_origins: Vec<String>,
+
+    /// Methods allowed in cross-origin requests
+    #[serde(default = "default_cors_methods")]
+    pub cors_methods: Vec<String>,
+
+    /// Let browsers send cookies and HTTP authentication (not with `"*"`)
+    #[serde(default)]
+    pub cors_credentials: bool,
+
+    /// How long browsers may cache a preflight response
+    #[serde(default = "default_cors_max_age_secs")]
+    pub cors_max_age_secs: u64,
+}
+
+impl ServerConfig {
+    /// The default zone for local days
+    pub fn zone(&self) -> Result<Zone> {
+        self.timezone
+            .as_deref()
+            .map_or(Ok(Zone::default()), str::parse)
+    }
+
+    /// Certificate and key paths, or None to serve plain HTTP
+    pub fn tls_paths(&self, database_path: &str) -> Option<(String, String)> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Some((cert.clone(), key.clone())),
+            _ if self.tls_self_signed => Some((
+                self.tls_cert
+                    .clone()
+                    .unwrap_or_else(|| format!("{}.cert.pem", database_path)),
+                self.tls_key
+                    .clone()
+                    .unwrap_or_else(|| format!("{}.key.pem", database_path)),
+            )),
+            _ => None,
+        }
+    }
+
+    /// The CORS policy for browser clients, or None if no origin is allowed
+    pub fn cors(&self) -> Result<Option<CorsLayer>> {
+        if self.cors_origins.is_empty() {
+            return Ok(None);
+        }
+
+        let origins = if self.cors_origins.iter().any(|o| o == "*") {
+            if self.cors_credentials {
+                bail!("cors_credentials can't be used with any origin (\"*\"); list the origins");
+            }
+            AllowOrigin::any()
+        } else {
+            let origins = self
+                .cors_origins
+                .iter()
+                // Browsers send origins without a trailing slash
+                .map(|o| HeaderValue::from_str(o.trim_end_matches('/')))
+                .collect::<Result<Vec<_>, _>>()?;
+            AllowOrigin::list(origins)
+        };
+        let methods = self
+            .cors_methods
+            .iter()
+            .map(|m| match Method::from_bytes(m.to_uppercase().as_bytes()) {
+                Ok(method) => Ok(method),
+                Err(_) => bail!("Invalid CORS method {:?}", m),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Some(
+            CorsLayer::new()
+                .allow_origin(origins)
+                .allow_methods(methods)
+                .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+                .expose_headers([header::CONTENT_DISPOSITION, header::WWW_AUTHENTICATE])
+                .allow_credentials(self.cors_credentials)
+                .max_age(Duration::from_secs(self.cors_max_age_secs)),
+        ))
+    }
+}
+
+fn default_cors_methods() -> Vec<String> {
+    ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_cors_max_age_secs() -> u64 {
+    3600
+}
+
+fn default_week_start() -> Weekday {
+    Weekday::Mon
 }
 
 fn default_host() -> String {
@@ -78,6 +229,342 @@ fn default_port() -> u16 {
     8080
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnergyConfig {
+    /// Body weight for calorie estimation (users may override their own)
+    #[serde(default = "default_body_weight_kg")]
+    pub body_weight_kg: f64,
+
+    /// Age and sex enable heart-rate-based estimation when HR is available
+    #[serde(default)]
+    pub age: Option<u32>,
+    #[serde(default)]
+    pub sex: Option<Sex>,
+
+    /// Calories reported in summaries unless a client asks otherwise
+    #[serde(default)]
+    pub calories_source: CaloriesSource,
+}
+
+fn default_body_weight_kg() -> f64 {
+    70.0
+}
+
+impl Default for EnergyConfig {
+    fn default() -> Self {
+        Self {
+            body_weight_kg: default_body_weight_kg(),
+            age: None,
+            sex: None,
+            calories_source: CaloriesSource::default(),
+        }
+    }
+}
+
+impl EnergyConfig {
+    /// Estimation profile, using `weight_kg` in place of the configured weight if given
+    pub fn profile(&self, weight_kg: Option<f64>) -> EnergyProfile {
+        EnergyProfile {
+            weight_kg: weight_kg.unwrap_or(self.body_weight_kg),
+            age: self.age,
+            sex: self.sex,
+        }
+    }
+}
+
+/// Buffering of live samples on their way to the database (see `writer`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriterConfig {
+    /// Samples held in memory before new ones are dropped
+    #[serde(default = "default_queue_capacity")]
+    pub queue_capacity: usize,
+
+    /// Most samples written per transaction
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+
+    /// Retries (with doubling backoff) before a batch is spilled to disk
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+
+    /// Where samples wait while the database is unavailable (default: next to the database)
+    #[serde(default)]
+    pub spill_path: Option<String>,
+}
+
+fn default_queue_capacity() -> usize {
+    1024
+}
+
+fn default_batch_size() -> usize {
+    50
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        Self {
+            queue_capacity: default_queue_capacity(),
+            batch_size: default_batch_size(),
+            max_retries: default_max_retries(),
+            spill_path: None,
+        }
+    }
+}
+
+impl WriterConfig {
+    /// The spill file, defaulting to `<database>.spill`
+    pub fn spill_path(&self, database_path: &str) -> String {
+        self.spill_path
+            .clone()
+            .unwrap_or_else(|| format!("{}.spill", database_path))
+    }
+}
+
+/// API authentication (see `auth`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// Require a bearer token for everything but the dashboard page, health
+    /// check and API description. Off by default so existing setups keep working.
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// Copying samples and daily rollups to InfluxDB (see `influx`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InfluxConfig {
+    /// Write endpoint, with the database or bucket in its query string, e.g.
+    /// `http://influx:8086/api/v2/write?org=home&bucket=walkpad` or
+    /// `http://influx:8086/write?db=walkpad`. Unset disables the sink.
+    #[serde(default)]
+    pub url: Option<String>,
+
+    /// Sent as `Authorization: Token <token>` (`user:password` for InfluxDB 1.x)
+    #[serde(default)]
+    pub token: Option<String>,
+
+    /// Measurement for samples; daily rollups go to `<measurement>_daily`
+    #[serde(default = "default_influx_measurement")]
+    pub measurement: String,
+
+    /// Most lines sent per request
+    #[serde(default = "default_influx_batch_size")]
+    pub batch_size: usize,
+
+    /// Seconds between writes (and daily rollup updates)
+    #[serde(default = "default_influx_flush_interval")]
+    pub flush_interval_secs: u64,
+
+    /// Retries (with doubling backoff) before lines are buffered on disk
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+
+    /// Where lines wait while the endpoint is unreachable (default: next to the database)
+    #[serde(default)]
+    pub buffer_path: Option<String>,
+
+    /// Lines kept in the buffer before new ones are dropped
+    #[serde(default = "default_influx_buffer_max_lines")]
+    pub buffer_max_lines: usize,
+}
+
+fn default_influx_measurement() -> String {
+    "treadmill".to_string()
+}
+
+fn default_influx_batch_size() -> usize {
+    500
+}
+
+fn default_influx_flush_interval() -> u64 {
+    10
+}
+
+fn default_influx_buffer_max_lines() -> usize {
+    1_000_000
+}
+
+impl Default for InfluxConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            token: None,
+            measurement: default_influx_measurement(),
+            batch_size: default_influx_batch_size(),
+            flush_interval_secs: default_influx_flush_interval(),
+            max_retries: default_max_retries(),
+            buffer_path: None,
+            buffer_max_lines: default_influx_buffer_max_lines(),
+        }
+    }
+}
+
+impl InfluxConfig {
+    /// The buffer file, defaulting to `<database>.influx`
+    pub fn buffer_path(&self, database_path: &str) -> String {
+        self.buffer_path
+            .clone()
+            .unwrap_or_else(|| format!("{}.influx", database_path))
+    }
+}
+
+/// Publishing live data to an MQTT broker, with Home Assistant discovery (see `mqtt`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MqttConfig {
+    /// Broker host name or address. Unset disables the bridge.
+    #[serde(default)]
+    pub host: Option<String>,
+
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Client id, also used to identify the device in Home Assistant
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+
+    /// Prefix of the state topics (`<topic>/speed`, `<topic>/today`, ...)
+    #[serde(default = "default_mqtt_topic")]
+    pub topic: String,
+
+    /// Home Assistant discovery prefix; empty disables discovery messages
+    #[serde(default = "default_mqtt_discovery_prefix")]
+    pub discovery_prefix: String,
+
+    /// Topic to take commands from (`rescan`, `user <id>`, `user none`). Unset ignores commands.
+    #[serde(default)]
+    pub command_topic: Option<String>,
+
+    /// Seconds without a moving sample before walking turns off and speed drops to zero
+    #[serde(default = "default_mqtt_idle_timeout")]
+    pub idle_timeout_secs: u64,
+
+    /// Seconds between updates of today's totals while walking
+    #[serde(default = "default_mqtt_daily_interval")]
+    pub daily_interval_secs: u64,
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_client_id() -> String {
+    "walkpad".to_string()
+}
+
+fn default_mqtt_topic() -> String {
+    "walkpad".to_string()
+}
+
+fn default_mqtt_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+fn default_mqtt_idle_timeout() -> u64 {
+    15
+}
+
+fn default_mqtt_daily_interval() -> u64 {
+    60
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            host: None,
+            port: default_mqtt_port(),
+            username: None,
+            password: None,
+            client_id: default_mqtt_client_id(),
+            topic: default_mqtt_topic(),
+            discovery_prefix: default_mqtt_discovery_prefix(),
+            command_topic: None,
+            idle_timeout_secs: default_mqtt_idle_timeout(),
+            daily_interval_secs: default_mqtt_daily_interval(),
+        }
+    }
+}
+
+/// An endpoint notified of events (see `webhooks`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    /// Identifies the webhook in the delivery log
+    pub name: String,
+
+    pub url: String,
+
+    /// Events to send (default: all of them)
+    #[serde(default)]
+    pub events: Option<Vec<WebhookEvent>>,
+
+    /// Signs each request: `X-Walkpad-Signature: sha256=<HMAC of "<timestamp>.<body>">`
+    #[serde(default)]
+    pub secret: Option<String>,
+
+    /// Request body with `{{event}}`, `{{data.distance_km}}`, ... placeholders
+    /// (default: the event as JSON)
+    #[serde(default)]
+    pub template: Option<String>,
+
+    /// Retries (with doubling backoff) before a delivery is marked failed
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+impl WebhookConfig {
+    pub fn wants(&self, event: WebhookEvent) -> bool {
+        self.events
+            .as_ref()
+            .is_none_or(|events| events.contains(&event))
+    }
+}
+
+/// Limits used to quarantine implausible samples (see `quality`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QualityConfig {
+    /// Fastest plausible belt speed in m/s (default 5 mph, a walking pad's maximum)
+    #[serde(default = "default_max_speed_ms")]
+    pub max_speed_ms: f64,
+
+    /// Fastest plausible cadence
+    #[serde(default = "default_max_steps_per_sec")]
+    pub max_steps_per_sec: f64,
+
+    /// Seconds a counter may stay unchanged while the belt moves before it's flagged
+    #[serde(default = "default_frozen_counter_secs")]
+    pub frozen_counter_secs: i64,
+}
+
+fn default_max_speed_ms() -> f64 {
+    5.0 * 0.44704
+}
+
+fn default_max_steps_per_sec() -> f64 {
+    4.0
+}
+
+fn default_frozen_counter_secs() -> i64 {
+    120
+}
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        Self {
+            max_speed_ms: default_max_speed_ms(),
+            max_steps_per_sec: default_max_steps_per_sec(),
+            frozen_counter_secs: default_frozen_counter_secs(),
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -92,7 +579,23 @@ impl Default for Config {
             server: ServerConfig {
                 host: default_host(),
                 port: default_port(),
+                timezone: None,
+                week_start: default_week_start(),
+                tls_cert: None,
+                tls_key: None,
+                tls_self_signed: false,
+                cors_origins: Vec::new(),
+                cors_methods: default_cors_methods(),
+                cors_credentials: false,
+                cors_max_age_secs: default_cors_max_age_secs(),
             },
+            energy: EnergyConfig::default(),
+            quality: QualityConfig::default(),
+            writer: WriterConfig::default(),
+            auth: AuthConfig::default(),
+            influx: InfluxConfig::default(),
+            mqtt: MqttConfig::default(),
+            webhooks: Vec::new(),
         }
     }
 }
@@ -147,5 +650,82 @@ impl Config {
                 self.server.port = port;
             }
         }
+        if let Ok(val) = std::env::var("TREADMILL_TIMEZONE") {
+            self.server.timezone = Some(val);
+        }
+        if let Ok(val) = std::env::var("TREADMILL_WEEK_START") {
+            if let Ok(day) = val.parse() {
+                self.server.week_start = day;
+            }
+        }
+        if let Ok(val) = std::env::var("TREADMILL_TLS_CERT") {
+            self.server.tls_cert = Some(val);
+        }
+        if let Ok(val) = std::env::var("TREADMILL_TLS_KEY") {
+            self.server.tls_key = Some(val);
+        }
+        if let Ok(val) = std::env::var("TREADMILL_TLS_SELF_SIGNED") {
+            if let Ok(enabled) = val.parse() {
+                self.server.tls_self_signed = enabled;
+            }
+        }
+        if let Ok(val) = std::env::var("TREADMILL_CORS_ORIGINS") {
+            self.server.cors_origins = val
+                .split(',')
+                .map(str::trim)
+                .filter(|o| !o.is_empty())
+                .map(String::from)
+                .collect();
+        }
+
+        // Energy
+        if let Ok(val) = std::env::var("TREADMILL_BODY_WEIGHT_KG") {
+            if let Ok(kg) = val.parse() {
+                self.energy.body_weight_kg = kg;
+            }
+        }
+        if let Ok(val) = std::env::var("TREADMILL_CALORIES_SOURCE") {
+            if let Ok(source) = val.parse() {
+                self.energy.calories_source = source;
+            }
+        }
+
+        // Quality
+        if let Ok(val) = std::env::var("TREADMILL_MAX_SPEED_MS") {
+            if let Ok(speed) = val.parse() {
+                self.quality.max_speed_ms = speed;
+            }
+        }
+
+        // Auth
+        if let Ok(val) = std::env::var("TREADMILL_AUTH") {
+            if let Ok(enabled) = val.parse() {
+                self.auth.enabled = enabled;
+            }
+        }
+
+        // InfluxDB
+        if let Ok(val) = std::env::var("TREADMILL_INFLUX_URL") {
+            self.influx.url = Some(val);
+        }
+        if let Ok(val) = std::env::var("TREADMILL_INFLUX_TOKEN") {
+            self.influx.token = Some(val);
+        }
+
+        // MQTT
+        if let Ok(val) = std::env::var("TREADMILL_MQTT_HOST") {
+            self.mqtt.host = Some(val);
+        }
+        if let Ok(val) = std::env::var("TREADMILL_MQTT_PORT") {
+            if let Ok(port) = val.parse() {
+                self.mqtt.port = port;
+            }
+        }
+        if let Ok(val) = std::env::var("TREADMILL_MQTT_USERNAME") {
+            self.mqtt.username = Some(val);
+        }
+        if let Ok(val) = std::env::var("TREADMILL_MQTT_PASSWORD") {
+            self.mqtt.password = Some(val);
+        }
     }
 }
@@ -0,0 +1,259 @@
+//! Energy expenditure estimation.
+//!
+//! Treadmill calorie counters often assume a fixed body weight (LifeSpan units
+//! ignore the walker entirely), so the server also estimates kcal itself:
+//!
+//! - With a heart rate and the walker's age and sex, the Keytel et al. (2005)
+//!   heart-rate equation is used.
+//! - Otherwise the ACSM metabolic equations for walking (and running, above
+//!   [`RUNNING_SPEED_MS`]) are applied to speed and incline.
+//!
+//! Both device and estimated calories are stored; clients choose which one
+//! summaries report via [`CaloriesSource`].
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+use crate::config::EnergyConfig;
+use crate::storage::{Storage, TreadmillSample};
+
+/// Above this speed (m/s, ~8 km/h) the ACSM running equation applies
+pub const RUNNING_SPEED_MS: f64 = 2.22;
+
+/// Longest gap between samples credited with activity (matches how active
+/// duration is computed). Longer gaps are disconnects or pauses.
+pub const MAX_SAMPLE_GAP_SECS: i64 = 10;
+
+/// kcal per litre of O2 consumed
+const KCAL_PER_LITRE_O2: f64 = 5.0;
+
+/// Resting VO2 (ml/kg/min), one MET
+const RESTING_VO2: f64 = 3.5;
+
+const KJ_PER_KCAL: f64 = 4.184;
+
+/// Which calorie figure summaries report
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CaloriesSource {
+    /// The treadmill's own counter
+    #[default]
+    Device,
+    /// Estimated from body weight, speed, incline and heart rate
+    Estimated,
+}
+
+impl FromStr for CaloriesSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "device" => Ok(Self::Device),
+            "estimated" | "estimate" => Ok(Self::Estimated),
+            other => Err(anyhow::anyhow!(
+                "Unknown calories source: {} (expected device or estimated)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sex {
+    Male,
+    Female,
+}
+
+/// Body measurements used for estimation
+#[derive(Debug, Clone, Copy)]
+pub struct EnergyProfile {
+    pub weight_kg: f64,
+    pub age: Option<u32>,
+    pub sex: Option<Sex>,
+}
+
+impl EnergyProfile {
+    /// Estimated kcal burned over `elapsed_secs` at the given effort
+    pub fn estimate_kcal(
+        &self,
+        speed_ms: f64,
+        incline_percent: f64,
+        heart_rate: Option<u8>,
+        elapsed_secs: f64,
+    ) -> f64 {
+        if speed_ms <= 0.0 || elapsed_secs <= 0.0 {
+            return 0.0;
+        }
+
+        let per_minute = heart_rate
+            .filter(|&hr| hr > 0)
+            .and_then(|hr| self.heart_rate_kcal_per_min(hr))
+            .unwrap_or_else(|| self.acsm_kcal_per_min(speed_ms, incline_percent));
+
+        per_minute * elapsed_secs / 60.0
+    }
+
+    /// ACSM walking/running equations
+    fn acsm_kcal_per_min(&self, speed_ms: f64, incline_percent: f64) -> f64 {
+        let speed_m_per_min = speed_ms * 60.0;
+        let grade = incline_percent.max(0.0) / 100.0;
+
+        let vo2 = if speed_ms < RUNNING_SPEED_MS {
+            0.1 * speed_m_per_min + 1.8 * speed_m_per_min * grade + RESTING_VO2
+        } else {
+            0.2 * speed_m_per_min + 0.9 * speed_m_per_min * grade + RESTING_VO2
+        };
+
+        vo2 * self.weight_kg / 1000.0 * KCAL_PER_LITRE_O2
+    }
+
+    /// Keytel et al. heart-rate equation. Needs age and sex.
+    fn heart_rate_kcal_per_min(&self, heart_rate: u8) -> Option<f64> {
+        let (age, sex) = (f64::from(self.age?), self.sex?);
+        let hr = f64::from(heart_rate);
+        let w = self.weight_kg;
+
+        let kj_per_min = match sex {
+            Sex::Male => -55.0969 + 0.6309 * hr + 0.1988 * w + 0.2017 * age,
+            Sex::Female => -20.4022 + 0.4472 * hr - 0.1263 * w + 0.074 * age,
+        };
+
+        Some((kj_per_min / KJ_PER_KCAL).max(0.0))
+    }
+
+    /// Estimated kcal for a sample taken `elapsed_secs` after the previous one
+    pub fn estimate_sample(&self, sample: &TreadmillSample, elapsed_secs: i64) -> f64 {
+        if !(0..=MAX_SAMPLE_GAP_SECS).contains(&elapsed_secs) {
+            return 0.0;
+        }
+        self.estimate_kcal(
+            sample.speed.unwrap_or(0.0),
+            sample.incline.unwrap_or(0.0),
+            sample.heart_rate.and_then(|hr| u8::try_from(hr).ok()),
+            elapsed_secs as f64,
+        )
+    }
+}
+
+/// Estimation profile for a user (their own weight if set, else the configured one)
+pub async fn profile_for_user(
+    storage: &Storage,
+    config: &EnergyConfig,
+    user_id: Option<i64>,
+) -> anyhow::Result<EnergyProfile> {
+    let weight_kg = match user_id {
+        Some(id) => storage.get_user(id).await?.and_then(|u| u.weight_kg),
+        None => None,
+    };
+    Ok(config.profile(weight_kg))
+}
+
+/// Recalculate stored estimates with each sample's user's profile (e.g. after
+/// changing weights or reassigning samples, or for samples recorded before
+/// estimation existed), in `[start, end)` or everywhere. Returns how many
+/// samples were updated.
+pub async fn recalculate(
+    storage: &Storage,
+    config: &EnergyConfig,
+    range: Option<(i64, i64)>,
+    only_missing: bool,
+) -> anyhow::Result<u64> {
+    const BATCH_SIZE: i64 = 10_000;
+
+    let mut profiles: HashMap<Option<i64>, EnergyProfile> = HashMap::new();
+    // Start early enough to see the sample before the range, if it's close
+    // enough to count
+    let mut cursor = range.map(|(start, _)| start.saturating_sub(MAX_SAMPLE_GAP_SECS + 1));
+    let end = range.map_or(i64::MAX, |(_, end)| end);
+    let mut previous = None;
+    let mut updated = 0;
+
+    loop {
+        let samples = storage.get_samples_after(cursor, BATCH_SIZE).await?;
+        let Some(last) = samples.last() else {
+            break;
+        };
+        cursor = Some(last.timestamp);
+
+        let mut estimates = Vec::new();
+        for sample in samples.iter().take_while(|s| s.timestamp < end) {
+            let elapsed = previous.map_or(0, |p| sample.timestamp - p);
+            previous = Some(sample.timestamp);
+            if range.is_some_and(|(start, _)| sample.timestamp < start)
+                || (only_missing && sample.calories_estimated.is_some())
+            {
+                continue;
+            }
+
+            let profile = match profiles.get(&sample.user_id) {
+                Some(p) => *p,
+                None => {
+                    let p = profile_for_user(storage, config, sample.user_id).await?;
+                    *profiles.entry(sample.user_id).or_insert(p)
+                }
+            };
+            estimates.push((sample.timestamp, profile.estimate_sample(sample, elapsed)));
+        }
+
+        storage.set_calories_estimated(&estimates).await?;
+        updated += estimates.len() as u64;
+        if last.timestamp >= end {
+            break;
+        }
+    }
+
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(weight_kg: f64) -> EnergyProfile {
+        EnergyProfile {
+            weight_kg,
+            age: None,
+            sex: None,
+        }
+    }
+
+    #[test]
+    fn test_acsm_walking() {
+        // 5 km/h, flat, 70 kg: VO2 = 0.1 * 83.33 + 3.5 = 11.83 ml/kg/min
+        // => 11.83 * 70 / 1000 * 5 = 4.14 kcal/min
+        let kcal = profile(70.0).estimate_kcal(5.0 / 3.6, 0.0, None, 3600.0);
+        assert!((kcal - 248.5).abs() < 1.0, "got {}", kcal);
+    }
+
+    #[test]
+    fn test_weight_and_incline_increase_estimate() {
+        let light = profile(60.0).estimate_kcal(1.2, 0.0, None, 60.0);
+        let heavy = profile(90.0).estimate_kcal(1.2, 0.0, None, 60.0);
+        let uphill = profile(60.0).estimate_kcal(1.2, 5.0, None, 60.0);
+        assert!(heavy > light);
+        assert!(uphill > light);
+        assert_eq!(profile(90.0).estimate_kcal(0.0, 0.0, None, 60.0), 0.0);
+    }
+
+    #[test]
+    fn test_heart_rate_needs_age_and_sex() {
+        let without = profile(80.0);
+        let with = EnergyProfile {
+            age: Some(40),
+            sex: Some(Sex::Male),
+            ..without
+        };
+
+        // Without age/sex the heart rate is ignored
+        assert_eq!(
+            without.estimate_kcal(1.2, 0.0, Some(150), 60.0),
+            without.estimate_kcal(1.2, 0.0, None, 60.0)
+        );
+        // Male, 40, 80 kg at 150 bpm: (-55.0969 + 94.635 + 15.904 + 8.068) / 4.184
+        let kcal = with.estimate_kcal(1.2, 0.0, Some(150), 60.0);
+        assert!((kcal - 15.18).abs() < 0.05, "got {}", kcal);
+    }
+}
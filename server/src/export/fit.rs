@@ -0,0 +1,620 @@
+//! Garmin FIT activity files.
+//!
+//! A FIT file is a 14-byte header, a stream of messages and a CRC. Each
+//! message type is defined once (a local type number mapped to a global
+//! message and its fields) before its first data message. This writes the
+//! messages an activity needs: `file_id`, timer `event`s, a `record` per
+//! sample, a `lap` per session, one treadmill-walking `session` and the
+//! closing `activity`. Field numbers, base types and scales follow the FIT
+//! SDK profile.
+
+use super::{Lap, Workout};
+use crate::energy::{CaloriesSource, MAX_SAMPLE_GAP_SECS};
+
+/// Seconds between the Unix epoch and the FIT epoch (1989-12-31 00:00 UTC)
+const FIT_EPOCH_OFFSET: i64 = 631_065_600;
+
+const PROTOCOL_VERSION: u8 = 0x20; // 2.0
+const PROFILE_VERSION: u16 = 2140; // 21.40
+const HEADER_SIZE: u8 = 14;
+
+// Enum values from the FIT profile
+const FILE_ACTIVITY: u64 = 4;
+const MANUFACTURER_DEVELOPMENT: u64 = 255;
+const SPORT_WALKING: u64 = 11;
+const SUB_SPORT_TREADMILL: u64 = 1;
+const EVENT_TIMER: u64 = 0;
+const EVENT_SESSION: u64 = 8;
+const EVENT_LAP: u64 = 9;
+const EVENT_ACTIVITY: u64 = 26;
+const EVENT_TYPE_START: u64 = 0;
+const EVENT_TYPE_STOP: u64 = 1;
+const EVENT_TYPE_STOP_ALL: u64 = 4;
+const ACTIVITY_MANUAL: u64 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BaseType {
+    Enum,
+    Uint8,
+    Sint16,
+    Uint16,
+    Uint32,
+    Uint32z,
+}
+
+impl BaseType {
+    fn id(self) -> u8 {
+        match self {
+            BaseType::Enum => 0x00,
+            BaseType::Uint8 => 0x02,
+            BaseType::Sint16 => 0x83,
+            BaseType::Uint16 => 0x84,
+            BaseType::Uint32 => 0x86,
+            BaseType::Uint32z => 0x8C,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            BaseType::Enum | BaseType::Uint8 => 1,
+            BaseType::Sint16 | BaseType::Uint16 => 2,
+            BaseType::Uint32 | BaseType::Uint32z => 4,
+        }
+    }
+
+    /// The value meaning "not recorded"
+    fn invalid(self) -> u64 {
+        match self {
+            BaseType::Enum | BaseType::Uint8 => 0xFF,
+            BaseType::Sint16 => 0x7FFF,
+            BaseType::Uint16 => 0xFFFF,
+            BaseType::Uint32 => 0xFFFF_FFFF,
+            BaseType::Uint32z => 0,
+        }
+    }
+}
+
+/// A global message and the fields written for it, under a local type number
+struct MessageType {
+    global: u16,
+    local: u8,
+    fields: &'static [(u8, BaseType)],
+}
+
+// Message layouts. Fields are (number, base type), commented in order.
+use BaseType::*;
+
+const FILE_ID: MessageType = MessageType {
+    global: 0,
+    local: 0,
+    // type, manufacturer, product, serial_number, time_created
+    fields: &[
+        (0, Enum),
+        (1, Uint16),
+        (2, Uint16),
+        (3, Uint32z),
+        (4, Uint32),
+    ],
+};
+
+const EVENT: MessageType = MessageType {
+    global: 21,
+    local: 1,
+    // timestamp, event, event_type
+    fields: &[(253, Uint32), (0, Enum), (1, Enum)],
+};
+
+const RECORD: MessageType = MessageType {
+    global: 20,
+    local: 2,
+    // timestamp, distance (cm), speed (mm/s), heart_rate, cadence (strides/min),
+    // calories (accumulated kcal), grade (1/100 %)
+    fields: &[
+        (253, Uint32),
+        (5, Uint32),
+        (6, Uint16),
+        (3, Uint8),
+        (4, Uint8),
+        (33, Uint16),
+        (9, Sint16),
+    ],
+};
+
+const LAP: MessageType = MessageType {
+    global: 19,
+    local: 3,
+    // timestamp, message_index, event, event_type, start_time, total_elapsed_time (ms),
+    // total_timer_time (ms), total_distance (cm), total_cycles (strides), total_calories,
+    // avg_speed (mm/s), max_speed (mm/s), avg_heart_rate, max_heart_rate, avg_cadence,
+    // sport, sub_sport
+    fields: &[
+        (253, Uint32),
+        (254, Uint16),
+        (0, Enum),
+        (1, Enum),
+        (2, Uint32),
+        (7, Uint32),
+        (8, Uint32),
+        (9, Uint32),
+        (10, Uint32),
+        (11, Uint16),
+        (13, Uint16),
+        (14, Uint16),
+        (15, Uint8),
+        (16, Uint8),
+        (17, Uint8),
+        (25, Enum),
+        (39, Enum),
+    ],
+};
+
+const SESSION: MessageType = MessageType {
+    global: 18,
+    local: 4,
+    // timestamp, message_index, event, event_type, start_time, sport, sub_sport,
+    // total_elapsed_time (ms), total_timer_time (ms), total_distance (cm), total_cycles,
+    // total_calories, avg_speed (mm/s), max_speed (mm/s), avg_heart_rate, max_heart_rate,
+    // avg_cadence, first_lap_index, num_laps
+    fields: &[
+        (253, Uint32),
+        (254, Uint16),
+        (0, Enum),
+        (1, Enum),
+        (2, Uint32),
+        (5, Enum),
+        (6, Enum),
+        (7, Uint32),
+        (8, Uint32),
+        (9, Uint32),
+        (10, Uint32),
+        (11, Uint16),
+        (14, Uint16),
+        (15, Uint16),
+        (16, Uint8),
+        (17, Uint8),
+        (18, Uint8),
+        (25, Uint16),
+        (26, Uint16),
+    ],
+};
+
+const ACTIVITY: MessageType = MessageType {
+    global: 34,
+    local: 5,
+    // timestamp, total_timer_time (ms), num_sessions, type, event, event_type
+    fields: &[
+        (253, Uint32),
+        (0, Uint32),
+        (1, Uint16),
+        (2, Enum),
+        (3, Enum),
+        (4, Enum),
+    ],
+};
+
+/// Messages in order, each type defined before its first use
+#[derive(Default)]
+struct Encoder {
+    data: Vec<u8>,
+    defined: u16, // bit per local message type
+}
+
+impl Encoder {
+    /// One data message; `None` fields are written as the type's invalid value
+    fn write(&mut self, message: &MessageType, values: &[Option<u64>]) {
+        debug_assert_eq!(values.len(), message.fields.len());
+        if self.defined & (1 << message.local) == 0 {
+            self.define(message);
+        }
+
+        self.data.push(message.local);
+        for (&(_, base), value) in message.fields.iter().zip(values) {
+            let value = value.unwrap_or(base.invalid());
+            self.data
+                .extend_from_slice(&value.to_le_bytes()[..base.size()]);
+        }
+    }
+
+    fn define(&mut self, message: &MessageType) {
+        self.data.push(0x40 | message.local);
+        self.data.push(0); // reserved
+        self.data.push(0); // little-endian
+        self.data.extend_from_slice(&message.global.to_le_bytes());
+        self.data.push(message.fields.len() as u8);
+        for &(number, base) in message.fields {
+            self.data
+                .extend_from_slice(&[number, base.size() as u8, base.id()]);
+        }
+        self.defined |= 1 << message.local;
+    }
+
+    /// Header, messages and CRC
+    fn finish(self) -> Vec<u8> {
+        let mut file = Vec::with_capacity(HEADER_SIZE as usize + self.data.len() + 2);
+        file.push(HEADER_SIZE);
+        file.push(PROTOCOL_VERSION);
+        file.extend_from_slice(&PROFILE_VERSION.to_le_bytes());
+        file.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        file.extend_from_slice(b".FIT");
+        let header_crc = crc(&file);
+        file.extend_from_slice(&header_crc.to_le_bytes());
+
+        file.extend_from_slice(&self.data);
+        let file_crc = crc(&file);
+        file.extend_from_slice(&file_crc.to_le_bytes());
+        file
+    }
+}
+
+/// The FIT SDK's CRC-16
+fn crc(bytes: &[u8]) -> u16 {
+    const TABLE: [u16; 16] = [
+        0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800,
+        0xB401, 0x5000, 0x9C01, 0x8801, 0x4400,
+    ];
+    let mut crc = 0u16;
+    for &byte in bytes {
+        for nibble in [byte & 0x0F, byte >> 4] {
+            let tmp = TABLE[(crc & 0x0F) as usize];
+            crc = ((crc >> 4) & 0x0FFF) ^ tmp ^ TABLE[nibble as usize];
+        }
+    }
+    crc
+}
+
+fn fit_time(timestamp: i64) -> Option<u64> {
+    u64::try_from(timestamp - FIT_EPOCH_OFFSET).ok()
+}
+
+/// Speeds in mm/s and distances in cm, as FIT stores them
+fn scaled(value: f64, scale: f64) -> Option<u64> {
+    Some((value * scale).round().max(0.0) as u64)
+}
+
+/// Strides (two steps) per minute, FIT's running and walking cadence
+fn cadence(steps: i64, seconds: i64) -> Option<u64> {
+    (seconds > 0 && steps > 0)
+        .then(|| (steps as f64 * 30.0 / seconds as f64).round().min(254.0) as u64)
+}
+
+/// The workout as a FIT activity: one treadmill-walking session with a lap
+/// per walking session, the timer stopped between them
+pub fn encode(workout: &Workout) -> Vec<u8> {
+    let mut fit = Encoder::default();
+    let (Some(start), Some(end)) = (
+        workout.laps.first().map(|lap| lap.session.start),
+        workout.laps.last().map(|lap| lap.session.end),
+    ) else {
+        return fit.finish();
+    };
+
+    fit.write(
+        &FILE_ID,
+        &[
+            Some(FILE_ACTIVITY),
+            Some(MANUFACTURER_DEVELOPMENT),
+            Some(1),
+            None,
+            fit_time(start),
+        ],
+    );
+
+    let mut distance = 0i64; // metres since the start of the activity
+    let mut calories = 0.0; // kcal since the start of the activity
+    for (index, lap) in workout.laps.iter().enumerate() {
+        fit.write(
+            &EVENT,
+            &[
+                fit_time(lap.session.start),
+                Some(EVENT_TIMER),
+                Some(EVENT_TYPE_START),
+            ],
+        );
+        write_records(
+            &mut fit,
+            workout.calories_source,
+            lap,
+            &mut distance,
+            &mut calories,
+        );
+        fit.write(
+            &EVENT,
+            &[
+                fit_time(lap.session.end),
+                Some(EVENT_TIMER),
+                Some(EVENT_TYPE_STOP_ALL),
+            ],
+        );
+        write_lap(&mut fit, workout.calories_source, lap, index);
+    }
+
+    let timer_secs: i64 = workout
+        .laps
+        .iter()
+        .map(|l| l.session.duration_seconds)
+        .sum();
+    let distance_m: i64 = workout.laps.iter().map(|l| l.session.distance_meters).sum();
+    let steps: i64 = workout.laps.iter().map(|l| l.session.steps).sum();
+    let total_calories: f64 = workout
+        .laps
+        .iter()
+        .map(|l| l.calories(workout.calories_source))
+        .sum();
+    // Mean sample speed, as for laps and daily summaries
+    let samples: i64 = workout.laps.iter().map(|l| l.session.total_samples).sum();
+    let avg_speed = workout
+        .laps
+        .iter()
+        .map(|l| l.session.avg_speed * l.session.total_samples as f64)
+        .sum::<f64>()
+        / samples.max(1) as f64;
+    let max_speed = workout
+        .laps
+        .iter()
+        .map(|l| l.session.max_speed)
+        .fold(0.0, f64::max);
+    let rates: Vec<i64> = workout
+        .laps
+        .iter()
+        .flat_map(|l| l.samples.iter().filter_map(|s| s.heart_rate))
+        .collect();
+    let avg_rate = (!rates.is_empty())
+        .then(|| (rates.iter().sum::<i64>() as f64 / rates.len() as f64).round() as u64);
+
+    fit.write(
+        &SESSION,
+        &[
+            fit_time(end),
+            Some(0),
+            Some(EVENT_SESSION),
+            Some(EVENT_TYPE_STOP),
+            fit_time(start),
+            Some(SPORT_WALKING),
+            Some(SUB_SPORT_TREADMILL),
+            Some(((end - start) * 1000) as u64),
+            Some((timer_secs * 1000) as u64),
+            scaled(distance_m as f64, 100.0),
+            Some((steps / 2) as u64),
+            scaled(total_calories, 1.0),
+            scaled(avg_speed, 1000.0),
+            scaled(max_speed, 1000.0),
+            avg_rate,
+            rates.iter().max().map(|&r| r as u64),
+            cadence(steps, timer_secs),
+            Some(0),
+            Some(workout.laps.len() as u64),
+        ],
+    );
+    fit.write(
+        &ACTIVITY,
+        &[
+            fit_time(end),
+            Some((timer_secs * 1000) as u64),
+            Some(1),
+            Some(ACTIVITY_MANUAL),
+            Some(EVENT_ACTIVITY),
+            Some(EVENT_TYPE_STOP),
+        ],
+    );
+
+    fit.finish()
+}
+
+fn write_records(
+    fit: &mut Encoder,
+    source: CaloriesSource,
+    lap: &Lap,
+    distance: &mut i64,
+    calories: &mut f64,
+) {
+    let mut previous: Option<i64> = None;
+    for sample in &lap.samples {
+        *distance += sample.distance_delta.unwrap_or(0).max(0);
+        *calories += match source {
+            CaloriesSource::Device => sample.calories_delta.unwrap_or(0).max(0) as f64,
+            CaloriesSource::Estimated => sample.calories_estimated.unwrap_or(0.0),
+        };
+        let gap = previous
+            .map(|p| sample.timestamp - p)
+            .filter(|gap| *gap <= MAX_SAMPLE_GAP_SECS)
+            .unwrap_or(0);
+        previous = Some(sample.timestamp);
+
+        fit.write(
+            &RECORD,
+            &[
+                fit_time(sample.timestamp),
+                scaled(*distance as f64, 100.0),
+                sample.speed.and_then(|s| scaled(s, 1000.0)),
+                sample.heart_rate.map(|hr| hr.clamp(0, 254) as u64),
+                cadence(sample.steps_delta.unwrap_or(0), gap),
+                scaled(*calories, 1.0),
+                sample
+                    .incline
+                    .map(|grade| (grade * 100.0).round() as i16 as u16 as u64),
+            ],
+        );
+    }
+}
+
+fn write_lap(fit: &mut Encoder, source: CaloriesSource, lap: &Lap, index: usize) {
+    let session = &lap.session;
+    let heart_rate = lap.heart_rate();
+    fit.write(
+        &LAP,
+        &[
+            fit_time(session.end),
+            Some(index as u64),
+            Some(EVENT_LAP),
+            Some(EVENT_TYPE_STOP),
+            fit_time(session.start),
+            Some(((session.end - session.start) * 1000) as u64),
+            Some((session.duration_seconds * 1000) as u64),
+            scaled(session.distance_meters as f64, 100.0),
+            Some((session.steps.max(0) / 2) as u64),
+            scaled(lap.calories(source), 1.0),
+            scaled(session.avg_speed, 1000.0),
+            scaled(session.max_speed, 1000.0),
+            heart_rate.map(|(avg, _)| avg as u64),
+            heart_rate.map(|(_, max)| max as u64),
+            cadence(session.steps, session.duration_seconds),
+            Some(SPORT_WALKING),
+            Some(SUB_SPORT_TREADMILL),
+        ],
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sessions::split_with_samples;
+    use crate::storage::test_support::test_sample;
+    use crate::storage::TreadmillSample;
+    use std::collections::HashMap;
+
+    /// A FIT message: global number and raw field values by field number
+    type Message = (u16, HashMap<u8, u64>);
+
+    /// Just enough of a FIT reader to check the encoder: verifies both CRCs
+    /// and the declared size, and reads definition and data messages
+    fn decode(file: &[u8]) -> Vec<Message> {
+        let header_size = file[0] as usize;
+        assert_eq!(header_size, 14);
+        assert_eq!(&file[8..12], b".FIT");
+        assert_eq!(crc(&file[..12]), u16::from_le_bytes([file[12], file[13]]));
+        let data_size = u32::from_le_bytes(file[4..8].try_into().unwrap()) as usize;
+        assert_eq!(file.len(), header_size + data_size + 2);
+        // A CRC over data followed by its own CRC is zero
+        assert_eq!(crc(file), 0);
+
+        let mut definitions: HashMap<u8, (u16, Vec<(u8, usize)>)> = HashMap::new();
+        let mut messages = Vec::new();
+        let mut pos = header_size;
+        while pos < header_size + data_size {
+            let header = file[pos];
+            pos += 1;
+            assert_eq!(header & 0x80, 0, "compressed timestamps aren't written");
+            let local = header & 0x0F;
+            if header & 0x40 != 0 {
+                assert_eq!(file[pos + 1], 0, "little-endian");
+                let global = u16::from_le_bytes([file[pos + 2], file[pos + 3]]);
+                let count = file[pos + 4] as usize;
+                pos += 5;
+                let fields = (0..count)
+                    .map(|i| (file[pos + i * 3], file[pos + i * 3 + 1] as usize))
+                    .collect();
+                pos += count * 3;
+                definitions.insert(local, (global, fields));
+            } else {
+                let (global, fields) = &definitions[&local];
+                let mut values = HashMap::new();
+                for &(number, size) in fields {
+                    let mut bytes = [0u8; 8];
+                    bytes[..size].copy_from_slice(&file[pos..pos + size]);
+                    values.insert(number, u64::from_le_bytes(bytes));
+                    pos += size;
+                }
+                messages.push((*global, values));
+            }
+        }
+        messages
+    }
+
+    fn of_type(messages: &[Message], global: u16) -> Vec<&HashMap<u8, u64>> {
+        messages
+            .iter()
+            .filter(|(g, _)| *g == global)
+            .map(|(_, values)| values)
+            .collect()
+    }
+
+    fn sample(timestamp: i64) -> TreadmillSample {
+        TreadmillSample {
+            speed: Some(1.25),
+            distance_delta: Some(2),
+            calories_delta: Some(1),
+            steps_delta: Some(3),
+            incline: Some(-1.5),
+            calories_estimated: Some(0.25),
+            ..test_sample(timestamp)
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // Two sessions an hour apart from 2025-01-15 08:00 UTC, one with heart rate
+        let start = 1736928000;
+        let mut samples: Vec<_> = (0..3).map(|i| sample(start + i * 2)).collect();
+        samples.iter_mut().for_each(|s| s.heart_rate = Some(100));
+        samples.extend((0..2).map(|i| sample(start + 3600 + i * 2)));
+        let workout = Workout::new(
+            "Walk",
+            split_with_samples(samples, None),
+            CaloriesSource::Device,
+        );
+
+        let messages = decode(&encode(&workout));
+        let fit_start = (start - FIT_EPOCH_OFFSET) as u64;
+
+        let file_id = &of_type(&messages, 0)[0];
+        assert_eq!(file_id[&0], FILE_ACTIVITY);
+        assert_eq!(file_id[&4], fit_start);
+
+        let records = of_type(&messages, 20);
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[0][&253], fit_start);
+        assert_eq!(records[0][&6], 1250); // mm/s
+        assert_eq!(records[0][&3], 100);
+        assert_eq!(records[0][&4], 0xFF); // no cadence without a previous sample
+        assert_eq!(records[1][&4], 45); // 3 steps in 2 s = 90 steps/min
+        assert_eq!(records[0][&9] as u16 as i16, -150);
+        assert_eq!(records[3][&3], 0xFF);
+        // Distance (cm) and calories keep counting across laps
+        let distances: Vec<u64> = records.iter().map(|r| r[&5]).collect();
+        assert_eq!(distances, [200, 400, 600, 800, 1000]);
+        assert_eq!(records[4][&33], 5);
+
+        let laps = of_type(&messages, 19);
+        assert_eq!(laps.len(), 2);
+        assert_eq!(laps[0][&2], fit_start);
+        assert_eq!(laps[0][&7], 4000); // ms from first to last sample
+        assert_eq!(laps[0][&9], 600);
+        assert_eq!(laps[0][&11], 3);
+        assert_eq!(laps[0][&15], 100);
+        assert_eq!(laps[1][&15], 0xFF);
+        assert_eq!(laps[1][&254], 1);
+        assert_eq!(laps[1][&25], SPORT_WALKING);
+        assert_eq!(laps[1][&39], SUB_SPORT_TREADMILL);
+
+        let session = &of_type(&messages, 18)[0];
+        assert_eq!(session[&5], SPORT_WALKING);
+        assert_eq!(session[&6], SUB_SPORT_TREADMILL);
+        assert_eq!(session[&7], 3602 * 1000);
+        assert_eq!(session[&8], 6 * 1000); // timer stopped between laps
+        assert_eq!(session[&9], 1000);
+        assert_eq!(session[&11], 5);
+        assert_eq!(session[&14], 1250);
+        assert_eq!(session[&26], 2);
+
+        let events = of_type(&messages, 21);
+        let event_types: Vec<u64> = events.iter().map(|e| e[&1]).collect();
+        assert_eq!(
+            event_types,
+            [
+                EVENT_TYPE_START,
+                EVENT_TYPE_STOP_ALL,
+                EVENT_TYPE_START,
+                EVENT_TYPE_STOP_ALL
+            ]
+        );
+        let activity = &of_type(&messages, 34)[0];
+        assert_eq!(activity[&0], 6 * 1000);
+        assert_eq!(activity[&1], 1);
+    }
+
+    #[test]
+    fn test_empty_workout_is_a_valid_file() {
+        let workout = Workout::new("Nothing", Vec::new(), CaloriesSource::Device);
+        assert!(decode(&encode(&workout)).is_empty());
+    }
+}
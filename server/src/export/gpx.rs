@@ -0,0 +1,133 @@
+//! GPX 1.1 tracks.
+//!
+//! Treadmills don't move, but apps that import GPX compute distance from the
+//! positions, so each sample is placed on a synthetic indoor track: a
+//! standard 400 m oval around a chosen point, at the distance walked so far.
+//! Each session is a track segment; heart rate uses Garmin's extension.
+
+use std::f64::consts::PI;
+use std::fmt::Write;
+
+use super::{utc_time, xml_escape, Workout};
+
+/// Length of one lap of the oval
+const TRACK_LENGTH: f64 = 400.0;
+
+/// Each of the oval's two straights; the bends make up the rest
+const TRACK_STRAIGHT: f64 = 84.39;
+
+/// Mean Earth radius, for turning metres into degrees
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+/// Where the synthetic track is centred
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TrackOrigin {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl TrackOrigin {
+    /// Latitude and longitude `distance` metres along the oval, starting at
+    /// the beginning of the southern straight and running anticlockwise
+    pub fn position(&self, distance: f64) -> (f64, f64) {
+        let (x, y) = oval_point(distance.rem_euclid(TRACK_LENGTH));
+        let lat = self.lat + (y / EARTH_RADIUS).to_degrees();
+        let lon = self.lon + (x / (EARTH_RADIUS * self.lat.to_radians().cos())).to_degrees();
+        (lat, lon)
+    }
+}
+
+/// Metres east and north of the oval's centre, `s` metres into a lap
+fn oval_point(s: f64) -> (f64, f64) {
+    let radius = (TRACK_LENGTH - 2.0 * TRACK_STRAIGHT) / (2.0 * PI);
+    let half = TRACK_STRAIGHT / 2.0;
+    let bend = PI * radius;
+
+    if s < TRACK_STRAIGHT {
+        (-half + s, -radius)
+    } else if s < TRACK_STRAIGHT + bend {
+        let angle = -PI / 2.0 + (s - TRACK_STRAIGHT) / radius;
+        (half + radius * angle.cos(), radius * angle.sin())
+    } else if s < 2.0 * TRACK_STRAIGHT + bend {
+        (half - (s - TRACK_STRAIGHT - bend), radius)
+    } else {
+        let angle = PI / 2.0 + (s - 2.0 * TRACK_STRAIGHT - bend) / radius;
+        (-half + radius * angle.cos(), radius * angle.sin())
+    }
+}
+
+/// The workout as a GPX document
+pub fn encode(workout: &Workout, origin: TrackOrigin) -> String {
+    let mut xml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="WalkPad Sync Server" xmlns="http://www.topografix.com/GPX/1/1" xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v1">
+"#,
+    );
+    if let Some(start) = workout.start() {
+        let _ = writeln!(
+            xml,
+            "  <metadata><name>{}</name><time>{}</time></metadata>",
+            xml_escape(&workout.name),
+            utc_time(start)
+        );
+    }
+
+    xml.push_str("  <trk>\n");
+    let _ = writeln!(xml, "    <name>{}</name>", xml_escape(&workout.name));
+    xml.push_str("    <type>walking</type>\n");
+
+    let mut distance = 0;
+    for lap in &workout.laps {
+        xml.push_str("    <trkseg>\n");
+        for sample in &lap.samples {
+            distance += sample.distance_delta.unwrap_or(0).max(0);
+            let (lat, lon) = origin.position(distance as f64);
+            let _ = writeln!(xml, "      <trkpt lat=\"{:.7}\" lon=\"{:.7}\">", lat, lon);
+            let _ = writeln!(xml, "        <time>{}</time>", utc_time(sample.timestamp));
+            if let Some(bpm) = sample.heart_rate {
+                let _ = writeln!(
+                    xml,
+                    "        <extensions><gpxtpx:TrackPointExtension><gpxtpx:hr>{}</gpxtpx:hr></gpxtpx:TrackPointExtension></extensions>",
+                    bpm
+                );
+            }
+            xml.push_str("      </trkpt>\n");
+        }
+        xml.push_str("    </trkseg>\n");
+    }
+    xml.push_str("  </trk>\n");
+    xml.push_str("</gpx>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meters_between(a: (f64, f64), b: (f64, f64)) -> f64 {
+        let dy = (b.0 - a.0).to_radians() * EARTH_RADIUS;
+        let dx = (b.1 - a.1).to_radians() * EARTH_RADIUS * a.0.to_radians().cos();
+        dx.hypot(dy)
+    }
+
+    #[test]
+    fn test_track_distance_matches_distance_walked() {
+        let origin = TrackOrigin {
+            lat: 51.5,
+            lon: -0.12,
+        };
+
+        // Walking the positions metre by metre covers the distance, and a lap
+        // comes back to the start
+        let mut walked = 0.0;
+        let mut last = origin.position(0.0);
+        for d in 1..=1000 {
+            let next = origin.position(d as f64);
+            walked += meters_between(last, next);
+            last = next;
+        }
+        assert!((walked - 1000.0).abs() < 1.0, "walked {}", walked);
+        assert!(meters_between(origin.position(0.0), origin.position(400.0)) < 0.01);
+        assert!(meters_between(origin.position(0.0), origin.position(200.0)) > 70.0);
+    }
+}
@@ -0,0 +1,274 @@
+//! Exports for spreadsheets and other apps.
+//!
+//! CSV rows are encoded one at a time so the API can stream them straight
+//! from the database. Figures are converted to the requested unit system and
+//! each column header names its unit (`speed_kmh`, `distance_mi`, ...).
+//!
+//! Workout files (`fit`, `tcx`, `gpx`) describe a [`Workout`]: one or more walking
+//! sessions, each of which becomes a lap or track segment.
+
+pub mod fit;
+pub mod gpx;
+pub mod tcx;
+
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::energy::CaloriesSource;
+use crate::records::METERS_PER_MILE;
+use crate::sessions::Session;
+use crate::storage::{DailySummary, TreadmillSample};
+use crate::timezone::Zone;
+
+const METERS_PER_FOOT: f64 = 0.3048;
+
+/// Unit system for exported distances and speeds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    /// km/h, with metres per sample and kilometres per day
+    #[default]
+    Metric,
+    /// mph, with feet per sample and miles per day
+    Imperial,
+}
+
+impl Units {
+    fn speed(self, meters_per_second: f64) -> f64 {
+        match self {
+            Units::Metric => meters_per_second * 3.6,
+            Units::Imperial => meters_per_second * 3600.0 / METERS_PER_MILE,
+        }
+    }
+
+    fn speed_column(self) -> &'static str {
+        match self {
+            Units::Metric => "kmh",
+            Units::Imperial => "mph",
+        }
+    }
+
+    /// Short distances, as walked between two samples
+    fn step_distance(self, meters: f64) -> f64 {
+        match self {
+            Units::Metric => meters,
+            Units::Imperial => meters / METERS_PER_FOOT,
+        }
+    }
+
+    fn step_distance_column(self) -> &'static str {
+        match self {
+            Units::Metric => "m",
+            Units::Imperial => "ft",
+        }
+    }
+
+    /// Long distances, as walked in a day
+    fn distance(self, meters: f64) -> f64 {
+        match self {
+            Units::Metric => meters / 1000.0,
+            Units::Imperial => meters / METERS_PER_MILE,
+        }
+    }
+
+    fn distance_column(self) -> &'static str {
+        match self {
+            Units::Metric => "km",
+            Units::Imperial => "mi",
+        }
+    }
+}
+
+/// Header for `sample_row`
+pub fn sample_header(units: Units) -> Result<Vec<u8>> {
+    record([
+        "time".to_string(),
+        "timestamp".to_string(),
+        format!("speed_{}", units.speed_column()),
+        format!("distance_{}", units.step_distance_column()),
+        "steps".to_string(),
+        "calories".to_string(),
+        "calories_estimated".to_string(),
+        "incline_percent".to_string(),
+        "heart_rate_bpm".to_string(),
+        "user_id".to_string(),
+    ])
+}
+
+/// One sample, with its local time in `zone` (RFC 3339, including the UTC
+/// offset so DST changes stay unambiguous) and counters as per-sample deltas
+pub fn sample_row(sample: &TreadmillSample, zone: &Zone, units: Units) -> Result<Vec<u8>> {
+    record([
+        zone.local_datetime(sample.timestamp).to_rfc3339(),
+        sample.timestamp.to_string(),
+        optional(sample.speed.map(|s| round(units.speed(s), 2))),
+        optional(
+            sample
+                .distance_delta
+                .map(|d| round(units.step_distance(d as f64), 2)),
+        ),
+        optional(sample.steps_delta),
+        optional(sample.calories_delta),
+        optional(sample.calories_estimated.map(|c| round(c, 3))),
+        optional(sample.incline),
+        optional(sample.heart_rate),
+        optional(sample.user_id),
+    ])
+}
+
+/// Header for `summary_row`
+pub fn summary_header(units: Units) -> Result<Vec<u8>> {
+    record([
+        "date".to_string(),
+        format!("distance_{}", units.distance_column()),
+        "steps".to_string(),
+        "calories".to_string(),
+        "calories_source".to_string(),
+        "duration_seconds".to_string(),
+        format!("avg_speed_{}", units.speed_column()),
+        format!("max_speed_{}", units.speed_column()),
+        "samples".to_string(),
+        "corrected".to_string(),
+    ])
+}
+
+/// One local day
+pub fn summary_row(summary: &DailySummary, units: Units) -> Result<Vec<u8>> {
+    let calories_source = match summary.calories_source {
+        CaloriesSource::Device => "device",
+        CaloriesSource::Estimated => "estimated",
+    };
+    record([
+        summary.date.clone(),
+        round(units.distance(summary.distance_meters as f64), 3).to_string(),
+        summary.steps.to_string(),
+        summary.calories.to_string(),
+        calories_source.to_string(),
+        summary.duration_seconds.to_string(),
+        round(units.speed(summary.avg_speed), 2).to_string(),
+        round(units.speed(summary.max_speed), 2).to_string(),
+        summary.total_samples.to_string(),
+        summary.corrected.to_string(),
+    ])
+}
+
+/// Walking sessions with their samples, for workout files
+#[derive(Debug, Clone)]
+pub struct Workout {
+    pub name: String,
+    pub laps: Vec<Lap>,
+    pub calories_source: CaloriesSource,
+}
+
+/// One session of a workout
+#[derive(Debug, Clone)]
+pub struct Lap {
+    pub session: Session,
+    pub samples: Vec<TreadmillSample>, // moving samples, oldest first
+}
+
+impl Workout {
+    pub fn new(
+        name: impl Into<String>,
+        sessions: Vec<(Session, Vec<TreadmillSample>)>,
+        calories_source: CaloriesSource,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            laps: sessions
+                .into_iter()
+                .map(|(session, samples)| Lap { session, samples })
+                .collect(),
+            calories_source,
+        }
+    }
+
+    /// Timestamp of the first sample
+    pub fn start(&self) -> Option<i64> {
+        self.laps.first().map(|lap| lap.session.start)
+    }
+}
+
+impl Lap {
+    pub fn calories(&self, source: CaloriesSource) -> f64 {
+        match source {
+            CaloriesSource::Device => self.session.calories as f64,
+            CaloriesSource::Estimated => self.session.calories_estimated,
+        }
+    }
+
+    /// Average and maximum heart rate, if any sample has one
+    pub fn heart_rate(&self) -> Option<(i64, i64)> {
+        let rates: Vec<i64> = self.samples.iter().filter_map(|s| s.heart_rate).collect();
+        let max = *rates.iter().max()?;
+        let avg = rates.iter().sum::<i64>() as f64 / rates.len() as f64;
+        Some((avg.round() as i64, max))
+    }
+}
+
+/// A Unix timestamp as UTC in RFC 3339 (`2025-01-15T08:00:00Z`)
+fn utc_time(timestamp: i64) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Text for an XML element or attribute
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// A CSV line, quoted where needed
+fn record<const N: usize>(fields: [String; N]) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(fields)?;
+    Ok(writer.into_inner()?)
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn round(value: f64, decimals: i32) -> f64 {
+    let scale = 10f64.powi(decimals);
+    (value * scale).round() / scale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_support::test_sample;
+
+    #[test]
+    fn test_csv_rows_convert_units_and_zone() {
+        let sample = TreadmillSample {
+            distance_total: Some(500),
+            incline: Some(1.5),
+            ..test_sample(1736953200) // 2025-01-15 15:00 UTC
+        };
+        let zone: Zone = "America/Los_Angeles".parse().unwrap();
+
+        let header = sample_header(Units::Imperial).unwrap();
+        assert_eq!(
+            String::from_utf8(header).unwrap(),
+            "time,timestamp,speed_mph,distance_ft,steps,calories,calories_estimated,\
+             incline_percent,heart_rate_bpm,user_id\n"
+        );
+        let row = sample_row(&sample, &zone, Units::Imperial).unwrap();
+        assert_eq!(
+            String::from_utf8(row).unwrap(),
+            "2025-01-15T07:00:00-08:00,1736953200,2.24,3.28,2,0,,1.5,,\n"
+        );
+
+        let row = sample_row(&sample, &Zone::default(), Units::Metric).unwrap();
+        assert!(String::from_utf8(row)
+            .unwrap()
+            .starts_with("2025-01-15T15:00:00+00:00,1736953200,3.6,1,"));
+    }
+}
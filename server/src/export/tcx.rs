@@ -0,0 +1,168 @@
+//! Garmin Training Center (TCX) files.
+//!
+//! One activity with a lap per session. Laps carry their active time,
+//! distance, calories, maximum speed, heart rate and steps; trackpoints carry
+//! the distance walked since the start of the activity, speed and heart rate.
+//! TCX has no walking sport, so activities are `Other`.
+
+use std::fmt::Write;
+
+use super::{utc_time, xml_escape, Lap, Workout};
+
+const HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2" xmlns:ns3="http://www.garmin.com/xmlschemas/ActivityExtension/v2">
+"#;
+
+/// The workout as a TCX document. Elements follow the order the schema requires.
+pub fn encode(workout: &Workout) -> String {
+    let mut xml = String::from(HEADER);
+    xml.push_str("  <Activities>\n");
+    if let Some(start) = workout.start() {
+        xml.push_str("    <Activity Sport=\"Other\">\n");
+        let _ = writeln!(xml, "      <Id>{}</Id>", utc_time(start));
+
+        let mut distance = 0;
+        for lap in &workout.laps {
+            encode_lap(&mut xml, workout, lap, &mut distance);
+        }
+
+        let _ = writeln!(xml, "      <Notes>{}</Notes>", xml_escape(&workout.name));
+        xml.push_str("    </Activity>\n");
+    }
+    xml.push_str("  </Activities>\n");
+    xml.push_str("</TrainingCenterDatabase>\n");
+    xml
+}
+
+/// `distance` is the activity's running total in metres, carried across laps
+fn encode_lap(xml: &mut String, workout: &Workout, lap: &Lap, distance: &mut i64) {
+    let session = &lap.session;
+    let _ = writeln!(xml, "      <Lap StartTime=\"{}\">", utc_time(session.start));
+    let _ = writeln!(
+        xml,
+        "        <TotalTimeSeconds>{}</TotalTimeSeconds>",
+        session.duration_seconds
+    );
+    let _ = writeln!(
+        xml,
+        "        <DistanceMeters>{}</DistanceMeters>",
+        session.distance_meters
+    );
+    let _ = writeln!(
+        xml,
+        "        <MaximumSpeed>{:.3}</MaximumSpeed>",
+        session.max_speed
+    );
+    let calories = lap.calories(workout.calories_source).round().max(0.0) as u16;
+    let _ = writeln!(xml, "        <Calories>{}</Calories>", calories);
+    if let Some((avg, max)) = lap.heart_rate() {
+        let _ = writeln!(
+            xml,
+            "        <AverageHeartRateBpm><Value>{}</Value></AverageHeartRateBpm>",
+            avg
+        );
+        let _ = writeln!(
+            xml,
+            "        <MaximumHeartRateBpm><Value>{}</Value></MaximumHeartRateBpm>",
+            max
+        );
+    }
+    xml.push_str("        <Intensity>Active</Intensity>\n");
+    xml.push_str("        <TriggerMethod>Manual</TriggerMethod>\n");
+
+    xml.push_str("        <Track>\n");
+    for sample in &lap.samples {
+        *distance += sample.distance_delta.unwrap_or(0).max(0);
+        xml.push_str("          <Trackpoint>\n");
+        let _ = writeln!(
+            xml,
+            "            <Time>{}</Time>",
+            utc_time(sample.timestamp)
+        );
+        let _ = writeln!(
+            xml,
+            "            <DistanceMeters>{}</DistanceMeters>",
+            distance
+        );
+        if let Some(bpm) = sample.heart_rate {
+            let _ = writeln!(
+                xml,
+                "            <HeartRateBpm><Value>{}</Value></HeartRateBpm>",
+                bpm
+            );
+        }
+        if let Some(speed) = sample.speed {
+            let _ = writeln!(
+                xml,
+                "            <Extensions><ns3:TPX><ns3:Speed>{:.3}</ns3:Speed></ns3:TPX></Extensions>",
+                speed
+            );
+        }
+        xml.push_str("          </Trackpoint>\n");
+    }
+    xml.push_str("        </Track>\n");
+
+    let _ = writeln!(
+        xml,
+        "        <Extensions><ns3:LX><ns3:AvgSpeed>{:.3}</ns3:AvgSpeed><ns3:Steps>{}</ns3:Steps></ns3:LX></Extensions>",
+        session.avg_speed,
+        session.steps.clamp(0, u16::MAX.into())
+    );
+    xml.push_str("      </Lap>\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::CaloriesSource;
+    use crate::sessions::split_with_samples;
+    use crate::storage::test_support::test_sample;
+    use crate::storage::TreadmillSample;
+
+    fn sample(timestamp: i64, heart_rate: Option<i64>) -> TreadmillSample {
+        TreadmillSample {
+            speed: Some(1.25),
+            distance_delta: Some(5),
+            calories_delta: Some(1),
+            steps_delta: Some(8),
+            heart_rate,
+            calories_estimated: Some(0.5),
+            ..test_sample(timestamp)
+        }
+    }
+
+    #[test]
+    fn test_laps_and_cumulative_distance() {
+        // Two sessions an hour apart, 2025-01-15 08:00 UTC
+        let start = 1736928000;
+        let samples = vec![
+            sample(start, Some(100)),
+            sample(start + 4, Some(110)),
+            sample(start + 3600, None),
+            sample(start + 3604, None),
+        ];
+        let workout = Workout::new(
+            "Walk & talk",
+            split_with_samples(samples, None),
+            CaloriesSource::Estimated,
+        );
+        let xml = encode(&workout);
+
+        assert_eq!(xml.matches("<Lap StartTime=").count(), 2);
+        assert!(xml.contains("<Id>2025-01-15T08:00:00Z</Id>"));
+        assert!(xml.contains("<Lap StartTime=\"2025-01-15T09:00:00Z\">"));
+        assert!(xml.contains("<AverageHeartRateBpm><Value>105</Value></AverageHeartRateBpm>"));
+        assert_eq!(xml.matches("<AverageHeartRateBpm>").count(), 1);
+        assert!(xml.contains("<Calories>1</Calories>"));
+        assert!(xml.contains("<ns3:Speed>1.250</ns3:Speed>"));
+        assert!(xml.contains("<ns3:Steps>16</ns3:Steps>"));
+        assert!(xml.contains("<Notes>Walk &amp; talk</Notes>"));
+
+        // Trackpoint distances keep counting across laps
+        let distances: Vec<&str> = xml
+            .match_indices("<DistanceMeters>")
+            .map(|(i, _)| &xml[i + 16..i + xml[i..].find("</").unwrap()])
+            .collect();
+        assert_eq!(distances, ["10", "5", "10", "10", "15", "20"]);
+    }
+}
@@ -0,0 +1,620 @@
+//! Daily and weekly goals: progress, streaks, and live "goal reached" events.
+//!
+//! A goal targets one metric over a local day or a week (Monday to Sunday).
+//! Goals with a `user_id` measure that user's walking; goals without one
+//! measure everyone's, matching how summaries are filtered.
+
+use anyhow::Result;
+use chrono::{Duration, NaiveDate, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::collections::{hash_map::Entry, BTreeSet, HashMap, HashSet};
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::energy::{CaloriesSource, MAX_SAMPLE_GAP_SECS};
+use crate::storage::{DailySummary, Goal, Storage};
+use crate::timezone::Zone;
+use crate::websocket::{WsMessage, WsSample};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum GoalMetric {
+    Steps,
+    DistanceMeters,
+    ActiveMinutes,
+    Calories,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum GoalPeriod {
+    Daily,
+    Weekly,
+}
+
+impl GoalPeriod {
+    /// First day of the period containing `date`
+    pub fn start_of(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            GoalPeriod::Daily => date,
+            GoalPeriod::Weekly => date.week(Weekday::Mon).first_day(),
+        }
+    }
+
+    fn days(self) -> i64 {
+        match self {
+            GoalPeriod::Daily => 1,
+            GoalPeriod::Weekly => 7,
+        }
+    }
+}
+
+/// Totals for every goal metric
+#[derive(Debug, Clone, Copy, Default)]
+struct Amounts {
+    steps: f64,
+    distance_meters: f64,
+    active_seconds: f64,
+    calories: f64,
+}
+
+impl Amounts {
+    fn from_summary(s: &DailySummary) -> Self {
+        Self {
+            steps: s.steps as f64,
+            distance_meters: s.distance_meters as f64,
+            active_seconds: s.duration_seconds as f64,
+            calories: s.calories as f64,
+        }
+    }
+
+    fn add(&mut self, other: &Amounts) {
+        self.steps += other.steps;
+        self.distance_meters += other.distance_meters;
+        self.active_seconds += other.active_seconds;
+        self.calories += other.calories;
+    }
+
+    fn value(&self, metric: GoalMetric) -> f64 {
+        match metric {
+            GoalMetric::Steps => self.steps,
+            GoalMetric::DistanceMeters => self.distance_meters,
+            GoalMetric::ActiveMinutes => (self.active_seconds / 60.0).floor(),
+            GoalMetric::Calories => self.calories,
+        }
+    }
+}
+
+/// How far along a goal is on a given day
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct GoalProgress {
+    pub goal_id: i64,
+    pub metric: GoalMetric,
+    pub period: GoalPeriod,
+    pub target: f64,
+    /// The day's value, or the week-to-date value for weekly goals
+    pub value: f64,
+    pub percent: f64,
+    pub reached: bool,
+}
+
+impl GoalProgress {
+    fn new(goal: &Goal, value: f64) -> Self {
+        Self {
+            goal_id: goal.id,
+            metric: goal.metric,
+            period: goal.period,
+            target: goal.target,
+            value,
+            percent: (value / goal.target * 100.0).min(100.0),
+            reached: value >= goal.target,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Streak {
+    pub goal_id: i64,
+    pub metric: GoalMetric,
+    pub period: GoalPeriod,
+    pub target: f64,
+    /// Consecutive periods reached, ending with the current one (or the one
+    /// before it, while the current period is still in progress)
+    pub current: u32,
+    pub longest: u32,
+    /// Start date of the most recent period that reached the goal
+    pub last_reached: Option<String>,
+}
+
+/// Fill in `goals` on time-ordered or unordered summaries. Weekly goals report
+/// the week-to-date total as of each day, so `summaries` should hold every
+/// day of the weeks involved (as `get_all_daily_summaries` does).
+pub fn attach_progress(goals: &[Goal], summaries: &mut [DailySummary]) {
+    if goals.is_empty() {
+        return;
+    }
+
+    let mut days: Vec<(NaiveDate, Amounts)> = summaries
+        .iter()
+        .filter_map(|s| Some((parse_date(&s.date)?, Amounts::from_summary(s))))
+        .collect();
+    days.sort_by_key(|(date, _)| *date);
+
+    // Week-to-date totals for every day
+    let mut week_to_date: HashMap<NaiveDate, Amounts> = HashMap::new();
+    let mut running = Amounts::default();
+    let mut week = None;
+    for (date, amounts) in &days {
+        let start = GoalPeriod::Weekly.start_of(*date);
+        if week != Some(start) {
+            running = Amounts::default();
+            week = Some(start);
+        }
+        running.add(amounts);
+        week_to_date.insert(*date, running);
+    }
+
+    for summary in summaries.iter_mut() {
+        let Some(date) = parse_date(&summary.date) else {
+            continue;
+        };
+        let day = Amounts::from_summary(summary);
+        let week = week_to_date.get(&date).copied().unwrap_or(day);
+        summary.goals = goals
+            .iter()
+            .map(|goal| {
+                let amounts = match goal.period {
+                    GoalPeriod::Daily => &day,
+                    GoalPeriod::Weekly => &week,
+                };
+                GoalProgress::new(goal, amounts.value(goal.metric))
+            })
+            .collect();
+    }
+}
+
+/// Fill in `goals` on a single day's summary, looking up earlier days of the
+/// week for weekly goals
+pub async fn attach_progress_for_date(
+    storage: &Storage,
+    goals: &[Goal],
+    summary: &mut DailySummary,
+    zone: &Zone,
+    user_id: Option<i64>,
+    source: CaloriesSource,
+) -> Result<()> {
+    let Some(date) = parse_date(&summary.date) else {
+        return Ok(());
+    };
+
+    let mut week = vec![summary.clone()];
+    if goals.iter().any(|g| g.period == GoalPeriod::Weekly) {
+        let mut day = GoalPeriod::Weekly.start_of(date);
+        while day < date {
+            if let Some(mut s) = storage.get_daily_summary(day, zone, user_id).await? {
+                s.report_calories(source);
+                week.push(s);
+            }
+            day += Duration::days(1);
+        }
+    }
+
+    attach_progress(goals, &mut week);
+    summary.goals = std::mem::take(&mut week[0].goals);
+    Ok(())
+}
+
+/// Current and longest streaks for each goal
+pub async fn streaks(
+    storage: &Storage,
+    goals: &[Goal],
+    zone: &Zone,
+    source: CaloriesSource,
+) -> Result<Vec<Streak>> {
+    let today = zone.local_date(Utc::now().timestamp());
+    let mut history: HashMap<Option<i64>, Vec<DailySummary>> = HashMap::new();
+    let mut streaks = Vec::with_capacity(goals.len());
+
+    for goal in goals {
+        if let Entry::Vacant(entry) = history.entry(goal.user_id) {
+            let mut summaries = storage.get_all_daily_summaries(zone, goal.user_id).await?;
+            summaries.iter_mut().for_each(|s| s.report_calories(source));
+            entry.insert(summaries);
+        }
+
+        // Totals per period, then the periods that reached the target
+        let mut periods: HashMap<NaiveDate, Amounts> = HashMap::new();
+        for summary in &history[&goal.user_id] {
+            if let Some(date) = parse_date(&summary.date) {
+                periods
+                    .entry(goal.period.start_of(date))
+                    .or_default()
+                    .add(&Amounts::from_summary(summary));
+            }
+        }
+        let reached: BTreeSet<NaiveDate> = periods
+            .into_iter()
+            .filter(|(_, amounts)| amounts.value(goal.metric) >= goal.target)
+            .map(|(start, _)| start)
+            .collect();
+
+        let (current, longest) =
+            count_streaks(&reached, goal.period.start_of(today), goal.period.days());
+        streaks.push(Streak {
+            goal_id: goal.id,
+            metric: goal.metric,
+            period: goal.period,
+            target: goal.target,
+            current,
+            longest,
+            last_reached: reached.last().map(|d| d.format("%Y-%m-%d").to_string()),
+        });
+    }
+
+    Ok(streaks)
+}
+
+/// (current, longest) runs of consecutive periods in `reached`, each
+/// `step_days` apart. The current run may end at `current_period` or, if
+/// that period hasn't reached the goal yet, the one before it.
+fn count_streaks(
+    reached: &BTreeSet<NaiveDate>,
+    current_period: NaiveDate,
+    step_days: i64,
+) -> (u32, u32) {
+    let step = Duration::days(step_days);
+
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for &period in reached {
+        run = match previous {
+            Some(p) if p + step == period => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        previous = Some(period);
+    }
+
+    let mut period = if reached.contains(&current_period) {
+        current_period
+    } else {
+        current_period - step
+    };
+    let mut current = 0;
+    while reached.contains(&period) {
+        current += 1;
+        period -= step;
+    }
+
+    (current, longest)
+}
+
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+/// Live totals for one goal scope (a user, or everyone) on one local day
+struct LiveScope {
+    date: NaiveDate,
+    day: Amounts,
+    week: Amounts,
+    last_timestamp: i64,
+    /// Goals already reached this period (announced, or met before tracking started)
+    reached: HashSet<i64>,
+}
+
+/// Watch live samples and announce goals as they're crossed.
+///
+/// Totals for today and the week so far are loaded from storage the first
+/// time a scope sees a sample each day, then advanced from each sample.
+/// Goals already met at that point (e.g. after a restart) aren't announced again.
+pub async fn run_live(
+    storage: std::sync::Arc<Storage>,
+    ws_tx: broadcast::Sender<WsMessage>,
+    zone: Zone,
+    source: CaloriesSource,
+) {
+    let mut rx = ws_tx.subscribe();
+    let mut scopes: HashMap<Option<i64>, LiveScope> = HashMap::new();
+
+    loop {
+        let sample = match rx.recv().await {
+            Ok(WsMessage::NewSample { sample }) => sample,
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("Goal tracker lagged behind by {} messages", n);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+
+        let goals = match storage.get_goals(None).await {
+            Ok(goals) => goals,
+            Err(e) => {
+                error!("Failed to load goals: {}", e);
+                continue;
+            }
+        };
+        if goals.is_empty() {
+            continue;
+        }
+
+        // Goals without a user count everyone's samples
+        let mut affected = vec![None];
+        if sample.user_id.is_some() {
+            affected.push(sample.user_id);
+        }
+
+        for scope in affected {
+            let scope_goals: Vec<&Goal> = goals.iter().filter(|g| g.user_id == scope).collect();
+            if scope_goals.is_empty() {
+                continue;
+            }
+            if let Err(e) = track_sample(
+                &storage,
+                &ws_tx,
+                &zone,
+                source,
+                &mut scopes,
+                scope,
+                &scope_goals,
+                &sample,
+            )
+            .await
+            {
+                error!("Failed to update goal progress: {}", e);
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn track_sample(
+    storage: &Storage,
+    ws_tx: &broadcast::Sender<WsMessage>,
+    zone: &Zone,
+    source: CaloriesSource,
+    scopes: &mut HashMap<Option<i64>, LiveScope>,
+    scope: Option<i64>,
+    goals: &[&Goal],
+    sample: &WsSample,
+) -> Result<()> {
+    let date = zone.local_date(sample.timestamp);
+
+    if scopes.get(&scope).is_none_or(|state| state.date != date) {
+        // New day (or first sample): the stored totals include this sample,
+        // unless it's still queued for writing
+        let state = load_scope(storage, zone, source, scope, date, sample.timestamp).await?;
+        let reached = goals
+            .iter()
+            .filter(|g| state.value(g) >= g.target)
+            .map(|g| g.id)
+            .collect();
+        scopes.insert(scope, LiveScope { reached, ..state });
+        return Ok(());
+    }
+    let Some(state) = scopes.get_mut(&scope) else {
+        return Ok(());
+    };
+
+    // Advance from this sample
+    let gap = sample.timestamp - state.last_timestamp;
+    let delta = Amounts {
+        steps: sample.steps_delta.unwrap_or(0) as f64,
+        distance_meters: sample.distance_delta.unwrap_or(0) as f64,
+        active_seconds: if (1..=MAX_SAMPLE_GAP_SECS).contains(&gap) {
+            gap as f64
+        } else {
+            0.0
+        },
+        calories: match source {
+            CaloriesSource::Device => sample.calories_delta.unwrap_or(0) as f64,
+            CaloriesSource::Estimated => sample.calories_estimated.unwrap_or(0.0),
+        },
+    };
+    state.day.add(&delta);
+    state.week.add(&delta);
+    state.last_timestamp = sample.timestamp;
+
+    for goal in goals {
+        let value = state.value(goal);
+        if value >= goal.target && state.reached.insert(goal.id) {
+            info!(
+                "Goal {} reached: {:?} {:?} {} >= {}",
+                goal.id, goal.period, goal.metric, value, goal.target
+            );
+            let _ = ws_tx.send(WsMessage::GoalReached {
+                goal: (*goal).clone(),
+                value,
+                date: date.format("%Y-%m-%d").to_string(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+impl LiveScope {
+    fn value(&self, goal: &Goal) -> f64 {
+        match goal.period {
+            GoalPeriod::Daily => self.day.value(goal.metric),
+            GoalPeriod::Weekly => self.week.value(goal.metric),
+        }
+    }
+}
+
+async fn load_scope(
+    storage: &Storage,
+    zone: &Zone,
+    source: CaloriesSource,
+    user_id: Option<i64>,
+    date: NaiveDate,
+    timestamp: i64,
+) -> Result<LiveScope> {
+    let mut day = Amounts::default();
+    let mut week = Amounts::default();
+
+    let mut current = GoalPeriod::Weekly.start_of(date);
+    while current <= date {
+        if let Some(mut summary) = storage.get_daily_summary(current, zone, user_id).await? {
+            summary.report_calories(source);
+            let amounts = Amounts::from_summary(&summary);
+            week.add(&amounts);
+            if current == date {
+                day = amounts;
+            }
+        }
+        current += Duration::days(1);
+    }
+
+    Ok(LiveScope {
+        date,
+        day,
+        week,
+        last_timestamp: timestamp,
+        reached: HashSet::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_support::{test_sample, test_storage};
+
+    fn date(s: &str) -> NaiveDate {
+        parse_date(s).unwrap()
+    }
+
+    fn goal(id: i64, metric: GoalMetric, period: GoalPeriod, target: f64) -> Goal {
+        Goal {
+            id,
+            user_id: None,
+            metric,
+            period,
+            target,
+            created_at: 0,
+        }
+    }
+
+    fn summary(day: &str, steps: i64, duration_seconds: i64) -> DailySummary {
+        DailySummary {
+            date: day.to_string(),
+            total_samples: 1,
+            duration_seconds,
+            distance_meters: 0,
+            calories: 0,
+            calories_device: 0,
+            calories_estimated: 0,
+            calories_source: CaloriesSource::Device,
+            steps,
+            avg_speed: 0.0,
+            max_speed: 0.0,
+            corrected: false,
+            goals: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_progress_daily_and_week_to_date() {
+        let goals = [
+            goal(1, GoalMetric::Steps, GoalPeriod::Daily, 5000.0),
+            goal(2, GoalMetric::ActiveMinutes, GoalPeriod::Weekly, 60.0),
+        ];
+        // 2025-01-12 is a Sunday, 2025-01-13 a Monday
+        let mut summaries = vec![
+            summary("2025-01-14", 6000, 1800),
+            summary("2025-01-13", 3000, 1500),
+            summary("2025-01-12", 8000, 3600),
+        ];
+        attach_progress(&goals, &mut summaries);
+
+        let tuesday = &summaries[0].goals;
+        assert!(tuesday[0].reached);
+        assert_eq!(tuesday[1].value, 55.0); // 25 + 30 minutes this week
+        assert!(!tuesday[1].reached);
+
+        let monday = &summaries[1].goals;
+        assert_eq!(monday[0].percent, 60.0);
+        assert_eq!(monday[1].value, 25.0); // the Sunday belongs to last week
+
+        assert!(summaries[2].goals[1].reached);
+    }
+
+    #[tokio::test]
+    async fn test_live_goal_reached_once() {
+        let (_dir, storage) = test_storage().await;
+        let goal = storage
+            .set_goal(None, GoalMetric::Steps, GoalPeriod::Daily, 100.0)
+            .await
+            .unwrap();
+
+        let (ws_tx, mut rx) = broadcast::channel(16);
+        let mut scopes = HashMap::new();
+        let zone = Zone::default();
+
+        // 2025-01-15 10:00 UTC onwards, 60 steps per sample
+        for i in 0..3 {
+            let sample = crate::storage::TreadmillSample {
+                steps_delta: Some(60),
+                ..test_sample(1736935200 + i)
+            };
+            storage
+                .add_samples(std::slice::from_ref(&sample))
+                .await
+                .unwrap();
+            track_sample(
+                &storage,
+                &ws_tx,
+                &zone,
+                CaloriesSource::Device,
+                &mut scopes,
+                None,
+                &[&goal],
+                &WsSample::from(sample),
+            )
+            .await
+            .unwrap();
+        }
+
+        // Crossed on the second sample (120 steps), not announced again on the third
+        match rx.try_recv().unwrap() {
+            WsMessage::GoalReached { goal, value, date } => {
+                assert_eq!(goal.metric, GoalMetric::Steps);
+                assert_eq!(value, 120.0);
+                assert_eq!(date, "2025-01-15");
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_count_streaks() {
+        let reached: BTreeSet<NaiveDate> = [
+            "2025-01-01",
+            "2025-01-02",
+            "2025-01-03",
+            "2025-01-06",
+            "2025-01-07",
+        ]
+        .iter()
+        .map(|d| date(d))
+        .collect();
+
+        // Today not reached yet: the streak through yesterday still counts
+        assert_eq!(count_streaks(&reached, date("2025-01-08"), 1), (2, 3));
+        assert_eq!(count_streaks(&reached, date("2025-01-07"), 1), (2, 3));
+        // A missed day breaks it
+        assert_eq!(count_streaks(&reached, date("2025-01-09"), 1), (0, 3));
+
+        let weeks: BTreeSet<NaiveDate> = ["2024-12-23", "2024-12-30"]
+            .iter()
+            .map(|d| date(d))
+            .collect();
+        assert_eq!(count_streaks(&weeks, date("2025-01-06"), 7), (2, 2));
+    }
+}
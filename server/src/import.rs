@@ -0,0 +1,752 @@
+//! Historical data import from CSV and JSON exports.
+//!
+//! Each row carries a timestamp plus any of speed, distance, calories and
+//! steps (and optionally incline and heart rate). Counters can be cumulative (as the treadmill reports them) or
+//! per-row deltas; both are normalised into `TreadmillSample` rows with the
+//! same delta semantics the Bluetooth pipeline produces, so imported history
+//! is summarised exactly like live data.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config::EnergyConfig;
+use crate::energy::{self, MAX_SAMPLE_GAP_SECS};
+use crate::storage::{Storage, TreadmillSample};
+use crate::timezone::Zone;
+
+/// Fastest plausible treadmill speed (15 mph) - anything above is rejected
+const MAX_SPEED_MS: f64 = 6.7;
+
+/// Maximum number of row errors echoed back in the summary
+const MAX_REPORTED_ERRORS: usize = 100;
+
+// Accepted column names (compared case-insensitively)
+const TIMESTAMP_COLUMNS: &[&str] = &["timestamp", "time", "datetime", "date"];
+const SPEED_COLUMNS: &[&str] = &["speed"];
+const DISTANCE_COLUMNS: &[&str] = &["distance"];
+const CALORIES_COLUMNS: &[&str] = &["calories", "kcal", "energy"];
+const STEPS_COLUMNS: &[&str] = &["steps"];
+const INCLINE_COLUMNS: &[&str] = &["incline", "grade"];
+const HEART_RATE_COLUMNS: &[&str] = &["heart_rate", "heartrate", "hr", "bpm"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ImportFormat {
+    Csv,
+    Json,
+}
+
+impl ImportFormat {
+    /// Guess the format from a file name or MIME type
+    pub fn detect(hint: &str) -> Option<Self> {
+        let hint = hint.to_ascii_lowercase();
+        if hint.ends_with(".csv") || hint.contains("text/csv") {
+            Some(ImportFormat::Csv)
+        } else if hint.ends_with(".json") || hint.contains("application/json") {
+            Some(ImportFormat::Json)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether counter columns hold running totals or per-row increments
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum CounterMode {
+    #[default]
+    Cumulative,
+    Delta,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum SpeedUnit {
+    #[default]
+    MetersPerSecond,
+    KilometersPerHour,
+    MilesPerHour,
+}
+
+impl SpeedUnit {
+    fn to_meters_per_second(self, value: f64) -> f64 {
+        match self {
+            SpeedUnit::MetersPerSecond => value,
+            SpeedUnit::KilometersPerHour => value / 3.6,
+            SpeedUnit::MilesPerHour => value * 0.44704,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum DistanceUnit {
+    #[default]
+    Meters,
+    Kilometers,
+    Miles,
+}
+
+impl DistanceUnit {
+    fn to_meters(self, value: f64) -> f64 {
+        match self {
+            DistanceUnit::Meters => value,
+            DistanceUnit::Kilometers => value * 1000.0,
+            DistanceUnit::Miles => value * 1609.34,
+        }
+    }
+}
+
+macro_rules! impl_option_parsing {
+    ($ty:ty, $what:literal, { $($($name:literal)|+ => $variant:expr),+ $(,)? }) => {
+        impl FromStr for $ty {
+            type Err = anyhow::Error;
+
+            fn from_str(s: &str) -> Result<Self> {
+                match s.to_ascii_lowercase().as_str() {
+                    $($($name)|+ => Ok($variant),)+
+                    other => Err(anyhow!("Unknown {}: {}", $what, other)),
+                }
+            }
+        }
+
+        impl TryFrom<String> for $ty {
+            type Error = anyhow::Error;
+
+            fn try_from(s: String) -> Result<Self> {
+                s.parse()
+            }
+        }
+    };
+}
+
+impl_option_parsing!(ImportFormat, "import format", {
+    "csv" => ImportFormat::Csv,
+    "json" => ImportFormat::Json,
+});
+
+impl_option_parsing!(CounterMode, "counter mode", {
+    "cumulative" | "total" => CounterMode::Cumulative,
+    "delta" => CounterMode::Delta,
+});
+
+impl_option_parsing!(SpeedUnit, "speed unit", {
+    "mps" | "m/s" => SpeedUnit::MetersPerSecond,
+    "kmh" | "km/h" | "kph" => SpeedUnit::KilometersPerHour,
+    "mph" => SpeedUnit::MilesPerHour,
+});
+
+impl_option_parsing!(DistanceUnit, "distance unit", {
+    "m" | "meters" => DistanceUnit::Meters,
+    "km" | "kilometers" => DistanceUnit::Kilometers,
+    "mi" | "miles" => DistanceUnit::Miles,
+});
+
+/// How to interpret an import file
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportOptions {
+    /// `csv` or `json` (default: from the Content-Type)
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    pub format: Option<ImportFormat>,
+    /// `cumulative` or `delta`
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    pub mode: CounterMode,
+    /// `mps`, `kmh` or `mph`
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    pub speed_unit: SpeedUnit,
+    /// `m`, `km` or `mi`
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    pub distance_unit: DistanceUnit,
+    /// Offset applied to timestamps without an explicit zone (seconds east of UTC)
+    #[serde(default)]
+    pub tz_offset: i32,
+    /// IANA zone for timestamps without an explicit zone (overrides `tz_offset`)
+    #[serde(default)]
+    pub tz: Option<String>,
+    /// User the imported samples belong to (None = unassigned)
+    #[serde(default)]
+    pub user_id: Option<i64>,
+}
+
+/// Outcome of an import run
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ImportSummary {
+    pub total_rows: usize,
+    pub inserted: u64,
+    /// Rows whose timestamp already exists (in the database or earlier in the file)
+    pub duplicates: u64,
+    pub invalid: usize,
+    /// First few row-level problems (capped)
+    pub errors: Vec<ImportRowError>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ImportRowError {
+    pub row: usize,
+    pub message: String,
+}
+
+impl ImportSummary {
+    fn reject(&mut self, row: usize, message: String) {
+        self.invalid += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(ImportRowError { row, message });
+        }
+    }
+}
+
+/// Samples ready to insert, plus the summary so far
+#[derive(Debug)]
+pub struct ParsedImport {
+    pub samples: Vec<TreadmillSample>,
+    pub summary: ImportSummary,
+}
+
+/// A validated row in canonical units (meters, m/s, kcal)
+#[derive(Debug, Clone, PartialEq)]
+struct ImportRow {
+    row: usize, // as reported in errors
+    timestamp: i64,
+    speed: Option<f64>,
+    distance: Option<f64>,
+    calories: Option<f64>,
+    steps: Option<f64>,
+    incline: Option<f64>,
+    heart_rate: Option<i64>,
+}
+
+/// Parse and normalise an export file.
+///
+/// Fails only when the file as a whole is unreadable (bad JSON, no timestamp
+/// column); individual bad rows are counted as invalid and reported in the summary.
+pub fn parse(data: &[u8], options: &ImportOptions) -> Result<ParsedImport> {
+    let format = options
+        .format
+        .ok_or_else(|| anyhow!("Import format not specified (csv or json)"))?;
+
+    let raw_rows = match format {
+        ImportFormat::Csv => read_csv(data)?,
+        ImportFormat::Json => read_json(data)?,
+    };
+
+    // An offset of 0 is the default, not a fallback for an unknown zone name
+    let zone = Zone::resolve(
+        options.tz.as_deref(),
+        (options.tz_offset != 0).then_some(options.tz_offset),
+        Zone::default(),
+    )?;
+
+    let mut summary = ImportSummary {
+        total_rows: raw_rows.len(),
+        ..Default::default()
+    };
+
+    let mut rows = Vec::with_capacity(raw_rows.len());
+    for (row_number, fields) in raw_rows {
+        match validate_row(row_number, &fields, options, &zone) {
+            Ok(row) => rows.push(row),
+            Err(e) => summary.reject(row_number, e.to_string()),
+        }
+    }
+
+    // Keep the first row for each timestamp
+    rows.sort_by_key(|r| r.timestamp);
+    let before = rows.len();
+    rows.dedup_by_key(|r| r.timestamp);
+    summary.duplicates = (before - rows.len()) as u64;
+
+    let samples = normalise(&rows, options.mode, options.user_id, &mut summary);
+    summary.errors.sort_by_key(|e| e.row);
+
+    Ok(ParsedImport { samples, summary })
+}
+
+/// Parse an export file and insert every sample not already stored
+pub async fn import(
+    storage: &Storage,
+    data: &[u8],
+    options: &ImportOptions,
+    energy: &EnergyConfig,
+) -> Result<ImportSummary> {
+    insert(storage, parse(data, options)?, energy).await
+}
+
+/// Insert parsed samples, counting those already stored as duplicates, then
+/// estimate calories for them with their user's weight
+pub async fn insert(
+    storage: &Storage,
+    parsed: ParsedImport,
+    energy: &EnergyConfig,
+) -> Result<ImportSummary> {
+    let ParsedImport {
+        samples,
+        mut summary,
+    } = parsed;
+
+    let inserted = storage.insert_samples_if_absent(&samples).await?;
+    summary.inserted = inserted;
+    summary.duplicates += samples.len() as u64 - inserted;
+
+    // Samples are sorted. Live samples just after the import may now follow
+    // an imported one closely enough to be estimated differently too.
+    if let (Some(first), Some(last), true) = (samples.first(), samples.last(), inserted > 0) {
+        let range = (first.timestamp, last.timestamp + MAX_SAMPLE_GAP_SECS + 1);
+        energy::recalculate(storage, energy, Some(range), false).await?;
+    }
+
+    Ok(summary)
+}
+
+/// Read CSV into (row number, lowercase column -> value) pairs
+fn read_csv(data: &[u8]) -> Result<Vec<(usize, HashMap<String, String>)>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(data);
+
+    let headers: Vec<String> = reader
+        .headers()?
+        .iter()
+        .map(|h| h.to_ascii_lowercase())
+        .collect();
+    if find_field(&headers, TIMESTAMP_COLUMNS).is_none() {
+        return Err(anyhow!(
+            "CSV has no timestamp column (expected one of: {})",
+            TIMESTAMP_COLUMNS.join(", ")
+        ));
+    }
+
+    let mut rows = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        // Row numbers are 1-based and count the header line
+        let row_number = record
+            .as_ref()
+            .ok()
+            .and_then(|r| r.position())
+            .map(|p| p.line() as usize)
+            .unwrap_or(i + 2);
+        let record = match record {
+            Ok(r) => r,
+            Err(e) => {
+                rows.push((row_number, HashMap::from([("error".into(), e.to_string())])));
+                continue;
+            }
+        };
+
+        let fields = headers
+            .iter()
+            .cloned()
+            .zip(record.iter().map(str::to_string))
+            .filter(|(_, v)| !v.is_empty())
+            .collect();
+        rows.push((row_number, fields));
+    }
+
+    Ok(rows)
+}
+
+/// Read JSON (an array of objects, or `{"samples": [...]}`) into field maps
+fn read_json(data: &[u8]) -> Result<Vec<(usize, HashMap<String, String>)>> {
+    let value: serde_json::Value = serde_json::from_slice(data)?;
+    let items = match value {
+        serde_json::Value::Array(items) => items,
+        serde_json::Value::Object(mut obj) => match obj.remove("samples") {
+            Some(serde_json::Value::Array(items)) => items,
+            _ => return Err(anyhow!("JSON object must contain a \"samples\" array")),
+        },
+        _ => return Err(anyhow!("JSON must be an array of samples")),
+    };
+
+    let rows = items
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let fields = match item {
+                serde_json::Value::Object(obj) => obj
+                    .into_iter()
+                    .filter_map(|(k, v)| {
+                        let v = match v {
+                            serde_json::Value::String(s) => s,
+                            serde_json::Value::Number(n) => n.to_string(),
+                            _ => return None,
+                        };
+                        Some((k.to_ascii_lowercase(), v))
+                    })
+                    .collect(),
+                _ => HashMap::from([("error".into(), "Row is not an object".into())]),
+            };
+            (i + 1, fields)
+        })
+        .collect();
+
+    Ok(rows)
+}
+
+fn find_field<'a>(names: &'a [String], aliases: &[&str]) -> Option<&'a String> {
+    names.iter().find(|n| aliases.contains(&n.as_str()))
+}
+
+fn lookup<'a>(fields: &'a HashMap<String, String>, aliases: &[&str]) -> Option<&'a str> {
+    aliases
+        .iter()
+        .find_map(|a| fields.get(*a))
+        .map(String::as_str)
+}
+
+fn parse_metric(fields: &HashMap<String, String>, aliases: &[&str]) -> Result<Option<f64>> {
+    let Some(raw) = lookup(fields, aliases) else {
+        return Ok(None);
+    };
+    let value: f64 = raw
+        .parse()
+        .map_err(|_| anyhow!("Invalid {} value: {}", aliases[0], raw))?;
+    if !value.is_finite() || value < 0.0 {
+        return Err(anyhow!("{} must be a non-negative number", aliases[0]));
+    }
+    Ok(Some(value))
+}
+
+fn validate_row(
+    row_number: usize,
+    fields: &HashMap<String, String>,
+    options: &ImportOptions,
+    zone: &Zone,
+) -> Result<ImportRow> {
+    if let Some(e) = fields.get("error") {
+        return Err(anyhow!("{}", e));
+    }
+
+    let raw_timestamp =
+        lookup(fields, TIMESTAMP_COLUMNS).ok_or_else(|| anyhow!("Missing timestamp"))?;
+    let timestamp = parse_timestamp(raw_timestamp, zone)?;
+
+    let speed =
+        parse_metric(fields, SPEED_COLUMNS)?.map(|v| options.speed_unit.to_meters_per_second(v));
+    if let Some(s) = speed {
+        if s > MAX_SPEED_MS {
+            return Err(anyhow!("Speed {:.2} m/s is not plausible", s));
+        }
+    }
+
+    let row = ImportRow {
+        row: row_number,
+        timestamp,
+        speed,
+        distance: parse_metric(fields, DISTANCE_COLUMNS)?
+            .map(|v| options.distance_unit.to_meters(v)),
+        calories: parse_metric(fields, CALORIES_COLUMNS)?,
+        steps: parse_metric(fields, STEPS_COLUMNS)?,
+        incline: parse_metric(fields, INCLINE_COLUMNS)?,
+        heart_rate: parse_metric(fields, HEART_RATE_COLUMNS)?.map(|v| v.round() as i64),
+    };
+
+    if row.speed.is_none()
+        && row.distance.is_none()
+        && row.calories.is_none()
+        && row.steps.is_none()
+    {
+        return Err(anyhow!("Row has no speed, distance, calories or steps"));
+    }
+
+    Ok(row)
+}
+
+/// Accepts Unix seconds/milliseconds, RFC 3339, or a local date-time
+/// (`YYYY-MM-DD HH:MM:SS`) interpreted in `zone`.
+fn parse_timestamp(raw: &str, zone: &Zone) -> Result<i64> {
+    if let Ok(n) = raw.parse::<i64>() {
+        // Anything this large is milliseconds
+        return Ok(if n > 100_000_000_000 { n / 1000 } else { n });
+    }
+    if let Ok(n) = raw.parse::<f64>() {
+        return Ok(n as i64);
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(raw) {
+        return Ok(dt.timestamp());
+    }
+
+    const LOCAL_FORMATS: &[&str] = &[
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%m/%d/%Y %H:%M:%S",
+        "%m/%d/%Y %H:%M",
+    ];
+    LOCAL_FORMATS
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(raw, f).ok())
+        .map(|local| zone.timestamp_of(local))
+        .ok_or_else(|| anyhow!("Unrecognised timestamp: {}", raw))
+}
+
+/// Convert sorted, de-duplicated rows into samples with totals and deltas.
+/// Summaries only count moving samples, so a row that recorded movement
+/// without a speed (none given, and none derivable from distance) is
+/// rejected rather than stored where no total would include it.
+fn normalise(
+    rows: &[ImportRow],
+    mode: CounterMode,
+    user_id: Option<i64>,
+    summary: &mut ImportSummary,
+) -> Vec<TreadmillSample> {
+    let mut samples = Vec::with_capacity(rows.len());
+    let mut prev_timestamp: Option<i64> = None;
+    // Last value seen in each counter column, as the Bluetooth path tracks
+    // them, so a row missing a column doesn't hide what happened since
+    let mut last_distance: Option<(i64, f64)> = None; // (timestamp, meters)
+    let mut last_calories: Option<f64> = None;
+    let mut last_steps: Option<f64> = None;
+
+    for row in rows {
+        let (distance_total, distance_delta) =
+            counter(row.distance, last_distance.map(|(_, d)| d), mode);
+        let (calories_total, calories_delta) = counter(row.calories, last_calories, mode);
+        let (steps_total, steps_delta) = counter(row.steps, last_steps, mode);
+
+        // Derive speed from distance when the export doesn't include it, over
+        // the time the distance delta covers
+        let since = match mode {
+            CounterMode::Cumulative => last_distance.map(|(ts, _)| ts),
+            CounterMode::Delta => prev_timestamp,
+        };
+        let speed = row.speed.or_else(|| {
+            let elapsed = row.timestamp - since?;
+            let meters = distance_delta?;
+            (elapsed > 0).then(|| meters as f64 / elapsed as f64)
+        });
+        let moved = [distance_delta, calories_delta, steps_delta]
+            .iter()
+            .any(|d| d.is_some_and(|d| d > 0));
+        // Nothing happened since the last row (e.g. the first cumulative
+        // reading): stationary
+        let speed = speed.or((!moved).then_some(0.0));
+
+        prev_timestamp = Some(row.timestamp);
+        if let Some(distance) = row.distance {
+            last_distance = Some((row.timestamp, distance));
+        }
+        last_calories = row.calories.or(last_calories);
+        last_steps = row.steps.or(last_steps);
+
+        if speed.is_none() {
+            summary.reject(
+                row.row,
+                "Row has no speed, and none can be derived from distance".to_string(),
+            );
+            continue;
+        }
+
+        samples.push(TreadmillSample {
+            timestamp: row.timestamp,
+            speed,
+            distance_total,
+            calories_total,
+            steps_total,
+            distance_delta,
+            calories_delta,
+            steps_delta,
+            user_id,
+            incline: row.incline,
+            heart_rate: row.heart_rate,
+            calories_estimated: None,
+        });
+    }
+
+    samples
+}
+
+/// Returns (total, delta) for one counter column.
+/// Cumulative counters follow the Bluetooth rules: the first reading and any
+/// reset (value going backwards) contribute a zero delta.
+fn counter(
+    current: Option<f64>,
+    previous: Option<f64>,
+    mode: CounterMode,
+) -> (Option<i64>, Option<i64>) {
+    let Some(curr) = current.map(|v| v.round() as i64) else {
+        return (None, None);
+    };
+
+    match mode {
+        CounterMode::Delta => (None, Some(curr)),
+        CounterMode::Cumulative => {
+            let delta = match previous.map(|v| v.round() as i64) {
+                Some(last) if curr >= last => curr - last,
+                _ => 0,
+            };
+            (Some(curr), Some(delta))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_support::test_storage;
+
+    fn options(format: ImportFormat) -> ImportOptions {
+        ImportOptions {
+            format: Some(format),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_csv_cumulative_import() {
+        let csv = "timestamp,speed,distance,calories,steps\n\
+                   1700000000,1.0,100,10,150\n\
+                   1700000001,1.0,101,10,152\n\
+                   1700000002,1.0,103,11,154\n";
+        let parsed = parse(csv.as_bytes(), &options(ImportFormat::Csv)).unwrap();
+
+        assert_eq!(parsed.summary.total_rows, 3);
+        assert_eq!(parsed.summary.invalid, 0);
+        let deltas: Vec<_> = parsed.samples.iter().map(|s| s.steps_delta).collect();
+        assert_eq!(deltas, vec![Some(0), Some(2), Some(2)]);
+        assert_eq!(parsed.samples[2].distance_delta, Some(2));
+        assert_eq!(parsed.samples[2].distance_total, Some(103));
+    }
+
+    #[test]
+    fn test_cumulative_reset_gives_zero_delta() {
+        let csv = "time,speed,steps\n1700000000,1,500\n1700000001,1,510\n1700000002,1,3\n";
+        let parsed = parse(csv.as_bytes(), &options(ImportFormat::Csv)).unwrap();
+
+        let deltas: Vec<_> = parsed.samples.iter().map(|s| s.steps_delta).collect();
+        assert_eq!(deltas, vec![Some(0), Some(10), Some(0)]);
+    }
+
+    #[test]
+    fn test_cumulative_counters_skip_missing_cells() {
+        let csv = "timestamp,distance,steps\n\
+                   1700000000,100,500\n\
+                   1700000010,,520\n\
+                   1700000020,124,\n\
+                   1700000030,130,560\n";
+        let parsed = parse(csv.as_bytes(), &options(ImportFormat::Csv)).unwrap();
+
+        // The second row walked 20 steps at no known speed
+        assert_eq!(parsed.summary.invalid, 1);
+        assert_eq!(parsed.summary.errors[0].row, 3);
+        let steps: Vec<_> = parsed.samples.iter().map(|s| s.steps_delta).collect();
+        assert_eq!(steps, vec![Some(0), None, Some(40)]);
+        let distance: Vec<_> = parsed.samples.iter().map(|s| s.distance_delta).collect();
+        assert_eq!(distance, vec![Some(0), Some(24), Some(6)]);
+        // 24 m over the 20 s since the last distance reading
+        assert_eq!(parsed.samples[0].speed, Some(0.0));
+        assert!((parsed.samples[1].speed.unwrap() - 1.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_json_delta_import_with_units() {
+        let json = r#"{"samples": [
+            {"timestamp": "2025-01-15T08:00:00Z", "speed": 3.6, "distance": 0.01, "steps": 2},
+            {"timestamp": "2025-01-15T08:00:05Z", "speed": "3.6", "distance": 0.005, "steps": 8}
+        ]}"#;
+        let opts = ImportOptions {
+            format: Some(ImportFormat::Json),
+            mode: CounterMode::Delta,
+            speed_unit: SpeedUnit::KilometersPerHour,
+            distance_unit: DistanceUnit::Kilometers,
+            tz_offset: 0,
+            tz: None,
+            user_id: None,
+        };
+        let parsed = parse(json.as_bytes(), &opts).unwrap();
+
+        assert_eq!(parsed.samples.len(), 2);
+        assert!((parsed.samples[0].speed.unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!(parsed.samples[0].distance_delta, Some(10));
+        assert_eq!(parsed.samples[1].distance_delta, Some(5));
+        assert_eq!(parsed.samples[1].steps_delta, Some(8));
+        assert_eq!(parsed.samples[1].steps_total, None);
+    }
+
+    #[test]
+    fn test_invalid_and_duplicate_rows_are_reported() {
+        let csv = "timestamp,speed,steps\n\
+                   1700000000,1.0,10\n\
+                   not-a-time,1.0,10\n\
+                   1700000001,-1,10\n\
+                   1700000002,50,10\n\
+                   1700000000,1.0,12\n";
+        let parsed = parse(csv.as_bytes(), &options(ImportFormat::Csv)).unwrap();
+
+        assert_eq!(parsed.summary.total_rows, 5);
+        assert_eq!(parsed.summary.invalid, 3);
+        assert_eq!(parsed.summary.duplicates, 1);
+        assert_eq!(parsed.samples.len(), 1);
+        assert_eq!(parsed.summary.errors[0].row, 3);
+    }
+
+    #[test]
+    fn test_local_timestamps_use_zone() {
+        // 08:00 at UTC-8 is 16:00 UTC
+        let pst = Zone::from_offset(-28800).unwrap();
+        let ts = parse_timestamp("2025-01-15 08:00:00", &pst).unwrap();
+        assert_eq!(ts, 1736956800);
+        assert_eq!(
+            parse_timestamp("1736956800000", &Zone::default()).unwrap(),
+            1736956800
+        );
+
+        // A named zone applies daylight saving time: 08:00 PDT is 15:00 UTC
+        let la: Zone = "America/Los_Angeles".parse().unwrap();
+        let ts = parse_timestamp("2025-07-15 08:00:00", &la).unwrap();
+        assert_eq!(ts, 1752591600);
+    }
+
+    #[test]
+    fn test_speed_derived_from_distance() {
+        let csv = "timestamp,distance\n1700000000,0\n1700000010,12\n";
+        let parsed = parse(csv.as_bytes(), &options(ImportFormat::Csv)).unwrap();
+
+        assert_eq!(parsed.samples[0].speed, Some(0.0));
+        assert!((parsed.samples[1].speed.unwrap() - 1.2).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_imports_without_speed_count_in_summaries() {
+        let (_dir, storage) = test_storage().await;
+        // Distance gives a speed; steps alone can't
+        let csv = "timestamp,distance,steps\n\
+                   2025-01-15T08:00:00Z,0,0\n\
+                   2025-01-15T08:00:10Z,12,16\n\
+                   2025-01-15T08:00:20Z,24,32\n\
+                   2025-01-15T08:00:30Z,,48\n";
+        let summary = import(
+            &storage,
+            csv.as_bytes(),
+            &options(ImportFormat::Csv),
+            &EnergyConfig::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.inserted, 3);
+        assert_eq!(summary.invalid, 1);
+
+        let date = chrono::NaiveDate::from_ymd_opt(2025, 1, 15).unwrap();
+        let day = storage
+            .get_daily_summary(date, &Zone::default(), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(day.distance_meters, 24);
+        assert_eq!(day.steps, 32);
+    }
+
+    #[test]
+    fn test_missing_timestamp_column_fails() {
+        let csv = "speed,steps\n1.0,10\n";
+        assert!(parse(csv.as_bytes(), &options(ImportFormat::Csv)).is_err());
+    }
+}
@@ -0,0 +1,747 @@
+//! Optional copy of samples and daily rollups to InfluxDB.
+//!
+//! Live samples are picked up from the broadcast channel, encoded as line
+//! protocol and posted in batches to any InfluxDB-compatible write endpoint
+//! (1.x `/write`, 2.x `/api/v2/write`, VictoriaMetrics, ...). Each flush also
+//! rewrites today's and yesterday's rollups in `<measurement>_daily`, stamped
+//! at local midnight, so they converge on the final totals.
+//!
+//! A batch that still fails after its retries is appended to a buffer file
+//! and replayed before anything else once the endpoint answers again. Points
+//! are keyed by timestamp, so a replay that stops halfway can simply run
+//! again. Lines the endpoint rejects as malformed are logged and dropped.
+//!
+//! `backfill` sends history the same way, for the `influx-backfill` command.
+
+use anyhow::{anyhow, Result};
+use chrono::{Days, Utc, Weekday};
+use futures_util::TryStreamExt;
+use reqwest::{header, StatusCode, Url};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, Notify};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::config::InfluxConfig;
+use crate::energy::CaloriesSource;
+use crate::storage::{GroupBy, PeriodSummary, Storage};
+use crate::timezone::Zone;
+use crate::websocket::{WsMessage, WsSample};
+use crate::writer::{append_spill, read_spill};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// One sample as a line: per-sample amounts since the previous sample, tagged
+/// with the user when there is one. `None` if the sample has no fields.
+pub fn sample_line(measurement: &str, sample: &WsSample) -> Option<String> {
+    let mut fields = Fields::default();
+    fields.float("speed", sample.speed);
+    fields.integer("distance", sample.distance_delta);
+    fields.integer("steps", sample.steps_delta);
+    fields.integer("calories", sample.calories_delta);
+    fields.float("calories_estimated", sample.calories_estimated);
+    fields.float("incline", sample.incline);
+    fields.integer("heart_rate", sample.heart_rate);
+
+    let tags = sample
+        .user_id
+        .map(|id| format!(",user_id={}", id))
+        .unwrap_or_default();
+    fields.line(measurement, &tags, sample.timestamp)
+}
+
+/// One local day's totals, stamped at the day's start
+pub fn rollup_line(measurement: &str, day: &PeriodSummary) -> String {
+    let mut fields = Fields::default();
+    fields.integer("distance", Some(day.distance_meters));
+    fields.integer("steps", Some(day.steps));
+    fields.integer("calories", Some(day.calories));
+    fields.integer("calories_device", Some(day.calories_device));
+    fields.integer("calories_estimated", Some(day.calories_estimated));
+    fields.integer("duration_seconds", Some(day.duration_seconds));
+    fields.float("avg_speed", Some(day.avg_speed));
+    fields.float("max_speed", Some(day.max_speed));
+    fields.integer("samples", Some(day.total_samples));
+
+    let measurement = format!("{}_daily", measurement);
+    fields.line(&measurement, "", day.start).unwrap_or_default()
+}
+
+/// Comma-separated `key=value` pairs; missing and non-finite values are left out
+#[derive(Default)]
+struct Fields(Vec<String>);
+
+impl Fields {
+    fn float(&mut self, key: &str, value: Option<f64>) {
+        if let Some(v) = value.filter(|v| v.is_finite()) {
+            self.0.push(format!("{}={}", key, v));
+        }
+    }
+
+    fn integer(&mut self, key: &str, value: Option<i64>) {
+        if let Some(v) = value {
+            self.0.push(format!("{}={}i", key, v));
+        }
+    }
+
+    fn line(self, measurement: &str, tags: &str, timestamp: i64) -> Option<String> {
+        if self.0.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "{}{} {} {}",
+            escape_measurement(measurement),
+            tags,
+            self.0.join(","),
+            timestamp
+        ))
+    }
+}
+
+fn escape_measurement(name: &str) -> String {
+    name.replace(',', "\\,").replace(' ', "\\ ")
+}
+
+/// Why a write didn't go through
+#[derive(Debug)]
+enum WriteError {
+    /// Network trouble or a server error; worth trying again later
+    Unavailable(anyhow::Error),
+    /// The endpoint refused the lines themselves; sending them again won't help
+    Rejected(String),
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::Unavailable(e) => write!(f, "InfluxDB unavailable: {}", e),
+            WriteError::Rejected(e) => write!(f, "InfluxDB rejected the lines: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WriteError {}
+
+struct Client {
+    http: reqwest::Client,
+    url: Url,
+    token: Option<String>,
+}
+
+impl Client {
+    fn new(config: &InfluxConfig) -> Result<Self> {
+        let url = config
+            .url
+            .as_deref()
+            .ok_or_else(|| anyhow!("No InfluxDB write URL configured ([influx] url)"))?;
+        let mut url = Url::parse(url)?;
+
+        // Timestamps are whole seconds, whatever the URL said
+        let query: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(key, _)| key != "precision")
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+        url.query_pairs_mut()
+            .clear()
+            .extend_pairs(query)
+            .append_pair("precision", "s");
+
+        Ok(Self {
+            http: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+            url,
+            token: config.token.clone(),
+        })
+    }
+
+    async fn post(&self, lines: &[String]) -> Result<(), WriteError> {
+        let mut request = self
+            .http
+            .post(self.url.clone())
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(lines.join("\n"));
+        if let Some(token) = &self.token {
+            request = request.header(header::AUTHORIZATION, format!("Token {}", token));
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| WriteError::Unavailable(e.into()))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let body = response.text().await.unwrap_or_default();
+        let message = format!("{}: {}", status, body.trim());
+        match status {
+            StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
+                Err(WriteError::Rejected(message))
+            }
+            _ => Err(WriteError::Unavailable(anyhow!(message))),
+        }
+    }
+
+    async fn write(&self, lines: &[String], attempts: u32) -> Result<(), WriteError> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            match self.post(lines).await {
+                Err(WriteError::Unavailable(e)) if attempt < attempts => {
+                    warn!(
+                        "InfluxDB write failed (attempt {}/{}), retrying in {:?}: {}",
+                        attempt, attempts, backoff, e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Handle for the running sink
+pub struct InfluxSink {
+    stop: Arc<Notify>,
+}
+
+impl InfluxSink {
+    /// Start copying live samples. Anything left in the buffer by an earlier
+    /// run is sent first.
+    pub fn start(
+        config: &InfluxConfig,
+        buffer_path: impl Into<PathBuf>,
+        storage: Arc<Storage>,
+        ws_tx: &broadcast::Sender<WsMessage>,
+        zone: Zone,
+        calories_source: CaloriesSource,
+    ) -> Result<(Self, JoinHandle<()>)> {
+        let worker = Worker::new(config, buffer_path.into(), storage, zone, calories_source)?;
+        // Without the query string, which may hold credentials
+        let mut shown = worker.client.url.clone();
+        shown.set_query(None);
+        info!("Copying samples to InfluxDB at {}", shown);
+
+        let stop = Arc::new(Notify::new());
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(forward(ws_tx.subscribe(), tx, Arc::clone(&stop)));
+        let handle = tokio::spawn(worker.run(rx));
+        Ok((Self { stop }, handle))
+    }
+
+    /// Send what's pending (or buffer it) and stop
+    pub fn stop(&self) {
+        self.stop.notify_one();
+    }
+}
+
+/// Hand live samples to the worker as they arrive, so the broadcast receiver
+/// keeps up while the worker waits on (or retries) InfluxDB. Stopping drops
+/// the channel, which tells the worker to flush and finish.
+async fn forward(
+    mut rx: broadcast::Receiver<WsMessage>,
+    tx: mpsc::UnboundedSender<WsSample>,
+    stop: Arc<Notify>,
+) {
+    loop {
+        tokio::select! {
+            message = rx.recv() => match message {
+                Ok(WsMessage::NewSample { sample }) => {
+                    if tx.send(sample).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("InfluxDB sink lagged behind by {} messages", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = stop.notified() => break,
+        }
+    }
+}
+
+struct Worker {
+    client: Client,
+    storage: Arc<Storage>,
+    measurement: String,
+    batch_size: usize,
+    max_retries: u32,
+    flush_interval: Duration,
+    buffer_path: PathBuf,
+    buffer_max_lines: usize,
+    buffered: usize, // lines in the buffer file
+    zone: Zone,
+    calories_source: CaloriesSource,
+    pending: Vec<String>,
+    rollups_due: bool, // samples arrived since the rollups were last sent
+}
+
+impl Worker {
+    fn new(
+        config: &InfluxConfig,
+        buffer_path: PathBuf,
+        storage: Arc<Storage>,
+        zone: Zone,
+        calories_source: CaloriesSource,
+    ) -> Result<Self> {
+        Ok(Self {
+            client: Client::new(config)?,
+            storage,
+            measurement: config.measurement.clone(),
+            batch_size: config.batch_size.max(1),
+            max_retries: config.max_retries,
+            flush_interval: Duration::from_secs(config.flush_interval_secs.max(1)),
+            buffer_path,
+            buffer_max_lines: config.buffer_max_lines,
+            buffered: 0,
+            zone,
+            calories_source,
+            pending: Vec::new(),
+            rollups_due: false,
+        })
+    }
+
+    async fn run(mut self, mut rx: mpsc::UnboundedReceiver<WsSample>) {
+        match read_spill::<String>(&self.buffer_path).await {
+            Ok(lines) if !lines.is_empty() => {
+                info!(
+                    "{} InfluxDB lines waiting in {}",
+                    lines.len(),
+                    self.buffer_path.display()
+                );
+                self.buffered = lines.len();
+            }
+            Ok(_) => {}
+            Err(e) => error!("Couldn't read {}: {}", self.buffer_path.display(), e),
+        }
+
+        let mut ticker = tokio::time::interval(self.flush_interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                sample = rx.recv() => match sample {
+                    Some(sample) => {
+                        self.pending.extend(sample_line(&self.measurement, &sample));
+                        self.rollups_due = true;
+                        if self.pending.len() >= self.batch_size {
+                            self.flush().await;
+                        }
+                    }
+                    None => break,
+                },
+                _ = ticker.tick() => {
+                    self.queue_rollups().await;
+                    self.flush().await;
+                }
+            }
+        }
+
+        self.queue_rollups().await;
+        self.flush().await;
+    }
+
+    /// Queue yesterday's and today's rollups if samples arrived since the last ones
+    async fn queue_rollups(&mut self) {
+        if !std::mem::take(&mut self.rollups_due) {
+            return;
+        }
+        let today = self.zone.local_date(Utc::now().timestamp());
+        let yesterday = today - Days::new(1);
+        match self
+            .storage
+            .get_period_summaries(
+                GroupBy::Day,
+                Some((yesterday, today)),
+                &self.zone,
+                Weekday::Mon,
+                None,
+            )
+            .await
+        {
+            Ok(days) => self.pending.extend(days.into_iter().map(|mut day| {
+                day.report_calories(self.calories_source);
+                rollup_line(&self.measurement, &day)
+            })),
+            Err(e) => error!("Couldn't compute daily rollups for InfluxDB: {}", e),
+        }
+    }
+
+    /// Send the buffer (if any), then what's pending; buffer whatever couldn't be sent
+    async fn flush(&mut self) {
+        let lines = std::mem::take(&mut self.pending);
+        if self.buffered > 0 && !self.replay().await {
+            self.buffer(&lines).await;
+            return;
+        }
+
+        for (i, chunk) in lines.chunks(self.batch_size).enumerate() {
+            match self.client.write(chunk, self.max_retries + 1).await {
+                Ok(()) => {}
+                Err(WriteError::Rejected(e)) => {
+                    error!(
+                        "InfluxDB rejected {} lines, dropping them: {}",
+                        chunk.len(),
+                        e
+                    );
+                }
+                Err(WriteError::Unavailable(e)) => {
+                    warn!(
+                        "Couldn't write to InfluxDB, buffering in {}: {}",
+                        self.buffer_path.display(),
+                        e
+                    );
+                    self.buffer(&lines[i * self.batch_size..]).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Send buffered lines, once each. Returns false if the endpoint is still
+    /// unavailable (the buffer is kept).
+    async fn replay(&mut self) -> bool {
+        let lines = match read_spill::<String>(&self.buffer_path).await {
+            Ok(lines) => lines,
+            Err(e) => {
+                error!("Couldn't read {}: {}", self.buffer_path.display(), e);
+                return false;
+            }
+        };
+
+        for chunk in lines.chunks(self.batch_size) {
+            match self.client.write(chunk, 1).await {
+                Ok(()) => {}
+                Err(WriteError::Rejected(e)) => {
+                    error!(
+                        "InfluxDB rejected {} buffered lines, dropping them: {}",
+                        chunk.len(),
+                        e
+                    );
+                }
+                Err(WriteError::Unavailable(_)) => return false,
+            }
+        }
+        if let Err(e) = tokio::fs::remove_file(&self.buffer_path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                error!("Couldn't remove {}: {}", self.buffer_path.display(), e);
+                return false;
+            }
+        }
+
+        info!("Sent {} buffered lines to InfluxDB", lines.len());
+        self.buffered = 0;
+        true
+    }
+
+    async fn buffer(&mut self, lines: &[String]) {
+        let room = self.buffer_max_lines.saturating_sub(self.buffered);
+        if lines.len() > room {
+            error!(
+                "InfluxDB buffer is full, dropping {} lines",
+                lines.len() - room
+            );
+        }
+        let lines = &lines[..lines.len().min(room)];
+        if lines.is_empty() {
+            return;
+        }
+
+        match append_spill(&self.buffer_path, lines).await {
+            Ok(()) => self.buffered += lines.len(),
+            Err(e) => error!(
+                "Couldn't buffer {} lines in {}, dropping them: {}",
+                lines.len(),
+                self.buffer_path.display(),
+                e
+            ),
+        }
+    }
+}
+
+/// Send stored samples and daily rollups in `[start, end)` (Unix seconds).
+/// Returns the number of samples and days sent.
+pub async fn backfill(
+    config: &InfluxConfig,
+    storage: Arc<Storage>,
+    (start, end): (i64, i64),
+    zone: Zone,
+    calories_source: CaloriesSource,
+) -> Result<(u64, u64)> {
+    let client = Client::new(config)?;
+    let batch_size = config.batch_size.max(1);
+    let attempts = config.max_retries + 1;
+
+    let mut samples = 0;
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut stream =
+        std::pin::pin!(Arc::clone(&storage).stream_samples((start, end), None, None, None));
+    while let Some(sample) = stream.try_next().await? {
+        batch.extend(sample_line(&config.measurement, &WsSample::from(sample)));
+        if batch.len() >= batch_size {
+            client.write(&batch, attempts).await?;
+            samples += batch.len() as u64;
+            batch.clear();
+            info!("Sent {} samples to InfluxDB", samples);
+        }
+    }
+    if !batch.is_empty() {
+        client.write(&batch, attempts).await?;
+        samples += batch.len() as u64;
+    }
+
+    let rollups: Vec<String> = storage
+        .get_period_summaries(GroupBy::Day, None, &zone, Weekday::Mon, None)
+        .await?
+        .into_iter()
+        .filter(|day| day.start >= start && day.start < end)
+        .map(|mut day| {
+            day.report_calories(calories_source);
+            rollup_line(&config.measurement, &day)
+        })
+        .collect();
+    for chunk in rollups.chunks(batch_size) {
+        client.write(chunk, attempts).await?;
+    }
+
+    Ok((samples, rollups.len() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_support::{test_sample, test_storage_in};
+    use crate::storage::TreadmillSample;
+    use axum::extract::{RawQuery, State};
+    use axum::http::HeaderMap;
+    use axum::routing::post;
+    use axum::Router;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    /// A write endpoint that records what it's sent, or fails on request
+    #[derive(Clone, Default)]
+    struct StandIn {
+        writes: Arc<Mutex<Vec<(String, String, String)>>>, // (query, authorization, body)
+        down: Arc<AtomicBool>,
+    }
+
+    impl StandIn {
+        async fn start() -> (Self, String) {
+            let stand_in = StandIn::default();
+            let router = Router::new()
+                .route("/api/v2/write", post(stand_in_write))
+                .with_state(stand_in.clone());
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!(
+                "http://{}/api/v2/write?bucket=walkpad&precision=ms",
+                listener.local_addr().unwrap()
+            );
+            tokio::spawn(async move { axum::serve(listener, router).await });
+            (stand_in, url)
+        }
+
+        fn lines(&self) -> Vec<String> {
+            let writes = self.writes.lock().unwrap();
+            writes
+                .iter()
+                .flat_map(|(_, _, body)| body.lines().map(str::to_string))
+                .collect()
+        }
+    }
+
+    async fn stand_in_write(
+        State(stand_in): State<StandIn>,
+        RawQuery(query): RawQuery,
+        headers: HeaderMap,
+        body: String,
+    ) -> StatusCode {
+        if stand_in.down.load(Ordering::Relaxed) {
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+        if body.contains("malformed") {
+            return StatusCode::BAD_REQUEST;
+        }
+        let authorization = headers
+            .get(header::AUTHORIZATION)
+            .map(|v| v.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        stand_in
+            .writes
+            .lock()
+            .unwrap()
+            .push((query.unwrap_or_default(), authorization, body));
+        StatusCode::NO_CONTENT
+    }
+
+    fn config(url: &str) -> InfluxConfig {
+        InfluxConfig {
+            url: Some(url.to_string()),
+            token: Some("secret".to_string()),
+            batch_size: 2,
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    fn sample(timestamp: i64) -> TreadmillSample {
+        TreadmillSample {
+            speed: Some(1.25),
+            distance_delta: Some(2),
+            steps_delta: Some(3),
+            user_id: Some(1),
+            heart_rate: Some(98),
+            calories_estimated: Some(0.5),
+            ..test_sample(timestamp)
+        }
+    }
+
+    #[test]
+    fn test_line_protocol() {
+        let line = sample_line("walk pad", &WsSample::from(sample(1736899200))).unwrap();
+        assert_eq!(
+            line,
+            "walk\\ pad,user_id=1 speed=1.25,distance=2i,steps=3i,calories=0i,\
+             calories_estimated=0.5,heart_rate=98i 1736899200"
+        );
+
+        let mut empty = WsSample::from(sample(1736899200));
+        empty.speed = None;
+        empty.distance_delta = None;
+        empty.steps_delta = None;
+        empty.calories_delta = None;
+        empty.calories_estimated = None;
+        empty.heart_rate = None;
+        assert_eq!(sample_line("treadmill", &empty), None);
+    }
+
+    #[tokio::test]
+    async fn test_buffers_while_unavailable_then_replays() {
+        let dir = tempfile::tempdir().unwrap();
+        let (stand_in, url) = StandIn::start().await;
+        let buffer = dir.path().join("influx");
+        let mut worker = Worker::new(
+            &config(&url),
+            buffer.clone(),
+            Arc::new(test_storage_in(&dir).await),
+            Zone::default(),
+            CaloriesSource::Device,
+        )
+        .unwrap();
+
+        // Down: everything goes to the buffer
+        stand_in.down.store(true, Ordering::Relaxed);
+        worker.pending = vec!["a x=1i 1".into(), "a x=2i 2".into(), "a x=3i 3".into()];
+        worker.flush().await;
+        assert_eq!(worker.buffered, 3);
+        assert_eq!(read_spill::<String>(&buffer).await.unwrap().len(), 3);
+        assert!(stand_in.lines().is_empty());
+
+        // Still down: new lines join the buffer
+        worker.pending = vec!["a x=4i 4".into()];
+        worker.flush().await;
+        assert_eq!(worker.buffered, 4);
+
+        // Back: the buffer goes first, in order; malformed lines are dropped
+        stand_in.down.store(false, Ordering::Relaxed);
+        worker.pending = vec!["malformed".into(), "a x=5i 5".into()];
+        worker.flush().await;
+        assert_eq!(worker.buffered, 0);
+        assert!(!buffer.exists());
+        assert_eq!(
+            stand_in.lines(),
+            ["a x=1i 1", "a x=2i 2", "a x=3i 3", "a x=4i 4"]
+        );
+
+        // Seconds precision replaces whatever the URL asked for, and the token is sent
+        let writes = stand_in.writes.lock().unwrap();
+        assert_eq!(writes[0].0, "bucket=walkpad&precision=s");
+        assert_eq!(writes[0].1, "Token secret");
+    }
+
+    #[tokio::test]
+    async fn test_keeps_up_with_samples_while_retrying() {
+        let dir = tempfile::tempdir().unwrap();
+        let (stand_in, url) = StandIn::start().await;
+        stand_in.down.store(true, Ordering::Relaxed);
+        let buffer = dir.path().join("influx");
+        let config = InfluxConfig {
+            max_retries: 1,
+            ..config(&url)
+        };
+        let (ws_tx, _) = broadcast::channel(4);
+        let (sink, handle) = InfluxSink::start(
+            &config,
+            buffer.clone(),
+            Arc::new(test_storage_in(&dir).await),
+            &ws_tx,
+            Zone::default(),
+            CaloriesSource::Device,
+        )
+        .unwrap();
+
+        // The first batch waits out a retry; far more samples than the
+        // broadcast channel holds arrive meanwhile
+        for ts in 0..20 {
+            let sample = WsSample::from(sample(1736899200 + ts));
+            ws_tx.send(WsMessage::NewSample { sample }).unwrap();
+            tokio::task::yield_now().await;
+        }
+        sink.stop();
+        handle.await.unwrap();
+
+        let buffered = read_spill::<String>(&buffer).await.unwrap();
+        let samples = buffered.iter().filter(|l| l.starts_with("treadmill,"));
+        assert_eq!(samples.count(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_sends_samples_and_rollups() {
+        let dir = tempfile::tempdir().unwrap();
+        let (stand_in, url) = StandIn::start().await;
+        let storage = Arc::new(test_storage_in(&dir).await);
+
+        // 2025-01-15 and 2025-01-16, UTC
+        let samples = [1736899200, 1736899201, 1736899202, 1736985600].map(sample);
+        storage.insert_samples_if_absent(&samples).await.unwrap();
+
+        let (sent, days) = backfill(
+            &config(&url),
+            Arc::clone(&storage),
+            (0, i64::MAX),
+            Zone::default(),
+            CaloriesSource::Device,
+        )
+        .await
+        .unwrap();
+        assert_eq!((sent, days), (4, 2));
+
+        let lines = stand_in.lines();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[3].ends_with(" 1736985600"));
+        assert!(lines[4].starts_with("treadmill_daily distance=6i,steps=9i,"));
+        assert!(lines[4].ends_with(" 1736899200"));
+
+        // A range only sends what's in it
+        let (sent, days) = backfill(
+            &config(&url),
+            storage,
+            (1736985600, 1737072000),
+            Zone::default(),
+            CaloriesSource::Device,
+        )
+        .await
+        .unwrap();
+        assert_eq!((sent, days), (1, 1));
+    }
+}
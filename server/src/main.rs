@@ -1,19 +1,50 @@
 mod api;
+mod auth;
 mod bluetooth;
+mod cli;
 mod config;
+mod energy;
+mod export;
+mod goals;
+mod import;
+mod influx;
+mod metrics;
+mod mqtt;
+mod quality;
+mod records;
+mod sessions;
 mod storage;
+mod sync;
+mod timezone;
+mod tls;
+mod users;
+mod webhooks;
 mod websocket;
+mod writer;
 
 use anyhow::Result;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::{signal, sync::broadcast};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use api::{create_router, AppState};
 use bluetooth::{BluetoothManager, ConnectionStatus};
+use cli::Command;
 use config::Config;
+use influx::InfluxSink;
+use metrics::Metrics;
+use mqtt::MqttBridge;
+use records::{RebuildRequests, RecordsEngine};
 use storage::Storage;
+use tls::TlsFiles;
+use users::ActiveUser;
+use webhooks::Webhooks;
+use writer::SampleWriter;
+
+/// How long open connections get to finish when stopping (TLS listener)
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -26,14 +57,22 @@ async fn main() -> Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    info!("🚀 Starting WalkPad Sync Server");
-
     // Load configuration (file -> env vars -> defaults)
     // Environment variables override config file values
     let config = Config::load("config.toml");
+
+    // One-off subcommands (e.g. `import`) run and exit without starting the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(command) = Command::parse(&args)? {
+        return command.run(&config).await;
+    }
+
+    info!("🚀 Starting WalkPad Sync Server");
+    let timezone = config.server.zone()?;
+    let cors = config.server.cors()?;
     info!(
-        "Configuration: database={}, port={}, device_filter={}",
-        config.database.path, config.server.port, config.bluetooth.device_name_filter
+        "Configuration: database={}, port={}, device_filter={}, timezone={}",
+        config.database.path, config.server.port, config.bluetooth.device_name_filter, timezone
     );
 
     // Initialize storage
@@ -45,14 +84,96 @@ async fn main() -> Result<()> {
     let (ws_tx, _) = broadcast::channel(100);
     info!("✅ WebSocket broadcast channel created");
 
+    // Restore the active user and follow any configured schedules
+    let active_user = Arc::new(ActiveUser::load(Arc::clone(&storage), ws_tx.clone()).await?);
+    tokio::spawn(Arc::clone(&active_user).run_schedules(timezone));
+
+    // Announce goals as live samples cross them
+    tokio::spawn(goals::run_live(
+        Arc::clone(&storage),
+        ws_tx.clone(),
+        timezone,
+        config.energy.calories_source,
+    ));
+
+    // Live samples are queued and written in batches
+    let (sample_writer, writer_handle) = SampleWriter::start(
+        Arc::clone(&storage),
+        &config.writer,
+        config.writer.spill_path(&config.database.path),
+    );
+
+    // Keep personal records up to date as samples are committed
+    let records = Arc::new(tokio::sync::Mutex::new(
+        RecordsEngine::load(Arc::clone(&storage), ws_tx.clone(), timezone).await?,
+    ));
+    let records_rebuild = RebuildRequests::default();
+    tokio::spawn(records::run_live(
+        Arc::clone(&records),
+        sample_writer.committed(),
+        records_rebuild.clone(),
+    ));
+
+    // Optionally copy live samples to InfluxDB
+    let influx = match config.influx.url {
+        Some(_) => Some(InfluxSink::start(
+            &config.influx,
+            config.influx.buffer_path(&config.database.path),
+            Arc::clone(&storage),
+            &ws_tx,
+            timezone,
+            config.energy.calories_source,
+        )?),
+        None => None,
+    };
+
+    // Counters for Prometheus, updated as things happen
+    let metrics = Arc::new(Metrics::default());
+
     // Initialize Bluetooth manager
     let (bluetooth_manager, status_rx) = BluetoothManager::new(
         Arc::clone(&storage),
+        Arc::clone(&active_user),
         config.bluetooth.clone(),
+        config.energy.clone(),
+        config.quality.clone(),
+        sample_writer.clone(),
         ws_tx.clone(),
+        Arc::clone(&metrics),
     );
     let bluetooth_manager = Arc::new(bluetooth_manager);
 
+    // Outgoing webhooks, fired from the live feed and the Bluetooth status
+    let webhooks = Arc::new(Webhooks::new(
+        config.webhooks.clone(),
+        Arc::clone(&storage),
+        webhooks::INITIAL_BACKOFF,
+    )?);
+    if !webhooks.is_empty() {
+        webhooks
+            .start(
+                &ws_tx,
+                bluetooth_manager.subscribe_status(),
+                timezone,
+                config.energy.calories_source,
+            )
+            .await?;
+    }
+
+    // Optionally publish live state to an MQTT broker (Home Assistant)
+    let mqtt = match config.mqtt.host {
+        Some(_) => Some(MqttBridge::start(
+            &config.mqtt,
+            Arc::clone(&storage),
+            Arc::clone(&bluetooth_manager),
+            Arc::clone(&active_user),
+            &ws_tx,
+            timezone,
+            config.energy.calories_source,
+        )?),
+        None => None,
+    };
+
     // Create shared Bluetooth status for API
     let bt_status = Arc::new(tokio::sync::RwLock::new(ConnectionStatus::Disconnected));
     let bt_status_clone = Arc::clone(&bt_status);
@@ -81,36 +202,99 @@ async fn main() -> Result<()> {
         storage: Arc::clone(&storage),
         ws_tx: ws_tx.clone(),
         bluetooth_status: Arc::clone(&bt_status),
+        active_user: Arc::clone(&active_user),
+        energy: config.energy.clone(),
+        timezone,
+        week_start: config.server.week_start,
+        writer: sample_writer.clone(),
+        records,
+        records_rebuild,
+        metrics,
+        webhooks,
+        auth_enabled: config.auth.enabled,
+        cors,
     });
 
+    // Optional TLS, reloaded when the certificate files change
+    let rustls_config = match config.server.tls_paths(&config.database.path) {
+        Some((cert, key)) => {
+            let files = TlsFiles::new(cert, key);
+            let rustls_config =
+                tls::load(&files, config.server.tls_self_signed, &config.server.host).await?;
+            tls::watch(rustls_config.clone(), files);
+            Some(rustls_config)
+        }
+        None => None,
+    };
+    let (http, ws) = match rustls_config {
+        Some(_) => ("https", "wss"),
+        None => ("http", "ws"),
+    };
+
     // Start HTTP server
     let addr = format!("{}:{}", config.server.host, config.server.port);
-    info!("🌐 Starting HTTP server on {}", addr);
-
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    let server_handle = tokio::spawn(async move {
-        if let Err(e) = axum::serve(listener, app)
-            .with_graceful_shutdown(shutdown_signal())
-            .await
-        {
-            error!("Server error: {}", e);
+    info!("🌐 Starting {} server on {}", http.to_uppercase(), addr);
+
+    let server_handle = match rustls_config {
+        Some(rustls_config) => {
+            let listener = std::net::TcpListener::bind(&addr)?;
+            listener.set_nonblocking(true)?;
+            let handle = axum_server::Handle::new();
+            tokio::spawn({
+                let handle = handle.clone();
+                async move {
+                    shutdown_signal().await;
+                    handle.graceful_shutdown(Some(SHUTDOWN_GRACE));
+                }
+            });
+            tokio::spawn(async move {
+                if let Err(e) = axum_server::from_tcp_rustls(listener, rustls_config)
+                    .handle(handle)
+                    .serve(app.into_make_service())
+                    .await
+                {
+                    error!("Server error: {}", e);
+                }
+            })
         }
-    });
+        None => {
+            let listener = tokio::net::TcpListener::bind(&addr).await?;
+            tokio::spawn(async move {
+                if let Err(e) = axum::serve(listener, app)
+                    .with_graceful_shutdown(shutdown_signal())
+                    .await
+                {
+                    error!("Server error: {}", e);
+                }
+            })
+        }
+    };
 
     info!("✨ WalkPad Sync Server is running!");
     info!(
-        "📊 Dashboard: http://{}:{}",
-        config.server.host, config.server.port
+        "📊 Dashboard: {}://{}:{}",
+        http, config.server.host, config.server.port
     );
     info!(
-        "📈 API: http://{}:{}/api/health",
-        config.server.host, config.server.port
+        "📈 API: {}://{}:{}/api/health",
+        http, config.server.host, config.server.port
     );
     info!(
-        "🔌 WebSocket: ws://{}:{}/ws/live",
-        config.server.host, config.server.port
+        "🔌 WebSocket: {}://{}:{}/ws/live",
+        ws, config.server.host, config.server.port
     );
     info!("💾 Database: {}", config.database.path);
+    if !config.server.cors_origins.is_empty() {
+        info!(
+            "🌐 Browser access allowed from: {}",
+            config.server.cors_origins.join(", ")
+        );
+    }
+    if !config.auth.enabled {
+        warn!("🔓 API authentication is disabled; anyone on the network can read and change data");
+    } else if storage.get_api_tokens().await?.is_empty() {
+        warn!("🔒 API authentication is enabled but no tokens exist; create one with `walkpad-server token create <name>`");
+    }
     info!("⏹️  Press Ctrl+C to stop");
 
     // Wait for either task to complete (or Ctrl+C)
@@ -126,6 +310,24 @@ async fn main() -> Result<()> {
         }
     }
 
+    // Write out anything still queued
+    sample_writer.stop();
+    if let Err(e) = writer_handle.await {
+        error!("Sample writer failed: {}", e);
+    }
+    if let Some((sink, handle)) = influx {
+        sink.stop();
+        if let Err(e) = handle.await {
+            error!("InfluxDB sink failed: {}", e);
+        }
+    }
+    if let Some((bridge, handle)) = mqtt {
+        bridge.stop();
+        if let Err(e) = handle.await {
+            error!("MQTT bridge failed: {}", e);
+        }
+    }
+
     info!("👋 WalkPad Sync Server stopped");
     Ok(())
 }
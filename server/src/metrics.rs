@@ -0,0 +1,332 @@
+//! Prometheus metrics, served as text at `/metrics`.
+//!
+//! Everything is a plain atomic updated where the event happens (Bluetooth
+//! loop, writer, WebSocket handler) and rendered on scrape, so there is no
+//! registry and recording never blocks. Counters start at zero on each run;
+//! Prometheus' `rate()` and `increase()` handle the resets.
+//!
+//! `walkpad_last_notification_timestamp_seconds` is the one to alert on when
+//! the treadmill stops reporting, e.g.
+//! `time() - walkpad_last_notification_timestamp_seconds > 120`.
+
+use chrono::Utc;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::bluetooth::ConnectionStatus;
+use crate::storage::TreadmillSample;
+use crate::writer::SampleWriter;
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Upper bounds of the database write latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// Live counters shared by the Bluetooth manager, API and WebSocket handler
+#[derive(Debug, Default)]
+pub struct Metrics {
+    speed: AtomicU64, // f64 bits, m/s as last reported
+    distance_meters: AtomicU64,
+    steps: AtomicU64,
+    calories: AtomicU64,
+    samples_recorded: AtomicU64,
+    last_notification: AtomicI64, // Unix time of the last parsed reading (0 = never)
+    last_sample: AtomicI64,       // Unix time of the last recorded sample (0 = never)
+    reconnects: AtomicU64,
+    notification_timeouts: AtomicU64,
+    parse_failures: AtomicU64,
+    poll_write_errors: AtomicU64,
+    websocket_clients: AtomicI64,
+    websocket_lagged: AtomicU64,
+}
+
+impl Metrics {
+    /// A reading was parsed, moving or not
+    pub fn observe_reading(&self, speed: Option<f64>) {
+        self.speed
+            .store(speed.unwrap_or(0.0).to_bits(), Ordering::Relaxed);
+        self.last_notification
+            .store(Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    /// A sample was accepted and queued for writing
+    pub fn observe_sample(&self, sample: &TreadmillSample) {
+        let add = |counter: &AtomicU64, delta: Option<i64>| {
+            counter.fetch_add(delta.unwrap_or(0).max(0) as u64, Ordering::Relaxed);
+        };
+        add(&self.distance_meters, sample.distance_delta);
+        add(&self.steps, sample.steps_delta);
+        add(&self.calories, sample.calories_delta);
+        self.samples_recorded.fetch_add(1, Ordering::Relaxed);
+        self.last_sample.store(sample.timestamp, Ordering::Relaxed);
+    }
+
+    /// The treadmill went away; it isn't moving as far as anyone can tell
+    pub fn disconnected(&self) {
+        self.speed.store(0f64.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn notification_timeout(&self) {
+        self.notification_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn parse_failure(&self) {
+        self.parse_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn poll_write_error(&self) {
+        self.poll_write_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a connected WebSocket client until the guard is dropped
+    pub fn websocket_client(self: &Arc<Self>) -> WebSocketClient {
+        self.websocket_clients.fetch_add(1, Ordering::Relaxed);
+        WebSocketClient(Arc::clone(self))
+    }
+
+    /// Broadcast messages a slow client missed
+    pub fn websocket_lagged(&self, messages: u64) {
+        self.websocket_lagged.fetch_add(messages, Ordering::Relaxed);
+    }
+
+    /// Everything in the Prometheus text format
+    pub fn render(&self, status: &ConnectionStatus, writer: &SampleWriter) -> String {
+        let mut out = String::new();
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        gauge(
+            &mut out,
+            "walkpad_speed_meters_per_second",
+            "Belt speed from the latest reading",
+            f64::from_bits(load(&self.speed)),
+        );
+        counter(
+            &mut out,
+            "walkpad_distance_meters_total",
+            "Distance walked in recorded samples",
+            load(&self.distance_meters),
+        );
+        counter(
+            &mut out,
+            "walkpad_steps_total",
+            "Steps in recorded samples",
+            load(&self.steps),
+        );
+        counter(
+            &mut out,
+            "walkpad_calories_total",
+            "Calories (kcal, device counter) in recorded samples",
+            load(&self.calories),
+        );
+        counter(
+            &mut out,
+            "walkpad_samples_recorded_total",
+            "Samples accepted from the treadmill",
+            load(&self.samples_recorded),
+        );
+        gauge(
+            &mut out,
+            "walkpad_last_notification_timestamp_seconds",
+            "Unix time of the latest reading from the treadmill",
+            self.last_notification.load(Ordering::Relaxed) as f64,
+        );
+        gauge(
+            &mut out,
+            "walkpad_last_sample_timestamp_seconds",
+            "Unix time of the latest recorded sample",
+            self.last_sample.load(Ordering::Relaxed) as f64,
+        );
+
+        header(
+            &mut out,
+            "walkpad_bluetooth_status",
+            "gauge",
+            "Bluetooth connection state (1 for the current one)",
+        );
+        for state in ConnectionStatus::ALL {
+            let current = std::mem::discriminant(&state) == std::mem::discriminant(status);
+            let _ = writeln!(
+                out,
+                "walkpad_bluetooth_status{{state=\"{}\"}} {}",
+                state.as_str(),
+                u8::from(current)
+            );
+        }
+        counter(
+            &mut out,
+            "walkpad_bluetooth_reconnects_total",
+            "Reconnection attempts after a connection ended",
+            load(&self.reconnects),
+        );
+        counter(
+            &mut out,
+            "walkpad_bluetooth_notification_timeouts_total",
+            "Connections dropped because the treadmill went quiet",
+            load(&self.notification_timeouts),
+        );
+        counter(
+            &mut out,
+            "walkpad_bluetooth_parse_failures_total",
+            "Notifications that couldn't be parsed",
+            load(&self.parse_failures),
+        );
+        counter(
+            &mut out,
+            "walkpad_bluetooth_poll_write_errors_total",
+            "Failed query writes to polling treadmills",
+            load(&self.poll_write_errors),
+        );
+
+        let stats = writer.stats();
+        gauge(
+            &mut out,
+            "walkpad_writer_queue_depth",
+            "Samples waiting to be written",
+            stats.queue_depth as f64,
+        );
+        counter(
+            &mut out,
+            "walkpad_writer_samples_written_total",
+            "Samples stored in the database",
+            stats.written,
+        );
+        counter(
+            &mut out,
+            "walkpad_writer_samples_dropped_total",
+            "Samples lost because the queue was full or the spill file failed",
+            stats.dropped,
+        );
+        gauge(
+            &mut out,
+            "walkpad_writer_samples_spilled",
+            "Samples waiting in the spill file",
+            stats.spilled as f64,
+        );
+        counter(
+            &mut out,
+            "walkpad_writer_retries_total",
+            "Failed database writes that were retried",
+            stats.retries,
+        );
+        writer.write_latency().render(
+            &mut out,
+            "walkpad_db_write_seconds",
+            "Time taken by each batch write to the database",
+        );
+
+        gauge(
+            &mut out,
+            "walkpad_websocket_clients",
+            "Connected WebSocket clients",
+            self.websocket_clients.load(Ordering::Relaxed) as f64,
+        );
+        counter(
+            &mut out,
+            "walkpad_websocket_lagged_messages_total",
+            "Broadcast messages skipped because a client fell behind",
+            load(&self.websocket_lagged),
+        );
+        out
+    }
+}
+
+/// Keeps a WebSocket client counted while it's connected
+pub struct WebSocketClient(Arc<Metrics>);
+
+impl Drop for WebSocketClient {
+    fn drop(&mut self) {
+        self.0.websocket_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Cumulative latency histogram with fixed buckets
+#[derive(Debug, Default)]
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|&le| seconds <= le) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        header(out, name, "histogram", help);
+        let mut cumulative = 0;
+        for (le, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "counter", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    header(out, name, "gauge", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let histogram = LatencyHistogram::default();
+        histogram.observe(Duration::from_micros(800));
+        histogram.observe(Duration::from_millis(20));
+        histogram.observe(Duration::from_secs(10));
+
+        let mut out = String::new();
+        histogram.render(&mut out, "write_seconds", "Writes");
+        assert!(out.contains("# TYPE write_seconds histogram\n"));
+        assert!(out.contains("write_seconds_bucket{le=\"0.001\"} 1\n"));
+        assert!(out.contains("write_seconds_bucket{le=\"0.01\"} 1\n"));
+        assert!(out.contains("write_seconds_bucket{le=\"0.025\"} 2\n"));
+        assert!(out.contains("write_seconds_bucket{le=\"2.5\"} 2\n"));
+        assert!(out.contains("write_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("write_seconds_sum 10.0208\n"));
+        assert!(out.contains("write_seconds_count 3\n"));
+    }
+
+    #[test]
+    fn test_websocket_clients_are_counted_while_connected() {
+        let metrics = Arc::new(Metrics::default());
+        let first = metrics.websocket_client();
+        let second = metrics.websocket_client();
+        drop(first);
+        assert_eq!(metrics.websocket_clients.load(Ordering::Relaxed), 1);
+        drop(second);
+        assert_eq!(metrics.websocket_clients.load(Ordering::Relaxed), 0);
+    }
+}
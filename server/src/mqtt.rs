@@ -0,0 +1,738 @@
+//! Optional bridge to an MQTT broker, mainly for Home Assistant.
+//!
+//! Live state is published, retained, under `topic` (default `walkpad`):
+//!
+//! - `<topic>/availability` - `online`, or `offline` (also the last will)
+//! - `<topic>/bluetooth` - treadmill connection status, e.g. `connected`
+//! - `<topic>/walking` - `ON` while moving samples arrive, `OFF` after `idle_timeout_secs`
+//! - `<topic>/speed` - belt speed in m/s (0 once walking stops)
+//! - `<topic>/session` - JSON totals of the session under way
+//! - `<topic>/today` - JSON totals of the local day
+//!
+//! On every connection the bridge also publishes retained Home Assistant
+//! discovery configs under `discovery_prefix`, so the treadmill shows up as a
+//! device with a sensor for each value (and a Rescan button with commands on).
+//!
+//! With `command_topic` set, `rescan` drops the Bluetooth connection and
+//! scans again, and `user <id>` / `user none` switches the active user.
+
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDate, Utc, Weekday};
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+use tracing::{error, info, warn};
+
+use crate::bluetooth::{BluetoothManager, ConnectionStatus};
+use crate::config::MqttConfig;
+use crate::energy::{CaloriesSource, MAX_SAMPLE_GAP_SECS};
+use crate::sessions::SESSION_GAP_SECS;
+use crate::storage::{GroupBy, Storage};
+use crate::timezone::Zone;
+use crate::users::{ActiveUser, ChangeSource};
+use crate::websocket::{WsMessage, WsSample};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const REQUEST_CAPACITY: usize = 64;
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Totals of the session under way, kept from the live samples
+#[derive(Debug, Clone, Default, PartialEq)]
+struct LiveSession {
+    start: i64, // 0 until the first sample
+    end: i64,   // timestamp of the latest sample
+    duration_seconds: i64,
+    distance_meters: i64,
+    steps: i64,
+    calories_device: i64,
+    calories_estimated: f64,
+}
+
+impl LiveSession {
+    /// Add a moving sample, starting over after a session break
+    fn add(&mut self, sample: &WsSample) {
+        let gap = sample.timestamp - self.end;
+        if self.start == 0 || gap > SESSION_GAP_SECS {
+            *self = Self {
+                start: sample.timestamp,
+                end: sample.timestamp,
+                ..Self::default()
+            };
+        } else if (1..=MAX_SAMPLE_GAP_SECS).contains(&gap) {
+            self.duration_seconds += gap;
+        }
+        self.distance_meters += sample.distance_delta.unwrap_or(0);
+        self.steps += sample.steps_delta.unwrap_or(0);
+        self.calories_device += sample.calories_delta.unwrap_or(0);
+        self.calories_estimated += sample.calories_estimated.unwrap_or(0.0);
+        self.end = sample.timestamp;
+    }
+
+    fn payload(&self, calories_source: CaloriesSource) -> Value {
+        let calories = match calories_source {
+            CaloriesSource::Device => self.calories_device,
+            CaloriesSource::Estimated => self.calories_estimated.round() as i64,
+        };
+        json!({
+            "start": self.start,
+            "end": self.end,
+            "duration_seconds": self.duration_seconds,
+            "distance_meters": self.distance_meters,
+            "steps": self.steps,
+            "calories": calories,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Rescan,
+    SetUser(Option<i64>),
+}
+
+fn parse_command(payload: &str) -> Option<Command> {
+    let mut words = payload.split_whitespace();
+    match (words.next()?, words.next(), words.next()) {
+        ("rescan", None, None) => Some(Command::Rescan),
+        ("user", Some("none"), None) => Some(Command::SetUser(None)),
+        ("user", Some(id), None) => id.parse().ok().map(|id| Command::SetUser(Some(id))),
+        _ => None,
+    }
+}
+
+/// Home Assistant discovery configs, as (topic, payload)
+fn discovery(config: &MqttConfig) -> Vec<(String, String)> {
+    let node: String = config
+        .client_id
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect();
+    let state = |name: &str| format!("{}/{}", config.topic, name);
+
+    let mut entities = vec![
+        (
+            "sensor",
+            "speed".to_string(),
+            json!({
+                "name": "Speed",
+                "state_topic": state("speed"),
+                "device_class": "speed",
+                "unit_of_measurement": "m/s",
+                "state_class": "measurement",
+                "suggested_display_precision": 2,
+            }),
+        ),
+        (
+            "binary_sensor",
+            "walking".to_string(),
+            json!({
+                "name": "Walking",
+                "state_topic": state("walking"),
+                "device_class": "moving",
+            }),
+        ),
+        (
+            "sensor",
+            "bluetooth".to_string(),
+            json!({
+                "name": "Bluetooth",
+                "state_topic": state("bluetooth"),
+                "device_class": "enum",
+                "options": ConnectionStatus::ALL.map(|s| s.as_str()),
+                "entity_category": "diagnostic",
+            }),
+        ),
+    ];
+    for (group, label) in [("session", "Session"), ("today", "Today")] {
+        let totals = [
+            ("distance", "distance_meters", Some("distance"), "m"),
+            ("steps", "steps", None, "steps"),
+            ("duration", "duration_seconds", Some("duration"), "s"),
+            ("calories", "calories", None, "kcal"),
+        ];
+        for (name, field, device_class, unit) in totals {
+            let mut entity = json!({
+                "name": format!("{} {}", label, name),
+                "state_topic": state(group),
+                "value_template": format!("{{{{ value_json.{} }}}}", field),
+                "unit_of_measurement": unit,
+                "state_class": "total_increasing",
+            });
+            if let Some(class) = device_class {
+                entity["device_class"] = json!(class);
+            }
+            entities.push(("sensor", format!("{}_{}", group, name), entity));
+        }
+    }
+    if let Some(command_topic) = &config.command_topic {
+        entities.push((
+            "button",
+            "rescan".to_string(),
+            json!({
+                "name": "Rescan",
+                "command_topic": command_topic,
+                "payload_press": "rescan",
+                "entity_category": "config",
+            }),
+        ));
+    }
+
+    let device = json!({
+        "identifiers": [node],
+        "name": "Walking pad",
+        "manufacturer": "walkpad-server",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+    entities
+        .into_iter()
+        .map(|(component, object_id, mut entity)| {
+            entity["unique_id"] = json!(format!("{}_{}", node, object_id));
+            entity["availability_topic"] = json!(state("availability"));
+            entity["device"] = device.clone();
+            let topic = format!(
+                "{}/{}/{}/{}/config",
+                config.discovery_prefix, component, node, object_id
+            );
+            (topic, entity.to_string())
+        })
+        .collect()
+}
+
+/// Handle on the running bridge
+pub struct MqttBridge {
+    stop: Arc<Notify>,
+}
+
+impl MqttBridge {
+    /// Connect (and keep reconnecting) to the broker in `config.host`
+    pub fn start(
+        config: &MqttConfig,
+        storage: Arc<Storage>,
+        bluetooth: Arc<BluetoothManager>,
+        active_user: Arc<ActiveUser>,
+        ws_tx: &broadcast::Sender<WsMessage>,
+        zone: Zone,
+        calories_source: CaloriesSource,
+    ) -> Result<(Self, JoinHandle<()>)> {
+        let host = config
+            .host
+            .clone()
+            .ok_or_else(|| anyhow!("No MQTT broker configured"))?;
+        let mut options = MqttOptions::new(&config.client_id, &host, config.port);
+        options.set_keep_alive(KEEP_ALIVE);
+        options.set_last_will(LastWill::new(
+            format!("{}/availability", config.topic),
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.clone().unwrap_or_default());
+        }
+        let (client, events) = AsyncClient::new(options, REQUEST_CAPACITY);
+        info!("Publishing to MQTT broker at {}:{}", host, config.port);
+
+        let worker = Worker {
+            config: config.clone(),
+            client,
+            storage,
+            status_rx: bluetooth.subscribe_status(),
+            bluetooth,
+            active_user,
+            zone,
+            calories_source,
+            connected: false,
+            bluetooth_status: ConnectionStatus::Disconnected,
+            session: LiveSession::default(),
+            walking: false,
+            today: None,
+            today_due: false,
+        };
+        let stop = Arc::new(Notify::new());
+        let handle = tokio::spawn(worker.run(events, ws_tx.subscribe(), Arc::clone(&stop)));
+        Ok((Self { stop }, handle))
+    }
+
+    /// Mark the treadmill offline and disconnect
+    pub fn stop(&self) {
+        self.stop.notify_one();
+    }
+}
+
+struct Worker {
+    config: MqttConfig,
+    client: AsyncClient,
+    storage: Arc<Storage>,
+    bluetooth: Arc<BluetoothManager>,
+    status_rx: broadcast::Receiver<ConnectionStatus>,
+    active_user: Arc<ActiveUser>,
+    zone: Zone,
+    calories_source: CaloriesSource,
+    connected: bool, // to the broker; nothing is published while away
+    bluetooth_status: ConnectionStatus,
+    session: LiveSession,
+    walking: bool,
+    today: Option<NaiveDate>, // local day of the last published totals
+    today_due: bool,          // samples arrived since today's totals were published
+}
+
+impl Worker {
+    async fn run(
+        mut self,
+        mut events: EventLoop,
+        mut rx: broadcast::Receiver<WsMessage>,
+        stop: Arc<Notify>,
+    ) {
+        let mut idle_check = tokio::time::interval(Duration::from_secs(1));
+        let mut daily =
+            tokio::time::interval(Duration::from_secs(self.config.daily_interval_secs.max(1)));
+        daily.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                event = events.poll() => match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        info!("Connected to MQTT broker");
+                        self.connected = true;
+                        self.announce().await;
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        self.command(&publish.topic, &publish.payload).await;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        if self.connected {
+                            warn!("Lost MQTT connection: {}", e);
+                        }
+                        self.connected = false;
+                        sleep(RETRY_DELAY).await;
+                    }
+                },
+                message = rx.recv() => match message {
+                    Ok(WsMessage::NewSample { sample }) => self.sample(&sample),
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("MQTT bridge lagged behind by {} messages", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                status = self.status_rx.recv() => match status {
+                    Ok(status) => {
+                        self.publish("bluetooth", status.as_str());
+                        self.bluetooth_status = status;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = idle_check.tick() => {
+                    let idle = Utc::now().timestamp() - self.session.end;
+                    if self.walking && idle >= self.config.idle_timeout_secs as i64 {
+                        self.walking = false;
+                        self.publish("walking", "OFF");
+                        self.publish_speed(0.0);
+                        self.publish_today().await;
+                    }
+                }
+                _ = daily.tick() => {
+                    let today = self.zone.local_date(Utc::now().timestamp());
+                    if self.today_due || self.today != Some(today) {
+                        self.publish_today().await;
+                    }
+                }
+                _ = stop.notified() => break,
+            }
+        }
+
+        if self.connected {
+            self.publish("availability", "offline");
+            let _ = self.client.try_disconnect();
+            let _ = timeout(DISCONNECT_TIMEOUT, async {
+                while let Ok(event) = events.poll().await {
+                    if let Event::Outgoing(Outgoing::Disconnect) = event {
+                        break;
+                    }
+                }
+            })
+            .await;
+        }
+    }
+
+    /// Publish everything retained, after each (re)connection
+    async fn announce(&mut self) {
+        if !self.config.discovery_prefix.is_empty() {
+            for (topic, payload) in discovery(&self.config) {
+                self.send(topic, QoS::AtLeastOnce, payload);
+            }
+        }
+        self.publish("availability", "online");
+        self.publish("bluetooth", self.bluetooth_status.as_str());
+        self.publish("walking", if self.walking { "ON" } else { "OFF" });
+        if !self.walking {
+            self.publish_speed(0.0);
+        }
+        if self.session.start != 0 {
+            let session = self.session.payload(self.calories_source).to_string();
+            self.publish("session", session);
+        }
+        self.publish_today().await;
+
+        if let Some(topic) = &self.config.command_topic {
+            if let Err(e) = self.client.try_subscribe(topic, QoS::AtLeastOnce) {
+                error!("Couldn't subscribe to {}: {}", topic, e);
+            }
+        }
+    }
+
+    fn sample(&mut self, sample: &WsSample) {
+        self.session.add(sample);
+        self.today_due = true;
+        if !self.walking {
+            self.walking = true;
+            self.publish("walking", "ON");
+        }
+        self.publish_speed(sample.speed.unwrap_or(0.0));
+        let session = self.session.payload(self.calories_source).to_string();
+        self.publish("session", session);
+    }
+
+    async fn publish_today(&mut self) {
+        let today = self.zone.local_date(Utc::now().timestamp());
+        let days = self
+            .storage
+            .get_period_summaries(
+                GroupBy::Day,
+                Some((today, today)),
+                &self.zone,
+                Weekday::Mon,
+                None,
+            )
+            .await;
+        let payload = match days {
+            Ok(days) => match days.into_iter().next() {
+                Some(mut day) => {
+                    day.report_calories(self.calories_source);
+                    json!({
+                        "date": today,
+                        "duration_seconds": day.duration_seconds,
+                        "distance_meters": day.distance_meters,
+                        "steps": day.steps,
+                        "calories": day.calories,
+                    })
+                }
+                None => json!({
+                    "date": today,
+                    "duration_seconds": 0,
+                    "distance_meters": 0,
+                    "steps": 0,
+                    "calories": 0,
+                }),
+            },
+            Err(e) => {
+                error!("Couldn't compute today's totals for MQTT: {}", e);
+                return;
+            }
+        };
+        self.publish("today", payload.to_string());
+        self.today = Some(today);
+        self.today_due = false;
+    }
+
+    async fn command(&self, topic: &str, payload: &[u8]) {
+        if self.config.command_topic.as_deref() != Some(topic) {
+            return;
+        }
+        let text = String::from_utf8_lossy(payload);
+        match parse_command(&text) {
+            Some(Command::Rescan) => {
+                info!("Rescan requested over MQTT");
+                self.bluetooth.rescan();
+            }
+            Some(Command::SetUser(user_id)) => {
+                match self.active_user.set(user_id, ChangeSource::Mqtt).await {
+                    Ok(true) => info!("Active user set to {:?} over MQTT", user_id),
+                    Ok(false) => warn!("MQTT command for unknown user: {}", text),
+                    Err(e) => error!("Couldn't set the active user: {}", e),
+                }
+            }
+            None => warn!("Unknown MQTT command: {}", text),
+        }
+    }
+
+    fn publish_speed(&self, speed: f64) {
+        // Frequent and soon outdated, so no acknowledgement
+        let topic = format!("{}/speed", self.config.topic);
+        self.send(topic, QoS::AtMostOnce, format!("{:.2}", speed));
+    }
+
+    fn publish(&self, name: &str, payload: impl Into<Vec<u8>>) {
+        let topic = format!("{}/{}", self.config.topic, name);
+        self.send(topic, QoS::AtLeastOnce, payload);
+    }
+
+    /// Queue a retained message, unless the broker is away (everything is
+    /// published again on reconnection)
+    fn send(&self, topic: String, qos: QoS, payload: impl Into<Vec<u8>>) {
+        if !self.connected {
+            return;
+        }
+        if let Err(e) = self.client.try_publish(&topic, qos, true, payload) {
+            warn!("Couldn't publish to {}: {}", topic, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, EnergyConfig, QualityConfig, WriterConfig};
+    use crate::metrics::Metrics;
+    use crate::storage::test_support::test_storage_in;
+    use crate::writer::SampleWriter;
+    use bytes::BytesMut;
+    use rumqttc::{ConnAck, ConnectReturnCode, PubAck, Publish, SubAck, SubscribeReasonCode};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// Just enough of a broker for one client: acknowledges everything,
+    /// reports what was published and forwards injected messages
+    async fn broker() -> (
+        u16,
+        mpsc::UnboundedReceiver<Publish>,
+        mpsc::UnboundedSender<Publish>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (published_tx, published_rx) = mpsc::unbounded_channel();
+        let (inject_tx, mut inject_rx) = mpsc::unbounded_channel::<Publish>();
+
+        tokio::spawn(async move {
+            // The client may drop a connection and reconnect, as with a real broker
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut incoming = BytesMut::new();
+                loop {
+                    let mut out = BytesMut::new();
+                    tokio::select! {
+                        read = socket.read_buf(&mut incoming) => {
+                            if read.unwrap_or(0) == 0 {
+                                break;
+                            }
+                            while let Ok(packet) = rumqttc::mqttbytes::v4::read(&mut incoming, 1 << 20) {
+                                match packet {
+                                    Packet::Connect(_) => {
+                                        ConnAck::new(ConnectReturnCode::Success, false).write(&mut out).unwrap();
+                                    }
+                                    Packet::Publish(publish) => {
+                                        if publish.qos == QoS::AtLeastOnce {
+                                            PubAck::new(publish.pkid).write(&mut out).unwrap();
+                                        }
+                                        let _ = published_tx.send(publish);
+                                    }
+                                    Packet::Subscribe(subscribe) => {
+                                        let codes = subscribe
+                                            .filters
+                                            .iter()
+                                            .map(|_| SubscribeReasonCode::Success(QoS::AtLeastOnce))
+                                            .collect();
+                                        SubAck::new(subscribe.pkid, codes).write(&mut out).unwrap();
+                                    }
+                                    Packet::PingReq => {
+                                        rumqttc::PingResp.write(&mut out).unwrap();
+                                    }
+                                    _ => {}
+                                }
+                            }
+                        }
+                        Some(publish) = inject_rx.recv() => {
+                            publish.write(&mut out).unwrap();
+                        }
+                    }
+                    if !out.is_empty() && socket.write_all(&out).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        (port, published_rx, inject_tx)
+    }
+
+    /// Payload of the next message published to `topic`
+    async fn next_on(rx: &mut mpsc::UnboundedReceiver<Publish>, topic: &str) -> String {
+        timeout(Duration::from_secs(5), async {
+            loop {
+                let publish = rx.recv().await.expect("broker stopped");
+                if publish.topic == topic {
+                    assert!(publish.retain, "{} should be retained", topic);
+                    return String::from_utf8(publish.payload.to_vec()).unwrap();
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("nothing published to {}", topic))
+    }
+
+    fn sample(timestamp: i64, speed: f64) -> WsSample {
+        WsSample {
+            timestamp,
+            speed: Some(speed),
+            distance_delta: Some(2),
+            calories_delta: Some(1),
+            steps_delta: Some(3),
+            calories_estimated: Some(0.5),
+            user_id: None,
+            incline: None,
+            heart_rate: None,
+        }
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(parse_command("rescan"), Some(Command::Rescan));
+        assert_eq!(parse_command(" user 3\n"), Some(Command::SetUser(Some(3))));
+        assert_eq!(parse_command("user none"), Some(Command::SetUser(None)));
+        assert_eq!(parse_command("user bob"), None);
+        assert_eq!(parse_command("rescan now"), None);
+        assert_eq!(parse_command(""), None);
+    }
+
+    #[test]
+    fn test_live_session_starts_over_after_a_break() {
+        let mut session = LiveSession::default();
+        session.add(&sample(1000, 1.0));
+        session.add(&sample(1001, 1.0));
+        session.add(&sample(1003, 1.0));
+        assert_eq!(session.start, 1000);
+        assert_eq!(session.duration_seconds, 3);
+        assert_eq!(session.distance_meters, 6);
+        assert_eq!(session.payload(CaloriesSource::Estimated)["calories"], 2);
+
+        session.add(&sample(1003 + SESSION_GAP_SECS + 1, 1.0));
+        assert_eq!(session.start, 1003 + SESSION_GAP_SECS + 1);
+        assert_eq!(session.duration_seconds, 0);
+        assert_eq!(session.steps, 3);
+    }
+
+    #[tokio::test]
+    async fn test_publishes_state_and_takes_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Arc::new(test_storage_in(&dir).await);
+        let user = storage.create_user("Sam", None).await.unwrap();
+        let (ws_tx, _) = broadcast::channel(16);
+        let active_user = Arc::new(
+            ActiveUser::load(Arc::clone(&storage), ws_tx.clone())
+                .await
+                .unwrap(),
+        );
+        let (writer, _) = SampleWriter::start(
+            Arc::clone(&storage),
+            &WriterConfig::default(),
+            dir.path().join("test.db.spill"),
+        );
+        let (bluetooth, _) = BluetoothManager::new(
+            Arc::clone(&storage),
+            Arc::clone(&active_user),
+            Config::default().bluetooth,
+            EnergyConfig::default(),
+            QualityConfig::default(),
+            writer,
+            ws_tx.clone(),
+            Arc::new(Metrics::default()),
+        );
+
+        let (port, mut published, inject) = broker().await;
+        let config = MqttConfig {
+            host: Some("127.0.0.1".to_string()),
+            port,
+            command_topic: Some("walkpad/command".to_string()),
+            idle_timeout_secs: 1,
+            ..MqttConfig::default()
+        };
+        let (bridge, handle) = MqttBridge::start(
+            &config,
+            Arc::clone(&storage),
+            Arc::new(bluetooth),
+            Arc::clone(&active_user),
+            &ws_tx,
+            Zone::default(),
+            CaloriesSource::Device,
+        )
+        .unwrap();
+
+        // Discovery first, then the current state
+        let speed: Value = serde_json::from_str(
+            &next_on(&mut published, "homeassistant/sensor/walkpad/speed/config").await,
+        )
+        .unwrap();
+        assert_eq!(speed["state_topic"], "walkpad/speed");
+        assert_eq!(speed["unique_id"], "walkpad_speed");
+        assert_eq!(speed["availability_topic"], "walkpad/availability");
+        assert_eq!(speed["device"]["identifiers"][0], "walkpad");
+        let rescan: Value = serde_json::from_str(
+            &next_on(&mut published, "homeassistant/button/walkpad/rescan/config").await,
+        )
+        .unwrap();
+        assert_eq!(rescan["command_topic"], "walkpad/command");
+        assert_eq!(
+            next_on(&mut published, "walkpad/availability").await,
+            "online"
+        );
+        assert_eq!(
+            next_on(&mut published, "walkpad/bluetooth").await,
+            "disconnected"
+        );
+        assert_eq!(next_on(&mut published, "walkpad/walking").await, "OFF");
+        let today: Value =
+            serde_json::from_str(&next_on(&mut published, "walkpad/today").await).unwrap();
+        assert_eq!(today["distance_meters"], 0);
+
+        // Live samples
+        let now = Utc::now().timestamp();
+        ws_tx
+            .send(WsMessage::NewSample {
+                sample: sample(now, 1.25),
+            })
+            .unwrap();
+        assert_eq!(next_on(&mut published, "walkpad/walking").await, "ON");
+        assert_eq!(next_on(&mut published, "walkpad/speed").await, "1.25");
+        let session: Value =
+            serde_json::from_str(&next_on(&mut published, "walkpad/session").await).unwrap();
+        assert_eq!(session["start"], now);
+        assert_eq!(session["distance_meters"], 2);
+        assert_eq!(session["calories"], 1);
+
+        // Stopped walking
+        assert_eq!(next_on(&mut published, "walkpad/walking").await, "OFF");
+        assert_eq!(next_on(&mut published, "walkpad/speed").await, "0.00");
+
+        // Commands
+        inject
+            .send(Publish::new(
+                "walkpad/command",
+                QoS::AtMostOnce,
+                format!("user {}", user.id),
+            ))
+            .unwrap();
+        timeout(Duration::from_secs(5), async {
+            while active_user.get().await != Some(user.id) {
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("active user wasn't switched");
+
+        bridge.stop();
+        assert_eq!(
+            next_on(&mut published, "walkpad/availability").await,
+            "offline"
+        );
+        handle.await.unwrap();
+    }
+}
@@ -0,0 +1,471 @@
+//! Data quality checks for live samples.
+//!
+//! Every sample parsed from the treadmill passes through a [`QualityMonitor`]
+//! before it is recorded:
+//!
+//! - Physically implausible samples (a speed beyond the treadmill's range, or
+//!   counters jumping faster than anyone walks) are **rejected**. They never
+//!   reach the samples table, so they can't inflate totals, but are kept in
+//!   the quarantine table for inspection. Counter deltas skip over them: the
+//!   next accepted sample counts from the last accepted readings.
+//! - Gaps in the data while the belt was moving, and counters that stop
+//!   advancing while the belt runs, are **flagged**. The sample is recorded
+//!   normally and a quarantine entry notes the problem.
+//!
+//! [`daily_reports`] combines the quarantine with recorded sample counts into
+//! per-day reports (`/api/quality`).
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+use crate::config::QualityConfig;
+use crate::energy::MAX_SAMPLE_GAP_SECS;
+use crate::storage::{QuarantinedSample, Storage, TreadmillSample};
+use crate::timezone::Zone;
+
+/// Gaps longer than this are treated as the walker having left, not lost data
+const MAX_REPORTED_GAP_SECS: i64 = 60 * 60;
+
+/// Counters only update in whole units (LifeSpan distance moves in 0.01 mile,
+/// ~16 m, steps), so allow a little on top of the rate limits
+const DISTANCE_SLACK_M: f64 = 20.0;
+const STEPS_SLACK: f64 = 5.0;
+const CALORIES_SLACK: f64 = 2.0;
+
+/// Far above any walking or running effort (1800 kcal/h)
+const MAX_KCAL_PER_SEC: f64 = 0.5;
+
+/// Why a sample was quarantined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    SpeedOutOfRange,
+    DistanceJump,
+    StepsJump,
+    CaloriesJump,
+    Gap,
+    FrozenCounter,
+}
+
+impl Reason {
+    /// Whether samples with this problem are kept out of the samples table
+    pub fn rejects(self) -> bool {
+        !matches!(self, Reason::Gap | Reason::FrozenCounter)
+    }
+}
+
+/// One problem found with a sample
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Issue {
+    pub reason: Reason,
+    pub message: String,
+    /// The offending measurement (speed in m/s, counter delta, or seconds)
+    pub value: f64,
+}
+
+impl Issue {
+    fn new(reason: Reason, value: f64, message: String) -> Self {
+        Self {
+            reason,
+            message,
+            value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QuarantineStatus {
+    /// Not recorded
+    Rejected,
+    /// Recorded, but suspicious
+    Flagged,
+}
+
+impl QuarantineStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QuarantineStatus::Rejected => "rejected",
+            QuarantineStatus::Flagged => "flagged",
+        }
+    }
+
+    /// Rejected if any issue rejects the sample
+    pub fn for_issues(issues: &[Issue]) -> Self {
+        if issues.iter().any(|i| i.reason.rejects()) {
+            QuarantineStatus::Rejected
+        } else {
+            QuarantineStatus::Flagged
+        }
+    }
+}
+
+/// Tracks when a cumulative counter last advanced while the belt was moving
+#[derive(Debug, Default)]
+struct FrozenTracker {
+    unchanged_since: Option<i64>,
+    reported: bool,
+}
+
+impl FrozenTracker {
+    /// Returns how long the counter has been stuck, once it passes `limit`
+    fn update(&mut self, timestamp: i64, delta: Option<i64>, limit: i64) -> Option<i64> {
+        match delta {
+            Some(0) => {
+                let since = *self.unchanged_since.get_or_insert(timestamp);
+                let stuck = timestamp - since;
+                if stuck >= limit && !self.reported {
+                    self.reported = true;
+                    return Some(stuck);
+                }
+                None
+            }
+            // Advancing again, or not reported by this treadmill
+            _ => {
+                *self = Self::default();
+                None
+            }
+        }
+    }
+}
+
+/// Validation state for one live sample stream
+#[derive(Debug)]
+pub struct QualityMonitor {
+    config: QualityConfig,
+    /// Last notification of any kind, and whether the belt was moving then
+    last_seen: Option<(i64, bool)>,
+    /// Last sample the counter deltas are relative to (rejected ones don't
+    /// move the recorder's baselines, so they don't count)
+    last_checked: Option<i64>,
+    distance: FrozenTracker,
+    steps: FrozenTracker,
+}
+
+impl QualityMonitor {
+    pub fn new(config: QualityConfig) -> Self {
+        Self {
+            config,
+            last_seen: None,
+            last_checked: None,
+            distance: FrozenTracker::default(),
+            steps: FrozenTracker::default(),
+        }
+    }
+
+    /// Note a notification with the belt stopped (such samples aren't recorded)
+    pub fn observe_idle(&mut self, timestamp: i64) {
+        self.last_seen = Some((timestamp, false));
+        self.distance = FrozenTracker::default();
+        self.steps = FrozenTracker::default();
+    }
+
+    /// Check a moving sample. An empty result means the sample is clean.
+    pub fn check(&mut self, sample: &TreadmillSample) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        let ts = sample.timestamp;
+
+        // Data stopped arriving while the belt was moving
+        if let Some((last, true)) = self.last_seen {
+            let gap = ts - last;
+            if gap > MAX_SAMPLE_GAP_SECS && gap <= MAX_REPORTED_GAP_SECS {
+                issues.push(Issue::new(
+                    Reason::Gap,
+                    gap as f64,
+                    format!("No data for {}s while moving", gap),
+                ));
+            }
+        }
+        let resumed = self
+            .last_seen
+            .is_none_or(|(last, _)| ts - last > MAX_SAMPLE_GAP_SECS);
+        self.last_seen = Some((ts, true));
+
+        if let Some(speed) = sample.speed {
+            if !(0.0..=self.config.max_speed_ms).contains(&speed) {
+                issues.push(Issue::new(
+                    Reason::SpeedOutOfRange,
+                    speed,
+                    format!(
+                        "Speed {:.2} m/s outside 0-{:.2} m/s",
+                        speed, self.config.max_speed_ms
+                    ),
+                ));
+            }
+        }
+
+        // Counter deltas cover the time since the previous accepted sample
+        let elapsed = self.last_checked.map_or(1, |last| (ts - last).max(1)) as f64;
+
+        let limits = [
+            (
+                Reason::DistanceJump,
+                sample.distance_delta,
+                self.config.max_speed_ms * elapsed + DISTANCE_SLACK_M,
+                "m",
+            ),
+            (
+                Reason::StepsJump,
+                sample.steps_delta,
+                self.config.max_steps_per_sec * elapsed + STEPS_SLACK,
+                "steps",
+            ),
+            (
+                Reason::CaloriesJump,
+                sample.calories_delta,
+                MAX_KCAL_PER_SEC * elapsed + CALORIES_SLACK,
+                "kcal",
+            ),
+        ];
+        for (reason, delta, limit, unit) in limits {
+            if let Some(delta) = delta.filter(|&d| d as f64 > limit) {
+                issues.push(Issue::new(
+                    reason,
+                    delta as f64,
+                    format!("+{} {} in {}s (limit {:.0})", delta, unit, elapsed, limit),
+                ));
+            }
+        }
+
+        // After a pause the counters legitimately sit still for a moment
+        if resumed {
+            self.distance = FrozenTracker::default();
+            self.steps = FrozenTracker::default();
+        }
+        let frozen_limit = self.config.frozen_counter_secs;
+        let frozen = [
+            (
+                "distance",
+                self.distance
+                    .update(ts, sample.distance_delta, frozen_limit),
+            ),
+            (
+                "steps",
+                self.steps.update(ts, sample.steps_delta, frozen_limit),
+            ),
+        ];
+        for (counter, stuck) in frozen {
+            if let Some(stuck) = stuck {
+                issues.push(Issue::new(
+                    Reason::FrozenCounter,
+                    stuck as f64,
+                    format!("{} counter unchanged for {}s while moving", counter, stuck),
+                ));
+            }
+        }
+
+        if !issues.iter().any(|i| i.reason.rejects()) {
+            self.last_checked = Some(ts);
+        }
+        issues
+    }
+
+    /// The recorder took a rejected sample's counters as its new baselines
+    /// (see `BluetoothManager`), so later deltas are relative to it
+    pub fn rebased(&mut self, timestamp: i64) {
+        self.last_checked = Some(timestamp);
+    }
+}
+
+/// Data quality for one local day
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct QualityReport {
+    pub date: String, // YYYY-MM-DD
+    /// Samples counted in the day's totals
+    pub recorded_samples: i64,
+    pub rejected_samples: i64,
+    pub flagged_samples: i64,
+    /// How many quarantined samples had each problem
+    pub reasons: BTreeMap<Reason, i64>,
+    /// Seconds of data missing while the belt was moving
+    pub gap_seconds: i64,
+}
+
+impl QualityReport {
+    fn add(&mut self, entry: &QuarantinedSample) {
+        match entry.status {
+            QuarantineStatus::Rejected => self.rejected_samples += 1,
+            QuarantineStatus::Flagged => self.flagged_samples += 1,
+        }
+        for issue in &entry.issues {
+            *self.reasons.entry(issue.reason).or_default() += 1;
+            if issue.reason == Reason::Gap {
+                self.gap_seconds += issue.value as i64;
+            }
+        }
+    }
+}
+
+/// Reports for every day with recorded or quarantined samples, newest first
+pub async fn daily_reports(
+    storage: &Storage,
+    zone: &Zone,
+    user_id: Option<i64>,
+) -> Result<Vec<QualityReport>> {
+    let mut days: BTreeMap<String, QualityReport> = BTreeMap::new();
+
+    for summary in storage.get_all_daily_summaries(zone, user_id).await? {
+        if summary.total_samples > 0 {
+            days.entry(summary.date.clone())
+                .or_default()
+                .recorded_samples = summary.total_samples;
+        }
+    }
+    for entry in storage.get_quarantined(None, user_id).await? {
+        let date = zone.local_date(entry.timestamp).format("%Y-%m-%d");
+        days.entry(date.to_string()).or_default().add(&entry);
+    }
+
+    Ok(days
+        .into_iter()
+        .rev()
+        .map(|(date, report)| QualityReport { date, ..report })
+        .collect())
+}
+
+/// The report for one day, with the quarantined samples behind it
+pub async fn report_for_date(
+    storage: &Storage,
+    date: NaiveDate,
+    zone: &Zone,
+    user_id: Option<i64>,
+) -> Result<(QualityReport, Vec<QuarantinedSample>)> {
+    let mut report = QualityReport {
+        date: date.format("%Y-%m-%d").to_string(),
+        ..Default::default()
+    };
+
+    if let Some(summary) = storage.get_daily_summary(date, zone, user_id).await? {
+        report.recorded_samples = summary.total_samples;
+    }
+    let entries = storage
+        .get_quarantined(Some(zone.day_bounds(date)), user_id)
+        .await?;
+    entries.iter().for_each(|e| report.add(e));
+
+    Ok((report, entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_support::{test_sample, test_storage};
+
+    fn monitor() -> QualityMonitor {
+        QualityMonitor::new(QualityConfig::default())
+    }
+
+    fn sample(timestamp: i64, speed: f64, distance: i64, steps: i64) -> TreadmillSample {
+        TreadmillSample {
+            speed: Some(speed),
+            distance_delta: Some(distance),
+            steps_delta: Some(steps),
+            ..test_sample(timestamp)
+        }
+    }
+
+    fn reasons(issues: &[Issue]) -> Vec<Reason> {
+        issues.iter().map(|i| i.reason).collect()
+    }
+
+    #[test]
+    fn test_rejects_implausible_samples() {
+        let mut m = monitor();
+        assert!(m.check(&sample(100, 1.0, 0, 0)).is_empty());
+        assert!(m.check(&sample(101, 1.0, 16, 2)).is_empty());
+
+        // +2000 steps in a second
+        let issues = m.check(&sample(102, 1.0, 1, 2000));
+        assert_eq!(reasons(&issues), vec![Reason::StepsJump]);
+        assert_eq!(
+            QuarantineStatus::for_issues(&issues),
+            QuarantineStatus::Rejected
+        );
+
+        // 6 mph on a walking pad
+        let issues = m.check(&sample(103, 2.7, 2, 2));
+        assert_eq!(reasons(&issues), vec![Reason::SpeedOutOfRange]);
+
+        // Steps taken during a minute without data are plausible, only the gap is flagged
+        let issues = m.check(&sample(163, 1.0, 60, 100));
+        assert_eq!(reasons(&issues), vec![Reason::Gap]);
+    }
+
+    #[test]
+    fn test_flags_gaps_and_frozen_counters() {
+        let mut m = monitor();
+        m.check(&sample(100, 1.0, 0, 0));
+
+        // Lost data while moving
+        let issues = m.check(&sample(130, 1.0, 30, 40));
+        assert_eq!(reasons(&issues), vec![Reason::Gap]);
+        assert_eq!(issues[0].value, 30.0);
+        assert_eq!(
+            QuarantineStatus::for_issues(&issues),
+            QuarantineStatus::Flagged
+        );
+
+        // A pause (idle notifications) is not a gap
+        m.observe_idle(135);
+        m.observe_idle(400);
+        assert!(m.check(&sample(401, 1.0, 0, 0)).is_empty());
+
+        // Steps stop advancing while the belt keeps running: flagged once
+        let limit = QualityConfig::default().frozen_counter_secs;
+        let mut flagged = Vec::new();
+        for ts in 402..=402 + limit + 5 {
+            flagged.extend(m.check(&sample(ts, 1.0, 1, 0)));
+        }
+        assert_eq!(reasons(&flagged), vec![Reason::FrozenCounter]);
+        assert!(flagged[0].message.starts_with("steps"));
+    }
+
+    #[tokio::test]
+    async fn test_daily_reports() {
+        let (_dir, storage) = test_storage().await;
+
+        // 2025-01-15 UTC: one clean sample, one rejected, one flagged
+        let day = 1736899200 + 3600;
+        let mut m = monitor();
+        for s in [
+            sample(day, 1.0, 0, 0),
+            sample(day + 1, 1.0, 1, 3000),
+            sample(day + 40, 1.0, 30, 50),
+        ] {
+            let issues = m.check(&s);
+            if issues.is_empty() {
+                storage.add_samples(std::slice::from_ref(&s)).await.unwrap();
+                continue;
+            }
+            let status = QuarantineStatus::for_issues(&issues);
+            storage
+                .quarantine_sample(&s, status, &issues)
+                .await
+                .unwrap();
+            if status == QuarantineStatus::Flagged {
+                storage.add_samples(std::slice::from_ref(&s)).await.unwrap();
+            }
+        }
+
+        let reports = daily_reports(&storage, &Zone::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert_eq!(report.date, "2025-01-15");
+        assert_eq!(report.recorded_samples, 2);
+        assert_eq!((report.rejected_samples, report.flagged_samples), (1, 1));
+        assert_eq!(report.reasons[&Reason::StepsJump], 1);
+        assert_eq!(report.gap_seconds, 39);
+
+        let date = NaiveDate::from_ymd_opt(2025, 1, 15).unwrap();
+        let (_, entries) = report_for_date(&storage, date, &Zone::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].sample.steps_delta, Some(3000));
+    }
+}
@@ -0,0 +1,636 @@
+//! Personal records and lifetime achievements.
+//!
+//! Records are kept up to date incrementally: each scope (everyone, and each
+//! user) has a small set of running counters (the current session, day and
+//! week, the activity streak, lifetime distance) that every committed sample
+//! advances. The counters are persisted, so a restart doesn't rescan history.
+//! Only [`RecordsEngine::rebuild`] walks every stored sample, for a new
+//! database or after history was imported, deleted or reassigned. Those
+//! rebuilds run in the live task ([`RebuildRequests`]), not in the request
+//! that changed history.
+//!
+//! Days and weeks (Monday to Sunday) are local to the server's time zone.
+
+use anyhow::Result;
+use chrono::{Duration, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex, Notify};
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+use crate::energy::MAX_SAMPLE_GAP_SECS;
+use crate::sessions::SESSION_GAP_SECS;
+use crate::storage::{Achievement, PersonalRecord, Storage};
+use crate::timezone::Zone;
+use crate::websocket::{WsMessage, WsSample};
+
+pub(crate) const METERS_PER_MILE: f64 = 1609.344;
+
+const BATCH_SIZE: i64 = 10_000;
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum RecordKind {
+    /// Active seconds in one session
+    LongestSession,
+    /// Steps in one local day
+    MostStepsDay,
+    /// Seconds to cover a mile within one session (lower is better)
+    FastestMile,
+    /// Consecutive days with activity
+    LongestStreak,
+    /// Meters in one week
+    BiggestWeek,
+}
+
+impl RecordKind {
+    fn beats(self, value: f64, best: f64) -> bool {
+        match self {
+            RecordKind::FastestMile => value < best,
+            _ => value > best,
+        }
+    }
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
+pub enum Milestone {
+    #[serde(rename = "lifetime_100_miles")]
+    #[sqlx(rename = "lifetime_100_miles")]
+    Lifetime100Miles,
+    #[serde(rename = "lifetime_500_miles")]
+    #[sqlx(rename = "lifetime_500_miles")]
+    Lifetime500Miles,
+    #[serde(rename = "lifetime_1000_miles")]
+    #[sqlx(rename = "lifetime_1000_miles")]
+    Lifetime1000Miles,
+}
+
+impl Milestone {
+    const ALL: [Milestone; 3] = [
+        Milestone::Lifetime100Miles,
+        Milestone::Lifetime500Miles,
+        Milestone::Lifetime1000Miles,
+    ];
+
+    fn meters(self) -> f64 {
+        let miles = match self {
+            Milestone::Lifetime100Miles => 100.0,
+            Milestone::Lifetime500Miles => 500.0,
+            Milestone::Lifetime1000Miles => 1000.0,
+        };
+        miles * METERS_PER_MILE
+    }
+}
+
+/// Running counters for one scope, persisted after every sample
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecordCounters {
+    pub last_timestamp: Option<i64>,
+    pub lifetime_distance: i64,
+    pub session_start: i64,
+    pub session_active_secs: i64,
+    pub session_distance: i64,
+    pub day: Option<NaiveDate>,
+    pub day_steps: i64,
+    pub week: Option<NaiveDate>,
+    pub week_distance: i64,
+    pub streak_days: i64,
+    /// (timestamp, session distance) points covering the last mile. Not
+    /// persisted: after a restart the fastest mile restarts with the next session.
+    #[serde(skip)]
+    mile_window: VecDeque<(i64, i64)>,
+}
+
+/// Everything tracked for one scope
+#[derive(Debug, Default)]
+struct Scope {
+    counters: RecordCounters,
+    records: BTreeMap<RecordKind, PersonalRecord>,
+    achievements: BTreeMap<Milestone, Achievement>,
+    /// Records already announced for the current session/day/week, so a
+    /// record-setting walk is announced once rather than every second
+    announced: BTreeSet<RecordKind>,
+}
+
+impl Scope {
+    /// Treat records set during the current session, day or week as already
+    /// announced, so resuming after a restart doesn't repeat them
+    fn mark_current_announced(&mut self) {
+        let c = &self.counters;
+        for record in self.records.values() {
+            let date = NaiveDate::parse_from_str(&record.date, "%Y-%m-%d").ok();
+            let current = match record.kind {
+                RecordKind::LongestSession | RecordKind::FastestMile => {
+                    record.achieved_at >= c.session_start
+                }
+                RecordKind::MostStepsDay | RecordKind::LongestStreak => date == c.day,
+                RecordKind::BiggestWeek => date.map(week_start) == c.week,
+            };
+            if current {
+                self.announced.insert(record.kind);
+            }
+        }
+    }
+}
+
+fn week_start(date: NaiveDate) -> NaiveDate {
+    date.week(Weekday::Mon).first_day()
+}
+
+/// What a sample changed
+#[derive(Debug, Default)]
+struct Changes {
+    records: Vec<(PersonalRecord, Option<f64>)>, // (new record, previous best)
+    achievements: Vec<Achievement>,
+}
+
+pub struct RecordsEngine {
+    storage: Arc<Storage>,
+    ws_tx: broadcast::Sender<WsMessage>,
+    zone: Zone,
+    scopes: HashMap<Option<i64>, Scope>,
+}
+
+impl RecordsEngine {
+    /// Load persisted counters, records and achievements
+    pub async fn load(
+        storage: Arc<Storage>,
+        ws_tx: broadcast::Sender<WsMessage>,
+        zone: Zone,
+    ) -> Result<Self> {
+        let mut scopes: HashMap<Option<i64>, Scope> = HashMap::new();
+
+        for (user_id, counters) in storage.get_record_counters().await? {
+            scopes.entry(user_id).or_default().counters = counters;
+        }
+        for record in storage.get_all_personal_records().await? {
+            scopes
+                .entry(record.user_id)
+                .or_default()
+                .records
+                .insert(record.kind, record);
+        }
+        for achievement in storage.get_all_achievements().await? {
+            scopes
+                .entry(achievement.user_id)
+                .or_default()
+                .achievements
+                .insert(achievement.milestone, achievement);
+        }
+        scopes.values_mut().for_each(Scope::mark_current_announced);
+
+        Ok(Self {
+            storage,
+            ws_tx,
+            zone,
+            scopes,
+        })
+    }
+
+    /// True when nothing has been tracked yet (a new or upgraded database)
+    pub fn is_empty(&self) -> bool {
+        self.scopes.is_empty()
+    }
+
+    /// Advance the counters with samples committed since the last one
+    /// counted, store what changed (once per batch) and broadcast new records
+    /// and achievements. Returns the number of samples processed.
+    pub async fn catch_up(&mut self) -> Result<u64> {
+        let mut processed = 0;
+
+        loop {
+            let after = self
+                .scopes
+                .get(&None)
+                .and_then(|scope| scope.counters.last_timestamp);
+            let samples = self
+                .storage
+                .get_active_samples_after(after, BATCH_SIZE)
+                .await?;
+            if samples.is_empty() {
+                break;
+            }
+
+            let mut touched = BTreeSet::new();
+            let mut improved = HashMap::new();
+            for sample in samples {
+                let sample = WsSample::from(sample);
+                for user_id in scopes_of(&sample) {
+                    let scope = self.scopes.entry(user_id).or_default();
+                    if scope
+                        .counters
+                        .last_timestamp
+                        .is_some_and(|last| sample.timestamp <= last)
+                    {
+                        continue; // already counted (e.g. by a rebuild)
+                    }
+                    touched.insert(user_id);
+
+                    let changes = advance(scope, user_id, &sample, &self.zone);
+                    for (record, previous) in changes.records {
+                        improved.insert((user_id, record.kind), record.clone());
+                        if scope.announced.insert(record.kind) {
+                            info!(
+                                "New record for {:?}: {:?} = {} (was {:?})",
+                                user_id, record.kind, record.value, previous
+                            );
+                            let _ = self.ws_tx.send(WsMessage::NewRecord { record, previous });
+                        }
+                    }
+                    for achievement in changes.achievements {
+                        self.storage.add_achievement(&achievement).await?;
+                        info!("Achievement for {:?}: {:?}", user_id, achievement.milestone);
+                        let _ = self
+                            .ws_tx
+                            .send(WsMessage::AchievementUnlocked { achievement });
+                    }
+                }
+                processed += 1;
+            }
+
+            for record in improved.values() {
+                self.storage.save_personal_record(record).await?;
+            }
+            for user_id in touched {
+                self.storage
+                    .save_record_counters(user_id, &self.scopes[&user_id].counters)
+                    .await?;
+            }
+        }
+
+        Ok(processed)
+    }
+
+    /// Recompute everything from stored samples. Nothing is broadcast.
+    /// Returns the number of samples processed.
+    pub async fn rebuild(&mut self) -> Result<u64> {
+        let mut scopes: HashMap<Option<i64>, Scope> = HashMap::new();
+        let mut after = None;
+        let mut processed = 0;
+
+        loop {
+            let samples = self
+                .storage
+                .get_active_samples_after(after, BATCH_SIZE)
+                .await?;
+            let Some(last) = samples.last() else {
+                break;
+            };
+            after = Some(last.timestamp);
+
+            for sample in samples {
+                let sample = WsSample::from(sample);
+                for user_id in scopes_of(&sample) {
+                    advance(
+                        scopes.entry(user_id).or_default(),
+                        user_id,
+                        &sample,
+                        &self.zone,
+                    );
+                }
+                processed += 1;
+            }
+        }
+
+        let counters: Vec<_> = scopes.iter().map(|(id, s)| (*id, &s.counters)).collect();
+        let records: Vec<_> = scopes.values().flat_map(|s| s.records.values()).collect();
+        let achievements: Vec<_> = scopes
+            .values()
+            .flat_map(|s| s.achievements.values())
+            .collect();
+
+        self.storage
+            .replace_records(&counters, &records, &achievements)
+            .await?;
+        scopes.values_mut().for_each(Scope::mark_current_announced);
+        self.scopes = scopes;
+
+        info!("Rebuilt records from {} samples", processed);
+        Ok(processed)
+    }
+}
+
+/// Scopes a sample counts towards: everyone, plus its user
+fn scopes_of(sample: &WsSample) -> Vec<Option<i64>> {
+    let mut scopes = vec![None];
+    if sample.user_id.is_some() {
+        scopes.push(sample.user_id);
+    }
+    scopes
+}
+
+/// Advance one scope's counters with a sample and collect improved records
+fn advance(scope: &mut Scope, user_id: Option<i64>, sample: &WsSample, zone: &Zone) -> Changes {
+    let c = &mut scope.counters;
+    let ts = sample.timestamp;
+    let distance = sample.distance_delta.unwrap_or(0).max(0);
+    let steps = sample.steps_delta.unwrap_or(0).max(0);
+    let date = zone.local_date(ts);
+    let week = week_start(date);
+
+    // Session
+    let gap = c.last_timestamp.map(|last| ts - last);
+    if gap.is_none_or(|g| g > SESSION_GAP_SECS) {
+        c.session_start = ts;
+        c.session_active_secs = 0;
+        c.session_distance = 0;
+        c.mile_window.clear();
+        scope.announced.remove(&RecordKind::LongestSession);
+        scope.announced.remove(&RecordKind::FastestMile);
+    } else if let Some(g) = gap.filter(|g| (1..=MAX_SAMPLE_GAP_SECS).contains(g)) {
+        c.session_active_secs += g;
+    }
+    c.session_distance += distance;
+    c.lifetime_distance += distance;
+    c.last_timestamp = Some(ts);
+
+    // Day and streak
+    if c.day != Some(date) {
+        c.streak_days = match c.day {
+            Some(previous) if previous + Duration::days(1) == date => c.streak_days + 1,
+            _ => 1,
+        };
+        c.day = Some(date);
+        c.day_steps = 0;
+        scope.announced.remove(&RecordKind::MostStepsDay);
+        scope.announced.remove(&RecordKind::LongestStreak);
+    }
+    c.day_steps += steps;
+
+    // Week
+    if c.week != Some(week) {
+        c.week = Some(week);
+        c.week_distance = 0;
+        scope.announced.remove(&RecordKind::BiggestWeek);
+    }
+    c.week_distance += distance;
+
+    // Fastest mile: the shortest window of this session covering a mile
+    c.mile_window.push_back((ts, c.session_distance));
+    while c.mile_window.len() > 1
+        && (c.session_distance - c.mile_window[1].1) as f64 >= METERS_PER_MILE
+    {
+        c.mile_window.pop_front();
+    }
+    let mile_secs = c
+        .mile_window
+        .front()
+        .filter(|(_, d)| (c.session_distance - d) as f64 >= METERS_PER_MILE)
+        .map(|(start, _)| (ts - start) as f64);
+
+    let candidates = [
+        (
+            RecordKind::LongestSession,
+            Some(c.session_active_secs as f64),
+        ),
+        (RecordKind::MostStepsDay, Some(c.day_steps as f64)),
+        (RecordKind::FastestMile, mile_secs),
+        (RecordKind::LongestStreak, Some(c.streak_days as f64)),
+        (RecordKind::BiggestWeek, Some(c.week_distance as f64)),
+    ];
+
+    let mut changes = Changes::default();
+    let date_str = date.format("%Y-%m-%d").to_string();
+    for (kind, value) in candidates {
+        let Some(value) = value.filter(|v| *v > 0.0) else {
+            continue;
+        };
+        let previous = scope.records.get(&kind).map(|r| r.value);
+        if previous.is_some_and(|best| !kind.beats(value, best)) {
+            continue;
+        }
+        let record = PersonalRecord {
+            user_id,
+            kind,
+            value,
+            date: date_str.clone(),
+            achieved_at: ts,
+        };
+        scope.records.insert(kind, record.clone());
+        changes.records.push((record, previous));
+    }
+
+    for milestone in Milestone::ALL {
+        if c.lifetime_distance as f64 >= milestone.meters()
+            && !scope.achievements.contains_key(&milestone)
+        {
+            let achievement = Achievement {
+                user_id,
+                milestone,
+                date: date_str.clone(),
+                achieved_at: ts,
+            };
+            scope.achievements.insert(milestone, achievement.clone());
+            changes.achievements.push(achievement);
+        }
+    }
+
+    changes
+}
+
+/// Handle for asking the live task to rebuild after history changed (cheap
+/// to clone). Requests made while a rebuild runs are served by one more.
+#[derive(Clone, Default)]
+pub struct RebuildRequests(Arc<Notify>);
+
+impl RebuildRequests {
+    pub fn request(&self) {
+        self.0.notify_one();
+    }
+}
+
+/// Keep the engine up to date with committed samples (`committed` is
+/// notified by the sample writer) and run requested rebuilds. On a new
+/// database, history is processed first.
+pub async fn run_live(
+    engine: Arc<Mutex<RecordsEngine>>,
+    committed: Arc<Notify>,
+    rebuilds: RebuildRequests,
+) {
+    {
+        let mut engine = engine.lock().await;
+        let result = if engine.is_empty() {
+            engine.rebuild().await
+        } else {
+            engine.catch_up().await
+        };
+        if let Err(e) = result {
+            error!("Failed to build personal records: {}", e);
+        }
+    }
+
+    loop {
+        tokio::select! {
+            _ = rebuilds.0.notified() => {
+                if let Err(e) = engine.lock().await.rebuild().await {
+                    error!("Failed to rebuild personal records: {}", e);
+                }
+            }
+            _ = committed.notified() => {
+                if let Err(e) = engine.lock().await.catch_up().await {
+                    error!("Failed to update personal records: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_support::{test_sample, test_storage};
+    use crate::storage::TreadmillSample;
+
+    // 2025-01-15 10:00 UTC, a Wednesday
+    const START: i64 = 1736935200;
+
+    fn sample(timestamp: i64, distance: i64, steps: i64) -> TreadmillSample {
+        TreadmillSample {
+            speed: Some(1.5),
+            distance_delta: Some(distance),
+            steps_delta: Some(steps),
+            user_id: Some(1),
+            ..test_sample(timestamp)
+        }
+    }
+
+    fn walk(scope: &mut Scope, start: i64, secs: i64, meters_per_sec: i64) -> Changes {
+        let mut all = Changes::default();
+        for i in 0..secs {
+            let s = WsSample::from(sample(start + i, meters_per_sec, 2));
+            let changes = advance(scope, Some(1), &s, &Zone::default());
+            all.records.extend(changes.records);
+            all.achievements.extend(changes.achievements);
+        }
+        all
+    }
+
+    #[test]
+    fn test_sessions_miles_and_streaks() {
+        let mut scope = Scope::default();
+
+        // 20 minutes at 2 m/s: a 2400 m session with a mile in 805 s
+        walk(&mut scope, START, 1200, 2);
+        assert_eq!(scope.records[&RecordKind::LongestSession].value, 1199.0);
+        assert_eq!(scope.records[&RecordKind::FastestMile].value, 805.0);
+        assert_eq!(scope.records[&RecordKind::MostStepsDay].value, 2400.0);
+
+        // A later, shorter but faster session beats only the mile
+        let changes = walk(&mut scope, START + 3600, 700, 3);
+        let kinds: BTreeSet<RecordKind> = changes.records.iter().map(|(r, _)| r.kind).collect();
+        assert!(kinds.contains(&RecordKind::FastestMile));
+        assert!(!kinds.contains(&RecordKind::LongestSession));
+        assert_eq!(scope.records[&RecordKind::FastestMile].value, 537.0);
+        assert_eq!(scope.records[&RecordKind::LongestSession].value, 1199.0);
+
+        // Walking the next two days builds a streak; skipping a day resets it
+        walk(&mut scope, START + 86_400, 10, 1);
+        walk(&mut scope, START + 2 * 86_400, 10, 1);
+        assert_eq!(scope.records[&RecordKind::LongestStreak].value, 3.0);
+        walk(&mut scope, START + 4 * 86_400, 10, 1);
+        assert_eq!(scope.counters.streak_days, 1);
+        assert_eq!(scope.records[&RecordKind::LongestStreak].value, 3.0);
+    }
+
+    #[test]
+    fn test_lifetime_milestones() {
+        let mut scope = Scope::default();
+        scope.counters.lifetime_distance = (100.0 * METERS_PER_MILE) as i64 - 5;
+
+        let changes = walk(&mut scope, START, 3, 3);
+        assert_eq!(changes.achievements.len(), 1);
+        assert_eq!(
+            changes.achievements[0].milestone,
+            Milestone::Lifetime100Miles
+        );
+        assert!(walk(&mut scope, START + 10, 3, 3).achievements.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_process_persists_and_announces_once() {
+        let (_dir, storage) = test_storage().await;
+        let storage = Arc::new(storage);
+        let (ws_tx, mut rx) = broadcast::channel(64);
+
+        let mut engine = RecordsEngine::load(Arc::clone(&storage), ws_tx.clone(), Zone::default())
+            .await
+            .unwrap();
+        let samples: Vec<_> = (0..5).map(|i| sample(START + i, 1, 2)).collect();
+        storage.add_samples(&samples).await.unwrap();
+        assert_eq!(engine.catch_up().await.unwrap(), 5);
+        assert_eq!(engine.catch_up().await.unwrap(), 0);
+
+        // Each kind announced once per scope despite improving every sample
+        let mut announced = Vec::new();
+        while let Ok(msg) = rx.try_recv() {
+            if let WsMessage::NewRecord { record, .. } = msg {
+                announced.push((record.user_id, record.kind));
+            }
+        }
+        assert_eq!(announced.len(), 8); // 4 kinds (no mile yet) x 2 scopes
+
+        let records = storage.get_personal_records(Some(1)).await.unwrap();
+        let steps = records
+            .iter()
+            .find(|r| r.kind == RecordKind::MostStepsDay)
+            .unwrap();
+        assert_eq!(steps.value, 10.0);
+
+        // After a restart the counters resume and today's records stay quiet
+        let mut engine = RecordsEngine::load(Arc::clone(&storage), ws_tx, Zone::default())
+            .await
+            .unwrap();
+        storage
+            .add_samples(&[sample(START + 5, 1, 2)])
+            .await
+            .unwrap();
+        assert_eq!(engine.catch_up().await.unwrap(), 1);
+        assert!(rx.try_recv().is_err());
+        let records = storage.get_personal_records(None).await.unwrap();
+        let session = records
+            .iter()
+            .find(|r| r.kind == RecordKind::LongestSession)
+            .unwrap();
+        assert_eq!(session.value, 5.0);
+
+        // A rebuild reaches the same result from the samples alone
+        assert_eq!(engine.rebuild().await.unwrap(), 6);
+        let rebuilt = storage.get_personal_records(Some(1)).await.unwrap();
+        assert_eq!(rebuilt.len(), 4);
+        assert!(rebuilt
+            .iter()
+            .any(|r| r.kind == RecordKind::MostStepsDay && r.value == 12.0));
+    }
+}
@@ -0,0 +1,142 @@
+//! Walking sessions: runs of moving samples with no break longer than
+//! [`SESSION_GAP_SECS`].
+//!
+//! Sessions aren't stored; they're found from the samples on demand. A
+//! session is identified by the timestamp of its first sample, which stays
+//! the same as the session grows.
+
+use anyhow::Result;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::energy::MAX_SAMPLE_GAP_SECS;
+use crate::storage::{Storage, TreadmillSample};
+
+/// A break longer than this ends a session
+pub const SESSION_GAP_SECS: i64 = 5 * 60;
+
+/// How far before a range to look for the start of a session already under way
+const LOOKBACK_SECS: i64 = 12 * 3600;
+
+/// How far after its start `find_session` looks for the rest of a session
+const MAX_SESSION_SECS: i64 = 24 * 3600;
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Session {
+    pub id: i64,              // timestamp of the first sample
+    pub start: i64,           // Unix epoch seconds
+    pub end: i64,             // timestamp of the last sample
+    pub user_id: Option<i64>, // whose samples (None = everyone's)
+    pub total_samples: i64,
+    pub duration_seconds: i64, // active time, as in daily summaries
+    pub distance_meters: i64,
+    pub calories: i64,
+    pub calories_estimated: f64,
+    pub steps: i64,
+    pub avg_speed: f64, // m/s
+    pub max_speed: f64,
+}
+
+impl Session {
+    fn new(first: &TreadmillSample, user_id: Option<i64>) -> Self {
+        Self {
+            id: first.timestamp,
+            start: first.timestamp,
+            end: first.timestamp,
+            user_id,
+            total_samples: 0,
+            duration_seconds: 0,
+            distance_meters: 0,
+            calories: 0,
+            calories_estimated: 0.0,
+            steps: 0,
+            avg_speed: 0.0,
+            max_speed: 0.0,
+        }
+    }
+
+    fn add(&mut self, sample: &TreadmillSample) {
+        let gap = sample.timestamp - self.end;
+        if (1..=MAX_SAMPLE_GAP_SECS).contains(&gap) {
+            self.duration_seconds += gap;
+        }
+        let speed = sample.speed.unwrap_or(0.0);
+        self.avg_speed += (speed - self.avg_speed) / (self.total_samples + 1) as f64;
+        self.max_speed = self.max_speed.max(speed);
+        self.total_samples += 1;
+        self.distance_meters += sample.distance_delta.unwrap_or(0);
+        self.calories += sample.calories_delta.unwrap_or(0);
+        self.calories_estimated += sample.calories_estimated.unwrap_or(0.0);
+        self.steps += sample.steps_delta.unwrap_or(0);
+        self.end = sample.timestamp;
+    }
+
+    /// Whether the session has ended (no sample for longer than a session break)
+    pub fn is_complete(&self, now: i64) -> bool {
+        now - self.end > SESSION_GAP_SECS
+    }
+}
+
+/// Split samples (oldest first) into sessions
+pub fn split_sessions(samples: &[TreadmillSample], user_id: Option<i64>) -> Vec<Session> {
+    split_with_samples(samples.to_vec(), user_id)
+        .into_iter()
+        .map(|(session, _)| session)
+        .collect()
+}
+
+/// Split samples (oldest first) into sessions, keeping each one's samples
+pub fn split_with_samples(
+    samples: Vec<TreadmillSample>,
+    user_id: Option<i64>,
+) -> Vec<(Session, Vec<TreadmillSample>)> {
+    let mut sessions: Vec<(Session, Vec<TreadmillSample>)> = Vec::new();
+    for sample in samples {
+        match sessions.last_mut() {
+            Some((s, samples)) if sample.timestamp - s.end <= SESSION_GAP_SECS => {
+                s.add(&sample);
+                samples.push(sample);
+            }
+            _ => {
+                let mut session = Session::new(&sample, user_id);
+                session.add(&sample);
+                sessions.push((session, vec![sample]));
+            }
+        }
+    }
+    sessions
+}
+
+/// The session that started at `id`, with its samples
+pub async fn find_session(
+    storage: &Storage,
+    id: i64,
+    user_id: Option<i64>,
+) -> Result<Option<(Session, Vec<TreadmillSample>)>> {
+    // From a break's length before it: a sample in there means `id` isn't a start
+    let samples = storage
+        .get_active_samples_between(id - SESSION_GAP_SECS, id + MAX_SESSION_SECS, user_id)
+        .await?;
+
+    Ok(split_with_samples(samples, user_id)
+        .into_iter()
+        .find(|(session, _)| session.id == id))
+}
+
+/// Sessions with samples in `[start, end)`, oldest first. A session already
+/// under way at `start` is included whole (looking back up to 12 hours).
+pub async fn sessions_overlapping(
+    storage: &Storage,
+    start: i64,
+    end: i64,
+    user_id: Option<i64>,
+) -> Result<Vec<Session>> {
+    let samples = storage
+        .get_active_samples_between(start - LOOKBACK_SECS, end, user_id)
+        .await?;
+
+    Ok(split_sessions(&samples, user_id)
+        .into_iter()
+        .filter(|s| s.end >= start)
+        .collect())
+}
@@ -0,0 +1,435 @@
+//! Time-bucketed totals.
+//!
+//! Samples are summed in SQL per quarter-hour bucket ([`BUCKET_SECS`]), then
+//! folded into local days (or hours, weeks, ...) in Rust using a time zone.
+//! Every UTC offset and DST transition lands on a quarter-hour, so a bucket
+//! never straddles a local day boundary.
+
+use anyhow::Result;
+use chrono::{Datelike, Months, NaiveDate, Timelike, Weekday};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+use super::corrections::CorrectionTotals;
+use super::{DailySummary, Storage};
+use crate::energy::CaloriesSource;
+use crate::timezone::{Zone, BUCKET_SECS};
+
+/// Maximum gap to consider as "continuous" activity (10 seconds)
+/// Samples typically arrive every 1-2 seconds, so 10s allows for some jitter
+pub(crate) const MAX_CONTINUOUS_GAP: i64 = 10;
+
+/// Sums for the active samples in one quarter-hour bucket
+#[derive(Debug, Clone)]
+pub(crate) struct BucketTotals {
+    pub bucket_start: i64, // Unix epoch seconds
+    pub total_samples: i64,
+    pub distance_meters: i64,
+    pub calories: i64,
+    pub calories_estimated: f64,
+    pub steps: i64,
+    pub speed_sum: f64,
+    pub max_speed: f64,
+    pub duration_seconds: i64,
+}
+
+/// Running totals over any number of buckets
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Totals {
+    pub total_samples: i64,
+    pub distance_meters: i64,
+    pub calories: i64,
+    pub calories_estimated: f64,
+    pub steps: i64,
+    pub speed_sum: f64,
+    pub max_speed: f64,
+    pub duration_seconds: i64,
+}
+
+impl Totals {
+    pub fn add(&mut self, b: &BucketTotals) {
+        self.total_samples += b.total_samples;
+        self.distance_meters += b.distance_meters;
+        self.calories += b.calories;
+        self.calories_estimated += b.calories_estimated;
+        self.steps += b.steps;
+        self.speed_sum += b.speed_sum;
+        self.max_speed = self.max_speed.max(b.max_speed);
+        self.duration_seconds += b.duration_seconds;
+    }
+
+    pub fn avg_speed(&self) -> f64 {
+        if self.total_samples == 0 {
+            0.0
+        } else {
+            self.speed_sum / self.total_samples as f64
+        }
+    }
+
+    pub fn into_summary(self, date: String) -> DailySummary {
+        DailySummary {
+            date,
+            total_samples: self.total_samples,
+            duration_seconds: self.duration_seconds,
+            distance_meters: self.distance_meters,
+            calories: self.calories,
+            calories_device: self.calories,
+            calories_estimated: self.calories_estimated.round() as i64,
+            calories_source: CaloriesSource::Device,
+            steps: self.steps,
+            avg_speed: self.avg_speed(),
+            max_speed: self.max_speed,
+            corrected: false,
+            goals: Vec::new(),
+        }
+    }
+}
+
+impl Totals {
+    /// Add a day's manual corrections, never going below zero (the same rule
+    /// as [`DailySummary`], so periods add up to their days)
+    fn apply_corrections(&mut self, c: &CorrectionTotals) {
+        self.distance_meters = (self.distance_meters + c.distance_meters).max(0);
+        self.calories = (self.calories + c.calories).max(0);
+        self.calories_estimated = (self.calories_estimated + c.calories as f64).max(0.0);
+        self.steps = (self.steps + c.steps).max(0);
+        self.duration_seconds = (self.duration_seconds + c.duration_seconds).max(0);
+    }
+
+    fn merge(&mut self, other: &Totals) {
+        self.total_samples += other.total_samples;
+        self.distance_meters += other.distance_meters;
+        self.calories += other.calories;
+        self.calories_estimated += other.calories_estimated;
+        self.steps += other.steps;
+        self.speed_sum += other.speed_sum;
+        self.max_speed = self.max_speed.max(other.max_speed);
+        self.duration_seconds += other.duration_seconds;
+    }
+}
+
+/// Period length for [`Storage::get_period_summaries`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Hour,
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl GroupBy {
+    /// First local day of the period containing `date` (hours use their day)
+    pub fn period_start(self, date: NaiveDate, week_start: Weekday) -> NaiveDate {
+        match self {
+            GroupBy::Hour | GroupBy::Day => date,
+            GroupBy::Week => date.week(week_start).first_day(),
+            GroupBy::Month => date.with_day(1).unwrap_or(date),
+            GroupBy::Year => date.with_ordinal(1).unwrap_or(date),
+        }
+    }
+
+    /// First local day of the following period
+    fn next_start(self, start: NaiveDate) -> NaiveDate {
+        match self {
+            GroupBy::Hour | GroupBy::Day => start + chrono::Duration::days(1),
+            GroupBy::Week => start + chrono::Duration::days(7),
+            GroupBy::Month => start + Months::new(1),
+            GroupBy::Year => start + Months::new(12),
+        }
+    }
+
+    /// `(label, start, end)` of the period containing a bucket. Hours are
+    /// labelled with their local start time and offset, longer periods with
+    /// their first local day.
+    fn period_of(self, bucket_start: i64, zone: &Zone, week_start: Weekday) -> (String, i64, i64) {
+        if self == GroupBy::Hour {
+            let local = zone.local_datetime(bucket_start);
+            let start = bucket_start - i64::from(local.minute() * 60 + local.second());
+            let label = zone.local_datetime(start).to_rfc3339();
+            return (label, start, start + 3600);
+        }
+        self.period_of_date(zone.local_date(bucket_start), zone, week_start)
+    }
+
+    fn period_of_date(
+        self,
+        date: NaiveDate,
+        zone: &Zone,
+        week_start: Weekday,
+    ) -> (String, i64, i64) {
+        let first = self.period_start(date, week_start);
+        let (start, _) = zone.day_bounds(first);
+        let (end, _) = zone.day_bounds(self.next_start(first));
+        (first.format("%Y-%m-%d").to_string(), start, end)
+    }
+}
+
+/// Totals for an hour, day, week, month or year
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PeriodSummary {
+    pub period: String, // first local day (YYYY-MM-DD), or local RFC 3339 time for hours
+    pub start: i64,     // Unix epoch seconds
+    pub end: i64,       // exclusive
+    pub total_samples: i64,
+    pub duration_seconds: i64,
+    pub distance_meters: i64,
+    pub calories: i64, // reported figure (see `calories_source`)
+    pub calories_device: i64,
+    pub calories_estimated: i64,
+    pub calories_source: CaloriesSource,
+    pub steps: i64,
+    pub avg_speed: f64, // m/s
+    pub max_speed: f64,
+    pub corrected: bool, // true if manual corrections were applied
+}
+
+impl PeriodSummary {
+    fn new(period: String, start: i64, end: i64, totals: &Totals, corrected: bool) -> Self {
+        Self {
+            period,
+            start,
+            end,
+            total_samples: totals.total_samples,
+            duration_seconds: totals.duration_seconds.max(0),
+            distance_meters: totals.distance_meters.max(0),
+            calories: totals.calories.max(0),
+            calories_device: totals.calories.max(0),
+            calories_estimated: (totals.calories_estimated.round() as i64).max(0),
+            calories_source: CaloriesSource::Device,
+            steps: totals.steps.max(0),
+            avg_speed: totals.avg_speed(),
+            max_speed: totals.max_speed,
+            corrected,
+        }
+    }
+
+    /// Choose which calorie figure `calories` reports
+    pub fn report_calories(&mut self, source: CaloriesSource) {
+        self.calories = match source {
+            CaloriesSource::Device => self.calories_device,
+            CaloriesSource::Estimated => self.calories_estimated,
+        };
+        self.calories_source = source;
+    }
+}
+
+/// Active samples in one bucket of [`Storage::get_sample_series`]
+#[derive(Debug, Clone)]
+pub struct SeriesPoint {
+    pub start: i64,     // Unix epoch seconds
+    pub avg_speed: f64, // m/s
+    pub max_speed: f64,
+    pub distance_meters: i64,
+    pub steps: i64,
+    pub calories: i64,
+    pub calories_estimated: f64,
+    pub heart_rate: Option<f64>, // average of samples that have one
+    pub incline: Option<f64>,
+}
+
+/// A period (or day) being accumulated
+#[derive(Default)]
+struct PeriodTotals {
+    label: String,
+    end: i64,
+    totals: Totals,
+    corrected: bool,
+}
+
+impl Storage {
+    /// Totals grouped into local hours, days, weeks, months or years, oldest first.
+    /// Only periods with samples or corrections are returned.
+    ///
+    /// # Arguments
+    /// * `range` - First and last local day to include (None = everything).
+    ///   Periods at either end only count the days inside the range.
+    /// * `week_start` - First day of the week for `GroupBy::Week`
+    /// * `user_id` - Only this user's samples and corrections (None = everyone)
+    ///
+    /// Daily corrections can't be placed within a day, so hourly totals leave them out.
+    pub async fn get_period_summaries(
+        &self,
+        group_by: GroupBy,
+        range: Option<(NaiveDate, NaiveDate)>,
+        zone: &Zone,
+        week_start: Weekday,
+        user_id: Option<i64>,
+    ) -> Result<Vec<PeriodSummary>> {
+        let bounds = range.map(|(first, last)| (zone.day_bounds(first).0, zone.day_bounds(last).1));
+        let buckets = self.get_bucket_totals(bounds, user_id).await?;
+        let mut periods: BTreeMap<i64, PeriodTotals> = BTreeMap::new();
+
+        if group_by == GroupBy::Hour {
+            for bucket in &buckets {
+                let (label, start, end) = group_by.period_of(bucket.bucket_start, zone, week_start);
+                let period = periods.entry(start).or_insert_with(|| PeriodTotals {
+                    label,
+                    end,
+                    ..Default::default()
+                });
+                period.totals.add(bucket);
+            }
+        } else {
+            // Correct each day first so negative corrections clamp per day
+            let mut days: BTreeMap<NaiveDate, PeriodTotals> = BTreeMap::new();
+            for bucket in &buckets {
+                days.entry(zone.local_date(bucket.bucket_start))
+                    .or_default()
+                    .totals
+                    .add(bucket);
+            }
+            for (date, c) in self.get_correction_totals(None, user_id).await? {
+                let Ok(date) = NaiveDate::parse_from_str(&date, "%Y-%m-%d") else {
+                    continue;
+                };
+                if range.is_some_and(|(first, last)| date < first || date > last) {
+                    continue;
+                }
+                let day = days.entry(date).or_default();
+                day.totals.apply_corrections(&c);
+                day.corrected = true;
+            }
+
+            for (date, day) in days {
+                let (label, start, end) = group_by.period_of_date(date, zone, week_start);
+                let period = periods.entry(start).or_insert_with(|| PeriodTotals {
+                    label,
+                    end,
+                    ..Default::default()
+                });
+                period.totals.merge(&day.totals);
+                period.corrected |= day.corrected;
+            }
+        }
+
+        Ok(periods
+            .into_iter()
+            .map(|(start, p)| PeriodSummary::new(p.label, start, p.end, &p.totals, p.corrected))
+            .collect())
+    }
+
+    /// Per-bucket totals of active samples in `[start, end)` (everything when None)
+    ///
+    /// Active duration sums the gaps between consecutive active samples, counting
+    /// only gaps of [`MAX_CONTINUOUS_GAP`] or less (longer gaps mean the treadmill
+    /// was stopped). Each gap is credited to the bucket of the later sample.
+    pub(crate) async fn get_bucket_totals(
+        &self,
+        range: Option<(i64, i64)>,
+        user_id: Option<i64>,
+    ) -> Result<Vec<BucketTotals>> {
+        let (start, end) = range.unwrap_or((i64::MIN + MAX_CONTINUOUS_GAP, i64::MAX));
+
+        let rows = sqlx::query(
+            r#"
+            WITH active AS (
+                SELECT
+                    timestamp, distance_delta, calories_delta, calories_estimated,
+                    steps_delta, speed,
+                    timestamp - LAG(timestamp) OVER (ORDER BY timestamp) AS gap
+                FROM treadmill_samples
+                WHERE timestamp >= ? AND timestamp < ?
+                  AND speed > 0.0 AND excluded = 0
+                  AND (? IS NULL OR user_id = ?)
+            )
+            SELECT
+                timestamp / ? AS bucket,
+                COUNT(*) AS total_samples,
+                COALESCE(SUM(distance_delta), 0) AS distance_meters,
+                COALESCE(SUM(calories_delta), 0) AS calories,
+                COALESCE(SUM(calories_estimated), 0.0) AS calories_estimated,
+                COALESCE(SUM(steps_delta), 0) AS steps,
+                COALESCE(SUM(speed), 0.0) AS speed_sum,
+                COALESCE(MAX(speed), 0.0) AS max_speed,
+                COALESCE(SUM(CASE WHEN gap <= ? THEN gap END), 0) AS duration_seconds
+            FROM active
+            WHERE timestamp >= ?
+            GROUP BY bucket
+            ORDER BY bucket ASC
+            "#,
+        )
+        // Look back one gap so the first sample in range can continue a walk
+        .bind(start - MAX_CONTINUOUS_GAP)
+        .bind(end)
+        .bind(user_id)
+        .bind(user_id)
+        .bind(BUCKET_SECS)
+        .bind(MAX_CONTINUOUS_GAP)
+        .bind(start)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| BucketTotals {
+                bucket_start: row.get::<i64, _>("bucket") * BUCKET_SECS,
+                total_samples: row.get("total_samples"),
+                distance_meters: row.get("distance_meters"),
+                calories: row.get("calories"),
+                calories_estimated: row.get("calories_estimated"),
+                steps: row.get("steps"),
+                speed_sum: row.get("speed_sum"),
+                max_speed: row.get("max_speed"),
+                duration_seconds: row.get("duration_seconds"),
+            })
+            .collect())
+    }
+
+    /// Active samples in `[start, end)` summed per `bucket_secs`, oldest first.
+    /// Buckets are aligned to multiples of `bucket_secs` since the epoch, so
+    /// they don't shift as the range moves; empty buckets are left out.
+    pub async fn get_sample_series(
+        &self,
+        range: (i64, i64),
+        bucket_secs: i64,
+        user_id: Option<i64>,
+    ) -> Result<Vec<SeriesPoint>> {
+        let bucket_secs = bucket_secs.max(1);
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                timestamp / ? AS bucket,
+                AVG(speed) AS avg_speed,
+                MAX(speed) AS max_speed,
+                COALESCE(SUM(distance_delta), 0) AS distance_meters,
+                COALESCE(SUM(steps_delta), 0) AS steps,
+                COALESCE(SUM(calories_delta), 0) AS calories,
+                COALESCE(SUM(calories_estimated), 0.0) AS calories_estimated,
+                AVG(heart_rate) AS heart_rate,
+                AVG(incline) AS incline
+            FROM treadmill_samples
+            WHERE timestamp >= ? AND timestamp < ?
+              AND speed > 0.0 AND excluded = 0
+              AND (? IS NULL OR user_id = ?)
+            GROUP BY bucket
+            ORDER BY bucket ASC
+            "#,
+        )
+        .bind(bucket_secs)
+        .bind(range.0)
+        .bind(range.1)
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| SeriesPoint {
+                start: row.get::<i64, _>("bucket") * bucket_secs,
+                avg_speed: row.get("avg_speed"),
+                max_speed: row.get("max_speed"),
+                distance_meters: row.get("distance_meters"),
+                steps: row.get("steps"),
+                calories: row.get("calories"),
+                calories_estimated: row.get("calories_estimated"),
+                heart_rate: row.get("heart_rate"),
+                incline: row.get("incline"),
+            })
+            .collect())
+    }
+}
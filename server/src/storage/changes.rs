@@ -0,0 +1,230 @@
+//! Change feed: every insert, edit and deletion of samples and corrections,
+//! numbered in the order they were committed.
+//!
+//! Changes are logged in the same transaction as the change itself, so a
+//! client that applies them in `seq` order from its last cursor ends up with
+//! the same data as the server. Sample upserts aren't copied into the log;
+//! the feed reads the sample's current row instead.
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::{Row, Sqlite, Transaction};
+use utoipa::ToSchema;
+
+use super::{DailyCorrection, Storage, TreadmillSample};
+
+/// What changed
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChangeData {
+    /// A sample was inserted or updated (None if it has since been deleted)
+    SampleUpserted {
+        timestamp: i64,
+        sample: Option<TreadmillSample>,
+    },
+    /// Samples in `[start, end)` were deleted
+    SamplesDeleted {
+        start: i64,
+        end: i64,
+    },
+    /// Samples in `[start, end)` no longer count towards totals
+    SamplesExcluded {
+        start: i64,
+        end: i64,
+    },
+    /// Samples in `[start, end)` count towards totals again
+    SamplesIncluded {
+        start: i64,
+        end: i64,
+    },
+    /// Samples in `[start, end)` now belong to `user_id` (None = unassigned)
+    SamplesAssigned {
+        start: i64,
+        end: i64,
+        user_id: Option<i64>,
+    },
+    /// A user was deleted and their samples unassigned
+    UserSamplesUnassigned {
+        user_id: i64,
+    },
+    CorrectionUpserted {
+        correction: DailyCorrection,
+    },
+    CorrectionDeleted {
+        correction: DailyCorrection,
+    },
+}
+
+impl ChangeData {
+    fn kind(&self) -> &'static str {
+        match self {
+            ChangeData::SampleUpserted { .. } => "sample_upserted",
+            ChangeData::SamplesDeleted { .. } => "samples_deleted",
+            ChangeData::SamplesExcluded { .. } => "samples_excluded",
+            ChangeData::SamplesIncluded { .. } => "samples_included",
+            ChangeData::SamplesAssigned { .. } => "samples_assigned",
+            ChangeData::UserSamplesUnassigned { .. } => "user_samples_unassigned",
+            ChangeData::CorrectionUpserted { .. } => "correction_upserted",
+            ChangeData::CorrectionDeleted { .. } => "correction_deleted",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Change {
+    pub seq: i64,
+    pub changed_at: i64, // Unix epoch seconds
+    #[serde(flatten)]
+    pub data: ChangeData,
+}
+
+impl Storage {
+    /// Changes after `since` (a previous `seq`, 0 for everything), oldest first
+    pub async fn get_changes(&self, since: i64, limit: i64) -> Result<Vec<Change>> {
+        let rows = sqlx::query(
+            "SELECT c.seq, c.changed_at, c.kind, c.timestamp, c.end_timestamp, c.user_id, c.data,
+                    s.timestamp AS s_timestamp, s.speed, s.distance_total, s.calories_total,
+                    s.steps_total, s.distance_delta, s.calories_delta, s.steps_delta,
+                    s.user_id AS s_user_id, s.incline, s.heart_rate, s.calories_estimated
+             FROM change_log c
+             LEFT JOIN treadmill_samples s
+               ON c.kind = 'sample_upserted' AND s.timestamp = c.timestamp
+             WHERE c.seq > ?
+             ORDER BY c.seq ASC
+             LIMIT ?",
+        )
+        .bind(since)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut changes = Vec::with_capacity(rows.len());
+        for row in rows {
+            let kind: String = row.get("kind");
+            let timestamp: Option<i64> = row.get("timestamp");
+            let start = timestamp.unwrap_or_default();
+            let end: i64 = row
+                .get::<Option<i64>, _>("end_timestamp")
+                .unwrap_or_default();
+            let user_id: Option<i64> = row.get("user_id");
+            let correction = || -> Result<DailyCorrection> {
+                let data: Option<String> = row.get("data");
+                Ok(serde_json::from_str(data.as_deref().unwrap_or("null"))?)
+            };
+
+            let data = match kind.as_str() {
+                "sample_upserted" => ChangeData::SampleUpserted {
+                    timestamp: start,
+                    sample: row.get::<Option<i64>, _>("s_timestamp").map(|timestamp| {
+                        TreadmillSample {
+                            timestamp,
+                            speed: row.get("speed"),
+                            distance_total: row.get("distance_total"),
+                            calories_total: row.get("calories_total"),
+                            steps_total: row.get("steps_total"),
+                            distance_delta: row.get("distance_delta"),
+                            calories_delta: row.get("calories_delta"),
+                            steps_delta: row.get("steps_delta"),
+                            user_id: row.get("s_user_id"),
+                            incline: row.get("incline"),
+                            heart_rate: row.get("heart_rate"),
+                            calories_estimated: row.get("calories_estimated"),
+                        }
+                    }),
+                },
+                "samples_deleted" => ChangeData::SamplesDeleted { start, end },
+                "samples_excluded" => ChangeData::SamplesExcluded { start, end },
+                "samples_included" => ChangeData::SamplesIncluded { start, end },
+                "samples_assigned" => ChangeData::SamplesAssigned {
+                    start,
+                    end,
+                    user_id,
+                },
+                "user_samples_unassigned" => ChangeData::UserSamplesUnassigned {
+                    user_id: user_id.unwrap_or_default(),
+                },
+                "correction_upserted" => ChangeData::CorrectionUpserted {
+                    correction: correction()?,
+                },
+                "correction_deleted" => ChangeData::CorrectionDeleted {
+                    correction: correction()?,
+                },
+                other => anyhow::bail!("Unknown change kind in change_log: {}", other),
+            };
+
+            changes.push(Change {
+                seq: row.get("seq"),
+                changed_at: row.get("changed_at"),
+                data,
+            });
+        }
+
+        Ok(changes)
+    }
+
+    /// The most recent `seq` (0 if nothing has changed yet)
+    pub async fn get_latest_change_seq(&self) -> Result<i64> {
+        let row = sqlx::query("SELECT COALESCE(MAX(seq), 0) AS seq FROM change_log")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(row.get("seq"))
+    }
+}
+
+/// Append a change to the feed as part of the transaction that made it
+pub(super) async fn log_change(
+    tx: &mut Transaction<'_, Sqlite>,
+    change: &ChangeData,
+) -> Result<()> {
+    let (timestamp, end, user_id, data) = match change {
+        ChangeData::SampleUpserted { timestamp, .. } => (Some(*timestamp), None, None, None),
+        ChangeData::SamplesDeleted { start, end }
+        | ChangeData::SamplesExcluded { start, end }
+        | ChangeData::SamplesIncluded { start, end } => (Some(*start), Some(*end), None, None),
+        ChangeData::SamplesAssigned {
+            start,
+            end,
+            user_id,
+        } => (Some(*start), Some(*end), *user_id, None),
+        ChangeData::UserSamplesUnassigned { user_id } => (None, None, Some(*user_id), None),
+        ChangeData::CorrectionUpserted { correction }
+        | ChangeData::CorrectionDeleted { correction } => (
+            None,
+            None,
+            correction.user_id,
+            Some(serde_json::to_string(correction)?),
+        ),
+    };
+
+    sqlx::query(
+        "INSERT INTO change_log (changed_at, kind, timestamp, end_timestamp, user_id, data)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(Utc::now().timestamp())
+    .bind(change.kind())
+    .bind(timestamp)
+    .bind(end)
+    .bind(user_id)
+    .bind(data)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Log that the sample at `timestamp` was inserted or updated
+pub(super) async fn log_sample_upsert(
+    tx: &mut Transaction<'_, Sqlite>,
+    timestamp: i64,
+) -> Result<()> {
+    log_change(
+        tx,
+        &ChangeData::SampleUpserted {
+            timestamp,
+            sample: None,
+        },
+    )
+    .await
+}
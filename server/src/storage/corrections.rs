@@ -0,0 +1,345 @@
+//! Manual data fixes: deleting and excluding samples, per-day corrections,
+//! and the audit trail that records who changed what.
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Row, Sqlite, Transaction};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+use super::changes::{log_change, ChangeData};
+use super::sync::rewind_sync_cursors;
+use super::Storage;
+
+/// Who made a change and why (recorded in the audit log)
+#[derive(Debug, Clone)]
+pub struct ChangeAuthor {
+    pub actor: String,
+    pub reason: Option<String>,
+}
+
+/// Adjustment applied on top of a day's sample totals
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct DailyCorrection {
+    pub id: i64,
+    pub date: String, // YYYY-MM-DD (local date the correction applies to)
+    pub user_id: Option<i64>,
+    pub distance_meters: i64,
+    pub calories: i64,
+    pub steps: i64,
+    pub duration_seconds: i64,
+    pub reason: Option<String>,
+    pub actor: String,
+    pub created_at: i64, // Unix epoch seconds
+}
+
+/// Amounts to add to (or, when negative, subtract from) a day's totals
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct CorrectionAmounts {
+    #[serde(default)]
+    pub distance_meters: i64,
+    #[serde(default)]
+    pub calories: i64,
+    #[serde(default)]
+    pub steps: i64,
+    #[serde(default)]
+    pub duration_seconds: i64,
+}
+
+/// Summed corrections for one date
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct CorrectionTotals {
+    pub distance_meters: i64,
+    pub calories: i64,
+    pub steps: i64,
+    pub duration_seconds: i64,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: i64, // Unix epoch seconds
+    pub actor: String,
+    pub action: String,
+    pub details: String, // JSON
+}
+
+impl Storage {
+    /// Permanently delete samples in `[start, end)` (Unix seconds)
+    pub async fn delete_samples(&self, start: i64, end: i64, author: &ChangeAuthor) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        let deleted =
+            sqlx::query("DELETE FROM treadmill_samples WHERE timestamp >= ? AND timestamp < ?")
+                .bind(start)
+                .bind(end)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        if deleted > 0 {
+            log_change(&mut tx, &ChangeData::SamplesDeleted { start, end }).await?;
+            rewind_sync_cursors(&mut tx, start).await?;
+        }
+
+        write_audit(
+            &mut tx,
+            author,
+            "delete_samples",
+            serde_json::json!({ "start": start, "end": end, "deleted": deleted }),
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(deleted)
+    }
+
+    /// Mark samples in `[start, end)` as excluded from (or restored to) summaries
+    pub async fn set_samples_excluded(
+        &self,
+        start: i64,
+        end: i64,
+        excluded: bool,
+        author: &ChangeAuthor,
+    ) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        let updated = sqlx::query(
+            "UPDATE treadmill_samples SET excluded = ?
+             WHERE timestamp >= ? AND timestamp < ? AND excluded != ?",
+        )
+        .bind(excluded)
+        .bind(start)
+        .bind(end)
+        .bind(excluded)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        if updated > 0 {
+            let change = if excluded {
+                ChangeData::SamplesExcluded { start, end }
+            } else {
+                ChangeData::SamplesIncluded { start, end }
+            };
+            log_change(&mut tx, &change).await?;
+            rewind_sync_cursors(&mut tx, start).await?;
+        }
+
+        let action = if excluded {
+            "exclude_samples"
+        } else {
+            "include_samples"
+        };
+        write_audit(
+            &mut tx,
+            author,
+            action,
+            serde_json::json!({ "start": start, "end": end, "updated": updated }),
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(updated)
+    }
+
+    /// Record a correction to a day's totals
+    pub async fn add_correction(
+        &self,
+        date: &str,
+        user_id: Option<i64>,
+        amounts: &CorrectionAmounts,
+        author: &ChangeAuthor,
+    ) -> Result<DailyCorrection> {
+        let mut tx = self.pool.begin().await?;
+
+        let correction = sqlx::query_as::<_, DailyCorrection>(
+            "INSERT INTO daily_corrections
+             (date, user_id, distance_meters, calories, steps, duration_seconds,
+              reason, actor, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+             RETURNING id, date, user_id, distance_meters, calories, steps, duration_seconds,
+                       reason, actor, created_at",
+        )
+        .bind(date)
+        .bind(user_id)
+        .bind(amounts.distance_meters)
+        .bind(amounts.calories)
+        .bind(amounts.steps)
+        .bind(amounts.duration_seconds)
+        .bind(&author.reason)
+        .bind(&author.actor)
+        .bind(Utc::now().timestamp())
+        .fetch_one(&mut *tx)
+        .await?;
+        log_change(
+            &mut tx,
+            &ChangeData::CorrectionUpserted {
+                correction: correction.clone(),
+            },
+        )
+        .await?;
+
+        write_audit(
+            &mut tx,
+            author,
+            "add_correction",
+            serde_json::to_value(&correction)?,
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(correction)
+    }
+
+    /// Remove a correction. Returns the removed correction, if it existed.
+    pub async fn delete_correction(
+        &self,
+        id: i64,
+        author: &ChangeAuthor,
+    ) -> Result<Option<DailyCorrection>> {
+        let mut tx = self.pool.begin().await?;
+
+        let correction = sqlx::query_as::<_, DailyCorrection>(
+            "DELETE FROM daily_corrections WHERE id = ?
+             RETURNING id, date, user_id, distance_meters, calories, steps, duration_seconds,
+                       reason, actor, created_at",
+        )
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(ref c) = correction {
+            log_change(
+                &mut tx,
+                &ChangeData::CorrectionDeleted {
+                    correction: c.clone(),
+                },
+            )
+            .await?;
+            write_audit(
+                &mut tx,
+                author,
+                "delete_correction",
+                serde_json::to_value(c)?,
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(correction)
+    }
+
+    /// Get all corrections recorded for a date (optionally one user's only)
+    pub async fn get_corrections_for_date(
+        &self,
+        date: &str,
+        user_id: Option<i64>,
+    ) -> Result<Vec<DailyCorrection>> {
+        let corrections = sqlx::query_as::<_, DailyCorrection>(
+            "SELECT id, date, user_id, distance_meters, calories, steps, duration_seconds,
+                    reason, actor, created_at
+             FROM daily_corrections
+             WHERE date = ? AND (? IS NULL OR user_id = ?)
+             ORDER BY id ASC",
+        )
+        .bind(date)
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(corrections)
+    }
+
+    /// Summed corrections per date (for applying to summaries)
+    pub(crate) async fn get_correction_totals(
+        &self,
+        date: Option<&str>,
+        user_id: Option<i64>,
+    ) -> Result<HashMap<String, CorrectionTotals>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                date,
+                SUM(distance_meters) as distance_meters,
+                SUM(calories) as calories,
+                SUM(steps) as steps,
+                SUM(duration_seconds) as duration_seconds
+            FROM daily_corrections
+            WHERE (? IS NULL OR date = ?)
+              AND (? IS NULL OR user_id = ?)
+            GROUP BY date
+            "#,
+        )
+        .bind(date)
+        .bind(date)
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                (
+                    row.get("date"),
+                    CorrectionTotals {
+                        distance_meters: row.get("distance_meters"),
+                        calories: row.get("calories"),
+                        steps: row.get("steps"),
+                        duration_seconds: row.get("duration_seconds"),
+                    },
+                )
+            })
+            .collect())
+    }
+
+    /// Record an audit entry outside of one of the mutations above (e.g. imports)
+    pub async fn record_audit(
+        &self,
+        author: &ChangeAuthor,
+        action: &str,
+        details: serde_json::Value,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        write_audit(&mut tx, author, action, details).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Most recent audit entries, newest first
+    pub async fn get_audit_log(&self, limit: i64) -> Result<Vec<AuditEntry>> {
+        let entries = sqlx::query_as::<_, AuditEntry>(
+            "SELECT id, created_at, actor, action, details
+             FROM audit_log
+             ORDER BY id DESC
+             LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+}
+
+pub(super) async fn write_audit(
+    tx: &mut Transaction<'_, Sqlite>,
+    author: &ChangeAuthor,
+    action: &str,
+    mut details: serde_json::Value,
+) -> Result<()> {
+    if let (Some(reason), Some(obj)) = (&author.reason, details.as_object_mut()) {
+        obj.entry("reason").or_insert_with(|| reason.clone().into());
+    }
+
+    sqlx::query("INSERT INTO audit_log (created_at, actor, action, details) VALUES (?, ?, ?, ?)")
+        .bind(Utc::now().timestamp())
+        .bind(&author.actor)
+        .bind(action)
+        .bind(details.to_string())
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
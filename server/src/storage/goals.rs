@@ -0,0 +1,109 @@
+//! Daily and weekly targets (see `crate::goals` for progress and streaks).
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use super::Storage;
+use crate::goals::{GoalMetric, GoalPeriod};
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Goal {
+    pub id: i64,
+    pub user_id: Option<i64>, // None = everyone's walking combined
+    pub metric: GoalMetric,
+    pub period: GoalPeriod,
+    pub target: f64,
+    pub created_at: i64, // Unix epoch seconds
+}
+
+impl Storage {
+    /// All goals, or only those for one user when `user_id` is given
+    pub async fn get_goals(&self, user_id: Option<i64>) -> Result<Vec<Goal>> {
+        let goals = sqlx::query_as::<_, Goal>(
+            "SELECT id, user_id, metric, period, target, created_at
+             FROM goals
+             WHERE ? IS NULL OR user_id = ?
+             ORDER BY id ASC",
+        )
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(goals)
+    }
+
+    /// Goals for exactly this scope (None = the everyone goals)
+    pub async fn get_goals_for_scope(&self, user_id: Option<i64>) -> Result<Vec<Goal>> {
+        let goals = sqlx::query_as::<_, Goal>(
+            "SELECT id, user_id, metric, period, target, created_at
+             FROM goals
+             WHERE user_id IS ?
+             ORDER BY id ASC",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(goals)
+    }
+
+    /// Set the target for a metric and period, replacing any existing one
+    pub async fn set_goal(
+        &self,
+        user_id: Option<i64>,
+        metric: GoalMetric,
+        period: GoalPeriod,
+        target: f64,
+    ) -> Result<Goal> {
+        let mut tx = self.pool.begin().await?;
+
+        let updated = sqlx::query_as::<_, Goal>(
+            "UPDATE goals SET target = ?
+             WHERE user_id IS ? AND metric = ? AND period = ?
+             RETURNING id, user_id, metric, period, target, created_at",
+        )
+        .bind(target)
+        .bind(user_id)
+        .bind(metric)
+        .bind(period)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let goal = match updated {
+            Some(goal) => goal,
+            None => {
+                sqlx::query_as::<_, Goal>(
+                    "INSERT INTO goals (user_id, metric, period, target, created_at)
+                     VALUES (?, ?, ?, ?, ?)
+                     RETURNING id, user_id, metric, period, target, created_at",
+                )
+                .bind(user_id)
+                .bind(metric)
+                .bind(period)
+                .bind(target)
+                .bind(Utc::now().timestamp())
+                .fetch_one(&mut *tx)
+                .await?
+            }
+        };
+
+        tx.commit().await?;
+        Ok(goal)
+    }
+
+    pub async fn delete_goal(&self, id: i64) -> Result<Option<Goal>> {
+        let goal = sqlx::query_as::<_, Goal>(
+            "DELETE FROM goals WHERE id = ?
+             RETURNING id, user_id, metric, period, target, created_at",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(goal)
+    }
+}
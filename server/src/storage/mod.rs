@@ -1,37 +1,142 @@
+mod aggregate;
+mod changes;
+mod corrections;
+mod goals;
+mod quarantine;
+mod records;
+mod sync;
+mod tokens;
+mod users;
+mod webhooks;
+
+pub use aggregate::{GroupBy, PeriodSummary, SeriesPoint};
+pub use changes::Change;
+pub use corrections::{AuditEntry, ChangeAuthor, CorrectionAmounts, DailyCorrection};
+pub use goals::Goal;
+pub use quarantine::QuarantinedSample;
+pub use records::{Achievement, PersonalRecord};
+pub use sync::{LedgerEntry, NewLedgerEntry, SyncClient};
+pub use tokens::ApiToken;
+pub use users::{User, UserSchedule};
+pub use webhooks::{DeliveryStatus, WebhookDelivery};
+
 use anyhow::Result;
-use chrono::{DateTime, NaiveDate, Utc};
+use chrono::NaiveDate;
+use futures_util::stream::{self, Stream, TryStreamExt};
 use serde::{Deserialize, Serialize};
 use sqlx::{
     sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
     FromRow, Row, SqlitePool,
 };
+use std::collections::BTreeMap;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
+use utoipa::ToSchema;
+
+use crate::energy::CaloriesSource;
+use crate::goals::GoalProgress;
+use crate::timezone::{Zone, BUCKET_SECS};
+use aggregate::Totals;
+use corrections::CorrectionTotals;
+
+/// Columns added after the v2 schema first shipped. `CREATE TABLE IF NOT EXISTS`
+/// doesn't touch existing tables, so these are added to older databases on startup.
+const COLUMN_MIGRATIONS: &[(&str, &str, &str)] = &[
+    (
+        "treadmill_samples",
+        "excluded",
+        "INTEGER NOT NULL DEFAULT 0",
+    ),
+    ("treadmill_samples", "user_id", "INTEGER"),
+    ("daily_corrections", "user_id", "INTEGER"),
+    ("treadmill_samples", "incline", "REAL"),
+    ("treadmill_samples", "heart_rate", "INTEGER"),
+    ("treadmill_samples", "calories_estimated", "REAL"),
+    ("users", "weight_kg", "REAL"),
+    ("api_tokens", "user_id", "INTEGER"),
+];
+
+/// Rows read per query when streaming samples
+const STREAM_PAGE_SIZE: i64 = 1000;
 
 /// A single raw sample from the treadmill
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct TreadmillSample {
-    pub timestamp: i64,              // Unix epoch seconds
-    pub speed: Option<f64>,          // m/s
-    pub distance_total: Option<i64>, // cumulative meters (raw, for debugging)
-    pub calories_total: Option<i64>, // cumulative kcal (raw, for debugging)
-    pub steps_total: Option<i64>,    // cumulative steps (raw, for debugging)
-    pub distance_delta: Option<i64>, // meters since last sample
-    pub calories_delta: Option<i64>, // kcal since last sample
-    pub steps_delta: Option<i64>,    // steps since last sample
+    pub timestamp: i64,                  // Unix epoch seconds
+    pub speed: Option<f64>,              // m/s
+    pub distance_total: Option<i64>,     // cumulative meters (raw, for debugging)
+    pub calories_total: Option<i64>,     // cumulative kcal (raw, for debugging)
+    pub steps_total: Option<i64>,        // cumulative steps (raw, for debugging)
+    pub distance_delta: Option<i64>,     // meters since last sample
+    pub calories_delta: Option<i64>,     // kcal since last sample
+    pub steps_delta: Option<i64>,        // steps since last sample
+    pub user_id: Option<i64>,            // who was walking (None = unassigned)
+    pub incline: Option<f64>,            // percent grade
+    pub heart_rate: Option<i64>,         // bpm
+    pub calories_estimated: Option<f64>, // estimated kcal since last sample
 }
 
 /// Summary of activity for a specific date
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DailySummary {
     pub date: String, // YYYY-MM-DD
     pub total_samples: i64,
     pub duration_seconds: i64,
     pub distance_meters: i64,
-    pub calories: i64,
+    pub calories: i64, // reported figure (see `calories_source`)
+    pub calories_device: i64,
+    pub calories_estimated: i64,
+    pub calories_source: CaloriesSource,
     pub steps: i64,
     pub avg_speed: f64, // m/s
     pub max_speed: f64,
+    pub corrected: bool, // true if manual corrections were applied
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub goals: Vec<GoalProgress>, // progress towards goals (filled in by the API)
+}
+
+impl DailySummary {
+    /// Add manual corrections on top of the sample totals (never going below zero)
+    fn apply_corrections(&mut self, c: &CorrectionTotals) {
+        self.distance_meters = (self.distance_meters + c.distance_meters).max(0);
+        self.calories = (self.calories + c.calories).max(0);
+        self.calories_device = (self.calories_device + c.calories).max(0);
+        self.calories_estimated = (self.calories_estimated + c.calories).max(0);
+        self.steps = (self.steps + c.steps).max(0);
+        self.duration_seconds = (self.duration_seconds + c.duration_seconds).max(0);
+        self.corrected = true;
+    }
+
+    /// A summary for a day that has corrections but no recorded samples
+    fn corrections_only(date: String, c: &CorrectionTotals) -> Self {
+        let mut summary = Self {
+            date,
+            total_samples: 0,
+            duration_seconds: 0,
+            distance_meters: 0,
+            calories: 0,
+            calories_device: 0,
+            calories_estimated: 0,
+            calories_source: CaloriesSource::Device,
+            steps: 0,
+            avg_speed: 0.0,
+            max_speed: 0.0,
+            corrected: false,
+            goals: Vec::new(),
+        };
+        summary.apply_corrections(c);
+        summary
+    }
+
+    /// Choose which calorie figure `calories` reports
+    pub fn report_calories(&mut self, source: CaloriesSource) {
+        self.calories = match source {
+            CaloriesSource::Device => self.calories_device,
+            CaloriesSource::Estimated => self.calories_estimated,
+        };
+        self.calories_source = source;
+    }
 }
 
 pub struct Storage {
@@ -53,6 +158,11 @@ impl Storage {
             .connect_with(options)
             .await?;
 
+        // Bring older databases up to date before the schema's indexes reference new columns
+        for (table, column, definition) in COLUMN_MIGRATIONS {
+            Self::ensure_column(&pool, table, column, definition).await?;
+        }
+
         // Run migrations using the new v2 schema
         let schema = include_str!("../../schema_v2.sql");
         for statement in schema.split(';').filter(|s| !s.trim().is_empty()) {
@@ -62,92 +172,200 @@ impl Storage {
         Ok(Self { pool })
     }
 
-    /// Add a raw sample from the treadmill
-    #[allow(clippy::too_many_arguments)]
-    pub async fn add_sample(
-        &self,
-        timestamp: DateTime<Utc>,
-        speed: Option<f64>,
-        distance_total: Option<i64>,
-        calories_total: Option<i64>,
-        steps_total: Option<i64>,
-        distance_delta: Option<i64>,
-        calories_delta: Option<i64>,
-        steps_delta: Option<i64>,
+    /// Add a column to an existing table if it's missing
+    async fn ensure_column(
+        pool: &SqlitePool,
+        table: &str,
+        column: &str,
+        definition: &str,
     ) -> Result<()> {
-        let timestamp_unix = timestamp.timestamp();
+        let columns: Vec<String> = sqlx::query(&format!("PRAGMA table_info({})", table))
+            .fetch_all(pool)
+            .await?
+            .iter()
+            .map(|row| row.get("name"))
+            .collect();
 
-        sqlx::query(
-            "INSERT OR REPLACE INTO treadmill_samples
-             (timestamp, speed, distance_total, calories_total, steps_total,
-              distance_delta, calories_delta, steps_delta)
-             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
-        )
-        .bind(timestamp_unix)
-        .bind(speed)
-        .bind(distance_total)
-        .bind(calories_total)
-        .bind(steps_total)
-        .bind(distance_delta)
-        .bind(calories_delta)
-        .bind(steps_delta)
-        .execute(&self.pool)
+        // Table doesn't exist yet - the schema will create it with the column
+        if columns.is_empty() || columns.iter().any(|c| c == column) {
+            return Ok(());
+        }
+
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .execute(pool)
         .await?;
+        tracing::info!("Migrated database: added {}.{}", table, column);
+
+        Ok(())
+    }
+
+    /// Add several raw samples in one transaction (all or none are written).
+    /// Rewriting a stored sample (e.g. replaying a spill) keeps its exclusion
+    /// and user, which may have been corrected since it was recorded.
+    pub async fn add_samples(&self, samples: &[TreadmillSample]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for sample in samples {
+            sqlx::query(
+                "INSERT INTO treadmill_samples
+                 (timestamp, speed, distance_total, calories_total, steps_total,
+                  distance_delta, calories_delta, steps_delta, user_id,
+                  incline, heart_rate, calories_estimated)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                 ON CONFLICT(timestamp) DO UPDATE SET
+                   speed = excluded.speed,
+                   distance_total = excluded.distance_total,
+                   calories_total = excluded.calories_total,
+                   steps_total = excluded.steps_total,
+                   distance_delta = excluded.distance_delta,
+                   calories_delta = excluded.calories_delta,
+                   steps_delta = excluded.steps_delta,
+                   incline = excluded.incline,
+                   heart_rate = excluded.heart_rate,
+                   calories_estimated = excluded.calories_estimated",
+            )
+            .bind(sample.timestamp)
+            .bind(sample.speed)
+            .bind(sample.distance_total)
+            .bind(sample.calories_total)
+            .bind(sample.steps_total)
+            .bind(sample.distance_delta)
+            .bind(sample.calories_delta)
+            .bind(sample.steps_delta)
+            .bind(sample.user_id)
+            .bind(sample.incline)
+            .bind(sample.heart_rate)
+            .bind(sample.calories_estimated)
+            .execute(&mut *tx)
+            .await?;
+            changes::log_sample_upsert(&mut tx, sample.timestamp).await?;
+        }
 
+        tx.commit().await?;
         Ok(())
     }
 
-    /// Get all samples for a specific date range
-    pub async fn get_samples_by_date_range(
+    /// Insert samples whose timestamp isn't already stored (used by imports)
+    /// Runs in a single transaction and returns how many rows were inserted
+    pub async fn insert_samples_if_absent(&self, samples: &[TreadmillSample]) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let mut inserted = 0;
+        let mut earliest: Option<i64> = None;
+
+        for sample in samples {
+            let result = sqlx::query(
+                "INSERT OR IGNORE INTO treadmill_samples
+                 (timestamp, speed, distance_total, calories_total, steps_total,
+                  distance_delta, calories_delta, steps_delta, user_id,
+                  incline, heart_rate, calories_estimated)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(sample.timestamp)
+            .bind(sample.speed)
+            .bind(sample.distance_total)
+            .bind(sample.calories_total)
+            .bind(sample.steps_total)
+            .bind(sample.distance_delta)
+            .bind(sample.calories_delta)
+            .bind(sample.steps_delta)
+            .bind(sample.user_id)
+            .bind(sample.incline)
+            .bind(sample.heart_rate)
+            .bind(sample.calories_estimated)
+            .execute(&mut *tx)
+            .await?;
+            if result.rows_affected() > 0 {
+                inserted += result.rows_affected();
+                changes::log_sample_upsert(&mut tx, sample.timestamp).await?;
+                earliest = Some(earliest.map_or(sample.timestamp, |t| t.min(sample.timestamp)));
+            }
+        }
+        if let Some(timestamp) = earliest {
+            sync::rewind_sync_cursors(&mut tx, timestamp).await?;
+        }
+
+        tx.commit().await?;
+        Ok(inserted)
+    }
+
+    /// Get samples in `[start, end)`, oldest first
+    ///
+    /// # Arguments
+    /// * `range` - Unix timestamps, e.g. from `Zone::day_bounds`
+    /// * `user_id` - Only this user's samples (None = everyone)
+    /// * `after` - Only samples after this timestamp. Timestamps are unique, so
+    ///   the last one of a page is the cursor for the next.
+    /// * `limit` - At most this many samples (None = all of them)
+    pub async fn get_samples_page(
         &self,
-        start: DateTime<Utc>,
-        end: DateTime<Utc>,
+        range: (i64, i64),
+        user_id: Option<i64>,
+        after: Option<i64>,
+        limit: Option<i64>,
     ) -> Result<Vec<TreadmillSample>> {
-        let start_unix = start.timestamp();
-        let end_unix = end.timestamp();
-
         let samples = sqlx::query_as::<_, TreadmillSample>(
             "SELECT timestamp, speed, distance_total, calories_total, steps_total,
-                    distance_delta, calories_delta, steps_delta
+                    distance_delta, calories_delta, steps_delta, user_id,
+                    incline, heart_rate, calories_estimated
              FROM treadmill_samples
              WHERE timestamp >= ? AND timestamp < ?
-             ORDER BY timestamp ASC",
+               AND timestamp > ?
+               AND excluded = 0
+               AND (? IS NULL OR user_id = ?)
+             ORDER BY timestamp ASC
+             LIMIT ?",
         )
-        .bind(start_unix)
-        .bind(end_unix)
+        .bind(range.0)
+        .bind(range.1)
+        .bind(after.unwrap_or(i64::MIN))
+        .bind(user_id)
+        .bind(user_id)
+        .bind(limit.unwrap_or(-1)) // SQLite treats a negative limit as none
         .fetch_all(&self.pool)
         .await?;
 
         Ok(samples)
     }
 
-    /// Get samples for a specific date in the user's local timezone
-    ///
-    /// # Arguments
-    /// * `date` - The date in the user's local timezone
-    /// * `tz_offset_seconds` - Timezone offset from UTC in seconds (e.g., PST = -28800 for UTC-8)
-    pub async fn get_samples_for_date(
-        &self,
-        date: NaiveDate,
-        tz_offset_seconds: i32,
-    ) -> Result<Vec<TreadmillSample>> {
-        // Convert local date to UTC timestamp range (same logic as get_daily_summary)
-        let start_local = date
-            .and_hms_opt(0, 0, 0)
-            .ok_or_else(|| anyhow::anyhow!("Invalid date time"))?;
-        let end_local = start_local + chrono::Duration::days(1);
-
-        // Apply timezone offset to get UTC timestamps
-        let start_unix = start_local.and_utc().timestamp() - tz_offset_seconds as i64;
-        let end_unix = end_local.and_utc().timestamp() - tz_offset_seconds as i64;
-
-        // Convert back to DateTime<Utc>
-        let start = DateTime::from_timestamp(start_unix, 0)
-            .ok_or_else(|| anyhow::anyhow!("Invalid start timestamp"))?;
-        let end = DateTime::from_timestamp(end_unix, 0)
-            .ok_or_else(|| anyhow::anyhow!("Invalid end timestamp"))?;
+    /// Stream samples in `[start, end)` after `after`, oldest first, reading
+    /// `STREAM_PAGE_SIZE` rows at a time so exports of any length use bounded
+    /// memory. Stops after `limit` samples if given.
+    pub fn stream_samples(
+        self: Arc<Self>,
+        range: (i64, i64),
+        user_id: Option<i64>,
+        after: Option<i64>,
+        limit: Option<i64>,
+    ) -> impl Stream<Item = Result<TreadmillSample>> + Send + 'static {
+        // (cursor, samples still wanted); None once the last page was read
+        let state = Some((after, limit));
+        stream::try_unfold(state, move |state| {
+            let storage = self.clone();
+            async move {
+                let Some((after, remaining)) = state else {
+                    return Ok::<_, anyhow::Error>(None);
+                };
+                let page_size = remaining.map_or(STREAM_PAGE_SIZE, |r| r.min(STREAM_PAGE_SIZE));
+                if page_size <= 0 {
+                    return Ok(None);
+                }
 
-        self.get_samples_by_date_range(start, end).await
+                let page = storage
+                    .get_samples_page(range, user_id, after, Some(page_size))
+                    .await?;
+                let next = match page.last() {
+                    Some(last) if page.len() as i64 == page_size => {
+                        Some((Some(last.timestamp), remaining.map(|r| r - page_size)))
+                    }
+                    _ => None,
+                };
+                Ok(Some((stream::iter(page.into_iter().map(Ok)), next)))
+            }
+        })
+        .try_flatten()
     }
 
     /// Get a daily summary for a specific date
@@ -155,140 +373,79 @@ impl Storage {
     ///
     /// # Arguments
     /// * `date` - The date in the user's local timezone
-    /// * `tz_offset_seconds` - Timezone offset from UTC in seconds (e.g., PST = -28800 for UTC-8)
+    /// * `zone` - Time zone that defines the local day (DST-aware)
+    /// * `user_id` - Only this user's samples and corrections (None = everyone)
     pub async fn get_daily_summary(
         &self,
         date: NaiveDate,
-        tz_offset_seconds: i32,
+        zone: &Zone,
+        user_id: Option<i64>,
     ) -> Result<Option<DailySummary>> {
         let date_str = date.format("%Y-%m-%d").to_string();
 
-        // Convert local date to UTC timestamp range
-        // e.g., 2025-11-19 00:00 PST (-8h) = 2025-11-19 08:00 UTC
-        let start_local = date
-            .and_hms_opt(0, 0, 0)
-            .ok_or_else(|| anyhow::anyhow!("Invalid date time"))?;
-        let end_local = start_local + chrono::Duration::days(1);
-
-        // Apply timezone offset to get UTC timestamps
-        let start_unix = start_local.and_utc().timestamp() - tz_offset_seconds as i64;
-        let end_unix = end_local.and_utc().timestamp() - tz_offset_seconds as i64;
+        // Local midnight to midnight, which is 23 or 25 hours on DST changes
+        let bounds = zone.day_bounds(date);
 
-        // Get aggregated stats using delta columns
-        let summary = sqlx::query(
-            r#"
-            SELECT
-                COUNT(*) as total_samples,
-                COALESCE(SUM(distance_delta), 0) as distance_meters,
-                COALESCE(SUM(calories_delta), 0) as calories,
-                COALESCE(SUM(steps_delta), 0) as steps,
-                COALESCE(AVG(speed), 0) as avg_speed,
-                COALESCE(MAX(speed), 0) as max_speed
-            FROM treadmill_samples
-            WHERE timestamp >= ? AND timestamp < ?
-              AND speed > 0.0
-            "#,
-        )
-        .bind(start_unix)
-        .bind(end_unix)
-        .fetch_one(&self.pool)
-        .await?;
-
-        let total_samples: i64 = summary.get("total_samples");
-
-        if total_samples == 0 {
-            return Ok(None);
+        let mut totals = Totals::default();
+        for bucket in self.get_bucket_totals(Some(bounds), user_id).await? {
+            totals.add(&bucket);
         }
 
-        let distance_meters: i64 = summary.get("distance_meters");
-        let calories: i64 = summary.get("calories");
-        let steps: i64 = summary.get("steps");
-        let avg_speed: f64 = summary.get("avg_speed");
-        let max_speed: f64 = summary.get("max_speed");
-
-        // Calculate actual active duration by summing intervals between consecutive samples
-        // This handles gaps properly (e.g., if someone takes a break, that time isn't counted)
-        let duration_seconds = self.calculate_active_duration(start_unix, end_unix).await?;
-
-        Ok(Some(DailySummary {
-            date: date_str,
-            total_samples,
-            duration_seconds,
-            distance_meters,
-            calories,
-            steps,
-            avg_speed,
-            max_speed,
-        }))
-    }
-
-    /// Calculate actual active duration by summing intervals between consecutive samples
-    /// Only counts intervals of 10 seconds or less (typical sample rate is ~1s)
-    /// Longer gaps indicate the treadmill was stopped
-    async fn calculate_active_duration(&self, start_unix: i64, end_unix: i64) -> Result<i64> {
-        // Get all timestamps for active samples (speed > 0)
-        let rows = sqlx::query(
-            r#"
-            SELECT timestamp
-            FROM treadmill_samples
-            WHERE timestamp >= ? AND timestamp < ?
-              AND speed > 0.0
-            ORDER BY timestamp ASC
-            "#,
-        )
-        .bind(start_unix)
-        .bind(end_unix)
-        .fetch_all(&self.pool)
-        .await?;
+        let corrections = self.get_correction_totals(Some(&date_str), user_id).await?;
+        let correction = corrections.get(&date_str);
 
-        if rows.is_empty() {
-            return Ok(0);
+        if totals.total_samples == 0 {
+            return Ok(correction.map(|c| DailySummary::corrections_only(date_str, c)));
         }
 
-        // Maximum gap to consider as "continuous" activity (10 seconds)
-        // Samples typically arrive every 1-2 seconds, so 10s allows for some jitter
-        const MAX_CONTINUOUS_GAP: i64 = 10;
-
-        let mut total_duration: i64 = 0;
-        let mut prev_timestamp: Option<i64> = None;
-
-        for row in rows {
-            let timestamp: i64 = row.get("timestamp");
-            if let Some(prev) = prev_timestamp {
-                let gap = timestamp - prev;
-                if gap <= MAX_CONTINUOUS_GAP {
-                    total_duration += gap;
-                }
-            }
-            prev_timestamp = Some(timestamp);
+        let mut daily = totals.into_summary(date_str);
+        if let Some(c) = correction {
+            daily.apply_corrections(c);
         }
 
-        Ok(total_duration)
+        Ok(Some(daily))
     }
 
     /// Get all dates that have activity (samples with speed > 0)
     ///
     /// # Arguments
-    /// * `tz_offset_seconds` - Timezone offset from UTC in seconds (e.g., PST = -28800 for UTC-8)
-    pub async fn get_activity_dates(&self, tz_offset_seconds: i32) -> Result<Vec<String>> {
-        // Apply timezone offset to timestamps before extracting date
-        // e.g., UTC timestamp + (-28800 seconds) = PST time
-        let rows = sqlx::query(
+    /// * `zone` - Time zone that defines the local day (DST-aware)
+    /// * `user_id` - Only this user's activity (None = everyone)
+    pub async fn get_activity_dates(
+        &self,
+        zone: &Zone,
+        user_id: Option<i64>,
+    ) -> Result<Vec<String>> {
+        // Find active quarter-hours, then map each to its local date
+        let buckets: Vec<i64> = sqlx::query_scalar(
             r#"
-            SELECT DISTINCT DATE(timestamp + ?, 'unixepoch') as date
+            SELECT DISTINCT timestamp / ? as bucket
             FROM treadmill_samples
-            WHERE speed > 0.0
-            ORDER BY date DESC
+            WHERE speed > 0.0 AND excluded = 0
+              AND (? IS NULL OR user_id = ?)
             "#,
         )
-        .bind(tz_offset_seconds)
+        .bind(BUCKET_SECS)
+        .bind(user_id)
+        .bind(user_id)
         .fetch_all(&self.pool)
         .await?;
 
-        let dates = rows
-            .iter()
-            .map(|row| row.get::<String, _>("date"))
+        let mut dates: Vec<String> = buckets
+            .into_iter()
+            .map(|b| {
+                zone.local_date(b * BUCKET_SECS)
+                    .format("%Y-%m-%d")
+                    .to_string()
+            })
             .collect();
+
+        // Days that only have manual corrections still count as activity
+        let corrected_dates = self.get_correction_totals(None, user_id).await?;
+        dates.extend(corrected_dates.into_keys());
+        dates.sort_unstable_by(|a, b| b.cmp(a));
+        dates.dedup();
+
         Ok(dates)
     }
 
@@ -296,7 +453,8 @@ impl Storage {
     pub async fn get_latest_sample(&self) -> Result<Option<TreadmillSample>> {
         let sample = sqlx::query_as::<_, TreadmillSample>(
             "SELECT timestamp, speed, distance_total, calories_total, steps_total,
-                    distance_delta, calories_delta, steps_delta
+                    distance_delta, calories_delta, steps_delta, user_id,
+                    incline, heart_rate, calories_estimated
              FROM treadmill_samples
              ORDER BY timestamp DESC
              LIMIT 1",
@@ -307,6 +465,105 @@ impl Storage {
         Ok(sample)
     }
 
+    /// Walk every stored sample (including excluded ones) in timestamp order,
+    /// `limit` at a time, starting after `after`
+    pub async fn get_samples_after(
+        &self,
+        after: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<TreadmillSample>> {
+        let samples = sqlx::query_as::<_, TreadmillSample>(
+            "SELECT timestamp, speed, distance_total, calories_total, steps_total,
+                    distance_delta, calories_delta, steps_delta, user_id,
+                    incline, heart_rate, calories_estimated
+             FROM treadmill_samples
+             WHERE ? IS NULL OR timestamp > ?
+             ORDER BY timestamp ASC
+             LIMIT ?",
+        )
+        .bind(after)
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(samples)
+    }
+
+    /// Walk the samples summaries count (moving, not excluded) in timestamp
+    /// order, `limit` at a time, starting after `after`
+    pub async fn get_active_samples_after(
+        &self,
+        after: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<TreadmillSample>> {
+        let samples = sqlx::query_as::<_, TreadmillSample>(
+            "SELECT timestamp, speed, distance_total, calories_total, steps_total,
+                    distance_delta, calories_delta, steps_delta, user_id,
+                    incline, heart_rate, calories_estimated
+             FROM treadmill_samples
+             WHERE (? IS NULL OR timestamp > ?) AND speed > 0.0 AND excluded = 0
+             ORDER BY timestamp ASC
+             LIMIT ?",
+        )
+        .bind(after)
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(samples)
+    }
+
+    /// Samples summaries count (moving, not excluded) in `[start, end)`, oldest first
+    /// `user_id` restricts results to one user's samples (None = everyone)
+    pub async fn get_active_samples_between(
+        &self,
+        start: i64,
+        end: i64,
+        user_id: Option<i64>,
+    ) -> Result<Vec<TreadmillSample>> {
+        let samples = sqlx::query_as::<_, TreadmillSample>(
+            "SELECT timestamp, speed, distance_total, calories_total, steps_total,
+                    distance_delta, calories_delta, steps_delta, user_id,
+                    incline, heart_rate, calories_estimated
+             FROM treadmill_samples
+             WHERE timestamp >= ? AND timestamp < ? AND speed > 0.0 AND excluded = 0
+               AND (? IS NULL OR user_id = ?)
+             ORDER BY timestamp ASC",
+        )
+        .bind(start)
+        .bind(end)
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(samples)
+    }
+
+    /// Store recalculated calorie estimates, as (timestamp, kcal) pairs
+    pub async fn set_calories_estimated(&self, estimates: &[(i64, f64)]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for (timestamp, kcal) in estimates {
+            let updated = sqlx::query(
+                "UPDATE treadmill_samples SET calories_estimated = ? WHERE timestamp = ?",
+            )
+            .bind(kcal)
+            .bind(timestamp)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            if updated > 0 {
+                changes::log_sample_upsert(&mut tx, *timestamp).await?;
+            }
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
     /// Get total sample count (for debugging/stats)
     pub async fn get_total_sample_count(&self) -> Result<i64> {
         let row = sqlx::query("SELECT COUNT(*) as count FROM treadmill_samples")
@@ -319,62 +576,488 @@ impl Storage {
     /// Get all daily summaries at once (more efficient than N+1 queries)
     ///
     /// # Arguments
-    /// * `tz_offset_seconds` - Timezone offset from UTC in seconds
+    /// * `zone` - Time zone that defines the local day. Each day gets its own
+    ///   UTC offset, so days on either side of a DST change are bucketed correctly.
+    /// * `user_id` - Only this user's samples and corrections (None = everyone)
     pub async fn get_all_daily_summaries(
         &self,
-        tz_offset_seconds: i32,
+        zone: &Zone,
+        user_id: Option<i64>,
     ) -> Result<Vec<DailySummary>> {
-        // First, get aggregated stats per day
-        let rows = sqlx::query(
-            r#"
-            SELECT
-                DATE(timestamp + ?, 'unixepoch') as date,
-                COUNT(*) as total_samples,
-                COALESCE(SUM(distance_delta), 0) as distance_meters,
-                COALESCE(SUM(calories_delta), 0) as calories,
-                COALESCE(SUM(steps_delta), 0) as steps,
-                COALESCE(AVG(speed), 0) as avg_speed,
-                COALESCE(MAX(speed), 0) as max_speed
-            FROM treadmill_samples
-            WHERE speed > 0.0
-            GROUP BY DATE(timestamp + ?, 'unixepoch')
-            ORDER BY date DESC
-            "#,
-        )
-        .bind(tz_offset_seconds)
-        .bind(tz_offset_seconds)
-        .fetch_all(&self.pool)
-        .await?;
-
-        // Calculate duration for each day using proper gap detection
-        let mut summaries = Vec::with_capacity(rows.len());
-
-        for row in rows {
-            let date: String = row.get("date");
-
-            // Parse date to get timestamp range for duration calculation
-            let naive_date = NaiveDate::parse_from_str(&date, "%Y-%m-%d")?;
-            let start_local = naive_date
-                .and_hms_opt(0, 0, 0)
-                .ok_or_else(|| anyhow::anyhow!("Invalid date time"))?;
-            let end_local = start_local + chrono::Duration::days(1);
-            let start_unix = start_local.and_utc().timestamp() - tz_offset_seconds as i64;
-            let end_unix = end_local.and_utc().timestamp() - tz_offset_seconds as i64;
-
-            let duration_seconds = self.calculate_active_duration(start_unix, end_unix).await?;
-
-            summaries.push(DailySummary {
-                date,
-                total_samples: row.get("total_samples"),
-                duration_seconds,
-                distance_meters: row.get("distance_meters"),
-                calories: row.get("calories"),
-                steps: row.get("steps"),
-                avg_speed: row.get("avg_speed"),
-                max_speed: row.get("max_speed"),
-            });
+        let mut days: BTreeMap<NaiveDate, Totals> = BTreeMap::new();
+        for bucket in self.get_bucket_totals(None, user_id).await? {
+            days.entry(zone.local_date(bucket.bucket_start))
+                .or_default()
+                .add(&bucket);
         }
 
+        let mut corrections = self.get_correction_totals(None, user_id).await?;
+
+        let mut summaries: Vec<DailySummary> = days
+            .into_iter()
+            .map(|(date, totals)| {
+                let mut daily = totals.into_summary(date.format("%Y-%m-%d").to_string());
+                if let Some(c) = corrections.remove(&daily.date) {
+                    daily.apply_corrections(&c);
+                }
+                daily
+            })
+            .collect();
+
+        // Days with corrections but no samples
+        summaries.extend(
+            corrections
+                .into_iter()
+                .map(|(date, c)| DailySummary::corrections_only(date, &c)),
+        );
+        summaries.sort_by(|a, b| b.date.cmp(&a.date));
+
         Ok(summaries)
     }
 }
+
+/// Fixtures for tests throughout the crate
+#[cfg(test)]
+pub(crate) mod test_support {
+    use super::{Storage, TreadmillSample};
+
+    pub(crate) fn test_database_url(dir: &tempfile::TempDir) -> String {
+        format!("sqlite://{}", dir.path().join("test.db").display())
+    }
+
+    /// A fresh database in `dir`
+    pub(crate) async fn test_storage_in(dir: &tempfile::TempDir) -> Storage {
+        Storage::new(&test_database_url(dir)).await.unwrap()
+    }
+
+    /// A fresh database in a directory of its own
+    pub(crate) async fn test_storage() -> (tempfile::TempDir, Storage) {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_storage_in(&dir).await;
+        (dir, storage)
+    }
+
+    /// A moving sample of 1 m and 2 steps in a second; change fields with
+    /// `TreadmillSample { ..test_sample(timestamp) }`
+    pub(crate) fn test_sample(timestamp: i64) -> TreadmillSample {
+        TreadmillSample {
+            timestamp,
+            speed: Some(1.0),
+            distance_total: None,
+            calories_total: None,
+            steps_total: None,
+            distance_delta: Some(1),
+            calories_delta: Some(0),
+            steps_delta: Some(2),
+            user_id: None,
+            incline: None,
+            heart_rate: None,
+            calories_estimated: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::changes::ChangeData;
+    use super::test_support::{test_sample, test_storage};
+    use super::*;
+
+    fn sample(timestamp: i64, steps_delta: i64) -> TreadmillSample {
+        TreadmillSample {
+            steps_delta: Some(steps_delta),
+            ..test_sample(timestamp)
+        }
+    }
+
+    fn author() -> ChangeAuthor {
+        ChangeAuthor {
+            actor: "test".to_string(),
+            reason: Some("testing".to_string()),
+        }
+    }
+
+    // 2025-01-15 00:00:00 UTC
+    const DAY_START: i64 = 1736899200;
+
+    #[tokio::test]
+    async fn test_summary_honours_exclusions_and_corrections() {
+        let (_dir, storage) = test_storage().await;
+        let date = NaiveDate::from_ymd_opt(2025, 1, 15).unwrap();
+
+        let samples: Vec<_> = (0..10).map(|i| sample(DAY_START + i, 2)).collect();
+        assert_eq!(
+            storage.insert_samples_if_absent(&samples).await.unwrap(),
+            10
+        );
+
+        // A glitch: one sample claiming 2000 steps
+        let glitch = sample(DAY_START + 100, 2000);
+        storage
+            .insert_samples_if_absent(std::slice::from_ref(&glitch))
+            .await
+            .unwrap();
+        let summary = storage
+            .get_daily_summary(date, &Zone::default(), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.steps, 2020);
+
+        let excluded = storage
+            .set_samples_excluded(DAY_START + 100, DAY_START + 101, true, &author())
+            .await
+            .unwrap();
+        assert_eq!(excluded, 1);
+        let summary = storage
+            .get_daily_summary(date, &Zone::default(), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.steps, 20);
+        assert_eq!(summary.total_samples, 10);
+        assert!(!summary.corrected);
+
+        // Writing it again (a replayed spill) doesn't undo the exclusion
+        storage.add_samples(&[glitch]).await.unwrap();
+        let summary = storage
+            .get_daily_summary(date, &Zone::default(), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.steps, 20);
+
+        let amounts = CorrectionAmounts {
+            steps: -5,
+            distance_meters: 100,
+            ..Default::default()
+        };
+        let correction = storage
+            .add_correction("2025-01-15", None, &amounts, &author())
+            .await
+            .unwrap();
+        let summary = storage
+            .get_daily_summary(date, &Zone::default(), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.steps, 15);
+        assert_eq!(summary.distance_meters, 110);
+        assert!(summary.corrected);
+
+        let all = storage
+            .get_all_daily_summaries(&Zone::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].steps, 15);
+
+        storage
+            .delete_correction(correction.id, &author())
+            .await
+            .unwrap()
+            .unwrap();
+        let audit = storage.get_audit_log(10).await.unwrap();
+        let actions: Vec<_> = audit.iter().map(|e| e.action.as_str()).collect();
+        assert_eq!(
+            actions,
+            vec!["delete_correction", "add_correction", "exclude_samples"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_samples_reads_page_by_page() {
+        let (_dir, storage) = test_storage().await;
+        let storage = Arc::new(storage);
+        let count = STREAM_PAGE_SIZE * 2 + 500;
+        let samples: Vec<_> = (0..count).map(|i| sample(DAY_START + i, 1)).collect();
+        storage.insert_samples_if_absent(&samples).await.unwrap();
+        let range = (DAY_START, DAY_START + 86400);
+
+        let all: Vec<_> = storage
+            .clone()
+            .stream_samples(range, None, None, None)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(all.len() as i64, count);
+        assert!(all.windows(2).all(|w| w[0].timestamp < w[1].timestamp));
+
+        // The cursor and limit carry across internal pages
+        let some: Vec<_> = storage
+            .clone()
+            .stream_samples(range, None, Some(DAY_START + 9), Some(STREAM_PAGE_SIZE + 1))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(some.len() as i64, STREAM_PAGE_SIZE + 1);
+        assert_eq!(some[0].timestamp, DAY_START + 10);
+
+        let page = storage
+            .get_samples_page(range, None, Some(DAY_START + count - 3), Some(10))
+            .await
+            .unwrap();
+        assert_eq!(page.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_corrections_only_day_is_listed() {
+        let (_dir, storage) = test_storage().await;
+
+        let amounts = CorrectionAmounts {
+            steps: 3000,
+            ..Default::default()
+        };
+        storage
+            .add_correction("2025-01-10", None, &amounts, &author())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            storage
+                .get_activity_dates(&Zone::default(), None)
+                .await
+                .unwrap(),
+            vec!["2025-01-10".to_string()]
+        );
+        let date = NaiveDate::from_ymd_opt(2025, 1, 10).unwrap();
+        let summary = storage
+            .get_daily_summary(date, &Zone::default(), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.steps, 3000);
+        assert_eq!(summary.total_samples, 0);
+    }
+
+    #[tokio::test]
+    async fn test_change_feed_orders_inserts_corrections_and_deletes() {
+        let (_dir, storage) = test_storage().await;
+
+        storage
+            .add_samples(&[sample(DAY_START, 5), sample(DAY_START + 1, 5)])
+            .await
+            .unwrap();
+        let correction = storage
+            .add_correction("2025-01-15", None, &CorrectionAmounts::default(), &author())
+            .await
+            .unwrap();
+        storage
+            .delete_samples(DAY_START + 1, DAY_START + 2, &author())
+            .await
+            .unwrap();
+        // Nothing to exclude: no change logged
+        storage
+            .set_samples_excluded(DAY_START + 100, DAY_START + 200, true, &author())
+            .await
+            .unwrap();
+
+        let changes = storage.get_changes(0, 100).await.unwrap();
+        let seqs: Vec<i64> = changes.iter().map(|c| c.seq).collect();
+        assert_eq!(seqs, vec![1, 2, 3, 4]);
+        assert!(matches!(
+            &changes[0].data,
+            ChangeData::SampleUpserted { sample: Some(s), .. } if s.steps_delta == Some(5)
+        ));
+        // The second sample's row is gone, so its upsert carries no data
+        assert!(matches!(
+            &changes[1].data,
+            ChangeData::SampleUpserted { timestamp, sample: None } if *timestamp == DAY_START + 1
+        ));
+        assert!(matches!(
+            &changes[2].data,
+            ChangeData::CorrectionUpserted { correction: c } if c.id == correction.id
+        ));
+        assert!(matches!(
+            changes[3].data,
+            ChangeData::SamplesDeleted { start, end } if start == DAY_START + 1 && end == DAY_START + 2
+        ));
+
+        let rest = storage.get_changes(2, 100).await.unwrap();
+        assert_eq!(rest.len(), 2);
+        assert_eq!(storage.get_latest_change_seq().await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_period_summaries_week_start_and_dst_hours() {
+        use chrono::Weekday;
+
+        let (_dir, storage) = test_storage().await;
+        let day = 86400;
+        // Saturday 2025-01-11, Sunday 2025-01-12 and Monday 2025-01-13 (UTC)
+        let samples: Vec<_> = [-4 * day, -3 * day, -2 * day]
+            .iter()
+            .flat_map(|offset| (0..5).map(move |i| sample(DAY_START + offset + i, 10)))
+            .collect();
+        storage.insert_samples_if_absent(&samples).await.unwrap();
+        let amounts = CorrectionAmounts {
+            steps: 7,
+            ..Default::default()
+        };
+        storage
+            .add_correction("2025-01-13", None, &amounts, &author())
+            .await
+            .unwrap();
+
+        let zone = Zone::default();
+        let weeks = |week_start| {
+            let storage = &storage;
+            async move {
+                storage
+                    .get_period_summaries(GroupBy::Week, None, &zone, week_start, None)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|p| (p.period, p.steps, p.corrected))
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(
+            weeks(Weekday::Mon).await,
+            vec![
+                ("2025-01-06".to_string(), 100, false),
+                ("2025-01-13".to_string(), 57, true),
+            ]
+        );
+        assert_eq!(
+            weeks(Weekday::Sun).await,
+            vec![
+                ("2025-01-05".to_string(), 50, false),
+                ("2025-01-12".to_string(), 107, true),
+            ]
+        );
+
+        let months = storage
+            .get_period_summaries(GroupBy::Month, None, &zone, Weekday::Mon, None)
+            .await
+            .unwrap();
+        assert_eq!(months.len(), 1);
+        assert_eq!(months[0].period, "2025-01-01");
+        assert_eq!(months[0].steps, 157);
+        assert_eq!(months[0].total_samples, 15);
+
+        // US clocks skip 02:00-03:00 local on 2025-03-09
+        let new_york: Zone = "America/New_York".parse().unwrap();
+        let before = 1741501800; // 01:30 EST
+        let after = 1741504500; // 03:15 EDT
+        storage
+            .insert_samples_if_absent(&[sample(before, 1), sample(after, 2)])
+            .await
+            .unwrap();
+        let date = NaiveDate::from_ymd_opt(2025, 3, 9).unwrap();
+        let hours = storage
+            .get_period_summaries(
+                GroupBy::Hour,
+                Some((date, date)),
+                &new_york,
+                Weekday::Mon,
+                None,
+            )
+            .await
+            .unwrap();
+        let labels: Vec<_> = hours.iter().map(|h| h.period.as_str()).collect();
+        assert_eq!(
+            labels,
+            vec!["2025-03-09T01:00:00-05:00", "2025-03-09T03:00:00-04:00"]
+        );
+        assert_eq!(hours[0].end, hours[1].start);
+    }
+
+    #[tokio::test]
+    async fn test_delete_samples() {
+        let (_dir, storage) = test_storage().await;
+
+        let samples: Vec<_> = (0..5).map(|i| sample(DAY_START + i, 1)).collect();
+        storage.insert_samples_if_absent(&samples).await.unwrap();
+
+        let deleted = storage
+            .delete_samples(DAY_START + 1, DAY_START + 3, &author())
+            .await
+            .unwrap();
+        assert_eq!(deleted, 2);
+        assert_eq!(storage.get_total_sample_count().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_summaries_filter_by_user() {
+        let (_dir, storage) = test_storage().await;
+        let date = NaiveDate::from_ymd_opt(2025, 1, 15).unwrap();
+        let alice = storage.create_user("alice", None).await.unwrap();
+        let bob = storage.create_user("bob", Some(90.0)).await.unwrap();
+
+        let samples: Vec<_> = (0..6)
+            .map(|i| TreadmillSample {
+                user_id: Some(if i < 4 { alice.id } else { bob.id }),
+                ..sample(DAY_START + i, 10)
+            })
+            .collect();
+        storage.insert_samples_if_absent(&samples).await.unwrap();
+
+        let steps = |user_id| {
+            let storage = &storage;
+            async move {
+                storage
+                    .get_daily_summary(date, &Zone::default(), user_id)
+                    .await
+                    .unwrap()
+                    .map(|s| s.steps)
+            }
+        };
+        assert_eq!(steps(None).await, Some(60));
+        assert_eq!(steps(Some(alice.id)).await, Some(40));
+        assert_eq!(steps(Some(bob.id)).await, Some(20));
+
+        // Reassign one of alice's samples, then delete bob entirely
+        storage
+            .assign_samples(DAY_START + 3, DAY_START + 4, Some(bob.id), &author())
+            .await
+            .unwrap();
+        assert_eq!(steps(Some(bob.id)).await, Some(30));
+
+        storage
+            .delete_user(bob.id, &author())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(steps(Some(bob.id)).await, None);
+        assert_eq!(steps(None).await, Some(60));
+    }
+
+    #[tokio::test]
+    async fn test_summaries_use_each_days_offset() {
+        let (_dir, storage) = test_storage().await;
+        let la: Zone = "America/Los_Angeles".parse().unwrap();
+
+        // 07:30 UTC is 23:30 PST in January but 00:30 PDT in July
+        let winter = 1736926200; // 2025-01-15 07:30 UTC
+        let summer = 1752564600; // 2025-07-15 07:30 UTC
+        let samples: Vec<_> = [winter, summer]
+            .iter()
+            .flat_map(|&t| (0..3).map(move |i| sample(t + i, 1)))
+            .collect();
+        storage.insert_samples_if_absent(&samples).await.unwrap();
+
+        let dates: Vec<_> = storage
+            .get_all_daily_summaries(&la, None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.date, s.steps, s.duration_seconds))
+            .collect();
+        assert_eq!(
+            dates,
+            vec![
+                ("2025-07-15".to_string(), 3, 2),
+                ("2025-01-14".to_string(), 3, 2)
+            ]
+        );
+        assert_eq!(
+            storage.get_activity_dates(&la, None).await.unwrap(),
+            vec!["2025-07-15".to_string(), "2025-01-14".to_string()]
+        );
+
+        let july_15 = NaiveDate::from_ymd_opt(2025, 7, 15).unwrap();
+        let summary = storage.get_daily_summary(july_15, &la, None).await.unwrap();
+        assert_eq!(summary.unwrap().steps, 3);
+    }
+}
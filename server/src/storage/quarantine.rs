@@ -0,0 +1,81 @@
+//! Samples the live quality checks rejected or flagged (see `crate::quality`).
+
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::Row;
+use utoipa::ToSchema;
+
+use super::{Storage, TreadmillSample};
+use crate::quality::{Issue, QuarantineStatus};
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct QuarantinedSample {
+    pub id: i64,
+    pub timestamp: i64, // Unix epoch seconds
+    pub status: QuarantineStatus,
+    pub issues: Vec<Issue>,
+    pub sample: TreadmillSample, // as parsed, before any rejection
+}
+
+impl Storage {
+    /// Record a sample's quality problems
+    pub async fn quarantine_sample(
+        &self,
+        sample: &TreadmillSample,
+        status: QuarantineStatus,
+        issues: &[Issue],
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO quarantined_samples (timestamp, user_id, status, issues, sample)
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(sample.timestamp)
+        .bind(sample.user_id)
+        .bind(status.as_str())
+        .bind(serde_json::to_string(issues)?)
+        .bind(serde_json::to_string(sample)?)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Quarantined samples in `[start, end)` (everything when None), oldest first
+    pub async fn get_quarantined(
+        &self,
+        range: Option<(i64, i64)>,
+        user_id: Option<i64>,
+    ) -> Result<Vec<QuarantinedSample>> {
+        let (start, end) = range.unwrap_or((i64::MIN, i64::MAX));
+
+        let rows = sqlx::query(
+            "SELECT id, timestamp, status, issues, sample
+             FROM quarantined_samples
+             WHERE timestamp >= ? AND timestamp < ?
+               AND (? IS NULL OR user_id = ?)
+             ORDER BY timestamp ASC, id ASC",
+        )
+        .bind(start)
+        .bind(end)
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                let status = match row.get::<String, _>("status").as_str() {
+                    "rejected" => QuarantineStatus::Rejected,
+                    _ => QuarantineStatus::Flagged,
+                };
+                Ok(QuarantinedSample {
+                    id: row.get("id"),
+                    timestamp: row.get("timestamp"),
+                    status,
+                    issues: serde_json::from_str(row.get("issues"))?,
+                    sample: serde_json::from_str(row.get("sample"))?,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,208 @@
+//! Personal records, achievements, and the running counters behind them
+//! (see `crate::records`).
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Row, Sqlite, Transaction};
+use utoipa::ToSchema;
+
+use super::Storage;
+use crate::records::{Milestone, RecordCounters, RecordKind};
+
+/// The best value of one kind for a scope (a user, or everyone when `user_id` is None)
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct PersonalRecord {
+    pub user_id: Option<i64>,
+    pub kind: RecordKind,
+    pub value: f64,
+    pub date: String,     // YYYY-MM-DD (local day it was set)
+    pub achieved_at: i64, // Unix epoch seconds
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Achievement {
+    pub user_id: Option<i64>,
+    pub milestone: Milestone,
+    pub date: String,     // YYYY-MM-DD (local day it was unlocked)
+    pub achieved_at: i64, // Unix epoch seconds
+}
+
+impl Storage {
+    pub async fn get_record_counters(&self) -> Result<Vec<(Option<i64>, RecordCounters)>> {
+        let rows = sqlx::query("SELECT user_id, counters FROM record_counters")
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok((
+                    row.get("user_id"),
+                    serde_json::from_str(row.get("counters"))?,
+                ))
+            })
+            .collect()
+    }
+
+    pub async fn save_record_counters(
+        &self,
+        user_id: Option<i64>,
+        counters: &RecordCounters,
+    ) -> Result<()> {
+        let json = serde_json::to_string(counters)?;
+        let mut tx = self.pool.begin().await?;
+
+        let updated = sqlx::query("UPDATE record_counters SET counters = ? WHERE user_id IS ?")
+            .bind(&json)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if updated == 0 {
+            sqlx::query("INSERT INTO record_counters (user_id, counters) VALUES (?, ?)")
+                .bind(user_id)
+                .bind(&json)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_all_personal_records(&self) -> Result<Vec<PersonalRecord>> {
+        let records = sqlx::query_as::<_, PersonalRecord>(
+            "SELECT user_id, kind, value, date, achieved_at FROM personal_records",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(records)
+    }
+
+    /// Records for exactly this scope (None = everyone combined)
+    pub async fn get_personal_records(&self, user_id: Option<i64>) -> Result<Vec<PersonalRecord>> {
+        let records = sqlx::query_as::<_, PersonalRecord>(
+            "SELECT user_id, kind, value, date, achieved_at
+             FROM personal_records
+             WHERE user_id IS ?
+             ORDER BY kind ASC",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(records)
+    }
+
+    /// Store a new best, replacing the previous one of the same kind
+    pub async fn save_personal_record(&self, record: &PersonalRecord) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM personal_records WHERE user_id IS ? AND kind = ?")
+            .bind(record.user_id)
+            .bind(record.kind)
+            .execute(&mut *tx)
+            .await?;
+        insert_record(&mut tx, record).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_all_achievements(&self) -> Result<Vec<Achievement>> {
+        let achievements = sqlx::query_as::<_, Achievement>(
+            "SELECT user_id, milestone, date, achieved_at FROM achievements",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(achievements)
+    }
+
+    /// Achievements for exactly this scope (None = everyone combined), oldest first
+    pub async fn get_achievements(&self, user_id: Option<i64>) -> Result<Vec<Achievement>> {
+        let achievements = sqlx::query_as::<_, Achievement>(
+            "SELECT user_id, milestone, date, achieved_at
+             FROM achievements
+             WHERE user_id IS ?
+             ORDER BY achieved_at ASC",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(achievements)
+    }
+
+    pub async fn add_achievement(&self, achievement: &Achievement) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        insert_achievement(&mut tx, achievement).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Replace all counters, records and achievements (after a rebuild)
+    pub async fn replace_records(
+        &self,
+        counters: &[(Option<i64>, &RecordCounters)],
+        records: &[&PersonalRecord],
+        achievements: &[&Achievement],
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for table in ["record_counters", "personal_records", "achievements"] {
+            sqlx::query(&format!("DELETE FROM {}", table))
+                .execute(&mut *tx)
+                .await?;
+        }
+        for (user_id, c) in counters {
+            sqlx::query("INSERT INTO record_counters (user_id, counters) VALUES (?, ?)")
+                .bind(user_id)
+                .bind(serde_json::to_string(c)?)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for record in records {
+            insert_record(&mut tx, record).await?;
+        }
+        for achievement in achievements {
+            insert_achievement(&mut tx, achievement).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+async fn insert_record(tx: &mut Transaction<'_, Sqlite>, record: &PersonalRecord) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO personal_records (user_id, kind, value, date, achieved_at)
+         VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(record.user_id)
+    .bind(record.kind)
+    .bind(record.value)
+    .bind(&record.date)
+    .bind(record.achieved_at)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert_achievement(
+    tx: &mut Transaction<'_, Sqlite>,
+    achievement: &Achievement,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO achievements (user_id, milestone, date, achieved_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(achievement.user_id)
+    .bind(achievement.milestone)
+    .bind(&achievement.date)
+    .bind(achievement.achieved_at)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,265 @@
+//! Sync clients and the ledger of what each has exported where
+//! (see `crate::sync`).
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Sqlite, Transaction};
+use utoipa::ToSchema;
+
+use super::Storage;
+
+/// A device that exports sessions (e.g. a phone writing to Apple Health)
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct SyncClient {
+    pub client_id: String,
+    pub name: Option<String>,
+    pub destination: String, // where it exports to, shared by clients writing to the same place
+    pub user_id: Option<i64>, // whose sessions it exports (None = everyone's)
+    pub cursor: i64,         // sessions starting before this are settled (Unix epoch seconds)
+    pub created_at: i64,
+    pub last_seen_at: i64,
+}
+
+/// One session exported to a destination
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct LedgerEntry {
+    pub id: i64,
+    pub destination: String,
+    pub item_id: String, // export token, e.g. walkpad-session-1736899200
+    pub user_id: Option<i64>,
+    pub session_start: i64,
+    pub session_end: i64,
+    pub fingerprint: String,         // session contents when exported
+    pub version: i64,                // increases each time the session is re-exported
+    pub client_id: String,           // who exported it
+    pub external_id: Option<String>, // the destination's ID for it (e.g. a HealthKit UUID)
+    pub exported_at: i64,
+}
+
+/// An export to record (see [`Storage::record_exports`])
+#[derive(Debug, Clone)]
+pub struct NewLedgerEntry {
+    pub item_id: String,
+    pub user_id: Option<i64>,
+    pub session_start: i64,
+    pub session_end: i64,
+    pub fingerprint: String,
+    pub version: i64,
+    pub external_id: Option<String>,
+}
+
+const LEDGER_COLUMNS: &str = "id, destination, item_id, user_id, session_start, session_end,
+     fingerprint, version, client_id, external_id, exported_at";
+
+impl Storage {
+    pub async fn get_sync_clients(&self) -> Result<Vec<SyncClient>> {
+        let clients = sqlx::query_as::<_, SyncClient>(
+            "SELECT client_id, name, destination, user_id, cursor, created_at, last_seen_at
+             FROM sync_clients
+             ORDER BY created_at ASC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(clients)
+    }
+
+    pub async fn get_sync_client(&self, client_id: &str) -> Result<Option<SyncClient>> {
+        let client = sqlx::query_as::<_, SyncClient>(
+            "SELECT client_id, name, destination, user_id, cursor, created_at, last_seen_at
+             FROM sync_clients
+             WHERE client_id = ?",
+        )
+        .bind(client_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(client)
+    }
+
+    /// Register a client or update its settings. `cursor` resets where it
+    /// syncs from (new clients start from the beginning when it's None).
+    pub async fn upsert_sync_client(
+        &self,
+        client_id: &str,
+        name: Option<&str>,
+        destination: &str,
+        user_id: Option<i64>,
+        cursor: Option<i64>,
+    ) -> Result<SyncClient> {
+        let now = Utc::now().timestamp();
+        let mut tx = self.pool.begin().await?;
+
+        let updated = sqlx::query_as::<_, SyncClient>(
+            "UPDATE sync_clients
+             SET name = ?, destination = ?, user_id = ?, cursor = COALESCE(?, cursor),
+                 last_seen_at = ?
+             WHERE client_id = ?
+             RETURNING client_id, name, destination, user_id, cursor, created_at, last_seen_at",
+        )
+        .bind(name)
+        .bind(destination)
+        .bind(user_id)
+        .bind(cursor)
+        .bind(now)
+        .bind(client_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let client = match updated {
+            Some(client) => client,
+            None => {
+                sqlx::query_as::<_, SyncClient>(
+                    "INSERT INTO sync_clients
+                     (client_id, name, destination, user_id, cursor, created_at, last_seen_at)
+                     VALUES (?, ?, ?, ?, ?, ?, ?)
+                     RETURNING client_id, name, destination, user_id, cursor, created_at,
+                               last_seen_at",
+                )
+                .bind(client_id)
+                .bind(name)
+                .bind(destination)
+                .bind(user_id)
+                .bind(cursor.unwrap_or(0))
+                .bind(now)
+                .bind(now)
+                .fetch_one(&mut *tx)
+                .await?
+            }
+        };
+
+        tx.commit().await?;
+        Ok(client)
+    }
+
+    /// Note that a client checked in, moving its cursor to `cursor`
+    pub async fn touch_sync_client(&self, client_id: &str, cursor: i64) -> Result<()> {
+        sqlx::query("UPDATE sync_clients SET cursor = ?, last_seen_at = ? WHERE client_id = ?")
+            .bind(cursor)
+            .bind(Utc::now().timestamp())
+            .bind(client_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns whether the client existed. Its exports stay in the ledger.
+    pub async fn delete_sync_client(&self, client_id: &str) -> Result<bool> {
+        let deleted = sqlx::query("DELETE FROM sync_clients WHERE client_id = ?")
+            .bind(client_id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+
+        Ok(deleted > 0)
+    }
+
+    /// Exports to a destination of sessions overlapping `[start, end)`
+    pub async fn get_ledger_for_sessions(
+        &self,
+        destination: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<LedgerEntry>> {
+        let entries = sqlx::query_as::<_, LedgerEntry>(&format!(
+            "SELECT {} FROM sync_ledger
+             WHERE destination = ? AND session_end >= ? AND session_start < ?
+             ORDER BY session_start ASC",
+            LEDGER_COLUMNS
+        ))
+        .bind(destination)
+        .bind(start)
+        .bind(end)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+
+    /// Most recent exports first, optionally for one destination or client
+    pub async fn get_ledger(
+        &self,
+        destination: Option<&str>,
+        client_id: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<LedgerEntry>> {
+        let entries = sqlx::query_as::<_, LedgerEntry>(&format!(
+            "SELECT {} FROM sync_ledger
+             WHERE (? IS NULL OR destination = ?) AND (? IS NULL OR client_id = ?)
+             ORDER BY exported_at DESC, id DESC
+             LIMIT ?",
+            LEDGER_COLUMNS
+        ))
+        .bind(destination)
+        .bind(destination)
+        .bind(client_id)
+        .bind(client_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+
+    /// Record that a client exported sessions to its destination, replacing
+    /// earlier exports of the same items there
+    pub async fn record_exports(
+        &self,
+        client_id: &str,
+        destination: &str,
+        exports: &[NewLedgerEntry],
+    ) -> Result<Vec<LedgerEntry>> {
+        let now = Utc::now().timestamp();
+        let mut tx = self.pool.begin().await?;
+        let mut entries = Vec::with_capacity(exports.len());
+
+        for export in exports {
+            sqlx::query("DELETE FROM sync_ledger WHERE destination = ? AND item_id = ?")
+                .bind(destination)
+                .bind(&export.item_id)
+                .execute(&mut *tx)
+                .await?;
+            let entry = sqlx::query_as::<_, LedgerEntry>(&format!(
+                "INSERT INTO sync_ledger
+                 (destination, item_id, user_id, session_start, session_end, fingerprint,
+                  version, client_id, external_id, exported_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                 RETURNING {}",
+                LEDGER_COLUMNS
+            ))
+            .bind(destination)
+            .bind(&export.item_id)
+            .bind(export.user_id)
+            .bind(export.session_start)
+            .bind(export.session_end)
+            .bind(&export.fingerprint)
+            .bind(export.version)
+            .bind(client_id)
+            .bind(&export.external_id)
+            .bind(now)
+            .fetch_one(&mut *tx)
+            .await?;
+            entries.push(entry);
+        }
+
+        tx.commit().await?;
+        Ok(entries)
+    }
+}
+
+/// Move every client's cursor back to `timestamp` (if it's past it) after
+/// samples there changed, so the sessions around it are checked again
+pub(super) async fn rewind_sync_cursors(
+    tx: &mut Transaction<'_, Sqlite>,
+    timestamp: i64,
+) -> Result<()> {
+    sqlx::query("UPDATE sync_clients SET cursor = ? WHERE cursor > ?")
+        .bind(timestamp)
+        .bind(timestamp)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
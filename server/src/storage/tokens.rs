@@ -0,0 +1,89 @@
+//! API tokens (see `crate::auth`). Only a hash of each token is stored.
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::FromRow;
+
+use super::Storage;
+use crate::auth::Scope;
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ApiToken {
+    pub id: i64,
+    pub name: String,
+    pub scope: Scope,
+    pub user_id: Option<i64>, // only this user's data (None = every user's)
+    pub created_at: i64,      // Unix epoch seconds
+    pub last_used_at: Option<i64>, // to the nearest minute
+}
+
+impl Storage {
+    pub async fn create_api_token(
+        &self,
+        name: &str,
+        scope: Scope,
+        user_id: Option<i64>,
+        token_hash: &str,
+    ) -> Result<ApiToken> {
+        let token = sqlx::query_as::<_, ApiToken>(
+            "INSERT INTO api_tokens (name, token_hash, scope, user_id, created_at)
+             VALUES (?, ?, ?, ?, ?)
+             RETURNING id, name, scope, user_id, created_at, last_used_at",
+        )
+        .bind(name)
+        .bind(token_hash)
+        .bind(scope)
+        .bind(user_id)
+        .bind(Utc::now().timestamp())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    pub async fn get_api_tokens(&self) -> Result<Vec<ApiToken>> {
+        let tokens = sqlx::query_as::<_, ApiToken>(
+            "SELECT id, name, scope, user_id, created_at, last_used_at FROM api_tokens ORDER BY id ASC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tokens)
+    }
+
+    pub async fn find_api_token(&self, token_hash: &str) -> Result<Option<ApiToken>> {
+        let token = sqlx::query_as::<_, ApiToken>(
+            "SELECT id, name, scope, user_id, created_at, last_used_at FROM api_tokens
+             WHERE token_hash = ?",
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    pub async fn touch_api_token(&self, id: i64) -> Result<()> {
+        sqlx::query("UPDATE api_tokens SET last_used_at = ? WHERE id = ?")
+            .bind(Utc::now().timestamp())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Revoke a token by name. Returns the revoked token, if it existed.
+    pub async fn delete_api_token(&self, name: &str) -> Result<Option<ApiToken>> {
+        let token = sqlx::query_as::<_, ApiToken>(
+            "DELETE FROM api_tokens WHERE name = ?
+             RETURNING id, name, scope, user_id, created_at, last_used_at",
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(token)
+    }
+}
@@ -0,0 +1,294 @@
+//! User profiles for a shared treadmill: who exists, who is currently
+//! walking, and the time-of-day schedules that switch between them.
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use super::changes::{log_change, ChangeData};
+use super::corrections::write_audit;
+use super::sync::rewind_sync_cursors;
+use super::{ChangeAuthor, DailyCorrection, Storage};
+
+const ACTIVE_USER_KEY: &str = "active_user_id";
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct User {
+    pub id: i64,
+    pub name: String,
+    pub created_at: i64,        // Unix epoch seconds
+    pub weight_kg: Option<f64>, // for calorie estimation (None = configured default)
+}
+
+/// A daily window during which a user becomes active automatically
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct UserSchedule {
+    pub id: i64,
+    pub user_id: i64,
+    pub start_minute: i64, // minutes after local midnight
+    pub end_minute: i64,   // exclusive; less than start_minute wraps past midnight
+}
+
+impl UserSchedule {
+    /// Whether `minute` (minutes after local midnight) falls inside this window
+    pub fn contains(&self, minute: i64) -> bool {
+        if self.start_minute <= self.end_minute {
+            minute >= self.start_minute && minute < self.end_minute
+        } else {
+            minute >= self.start_minute || minute < self.end_minute
+        }
+    }
+}
+
+impl Storage {
+    pub async fn create_user(&self, name: &str, weight_kg: Option<f64>) -> Result<User> {
+        let user = sqlx::query_as::<_, User>(
+            "INSERT INTO users (name, created_at, weight_kg) VALUES (?, ?, ?)
+             RETURNING id, name, created_at, weight_kg",
+        )
+        .bind(name)
+        .bind(Utc::now().timestamp())
+        .bind(weight_kg)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    pub async fn get_users(&self) -> Result<Vec<User>> {
+        let users = sqlx::query_as::<_, User>(
+            "SELECT id, name, created_at, weight_kg FROM users ORDER BY id ASC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(users)
+    }
+
+    pub async fn get_user(&self, id: i64) -> Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(
+            "SELECT id, name, created_at, weight_kg FROM users WHERE id = ?",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Change a user's body weight. Returns the updated user, if it exists.
+    pub async fn set_user_weight(&self, id: i64, weight_kg: Option<f64>) -> Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(
+            "UPDATE users SET weight_kg = ? WHERE id = ?
+             RETURNING id, name, created_at, weight_kg",
+        )
+        .bind(weight_kg)
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Remove a user with their schedules, goals, records and tokens. Their samples and corrections are kept
+    /// but become unassigned. Returns the removed user, if it existed.
+    pub async fn delete_user(&self, id: i64, author: &ChangeAuthor) -> Result<Option<User>> {
+        let mut tx = self.pool.begin().await?;
+
+        let user = sqlx::query_as::<_, User>(
+            "DELETE FROM users WHERE id = ? RETURNING id, name, created_at, weight_kg",
+        )
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(ref u) = user {
+            sqlx::query("DELETE FROM user_schedules WHERE user_id = ?")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            for table in [
+                "goals",
+                "record_counters",
+                "personal_records",
+                "achievements",
+                "sync_clients",
+                "sync_ledger",
+                "api_tokens",
+            ] {
+                sqlx::query(&format!("DELETE FROM {} WHERE user_id = ?", table))
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            let unassigned =
+                sqlx::query("UPDATE treadmill_samples SET user_id = NULL WHERE user_id = ?")
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+            if unassigned > 0 {
+                log_change(&mut tx, &ChangeData::UserSamplesUnassigned { user_id: id }).await?;
+            }
+            let corrections = sqlx::query_as::<_, DailyCorrection>(
+                "UPDATE daily_corrections SET user_id = NULL WHERE user_id = ?
+                 RETURNING id, date, user_id, distance_meters, calories, steps, duration_seconds,
+                           reason, actor, created_at",
+            )
+            .bind(id)
+            .fetch_all(&mut *tx)
+            .await?;
+            for correction in corrections {
+                log_change(&mut tx, &ChangeData::CorrectionUpserted { correction }).await?;
+            }
+            sqlx::query("DELETE FROM settings WHERE key = ? AND value = ?")
+                .bind(ACTIVE_USER_KEY)
+                .bind(id.to_string())
+                .execute(&mut *tx)
+                .await?;
+
+            write_audit(
+                &mut tx,
+                author,
+                "delete_user",
+                serde_json::json!({ "user": u, "unassigned_samples": unassigned }),
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    /// The user new samples are attributed to (None = unassigned)
+    pub async fn get_active_user_id(&self) -> Result<Option<i64>> {
+        let value: Option<String> = sqlx::query_scalar("SELECT value FROM settings WHERE key = ?")
+            .bind(ACTIVE_USER_KEY)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(value.and_then(|v| v.parse().ok()))
+    }
+
+    pub async fn set_active_user_id(&self, user_id: Option<i64>) -> Result<()> {
+        match user_id {
+            Some(id) => {
+                sqlx::query(
+                    "INSERT INTO settings (key, value) VALUES (?, ?)
+                     ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                )
+                .bind(ACTIVE_USER_KEY)
+                .bind(id.to_string())
+                .execute(&self.pool)
+                .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM settings WHERE key = ?")
+                    .bind(ACTIVE_USER_KEY)
+                    .execute(&self.pool)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Schedules for one user, or for everyone when `user_id` is None
+    pub async fn get_schedules(&self, user_id: Option<i64>) -> Result<Vec<UserSchedule>> {
+        let schedules = sqlx::query_as::<_, UserSchedule>(
+            "SELECT id, user_id, start_minute, end_minute
+             FROM user_schedules
+             WHERE ? IS NULL OR user_id = ?
+             ORDER BY start_minute ASC",
+        )
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(schedules)
+    }
+
+    pub async fn add_schedule(
+        &self,
+        user_id: i64,
+        start_minute: i64,
+        end_minute: i64,
+    ) -> Result<UserSchedule> {
+        let schedule = sqlx::query_as::<_, UserSchedule>(
+            "INSERT INTO user_schedules (user_id, start_minute, end_minute) VALUES (?, ?, ?)
+             RETURNING id, user_id, start_minute, end_minute",
+        )
+        .bind(user_id)
+        .bind(start_minute)
+        .bind(end_minute)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(schedule)
+    }
+
+    pub async fn delete_schedule(&self, id: i64) -> Result<Option<UserSchedule>> {
+        let schedule = sqlx::query_as::<_, UserSchedule>(
+            "DELETE FROM user_schedules WHERE id = ?
+             RETURNING id, user_id, start_minute, end_minute",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(schedule)
+    }
+
+    /// Attribute samples in `[start, end)` (Unix seconds) to a user, or unassign
+    /// them when `user_id` is None
+    pub async fn assign_samples(
+        &self,
+        start: i64,
+        end: i64,
+        user_id: Option<i64>,
+        author: &ChangeAuthor,
+    ) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        let updated = sqlx::query(
+            "UPDATE treadmill_samples SET user_id = ? WHERE timestamp >= ? AND timestamp < ?",
+        )
+        .bind(user_id)
+        .bind(start)
+        .bind(end)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        if updated > 0 {
+            log_change(
+                &mut tx,
+                &ChangeData::SamplesAssigned {
+                    start,
+                    end,
+                    user_id,
+                },
+            )
+            .await?;
+            rewind_sync_cursors(&mut tx, start).await?;
+        }
+
+        write_audit(
+            &mut tx,
+            author,
+            "assign_samples",
+            serde_json::json!({
+                "start": start,
+                "end": end,
+                "user_id": user_id,
+                "updated": updated,
+            }),
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(updated)
+    }
+}
@@ -0,0 +1,145 @@
+//! The webhook delivery log (see `crate::webhooks`)
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use super::Storage;
+use crate::webhooks::WebhookEvent;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending, // being sent or waiting for a retry
+    Delivered,
+    Failed, // out of retries, or rejected
+}
+
+/// One event sent (or being sent) to one webhook
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook: String, // name from the config
+    pub event: WebhookEvent,
+    pub payload: String, // request body
+    pub status: DeliveryStatus,
+    pub attempts: i64,
+    pub response_status: Option<i64>, // HTTP status of the last attempt
+    pub error: Option<String>,        // why the last attempt failed
+    pub redelivery_of: Option<i64>,
+    pub created_at: i64, // Unix epoch seconds
+    pub last_attempt_at: Option<i64>,
+}
+
+const DELIVERY_COLUMNS: &str = "id, webhook, event, payload, status, attempts, response_status,
+     error, redelivery_of, created_at, last_attempt_at";
+
+impl Storage {
+    pub async fn create_webhook_delivery(
+        &self,
+        webhook: &str,
+        event: WebhookEvent,
+        payload: &str,
+        redelivery_of: Option<i64>,
+    ) -> Result<WebhookDelivery> {
+        // Not `RETURNING`: the row must be committed before its delivery task reads or updates it
+        let id = sqlx::query(
+            "INSERT INTO webhook_deliveries
+             (webhook, event, payload, status, attempts, redelivery_of, created_at)
+             VALUES (?, ?, ?, ?, 0, ?, ?)",
+        )
+        .bind(webhook)
+        .bind(event)
+        .bind(payload)
+        .bind(DeliveryStatus::Pending)
+        .bind(redelivery_of)
+        .bind(Utc::now().timestamp())
+        .execute(&self.pool)
+        .await?
+        .last_insert_rowid();
+
+        self.get_webhook_delivery(id)
+            .await?
+            .ok_or_else(|| anyhow!("Webhook delivery {} vanished", id))
+    }
+
+    /// Record the outcome of an attempt
+    pub async fn update_webhook_delivery(
+        &self,
+        id: i64,
+        status: DeliveryStatus,
+        attempts: i64,
+        response_status: Option<i64>,
+        error: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE webhook_deliveries
+             SET status = ?, attempts = ?, response_status = ?, error = ?, last_attempt_at = ?
+             WHERE id = ?",
+        )
+        .bind(status)
+        .bind(attempts)
+        .bind(response_status)
+        .bind(error)
+        .bind(Utc::now().timestamp())
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Mark deliveries cut short by a shutdown as failed, so they can be
+    /// redelivered. Returns how many there were.
+    pub async fn fail_pending_webhook_deliveries(&self, error: &str) -> Result<u64> {
+        let result =
+            sqlx::query("UPDATE webhook_deliveries SET status = ?, error = ? WHERE status = ?")
+                .bind(DeliveryStatus::Failed)
+                .bind(error)
+                .bind(DeliveryStatus::Pending)
+                .execute(&self.pool)
+                .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn get_webhook_delivery(&self, id: i64) -> Result<Option<WebhookDelivery>> {
+        let delivery = sqlx::query_as::<_, WebhookDelivery>(&format!(
+            "SELECT {} FROM webhook_deliveries WHERE id = ?",
+            DELIVERY_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(delivery)
+    }
+
+    /// Most recent deliveries first, optionally for one webhook or status
+    pub async fn get_webhook_deliveries(
+        &self,
+        webhook: Option<&str>,
+        status: Option<DeliveryStatus>,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>> {
+        let deliveries = sqlx::query_as::<_, WebhookDelivery>(&format!(
+            "SELECT {} FROM webhook_deliveries
+             WHERE (? IS NULL OR webhook = ?) AND (? IS NULL OR status = ?)
+             ORDER BY id DESC
+             LIMIT ?",
+            DELIVERY_COLUMNS
+        ))
+        .bind(webhook)
+        .bind(webhook)
+        .bind(status)
+        .bind(status)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(deliveries)
+    }
+}
@@ -0,0 +1,408 @@
+//! Sync ledger for exports to Apple Health (or anywhere else).
+//!
+//! The unit of export is a completed [`Session`]. Each gets a stable export
+//! token (`walkpad-session-<start>`, or `walkpad-user-<id>-session-<start>`
+//! for one user's sessions) for the client to save as its HealthKit sync
+//! identifier, with a version that increases whenever the session changes
+//! after export (samples excluded, history imported), so a re-export
+//! replaces the earlier workout rather than duplicating it. A session keeps
+//! the token it was first exported under for as long as it overlaps that
+//! export, even if trimming or reassigning it moves its start.
+//!
+//! The ledger records which sessions were exported to which destination, by
+//! which client and in what state. Clients exporting to the same destination
+//! (a reinstalled app, a second phone on the same Apple ID) share it, so
+//! nothing is offered twice. Each client also keeps a cursor: sessions that
+//! end before it are settled and no longer scanned. Changing samples moves
+//! every cursor back to them.
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+use crate::sessions::{sessions_overlapping, Session};
+use crate::storage::{LedgerEntry, NewLedgerEntry, Storage, SyncClient};
+use crate::timezone::Zone;
+
+/// Destination for clients that don't name one
+pub const DEFAULT_DESTINATION: &str = "apple_health";
+
+/// The export token for a session that hasn't been exported
+pub fn export_token(session: &Session) -> String {
+    match session.user_id {
+        Some(user_id) => format!("walkpad-user-{}-session-{}", user_id, session.start),
+        None => format!("walkpad-session-{}", session.start),
+    }
+}
+
+/// A short digest of what an export contains, to notice sessions that changed
+pub fn fingerprint(session: &Session) -> String {
+    // FNV-1a: stable across builds, unlike `DefaultHasher`
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for value in [
+        session.end,
+        session.total_samples,
+        session.duration_seconds,
+        session.distance_meters,
+        session.calories,
+        session.steps,
+        session.calories_estimated.round() as i64,
+    ] {
+        for byte in value.to_le_bytes() {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+/// A session a client should export
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SyncItem {
+    pub id: String, // export token
+    pub version: i64,
+    pub fingerprint: String,
+    pub session: Session,
+    pub previous: Option<LedgerEntry>, // the earlier export this replaces
+}
+
+/// A client confirming it exported an item
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct Ack {
+    pub id: String,
+    pub fingerprint: String,
+    #[serde(default)]
+    pub external_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AckResult {
+    pub recorded: Vec<LedgerEntry>,
+    pub unknown: Vec<String>, // IDs that weren't pending (nothing recorded)
+    pub cursor: i64,
+}
+
+/// Pair time-ordered sessions with their exports (ledger entries overlapping
+/// them, each claimed once) and their tokens
+fn match_exports(
+    sessions: Vec<Session>,
+    ledger: &[LedgerEntry],
+) -> Vec<(String, Session, Option<&LedgerEntry>)> {
+    let mut claimed = vec![false; ledger.len()];
+    sessions
+        .into_iter()
+        .map(|session| {
+            let matched = ledger.iter().enumerate().position(|(i, e)| {
+                !claimed[i] && e.session_start <= session.end && e.session_end >= session.start
+            });
+            match matched {
+                Some(i) => {
+                    claimed[i] = true;
+                    (ledger[i].item_id.clone(), session, Some(&ledger[i]))
+                }
+                None => (export_token(&session), session, None),
+            }
+        })
+        .collect()
+}
+
+/// The client's view of its sessions from the cursor on
+struct Scan {
+    pending: Vec<SyncItem>,
+    cursor: i64, // where the cursor can move: the first unsettled session
+}
+
+async fn scan(storage: &Storage, client: &SyncClient, now: i64) -> Result<Scan> {
+    // Includes a session under way at the cursor, if it was moved back into one
+    let sessions = sessions_overlapping(storage, client.cursor, now, client.user_id).await?;
+    let from = sessions.first().map_or(client.cursor, |s| s.start);
+    let ledger = storage
+        .get_ledger_for_sessions(&client.destination, from, now + 1)
+        .await?;
+
+    let mut pending = Vec::new();
+    let mut cursor = None;
+    let mut settled_until = client.cursor;
+    for (id, session, previous) in match_exports(sessions, &ledger) {
+        if !session.is_complete(now) {
+            // Still being walked: offered once it ends
+            cursor.get_or_insert(session.start);
+            continue;
+        }
+        let fingerprint = fingerprint(&session);
+        if previous.is_some_and(|e| e.fingerprint == fingerprint) {
+            settled_until = session.end + 1;
+            continue;
+        }
+        cursor.get_or_insert(session.start);
+        pending.push(SyncItem {
+            version: previous.map_or(1, |e| e.version + 1),
+            id,
+            fingerprint,
+            session,
+            previous: previous.cloned(),
+        });
+    }
+
+    Ok(Scan {
+        pending,
+        cursor: cursor.unwrap_or(settled_until),
+    })
+}
+
+/// Completed sessions the client's destination doesn't have (or has an
+/// outdated copy of), oldest first
+pub async fn pending(storage: &Storage, client: &SyncClient, now: i64) -> Result<Vec<SyncItem>> {
+    let scan = scan(storage, client, now).await?;
+    storage
+        .touch_sync_client(&client.client_id, client.cursor)
+        .await?;
+    Ok(scan.pending)
+}
+
+/// Record exports the client made, then move its cursor past everything
+/// settled. Acknowledging an item that's already recorded with the same
+/// fingerprint is a no-op, so retries are safe.
+pub async fn acknowledge(
+    storage: &Storage,
+    client: &SyncClient,
+    acks: &[Ack],
+    now: i64,
+) -> Result<AckResult> {
+    let before = scan(storage, client, now).await?;
+    let pending: HashMap<&str, &SyncItem> =
+        before.pending.iter().map(|i| (i.id.as_str(), i)).collect();
+
+    let mut exports = Vec::new();
+    let mut unknown = Vec::new();
+    for ack in acks {
+        match pending.get(ack.id.as_str()) {
+            Some(item) => exports.push(NewLedgerEntry {
+                item_id: item.id.clone(),
+                user_id: item.session.user_id,
+                session_start: item.session.start,
+                session_end: item.session.end,
+                // As the client saw it: if the session changed since, it's offered again
+                fingerprint: ack.fingerprint.clone(),
+                version: item.version,
+                external_id: ack.external_id.clone(),
+            }),
+            None => unknown.push(ack.id.clone()),
+        }
+    }
+    let recorded = storage
+        .record_exports(&client.client_id, &client.destination, &exports)
+        .await?;
+
+    // Already-recorded acks (retries) aren't unknown
+    if !unknown.is_empty() {
+        let ledger = storage
+            .get_ledger_for_sessions(&client.destination, client.cursor, now + 1)
+            .await?;
+        unknown.retain(|id| {
+            !ledger.iter().any(|e| {
+                &e.item_id == id
+                    && acks
+                        .iter()
+                        .any(|a| &a.id == id && a.fingerprint == e.fingerprint)
+            })
+        });
+    }
+
+    let cursor = scan(storage, client, now).await?.cursor;
+    storage.touch_sync_client(&client.client_id, cursor).await?;
+
+    Ok(AckResult {
+        recorded,
+        unknown,
+        cursor,
+    })
+}
+
+/// Export state of one session
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SessionStatus {
+    pub id: String, // export token
+    pub session: Session,
+    pub complete: bool,
+    pub exported: Option<LedgerEntry>,
+    pub up_to_date: bool, // exported, and unchanged since
+}
+
+/// Whether a local day's sessions are in a destination
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DayStatus {
+    pub date: String,
+    pub destination: String,
+    pub synced: bool, // every session that day is exported and up to date
+    pub sessions: Vec<SessionStatus>,
+}
+
+/// Answer "is this day already in Health?" for one destination
+pub async fn day_status(
+    storage: &Storage,
+    date: NaiveDate,
+    zone: &Zone,
+    destination: &str,
+    user_id: Option<i64>,
+    now: i64,
+) -> Result<DayStatus> {
+    let (start, end) = zone.day_bounds(date);
+    let sessions: Vec<Session> = sessions_overlapping(storage, start, end, user_id)
+        .await?
+        .into_iter()
+        .filter(|s| s.start >= start)
+        .collect();
+    let from = sessions.first().map_or(start, |s| s.start);
+    let ledger = storage
+        .get_ledger_for_sessions(destination, from, end)
+        .await?;
+
+    let sessions: Vec<SessionStatus> = match_exports(sessions, &ledger)
+        .into_iter()
+        .map(|(id, session, exported)| {
+            let exported = exported.cloned();
+            SessionStatus {
+                up_to_date: exported
+                    .as_ref()
+                    .is_some_and(|e| e.fingerprint == fingerprint(&session)),
+                complete: session.is_complete(now),
+                id,
+                session,
+                exported,
+            }
+        })
+        .collect();
+
+    Ok(DayStatus {
+        date: date.format("%Y-%m-%d").to_string(),
+        destination: destination.to_string(),
+        synced: !sessions.is_empty() && sessions.iter().all(|s| s.up_to_date),
+        sessions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_support::{test_sample, test_storage};
+    use crate::storage::{ChangeAuthor, TreadmillSample};
+
+    fn sample(timestamp: i64, user_id: Option<i64>) -> TreadmillSample {
+        TreadmillSample {
+            user_id,
+            ..test_sample(timestamp)
+        }
+    }
+
+    // 2025-01-15 00:00:00 UTC
+    const DAY_START: i64 = 1736899200;
+
+    #[tokio::test]
+    async fn test_second_client_skips_exported_sessions() {
+        let (_dir, storage) = test_storage().await;
+
+        // Two sessions an hour apart, the second still going at `now`
+        let first: Vec<_> = (0..60).map(|i| sample(DAY_START + i, None)).collect();
+        let second: Vec<_> = (3600..3660).map(|i| sample(DAY_START + i, None)).collect();
+        storage.add_samples(&first).await.unwrap();
+        storage.add_samples(&second).await.unwrap();
+        let now = DAY_START + 3700;
+
+        let phone = storage
+            .upsert_sync_client("phone", None, DEFAULT_DESTINATION, None, None)
+            .await
+            .unwrap();
+        let items = pending(&storage, &phone, now).await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].id, format!("walkpad-session-{}", DAY_START));
+        assert_eq!(items[0].version, 1);
+
+        let acks = [Ack {
+            id: items[0].id.clone(),
+            fingerprint: items[0].fingerprint.clone(),
+            external_id: Some("hk-1".to_string()),
+        }];
+        let result = acknowledge(&storage, &phone, &acks, now).await.unwrap();
+        assert_eq!(result.recorded.len(), 1);
+        assert!(result.unknown.is_empty());
+        assert_eq!(result.cursor, DAY_START + 3600); // the open session
+
+        // Retrying the same ack is harmless
+        let retry = acknowledge(&storage, &phone, &acks, now).await.unwrap();
+        assert!(retry.recorded.is_empty() && retry.unknown.is_empty());
+
+        // A reinstalled app (new client, same destination) starts from scratch
+        // but only gets the session that's now finished
+        let later = now + 3600;
+        let tablet = storage
+            .upsert_sync_client("tablet", None, DEFAULT_DESTINATION, None, None)
+            .await
+            .unwrap();
+        let items = pending(&storage, &tablet, later).await.unwrap();
+        let ids: Vec<_> = items.iter().map(|i| i.id.as_str()).collect();
+        assert_eq!(ids, vec![format!("walkpad-session-{}", DAY_START + 3600)]);
+
+        let date = NaiveDate::from_ymd_opt(2025, 1, 15).unwrap();
+        let status = day_status(
+            &storage,
+            date,
+            &Zone::default(),
+            DEFAULT_DESTINATION,
+            None,
+            later,
+        )
+        .await
+        .unwrap();
+        assert!(!status.synced);
+        assert_eq!(status.sessions.len(), 2);
+        assert!(status.sessions[0].up_to_date);
+
+        // An imported sample changes the first session: offered again as
+        // version 2, also to the phone whose cursor had moved past it
+        storage
+            .insert_samples_if_absent(&[sample(DAY_START + 60, None)])
+            .await
+            .unwrap();
+        let phone = storage.get_sync_client("phone").await.unwrap().unwrap();
+        assert_eq!(phone.cursor, DAY_START + 60);
+        for client in [&tablet, &phone] {
+            let items = pending(&storage, client, later).await.unwrap();
+            assert_eq!(items.len(), 2);
+            assert_eq!(items[0].version, 2);
+            assert_eq!(
+                items[0].previous.as_ref().unwrap().external_id.as_deref(),
+                Some("hk-1")
+            );
+        }
+        let items = pending(&storage, &phone, later).await.unwrap();
+        let acks: Vec<_> = items
+            .iter()
+            .map(|i| Ack {
+                id: i.id.clone(),
+                fingerprint: i.fingerprint.clone(),
+                external_id: None,
+            })
+            .collect();
+        let result = acknowledge(&storage, &phone, &acks, later).await.unwrap();
+        assert_eq!(result.recorded.len(), 2);
+
+        // Excluding the first samples moves the session's start but keeps its token
+        let author = ChangeAuthor {
+            actor: "test".to_string(),
+            reason: None,
+        };
+        storage
+            .set_samples_excluded(DAY_START, DAY_START + 10, true, &author)
+            .await
+            .unwrap();
+        let phone = storage.get_sync_client("phone").await.unwrap().unwrap();
+        let items = pending(&storage, &phone, later).await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].id, format!("walkpad-session-{}", DAY_START));
+        assert_eq!(items[0].session.start, DAY_START + 10);
+        assert_eq!(items[0].version, 3);
+    }
+}
@@ -0,0 +1,204 @@
+//! Time zones for bucketing samples into local days.
+//!
+//! Clients may name an IANA zone (`tz=America/Los_Angeles`), which gets DST
+//! right for every day in the history, or pass a fixed `tz_offset` in seconds
+//! (the original API). With neither, the server's configured zone is used.
+//! Clients that send both get the offset when the server doesn't know the name.
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{
+    DateTime, Duration, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, Offset,
+    TimeZone, Timelike, Utc,
+};
+use chrono_tz::Tz;
+use std::fmt;
+use std::str::FromStr;
+
+/// Every modern UTC offset, and every DST transition since zones adopted
+/// standard time, falls on a multiple of this many seconds, so all instants
+/// inside one such bucket share a local date. Storage aggregates in SQL at
+/// this granularity and maps buckets to days. (Historical local mean time
+/// offsets, such as -07:52:58 in Los Angeles before 1883, don't.)
+pub const BUCKET_SECS: i64 = 15 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zone {
+    Named(Tz),
+    Fixed(FixedOffset),
+}
+
+impl Default for Zone {
+    fn default() -> Self {
+        Zone::Named(Tz::UTC)
+    }
+}
+
+impl Zone {
+    /// From a fixed offset in seconds east of UTC, a whole number of
+    /// [`BUCKET_SECS`] like every modern offset (+05:45 is fine, +00:07 isn't)
+    pub fn from_offset(seconds: i32) -> Result<Self> {
+        if i64::from(seconds) % BUCKET_SECS != 0 {
+            bail!(
+                "Invalid tz_offset: {} is not a multiple of {} seconds",
+                seconds,
+                BUCKET_SECS
+            );
+        }
+        FixedOffset::east_opt(seconds)
+            .map(Zone::Fixed)
+            .ok_or_else(|| anyhow!("Invalid tz_offset: {}", seconds))
+    }
+
+    /// Pick a zone from request parameters: a zone name wins over an offset
+    /// (which stands in if the name is unknown here), and `default` applies
+    /// when neither is given
+    pub fn resolve(tz: Option<&str>, tz_offset: Option<i32>, default: Zone) -> Result<Self> {
+        match (tz, tz_offset) {
+            (Some(name), None) => name.parse(),
+            (Some(name), Some(offset)) => name.parse().or_else(|_| Self::from_offset(offset)),
+            (None, Some(offset)) => Self::from_offset(offset),
+            (None, None) => Ok(default),
+        }
+    }
+
+    /// The local calendar date of a Unix timestamp
+    pub fn local_date(&self, timestamp: i64) -> NaiveDate {
+        let utc = DateTime::<Utc>::from_timestamp(timestamp, 0).unwrap_or_default();
+        match self {
+            Zone::Named(tz) => utc.with_timezone(tz).date_naive(),
+            Zone::Fixed(offset) => utc.with_timezone(offset).date_naive(),
+        }
+    }
+
+    /// A Unix timestamp as a local date-time with its UTC offset
+    pub fn local_datetime(&self, timestamp: i64) -> DateTime<FixedOffset> {
+        let utc = DateTime::<Utc>::from_timestamp(timestamp, 0).unwrap_or_default();
+        match self {
+            Zone::Named(tz) => utc.with_timezone(tz).fixed_offset(),
+            Zone::Fixed(offset) => utc.with_timezone(offset),
+        }
+    }
+
+    /// The Unix timestamp of a local date-time. Times skipped by a DST change
+    /// are shifted forward by the gap (so a skipped midnight becomes the end of
+    /// the gap); repeated times resolve to the first occurrence.
+    pub fn timestamp_of(&self, local: NaiveDateTime) -> i64 {
+        match self {
+            Zone::Named(tz) => resolve_local(tz, local),
+            Zone::Fixed(offset) => resolve_local(offset, local),
+        }
+    }
+
+    /// `[start, end)` Unix timestamps of a local day (23 or 25 hours long on DST changes)
+    pub fn day_bounds(&self, date: NaiveDate) -> (i64, i64) {
+        let start = self.timestamp_of(date.and_time(NaiveTime::MIN));
+        let end = date
+            .succ_opt()
+            .map(|next| self.timestamp_of(next.and_time(NaiveTime::MIN)))
+            .unwrap_or(start + 86_400);
+        (start, end)
+    }
+
+    /// Minutes after local midnight right now
+    pub fn minute_of_day_now(&self) -> i64 {
+        let now = Utc::now();
+        let local = match self {
+            Zone::Named(tz) => now.with_timezone(tz).time(),
+            Zone::Fixed(offset) => now.with_timezone(offset).time(),
+        };
+        i64::from(local.hour() * 60 + local.minute())
+    }
+}
+
+fn resolve_local<Z: TimeZone>(zone: &Z, local: NaiveDateTime) -> i64 {
+    match zone.from_local_datetime(&local) {
+        LocalResult::Single(dt) => dt.timestamp(),
+        LocalResult::Ambiguous(earliest, _) => earliest.timestamp(),
+        LocalResult::None => {
+            // In a DST gap: read the time with the offset from before the gap,
+            // so a skipped midnight maps to the instant the gap ends
+            let before = zone
+                .offset_from_utc_datetime(&(local - Duration::days(1)))
+                .fix();
+            local.and_utc().timestamp() - i64::from(before.local_minus_utc())
+        }
+    }
+}
+
+impl FromStr for Zone {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.parse::<Tz>()
+            .map(Zone::Named)
+            .map_err(|_| anyhow!("Unknown time zone: {}", s))
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Zone::Named(tz) => write!(f, "{}", tz.name()),
+            Zone::Fixed(offset) => write!(f, "{}", offset),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn test_day_bounds_follow_dst() {
+        let la: Zone = "America/Los_Angeles".parse().unwrap();
+
+        // Spring forward: a 23 hour day
+        let (start, end) = la.day_bounds(date(2025, 3, 9));
+        assert_eq!((start, end), (1741507200, 1741590000));
+        assert_eq!(end - start, 23 * 3600);
+
+        // Fall back: a 25 hour day
+        let (start, end) = la.day_bounds(date(2025, 11, 2));
+        assert_eq!((start, end), (1762066800, 1762156800));
+        assert_eq!(end - start, 25 * 3600);
+    }
+
+    #[test]
+    fn test_local_date_and_resolve() {
+        let la: Zone = "America/Los_Angeles".parse().unwrap();
+        // 2025-07-15 06:30 UTC is 23:30 PDT the day before
+        assert_eq!(la.local_date(1752561000), date(2025, 7, 14));
+        assert_eq!(Zone::default().local_date(1752561000), date(2025, 7, 15));
+
+        let fixed = Zone::resolve(None, Some(-28800), Zone::default()).unwrap();
+        assert_eq!(fixed.day_bounds(date(2025, 7, 15)).0, 1752566400);
+        assert_eq!(
+            Zone::resolve(Some("Europe/Paris"), Some(0), fixed)
+                .unwrap()
+                .to_string(),
+            "Europe/Paris"
+        );
+        assert_eq!(Zone::resolve(None, None, fixed).unwrap(), fixed);
+        assert!("Mars/Olympus_Mons".parse::<Zone>().is_err());
+
+        // An unknown name falls back to the offset sent alongside it
+        assert_eq!(
+            Zone::resolve(Some("Mars/Olympus_Mons"), Some(-28800), Zone::default()).unwrap(),
+            fixed
+        );
+        assert!(Zone::resolve(Some("Mars/Olympus_Mons"), None, Zone::default()).is_err());
+    }
+
+    #[test]
+    fn test_offsets_must_fit_buckets() {
+        // Nepal, +05:45
+        assert!(Zone::from_offset(20700).is_ok());
+        assert!(Zone::from_offset(420).is_err());
+        assert!(Zone::from_offset(123).is_err());
+        assert!(Zone::resolve(Some("Mars/Olympus_Mons"), Some(123), Zone::default()).is_err());
+    }
+}
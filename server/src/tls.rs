@@ -0,0 +1,234 @@
+//! HTTPS/WSS without a reverse proxy.
+//!
+//! The certificate and key are PEM files, checked for changes periodically
+//! and reloaded in place (e.g. after a renewal), so open connections and the
+//! listener carry on. For LAN use a self-signed certificate can be generated
+//! on first start; its SHA-256 fingerprint is logged so clients can pin it.
+
+use anyhow::{anyhow, Context, Result};
+use axum_server::tls_rustls::RustlsConfig;
+use chrono::{Datelike, Duration as ChronoDuration, Utc};
+use rcgen::{
+    date_time_ymd, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, KeyPair,
+};
+use rustls::pki_types::{pem::PemObject, CertificateDer};
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// How often the certificate files are checked for changes
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Apple platforms reject server certificates valid for longer than this
+const SELF_SIGNED_VALIDITY_DAYS: i64 = 825;
+
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl TlsFiles {
+    pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        Self {
+            cert: cert.into(),
+            key: key.into(),
+        }
+    }
+
+    fn modified(&self) -> Option<(SystemTime, SystemTime)> {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        Some((modified(&self.cert)?, modified(&self.key)?))
+    }
+}
+
+/// Load the certificate, generating a self-signed one first if allowed and
+/// the files don't exist yet
+pub async fn load(files: &TlsFiles, self_signed: bool, host: &str) -> Result<RustlsConfig> {
+    // Only the ring provider is compiled in; an error means it's already installed
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    if self_signed && !files.cert.exists() && !files.key.exists() {
+        let names = certificate_names(host);
+        generate_self_signed(files, &names)?;
+        info!(
+            "🔐 Generated a self-signed certificate for {} at {}",
+            names.join(", "),
+            files.cert.display()
+        );
+    }
+
+    let config = RustlsConfig::from_pem_file(&files.cert, &files.key)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to load TLS certificate {} and key {}",
+                files.cert.display(),
+                files.key.display()
+            )
+        })?;
+    info!(
+        "🔐 TLS certificate {} (SHA-256 {})",
+        files.cert.display(),
+        fingerprint(&files.cert)?
+    );
+
+    Ok(config)
+}
+
+/// Reload the certificate whenever its files change. A bad replacement
+/// (e.g. a half-written renewal) is logged and the previous one kept.
+pub fn watch(config: RustlsConfig, files: TlsFiles) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut last = files.modified();
+        let mut interval = tokio::time::interval(RELOAD_CHECK_INTERVAL);
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            let current = files.modified();
+            if current.is_none() || current == last {
+                continue;
+            }
+            match reload(&config, &files).await {
+                Ok(fingerprint) => {
+                    info!("🔐 Reloaded TLS certificate (SHA-256 {})", fingerprint);
+                    last = current;
+                }
+                Err(e) => warn!("Keeping the current TLS certificate: {:#}", e),
+            }
+        }
+    })
+}
+
+async fn reload(config: &RustlsConfig, files: &TlsFiles) -> Result<String> {
+    config
+        .reload_from_pem_file(&files.cert, &files.key)
+        .await
+        .context("Failed to reload TLS certificate")?;
+    fingerprint(&files.cert)
+}
+
+/// SHA-256 of the leaf certificate, as colon-separated hex
+pub fn fingerprint(cert_path: &Path) -> Result<String> {
+    let cert = CertificateDer::pem_file_iter(cert_path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", cert_path.display(), e))?
+        .next()
+        .ok_or_else(|| anyhow!("No certificate in {}", cert_path.display()))?
+        .map_err(|e| anyhow!("Failed to parse {}: {}", cert_path.display(), e))?;
+
+    let digest = Sha256::digest(cert.as_ref());
+    let hex: Vec<String> = digest.iter().map(|b| format!("{:02X}", b)).collect();
+    Ok(hex.join(":"))
+}
+
+/// Names a LAN client might use: localhost, this machine's hostname (and its
+/// mDNS `.local` name), the bind address and the primary LAN address
+fn certificate_names(host: &str) -> Vec<String> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+
+    if let Ok(hostname) = std::fs::read_to_string("/etc/hostname") {
+        let hostname = hostname.trim();
+        if !hostname.is_empty() {
+            names.push(hostname.to_string());
+            if !hostname.contains('.') {
+                names.push(format!("{}.local", hostname));
+            }
+        }
+    }
+
+    match host.parse::<IpAddr>() {
+        Ok(ip) if ip.is_unspecified() => names.extend(lan_address().map(|ip| ip.to_string())),
+        _ => names.push(host.to_string()),
+    }
+
+    names.dedup();
+    names
+}
+
+/// The address other devices reach us on. Connecting a UDP socket sends
+/// nothing; it only picks the outgoing interface.
+fn lan_address() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:9").ok()?;
+    Some(socket.local_addr().ok()?.ip()).filter(|ip| !ip.is_loopback())
+}
+
+fn generate_self_signed(files: &TlsFiles, names: &[String]) -> Result<()> {
+    let mut params = CertificateParams::new(names.to_vec())?;
+    let mut subject = DistinguishedName::new();
+    subject.push(DnType::CommonName, "WalkPad Sync Server");
+    params.distinguished_name = subject;
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+
+    let today = Utc::now().date_naive();
+    let expires = today + ChronoDuration::days(SELF_SIGNED_VALIDITY_DAYS);
+    params.not_before = date_time_ymd(today.year(), today.month() as u8, today.day() as u8);
+    params.not_after = date_time_ymd(expires.year(), expires.month() as u8, expires.day() as u8);
+
+    let key = KeyPair::generate()?;
+    let cert = params.self_signed(&key)?;
+
+    for path in [&files.cert, &files.key] {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+    }
+    std::fs::write(&files.cert, cert.pem())?;
+    write_private(&files.key, &key.serialize_pem())?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    std::fs::write(path, contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_self_signed_certificate_loads_and_reloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = TlsFiles::new(dir.path().join("cert.pem"), dir.path().join("key.pem"));
+
+        // Without self-signing there's nothing to load
+        assert!(load(&files, false, "0.0.0.0").await.is_err());
+
+        let config = load(&files, true, "192.168.1.20").await.unwrap();
+        let first = fingerprint(&files.cert).unwrap();
+        assert_eq!(first.len(), 32 * 3 - 1);
+
+        // Existing files are kept on the next start
+        load(&files, true, "192.168.1.20").await.unwrap();
+        assert_eq!(fingerprint(&files.cert).unwrap(), first);
+
+        // A replacement certificate is picked up; a broken one is rejected
+        generate_self_signed(&files, &["walkpad.local".to_string()]).unwrap();
+        let second = reload(&config, &files).await.unwrap();
+        assert_ne!(second, first);
+
+        std::fs::write(&files.cert, "not a certificate").unwrap();
+        assert!(reload(&config, &files).await.is_err());
+    }
+}
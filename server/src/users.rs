@@ -0,0 +1,124 @@
+//! Tracks which user is currently on the treadmill.
+//!
+//! The active user is persisted so it survives restarts, can be switched from
+//! the API or a WebSocket command, and can follow per-user time-of-day
+//! schedules. Every change is broadcast to WebSocket clients.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, RwLock};
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+use crate::storage::Storage;
+use crate::timezone::Zone;
+use crate::websocket::WsMessage;
+
+/// How often schedules are checked
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What caused the active user to change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeSource {
+    Api,
+    WebSocket,
+    Schedule,
+    Mqtt,
+}
+
+pub struct ActiveUser {
+    storage: Arc<Storage>,
+    ws_tx: broadcast::Sender<WsMessage>,
+    current: RwLock<Option<i64>>,
+}
+
+impl ActiveUser {
+    /// Load the persisted active user
+    pub async fn load(storage: Arc<Storage>, ws_tx: broadcast::Sender<WsMessage>) -> Result<Self> {
+        let current = storage.get_active_user_id().await?;
+        Ok(Self {
+            storage,
+            ws_tx,
+            current: RwLock::new(current),
+        })
+    }
+
+    /// The user new samples are attributed to (None = unassigned)
+    pub async fn get(&self) -> Option<i64> {
+        *self.current.read().await
+    }
+
+    /// Switch the active user. Returns false if `user_id` doesn't exist.
+    pub async fn set(&self, user_id: Option<i64>, source: ChangeSource) -> Result<bool> {
+        if let Some(id) = user_id {
+            if self.storage.get_user(id).await?.is_none() {
+                return Ok(false);
+            }
+        }
+
+        let mut current = self.current.write().await;
+        if *current == user_id {
+            return Ok(true);
+        }
+
+        self.storage.set_active_user_id(user_id).await?;
+        *current = user_id;
+        drop(current);
+
+        info!("Active user changed to {:?} ({:?})", user_id, source);
+        let _ = self
+            .ws_tx
+            .send(WsMessage::ActiveUserChanged { user_id, source });
+        Ok(true)
+    }
+
+    /// Forget the active user if it was just deleted
+    pub async fn clear_if(&self, user_id: i64) {
+        let mut current = self.current.write().await;
+        if *current == Some(user_id) {
+            *current = None;
+            drop(current);
+            let _ = self.ws_tx.send(WsMessage::ActiveUserChanged {
+                user_id: None,
+                source: ChangeSource::Api,
+            });
+        }
+    }
+
+    /// Switch users when a schedule window starts (windows are local times in `zone`).
+    ///
+    /// Only the start of a window triggers a switch, so a manual change made
+    /// during a window sticks until the next window begins.
+    pub async fn run_schedules(self: Arc<Self>, zone: Zone) {
+        let mut interval = tokio::time::interval(SCHEDULE_CHECK_INTERVAL);
+        let mut previous: Option<i64> = None;
+
+        loop {
+            interval.tick().await;
+
+            let minute = zone.minute_of_day_now();
+
+            let scheduled = match self.storage.get_schedules(None).await {
+                Ok(schedules) => schedules
+                    .into_iter()
+                    .find(|s| s.contains(minute))
+                    .map(|s| s.user_id),
+                Err(e) => {
+                    error!("Failed to load user schedules: {}", e);
+                    continue;
+                }
+            };
+
+            if scheduled.is_some() && scheduled != previous {
+                if let Err(e) = self.set(scheduled, ChangeSource::Schedule).await {
+                    error!("Failed to apply user schedule: {}", e);
+                    continue;
+                }
+            }
+            previous = scheduled;
+        }
+    }
+}
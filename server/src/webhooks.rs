@@ -0,0 +1,699 @@
+//! Outgoing webhooks, configured as `[[webhooks]]` entries.
+//!
+//! Events come from the live broadcast and the Bluetooth status: a session
+//! starting (first moving sample after a break) and ending (no sample for
+//! [`SESSION_GAP_SECS`], sent with its totals), a goal reached, the treadmill
+//! connecting or disconnecting, and a day's summary once the day is over and
+//! no session from it is still under way.
+//!
+//! Each event goes to every webhook that wants it, as
+//! `{"event": ..., "timestamp": ..., "data": {...}}` or the webhook's template
+//! filled in from that. Requests are signed with the webhook's secret when it
+//! has one and retried with doubling backoff; 4xx answers (other than 408
+//! and 429) aren't retried. Every delivery is logged in `webhook_deliveries`
+//! and can be sent again through the API.
+
+use anyhow::Result;
+use chrono::{NaiveDate, Utc, Weekday};
+use hmac::{Hmac, Mac};
+use reqwest::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::bluetooth::ConnectionStatus;
+use crate::config::WebhookConfig;
+use crate::energy::CaloriesSource;
+use crate::sessions::{self, SESSION_GAP_SECS};
+use crate::storage::{DeliveryStatus, GroupBy, Storage, WebhookDelivery};
+use crate::timezone::Zone;
+use crate::websocket::{WsMessage, WsSample};
+
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    SessionStarted,
+    SessionEnded,
+    GoalReached,
+    TreadmillConnected,
+    TreadmillDisconnected,
+    DailySummary,
+}
+
+impl WebhookEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::SessionStarted => "session_started",
+            WebhookEvent::SessionEnded => "session_ended",
+            WebhookEvent::GoalReached => "goal_reached",
+            WebhookEvent::TreadmillConnected => "treadmill_connected",
+            WebhookEvent::TreadmillDisconnected => "treadmill_disconnected",
+            WebhookEvent::DailySummary => "daily_summary",
+        }
+    }
+}
+
+/// Fill `{{path}}` placeholders from the event (`{{event}}`, `{{data.steps}}`).
+/// Strings are JSON-escaped without their quotes, so they can sit inside a
+/// JSON string; anything missing becomes empty.
+pub fn render_template(template: &str, event: &Value) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find("{{") {
+        let Some(close) = rest[open..].find("}}") else {
+            break;
+        };
+        out.push_str(&rest[..open]);
+        let path = rest[open + 2..open + close].trim();
+        match event.pointer(&format!("/{}", path.replace('.', "/"))) {
+            Some(Value::String(s)) => {
+                let quoted = Value::String(s.clone()).to_string();
+                out.push_str(&quoted[1..quoted.len() - 1]);
+            }
+            Some(Value::Null) | None => {}
+            Some(value) => out.push_str(&value.to_string()),
+        }
+        rest = &rest[open + close + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// `sha256=<hex>` HMAC of `<timestamp>.<body>`
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Distances in km and durations in minutes alongside the raw figures, for templates
+fn with_friendly_units(mut data: Value) -> Value {
+    if let Some(meters) = data["distance_meters"].as_i64() {
+        data["distance_km"] = json!((meters as f64 / 10.0).round() / 100.0);
+    }
+    if let Some(seconds) = data["duration_seconds"].as_i64() {
+        data["duration_minutes"] = json!(seconds / 60);
+    }
+    data
+}
+
+/// The configured webhooks and the client that calls them
+pub struct Webhooks {
+    hooks: Vec<WebhookConfig>,
+    storage: Arc<Storage>,
+    client: reqwest::Client,
+    initial_backoff: Duration,
+}
+
+impl Webhooks {
+    pub fn new(
+        hooks: Vec<WebhookConfig>,
+        storage: Arc<Storage>,
+        initial_backoff: Duration,
+    ) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .user_agent(concat!("walkpad-server/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        Ok(Self {
+            hooks,
+            storage,
+            client,
+            initial_backoff,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Watch for events in the background, until the broadcast channel closes
+    pub async fn start(
+        self: &Arc<Self>,
+        ws_tx: &broadcast::Sender<WsMessage>,
+        status_rx: broadcast::Receiver<ConnectionStatus>,
+        zone: Zone,
+        calories_source: CaloriesSource,
+    ) -> Result<()> {
+        let interrupted = self
+            .storage
+            .fail_pending_webhook_deliveries("Interrupted by a restart")
+            .await?;
+        if interrupted > 0 {
+            warn!(
+                "{} webhook deliveries were cut short by the last shutdown; redeliver them from /api/webhooks/deliveries",
+                interrupted
+            );
+        }
+        info!("Sending events to {} webhooks", self.hooks.len());
+
+        let watcher = Watcher {
+            webhooks: Arc::clone(self),
+            zone,
+            calories_source,
+            session: None,
+            open_days: BTreeSet::new(),
+            connected: false,
+        };
+        tokio::spawn(watcher.run(ws_tx.subscribe(), status_rx));
+        Ok(())
+    }
+
+    /// Log and send an event to every webhook that wants it
+    pub async fn fire(self: &Arc<Self>, event: WebhookEvent, data: Value) {
+        let body = json!({
+            "event": event,
+            "timestamp": Utc::now().timestamp(),
+            "data": data,
+        });
+        for hook in self.hooks.iter().filter(|hook| hook.wants(event)) {
+            let payload = match &hook.template {
+                Some(template) => render_template(template, &body),
+                None => body.to_string(),
+            };
+            match self
+                .storage
+                .create_webhook_delivery(&hook.name, event, &payload, None)
+                .await
+            {
+                Ok(delivery) => {
+                    tokio::spawn(Arc::clone(self).deliver(hook.clone(), delivery));
+                }
+                Err(e) => error!(
+                    "Couldn't log {} for webhook {}: {}",
+                    event.as_str(),
+                    hook.name,
+                    e
+                ),
+            }
+        }
+    }
+
+    /// Send a logged delivery's payload again, as a new delivery. None if its
+    /// webhook is no longer configured.
+    pub async fn redeliver(
+        self: &Arc<Self>,
+        original: &WebhookDelivery,
+    ) -> Result<Option<WebhookDelivery>> {
+        let Some(hook) = self.hooks.iter().find(|hook| hook.name == original.webhook) else {
+            return Ok(None);
+        };
+        let delivery = self
+            .storage
+            .create_webhook_delivery(
+                &hook.name,
+                original.event,
+                &original.payload,
+                Some(original.id),
+            )
+            .await?;
+        tokio::spawn(Arc::clone(self).deliver(hook.clone(), delivery.clone()));
+        Ok(Some(delivery))
+    }
+
+    async fn deliver(self: Arc<Self>, hook: WebhookConfig, delivery: WebhookDelivery) {
+        let mut backoff = self.initial_backoff;
+        let attempts = hook.max_retries as i64 + 1;
+        for attempt in 1..=attempts {
+            let (status, result) = self.attempt(&hook, &delivery).await;
+            let retry = result.is_err()
+                && attempt < attempts
+                && status.is_none_or(|s| {
+                    !s.is_client_error()
+                        || s == StatusCode::REQUEST_TIMEOUT
+                        || s == StatusCode::TOO_MANY_REQUESTS
+                });
+            let outcome = match (&result, retry) {
+                (Ok(()), _) => DeliveryStatus::Delivered,
+                (Err(_), true) => DeliveryStatus::Pending,
+                (Err(_), false) => DeliveryStatus::Failed,
+            };
+            if let Err(e) = self
+                .storage
+                .update_webhook_delivery(
+                    delivery.id,
+                    outcome,
+                    attempt,
+                    status.map(|s| s.as_u16() as i64),
+                    result.as_ref().err().map(String::as_str),
+                )
+                .await
+            {
+                error!("Couldn't update webhook delivery {}: {}", delivery.id, e);
+            }
+
+            match result {
+                Ok(()) => return,
+                Err(e) if retry => {
+                    warn!(
+                        "Webhook {} failed ({}), retrying in {:?}",
+                        hook.name, e, backoff
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                Err(e) => {
+                    error!(
+                        "Giving up on webhook {} delivery {}: {}",
+                        hook.name, delivery.id, e
+                    );
+                    return;
+                }
+            }
+        }
+    }
+
+    /// One request: the response status (if any) and whether it succeeded
+    async fn attempt(
+        &self,
+        hook: &WebhookConfig,
+        delivery: &WebhookDelivery,
+    ) -> (Option<StatusCode>, Result<(), String>) {
+        let timestamp = Utc::now().timestamp();
+        let mut request = self
+            .client
+            .post(&hook.url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("X-Walkpad-Event", delivery.event.as_str())
+            .header("X-Walkpad-Delivery", delivery.id)
+            .header("X-Walkpad-Timestamp", timestamp);
+        if let Some(secret) = &hook.secret {
+            request = request.header(
+                "X-Walkpad-Signature",
+                signature(secret, timestamp, &delivery.payload),
+            );
+        }
+
+        match request.body(delivery.payload.clone()).send().await {
+            Ok(response) if response.status().is_success() => (Some(response.status()), Ok(())),
+            Ok(response) => (
+                Some(response.status()),
+                Err(format!("HTTP {}", response.status())),
+            ),
+            Err(e) => (None, Err(e.to_string())),
+        }
+    }
+}
+
+/// Turns the live feed into events
+struct Watcher {
+    webhooks: Arc<Webhooks>,
+    zone: Zone,
+    calories_source: CaloriesSource,
+    session: Option<(i64, i64, Option<i64>)>, // (start, latest sample, user) of the session under way
+    open_days: BTreeSet<NaiveDate>,           // local days with samples, not yet summarised
+    connected: bool,
+}
+
+impl Watcher {
+    async fn run(
+        mut self,
+        mut rx: broadcast::Receiver<WsMessage>,
+        mut status_rx: broadcast::Receiver<ConnectionStatus>,
+    ) {
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                message = rx.recv() => match message {
+                    Ok(WsMessage::NewSample { sample }) => self.sample(&sample).await,
+                    Ok(WsMessage::GoalReached { goal, value, date }) => {
+                        let data = json!({ "goal": goal, "value": value, "date": date });
+                        self.webhooks.fire(WebhookEvent::GoalReached, data).await;
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("Webhooks lagged behind by {} messages", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                status = status_rx.recv() => match status {
+                    Ok(status) => self.status(status).await,
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = ticker.tick() => self.check(Utc::now().timestamp()).await,
+            }
+        }
+    }
+
+    async fn sample(&mut self, sample: &WsSample) {
+        // A late check: the previous session may have ended unnoticed
+        self.check(sample.timestamp).await;
+        match &mut self.session {
+            Some((_, latest, _)) => *latest = sample.timestamp,
+            None => {
+                self.session = Some((sample.timestamp, sample.timestamp, sample.user_id));
+                let data = json!({ "start": sample.timestamp, "user_id": sample.user_id });
+                self.webhooks.fire(WebhookEvent::SessionStarted, data).await;
+            }
+        }
+        self.open_days
+            .insert(self.zone.local_date(sample.timestamp));
+    }
+
+    async fn status(&mut self, status: ConnectionStatus) {
+        match status {
+            ConnectionStatus::Connected if !self.connected => {
+                self.connected = true;
+                let data = json!({ "status": status.as_str() });
+                self.webhooks
+                    .fire(WebhookEvent::TreadmillConnected, data)
+                    .await;
+            }
+            ConnectionStatus::Disconnected | ConnectionStatus::Error if self.connected => {
+                self.connected = false;
+                let data = json!({ "status": status.as_str() });
+                self.webhooks
+                    .fire(WebhookEvent::TreadmillDisconnected, data)
+                    .await;
+            }
+            _ => {}
+        }
+    }
+
+    /// End a session that has gone quiet, then summarise the days that are
+    /// over (a session running past midnight holds the day back until it ends)
+    async fn check(&mut self, now: i64) {
+        if let Some((start, latest, user_id)) = self.session {
+            if now - latest <= SESSION_GAP_SECS {
+                return;
+            }
+            self.session = None;
+            match sessions::find_session(&self.webhooks.storage, start, None).await {
+                Ok(Some((session, _))) => {
+                    let mut data = serde_json::to_value(&session).unwrap_or_default();
+                    data["user_id"] = json!(user_id);
+                    if self.calories_source == CaloriesSource::Estimated {
+                        data["calories"] = json!(session.calories_estimated.round() as i64);
+                    }
+                    self.webhooks
+                        .fire(WebhookEvent::SessionEnded, with_friendly_units(data))
+                        .await;
+                }
+                Ok(None) => warn!("Session starting at {} wasn't recorded", start),
+                Err(e) => error!("Couldn't load the session starting at {}: {}", start, e),
+            }
+        }
+
+        let today = self.zone.local_date(now);
+        while let Some(day) = self.open_days.first().copied().filter(|day| *day < today) {
+            self.open_days.remove(&day);
+            self.summarise(day).await;
+        }
+    }
+
+    async fn summarise(&self, day: NaiveDate) {
+        let summaries = self
+            .webhooks
+            .storage
+            .get_period_summaries(
+                GroupBy::Day,
+                Some((day, day)),
+                &self.zone,
+                Weekday::Mon,
+                None,
+            )
+            .await;
+        match summaries {
+            Ok(days) => {
+                if let Some(mut summary) = days.into_iter().next() {
+                    summary.report_calories(self.calories_source);
+                    let mut data = serde_json::to_value(&summary).unwrap_or_default();
+                    data["date"] = json!(day);
+                    self.webhooks
+                        .fire(WebhookEvent::DailySummary, with_friendly_units(data))
+                        .await;
+                }
+            }
+            Err(e) => error!("Couldn't summarise {} for webhooks: {}", day, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_support::{test_sample, test_storage_in};
+    use crate::storage::TreadmillSample;
+    use axum::extract::State;
+    use axum::http::HeaderMap;
+    use axum::routing::post;
+    use axum::Router;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Mutex;
+
+    /// A receiver that records what it's sent, failing the first `failures`
+    /// requests and rejecting bodies that mention "reject"
+    #[derive(Clone, Default)]
+    struct StandIn {
+        requests: Arc<Mutex<Vec<(HeaderMap, String)>>>,
+        failures: Arc<AtomicU32>,
+    }
+
+    impl StandIn {
+        async fn start() -> (Self, String) {
+            let stand_in = StandIn::default();
+            let router = Router::new()
+                .route("/hook", post(stand_in_hook))
+                .with_state(stand_in.clone());
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/hook", listener.local_addr().unwrap());
+            tokio::spawn(async move { axum::serve(listener, router).await });
+            (stand_in, url)
+        }
+    }
+
+    async fn stand_in_hook(
+        State(stand_in): State<StandIn>,
+        headers: HeaderMap,
+        body: String,
+    ) -> StatusCode {
+        let failing = stand_in
+            .failures
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok();
+        if failing {
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+        if body.contains("reject") {
+            return StatusCode::BAD_REQUEST;
+        }
+        stand_in.requests.lock().unwrap().push((headers, body));
+        StatusCode::OK
+    }
+
+    fn hook(name: &str, url: &str) -> WebhookConfig {
+        WebhookConfig {
+            name: name.to_string(),
+            url: url.to_string(),
+            events: None,
+            secret: None,
+            template: None,
+            max_retries: 3,
+        }
+    }
+
+    async fn test_webhooks(dir: &tempfile::TempDir, hooks: Vec<WebhookConfig>) -> Arc<Webhooks> {
+        let storage = Arc::new(test_storage_in(dir).await);
+        Arc::new(Webhooks::new(hooks, storage, Duration::from_millis(10)).unwrap())
+    }
+
+    /// Wait for a delivery to be settled either way
+    async fn settled(webhooks: &Webhooks, id: i64) -> WebhookDelivery {
+        for _ in 0..200 {
+            let delivery = webhooks
+                .storage
+                .get_webhook_delivery(id)
+                .await
+                .unwrap()
+                .unwrap();
+            if delivery.status != DeliveryStatus::Pending {
+                return delivery;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("delivery {} is still pending", id);
+    }
+
+    async fn logged_events(webhooks: &Webhooks) -> Vec<WebhookEvent> {
+        let mut deliveries = webhooks
+            .storage
+            .get_webhook_deliveries(None, None, 100)
+            .await
+            .unwrap();
+        deliveries.reverse();
+        deliveries.into_iter().map(|d| d.event).collect()
+    }
+
+    #[test]
+    fn test_render_template() {
+        let event = json!({
+            "event": "session_ended",
+            "data": { "distance_km": 1.5, "note": "a \"quoted\" word", "missing": null },
+        });
+        assert_eq!(
+            render_template(
+                r#"{"text": "{{ event }}: {{data.distance_km}} km, {{data.note}}{{data.missing}}{{nope}}"}"#,
+                &event
+            ),
+            r#"{"text": "session_ended: 1.5 km, a \"quoted\" word"}"#
+        );
+        assert_eq!(render_template("{{event", &event), "{{event");
+    }
+
+    #[tokio::test]
+    async fn test_delivery_is_signed_retried_and_logged() {
+        let dir = tempfile::tempdir().unwrap();
+        let (stand_in, url) = StandIn::start().await;
+        let slack = WebhookConfig {
+            secret: Some("s3cret".to_string()),
+            template: Some(r#"{"text": "{{event}}: {{data.note}}"}"#.to_string()),
+            ..hook("slack", &url)
+        };
+        let daily_only = WebhookConfig {
+            events: Some(vec![WebhookEvent::DailySummary]),
+            ..hook("daily", &url)
+        };
+        let webhooks = test_webhooks(&dir, vec![slack, daily_only]).await;
+
+        // Retried after a 503, and only sent to the webhook that wants it
+        stand_in.failures.store(1, Ordering::Relaxed);
+        webhooks
+            .fire(WebhookEvent::SessionStarted, json!({ "note": "off we go" }))
+            .await;
+        let delivery = settled(&webhooks, 1).await;
+        assert_eq!(delivery.webhook, "slack");
+        assert_eq!(delivery.status, DeliveryStatus::Delivered);
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(delivery.response_status, Some(200));
+        assert_eq!(
+            logged_events(&webhooks).await,
+            [WebhookEvent::SessionStarted]
+        );
+
+        let (headers, body) = stand_in.requests.lock().unwrap()[0].clone();
+        assert_eq!(body, r#"{"text": "session_started: off we go"}"#);
+        assert_eq!(headers["x-walkpad-event"], "session_started");
+        assert_eq!(headers["x-walkpad-delivery"], "1");
+        let timestamp: i64 = headers["x-walkpad-timestamp"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(
+            headers["x-walkpad-signature"].to_str().unwrap(),
+            signature("s3cret", timestamp, &body)
+        );
+
+        // Client errors aren't retried
+        webhooks
+            .fire(WebhookEvent::GoalReached, json!({ "note": "reject me" }))
+            .await;
+        let delivery = settled(&webhooks, 2).await;
+        assert_eq!(delivery.status, DeliveryStatus::Failed);
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.response_status, Some(400));
+        assert_eq!(delivery.error.as_deref(), Some("HTTP 400 Bad Request"));
+
+        // Sent again as a new delivery
+        let again = webhooks.redeliver(&delivery).await.unwrap().unwrap();
+        assert_eq!(again.redelivery_of, Some(2));
+        assert_eq!(again.payload, delivery.payload);
+        assert_eq!(
+            settled(&webhooks, again.id).await.status,
+            DeliveryStatus::Failed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watcher_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_stand_in, url) = StandIn::start().await;
+        let webhooks = test_webhooks(&dir, vec![hook("all", &url)]).await;
+        let mut watcher = Watcher {
+            webhooks: Arc::clone(&webhooks),
+            zone: Zone::default(),
+            calories_source: CaloriesSource::Device,
+            session: None,
+            open_days: BTreeSet::new(),
+            connected: false,
+        };
+
+        watcher.status(ConnectionStatus::Scanning).await;
+        watcher.status(ConnectionStatus::Connected).await;
+
+        // A ten-minute walk from 23:55 UTC on 2025-01-15
+        let start = 1736985300;
+        let samples: Vec<TreadmillSample> = (0..600)
+            .map(|i| TreadmillSample {
+                speed: Some(1.25),
+                ..test_sample(start + i)
+            })
+            .collect();
+        webhooks.storage.add_samples(&samples).await.unwrap();
+        for sample in &samples {
+            watcher.sample(&WsSample::from(sample.clone())).await;
+        }
+
+        // Still walking past midnight: the day stays open
+        watcher.check(start + 600).await;
+        assert_eq!(
+            logged_events(&webhooks).await,
+            [
+                WebhookEvent::TreadmillConnected,
+                WebhookEvent::SessionStarted
+            ]
+        );
+
+        // Once the session has ended the first day is over; the second ends at its midnight
+        watcher.check(start + 599 + SESSION_GAP_SECS + 1).await;
+        watcher.status(ConnectionStatus::Error).await;
+        watcher.status(ConnectionStatus::Disconnected).await;
+        watcher.check(start + 2 * 86400).await;
+        assert_eq!(
+            logged_events(&webhooks).await,
+            [
+                WebhookEvent::TreadmillConnected,
+                WebhookEvent::SessionStarted,
+                WebhookEvent::SessionEnded,
+                WebhookEvent::DailySummary,
+                WebhookEvent::TreadmillDisconnected,
+                WebhookEvent::DailySummary,
+            ]
+        );
+
+        let deliveries = webhooks
+            .storage
+            .get_webhook_deliveries(None, None, 100)
+            .await
+            .unwrap();
+        let payload = |event| -> Value {
+            let delivery = deliveries.iter().rev().find(|d| d.event == event).unwrap();
+            serde_json::from_str(&delivery.payload).unwrap()
+        };
+        let ended = payload(WebhookEvent::SessionEnded);
+        assert_eq!(ended["event"], "session_ended");
+        assert_eq!(ended["data"]["start"], start);
+        assert_eq!(ended["data"]["distance_meters"], 600);
+        assert_eq!(ended["data"]["distance_km"], 0.6);
+        assert_eq!(ended["data"]["duration_minutes"], 9);
+        let day = payload(WebhookEvent::DailySummary);
+        assert_eq!(day["data"]["date"], "2025-01-15");
+        assert_eq!(day["data"]["distance_meters"], 300);
+        assert_eq!(day["data"]["distance_km"], 0.3);
+    }
+}
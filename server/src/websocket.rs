@@ -1,7 +1,7 @@
 use axum::{
     extract::{
         ws::{Message, WebSocket},
-        State, WebSocketUpgrade,
+        Extension, State, WebSocketUpgrade,
     },
     response::IntoResponse,
 };
@@ -10,31 +10,64 @@ use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tokio::sync::broadcast;
 use tracing::{debug, error, info, warn};
+use utoipa::ToSchema;
 
 use crate::api::AppState;
-use crate::storage::TreadmillSample;
+use crate::auth::Scope;
+use crate::storage::{Achievement, ApiToken, Goal, PersonalRecord, TreadmillSample};
+use crate::users::{ActiveUser, ChangeSource};
 
 /// Interval for sending heartbeat messages to keep connection alive
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
 
 /// Message sent to WebSocket clients when a new sample arrives
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 #[serde(tag = "type")]
 pub enum WsMessage {
     /// A new sample has been added
     NewSample { sample: WsSample },
     /// Heartbeat to keep connection alive
     Heartbeat,
+    /// The user new samples are attributed to has changed
+    ActiveUserChanged {
+        user_id: Option<i64>,
+        source: ChangeSource,
+    },
+    /// A live sample took a goal past its target
+    GoalReached {
+        goal: Goal,
+        value: f64,
+        date: String, // YYYY-MM-DD (local day the goal was reached)
+    },
+    /// A personal record was beaten (sent once per session, day or week)
+    NewRecord {
+        record: PersonalRecord,
+        previous: Option<f64>,
+    },
+    /// A lifetime milestone was reached
+    AchievementUnlocked { achievement: Achievement },
+}
+
+/// Commands clients may send over the socket
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "type")]
+pub(crate) enum WsCommand {
+    /// Switch the active user (null = unassigned)
+    SetActiveUser { user_id: Option<i64> },
 }
 
 /// Simplified sample format for WebSocket
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct WsSample {
     pub timestamp: i64,
     pub speed: Option<f64>,
     pub distance_delta: Option<i64>,
     pub calories_delta: Option<i64>,
     pub steps_delta: Option<i64>,
+    pub calories_estimated: Option<f64>,
+    pub user_id: Option<i64>,
+    pub incline: Option<f64>,    // percent
+    pub heart_rate: Option<i64>, // bpm
 }
 
 impl From<TreadmillSample> for WsSample {
@@ -45,18 +78,40 @@ impl From<TreadmillSample> for WsSample {
             distance_delta: s.distance_delta,
             calories_delta: s.calories_delta,
             steps_delta: s.steps_delta,
+            calories_estimated: s.calories_estimated,
+            user_id: s.user_id,
+            incline: s.incline,
+            heart_rate: s.heart_rate,
         }
     }
 }
 
-/// WebSocket handler
-pub async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+/// WebSocket handler. The token (if auth is enabled) was checked before the
+/// upgrade; commands additionally need the control scope.
+#[utoipa::path(
+    get,
+    path = "/ws/live",
+    tag = "live",
+    params(
+        ("access_token" = Option<String>, Query, description = "API token, for clients that can't set headers on a WebSocket"),
+    ),
+    responses(
+        (status = 101, description = "Upgraded. The server sends `WsMessage` JSON objects; clients may send `WsCommand`s."),
+    )
+)]
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    token: Option<Extension<ApiToken>>,
+) -> impl IntoResponse {
+    let can_control = token.is_none_or(|Extension(t)| t.scope.allows(Scope::Control));
+    ws.on_upgrade(move |socket| handle_socket(socket, state, can_control))
 }
 
 /// Handle a WebSocket connection
-async fn handle_socket(socket: WebSocket, state: AppState) {
+async fn handle_socket(socket: WebSocket, state: AppState, can_control: bool) {
     info!("WebSocket client connected");
+    let _client = state.metrics.websocket_client();
 
     // Subscribe to the broadcast channel
     let mut rx = state.ws_tx.subscribe();
@@ -65,16 +120,21 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
     let (mut sender, mut receiver) = socket.split();
 
     // Spawn a task to handle incoming messages from client
+    let active_user = state.active_user.clone();
     let mut recv_task = tokio::spawn(async move {
         while let Some(Ok(msg)) = receiver.next().await {
-            // Handle ping/pong to keep connection alive
-            if let Message::Close(_) = msg {
-                break;
+            match msg {
+                Message::Text(text) if can_control => handle_command(&active_user, &text).await,
+                Message::Text(_) => warn!("Ignoring command from a read-only WebSocket client"),
+                // Handle ping/pong to keep connection alive
+                Message::Close(_) => break,
+                _ => {}
             }
         }
     });
 
     // Spawn a task to send broadcast messages and heartbeats to client
+    let metrics = state.metrics.clone();
     let mut send_task = tokio::spawn(async move {
         let mut heartbeat_interval = tokio::time::interval(HEARTBEAT_INTERVAL);
 
@@ -112,6 +172,7 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                         }
                         Err(broadcast::error::RecvError::Lagged(n)) => {
                             warn!("WebSocket client lagged behind by {} messages", n);
+                            metrics.websocket_lagged(n);
                             // Continue receiving - we'll just skip the lagged messages
                         }
                         Err(broadcast::error::RecvError::Closed) => {
@@ -137,6 +198,27 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
     info!("WebSocket client disconnected");
 }
 
+/// Apply a command sent by a client. Results are observed via broadcasts.
+async fn handle_command(active_user: &ActiveUser, text: &str) {
+    let command = match serde_json::from_str::<WsCommand>(text) {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("Ignoring unrecognised WebSocket message: {}", e);
+            return;
+        }
+    };
+
+    match command {
+        WsCommand::SetActiveUser { user_id } => {
+            match active_user.set(user_id, ChangeSource::WebSocket).await {
+                Ok(true) => {}
+                Ok(false) => warn!("WebSocket client selected unknown user {:?}", user_id),
+                Err(e) => error!("Failed to set active user: {}", e),
+            }
+        }
+    }
+}
+
 /// Broadcast a new sample to all connected WebSocket clients
 pub fn broadcast_sample(tx: &broadcast::Sender<WsMessage>, sample: &TreadmillSample) {
     let ws_sample = WsSample::from(sample.clone());
@@ -0,0 +1,441 @@
+//! Buffered writes of live samples.
+//!
+//! `record_sample` hands each sample to a bounded in-memory queue instead of
+//! awaiting its own INSERT. One task drains the queue in batches, each written
+//! in a single transaction and retried with exponential backoff. A batch that
+//! still can't be written (SQLite busy for too long, a full disk) is appended
+//! to a spill file as JSON lines, and the spill is replayed into the database
+//! as soon as a write succeeds again, including after a restart.
+//!
+//! Samples are only lost when the queue is full or the spill file can't be
+//! written either; both are counted as dropped in [`WriterStats`].
+
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::config::WriterConfig;
+use crate::metrics::LatencyHistogram;
+use crate::storage::{Storage, TreadmillSample};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+struct Counters {
+    written: AtomicU64,
+    dropped: AtomicU64,
+    spilled: AtomicU64,
+    retries: AtomicU64,
+    write_latency: LatencyHistogram,
+}
+
+/// Queue and write counters, for health checks and metrics
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WriterStats {
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub written: u64, // samples stored since startup
+    pub dropped: u64, // samples lost since startup
+    pub spilled: u64, // samples waiting in the spill file
+    pub retries: u64, // failed write attempts that were retried
+}
+
+/// Handle for queueing samples (cheap to clone)
+#[derive(Clone)]
+pub struct SampleWriter {
+    tx: mpsc::Sender<TreadmillSample>,
+    counters: Arc<Counters>,
+    stop: Arc<Notify>,
+    committed: Arc<Notify>,
+}
+
+impl SampleWriter {
+    /// Start the writer task. Anything left in `spill_path` by an earlier run
+    /// is replayed first.
+    pub fn start(
+        storage: Arc<Storage>,
+        config: &WriterConfig,
+        spill_path: impl Into<PathBuf>,
+    ) -> (Self, JoinHandle<()>) {
+        let (tx, rx) = mpsc::channel(config.queue_capacity.max(1));
+        let counters = Arc::new(Counters::default());
+        let stop = Arc::new(Notify::new());
+        let committed = Arc::new(Notify::new());
+
+        let worker = Worker {
+            storage,
+            rx,
+            counters: Arc::clone(&counters),
+            batch_size: config.batch_size.max(1),
+            max_retries: config.max_retries,
+            spill_path: spill_path.into(),
+            committed: Arc::clone(&committed),
+        };
+        let handle = tokio::spawn(worker.run(Arc::clone(&stop)));
+
+        (
+            Self {
+                tx,
+                counters,
+                stop,
+                committed,
+            },
+            handle,
+        )
+    }
+
+    /// Queue a sample for writing without waiting. Returns false (and counts
+    /// the sample as dropped) if the queue is full or the writer has stopped.
+    pub fn enqueue(&self, sample: TreadmillSample) -> bool {
+        match self.tx.try_send(sample) {
+            Ok(()) => true,
+            Err(TrySendError::Full(sample)) => {
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                warn!("Write queue full, dropping sample {}", sample.timestamp);
+                false
+            }
+            Err(TrySendError::Closed(sample)) => {
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                warn!("Writer stopped, dropping sample {}", sample.timestamp);
+                false
+            }
+        }
+    }
+
+    pub fn stats(&self) -> WriterStats {
+        WriterStats {
+            queue_depth: self.tx.max_capacity() - self.tx.capacity(),
+            queue_capacity: self.tx.max_capacity(),
+            written: self.counters.written.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+            spilled: self.counters.spilled.load(Ordering::Relaxed),
+            retries: self.counters.retries.load(Ordering::Relaxed),
+        }
+    }
+
+    /// How long batch writes take, successful or not
+    pub fn write_latency(&self) -> &LatencyHistogram {
+        &self.counters.write_latency
+    }
+
+    /// Stop accepting samples; the task exits once everything queued is
+    /// written (or spilled)
+    pub fn stop(&self) {
+        self.stop.notify_one();
+    }
+
+    /// Notified after samples are committed to the database. Samples are
+    /// committed in timestamp order, spilled ones before anything newer.
+    pub fn committed(&self) -> Arc<Notify> {
+        Arc::clone(&self.committed)
+    }
+}
+
+struct Worker {
+    storage: Arc<Storage>,
+    rx: mpsc::Receiver<TreadmillSample>,
+    counters: Arc<Counters>,
+    batch_size: usize,
+    max_retries: u32,
+    spill_path: PathBuf,
+    committed: Arc<Notify>,
+}
+
+impl Worker {
+    async fn run(mut self, stop: Arc<Notify>) {
+        match read_spill::<TreadmillSample>(&self.spill_path).await {
+            Ok(spilled) if !spilled.is_empty() => {
+                info!(
+                    "{} samples waiting in {}",
+                    spilled.len(),
+                    self.spill_path.display()
+                );
+                self.counters
+                    .spilled
+                    .store(spilled.len() as u64, Ordering::Relaxed);
+                self.replay().await;
+            }
+            Ok(_) => {}
+            Err(e) => error!("Couldn't read {}: {}", self.spill_path.display(), e),
+        }
+
+        let mut stopping = false;
+        loop {
+            let first = tokio::select! {
+                biased;
+                _ = stop.notified(), if !stopping => {
+                    // Keep draining what's already queued
+                    self.rx.close();
+                    stopping = true;
+                    continue;
+                }
+                sample = self.rx.recv() => sample,
+            };
+            let Some(first) = first else {
+                break;
+            };
+
+            // Whatever queued up during the last write goes in this batch
+            let mut batch = vec![first];
+            while batch.len() < self.batch_size {
+                match self.rx.try_recv() {
+                    Ok(sample) => batch.push(sample),
+                    Err(_) => break,
+                }
+            }
+            self.write(&batch).await;
+        }
+
+        info!("Sample writer stopped");
+    }
+
+    fn has_spill(&self) -> bool {
+        self.counters.spilled.load(Ordering::Relaxed) > 0
+    }
+
+    async fn write(&self, batch: &[TreadmillSample]) {
+        // Earlier samples waiting on disk go first, so samples are committed
+        // in order. If they still can't be written the database is probably
+        // unavailable, so this batch joins them rather than stalling the
+        // queue on backoff.
+        if self.has_spill() {
+            self.replay().await;
+            if self.has_spill() {
+                self.spill(batch).await;
+                return;
+            }
+        }
+
+        match self.write_with_retry(batch, self.max_retries + 1).await {
+            Ok(()) => {
+                self.counters
+                    .written
+                    .fetch_add(batch.len() as u64, Ordering::Relaxed);
+                self.committed.notify_one();
+            }
+            Err(e) => {
+                warn!(
+                    "Couldn't write {} samples, spilling to {}: {}",
+                    batch.len(),
+                    self.spill_path.display(),
+                    e
+                );
+                self.spill(batch).await;
+            }
+        }
+    }
+
+    async fn write_with_retry(&self, batch: &[TreadmillSample], attempts: u32) -> Result<()> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            match self.add_samples(batch).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= attempts => return Err(e),
+                Err(e) => {
+                    self.counters.retries.fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        "Sample write failed (attempt {}/{}), retrying in {:?}: {}",
+                        attempt, attempts, backoff, e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    async fn add_samples(&self, batch: &[TreadmillSample]) -> Result<()> {
+        let started = Instant::now();
+        let result = self.storage.add_samples(batch).await;
+        self.counters.write_latency.observe(started.elapsed());
+        result
+    }
+
+    async fn spill(&self, batch: &[TreadmillSample]) {
+        match append_spill(&self.spill_path, batch).await {
+            Ok(()) => {
+                self.counters
+                    .spilled
+                    .fetch_add(batch.len() as u64, Ordering::Relaxed);
+            }
+            Err(e) => {
+                self.counters
+                    .dropped
+                    .fetch_add(batch.len() as u64, Ordering::Relaxed);
+                error!(
+                    "Couldn't spill {} samples to {}, dropping them: {}",
+                    batch.len(),
+                    self.spill_path.display(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Move spilled samples into the database. The file is only removed once
+    /// every sample is written; writes replace by timestamp, so a replay that
+    /// fails halfway can simply run again.
+    async fn replay(&self) {
+        let samples = match read_spill(&self.spill_path).await {
+            Ok(samples) => samples,
+            Err(e) => {
+                error!("Couldn't read {}: {}", self.spill_path.display(), e);
+                return;
+            }
+        };
+
+        for chunk in samples.chunks(self.batch_size) {
+            if let Err(e) = self.add_samples(chunk).await {
+                warn!("Replaying spilled samples failed, will retry: {}", e);
+                return;
+            }
+        }
+        if let Err(e) = tokio::fs::remove_file(&self.spill_path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                error!("Couldn't remove {}: {}", self.spill_path.display(), e);
+                return;
+            }
+        }
+
+        self.counters
+            .written
+            .fetch_add(samples.len() as u64, Ordering::Relaxed);
+        self.counters.spilled.store(0, Ordering::Relaxed);
+        self.committed.notify_one();
+        info!("Replayed {} spilled samples", samples.len());
+    }
+}
+
+/// Append items to a spill file, one JSON value per line (also used for the
+/// InfluxDB buffer)
+pub async fn append_spill<T: Serialize>(path: &Path, items: &[T]) -> Result<()> {
+    let mut lines = String::new();
+    for item in items {
+        lines.push_str(&serde_json::to_string(item)?);
+        lines.push('\n');
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(lines.as_bytes()).await?;
+    file.sync_data().await?;
+    Ok(())
+}
+
+/// Items in a spill file (none if it doesn't exist). A line cut short by a
+/// crash is skipped.
+pub async fn read_spill<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let contents = match tokio::fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(item) => Some(item),
+            Err(e) => {
+                warn!("Skipping unreadable line in {}: {}", path.display(), e);
+                None
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_support::{test_database_url, test_sample, test_storage_in};
+    use sqlx::sqlite::SqlitePool;
+
+    fn config(max_retries: u32) -> WriterConfig {
+        WriterConfig {
+            queue_capacity: 16,
+            batch_size: 4,
+            max_retries,
+            spill_path: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batches_everything_queued_before_stop() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Arc::new(test_storage_in(&dir).await);
+
+        let (writer, handle) =
+            SampleWriter::start(Arc::clone(&storage), &config(0), dir.path().join("spill"));
+        for t in 0..10 {
+            assert!(writer.enqueue(test_sample(1000 + t)));
+        }
+        writer.stop();
+        handle.await.unwrap();
+
+        assert_eq!(storage.get_total_sample_count().await.unwrap(), 10);
+        let stats = writer.stats();
+        assert_eq!((stats.written, stats.dropped, stats.spilled), (10, 0, 0));
+        assert!(!writer.enqueue(test_sample(2000)));
+        assert_eq!(writer.stats().dropped, 1);
+    }
+
+    #[tokio::test]
+    async fn test_spills_while_database_unavailable_and_replays() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = test_database_url(&dir);
+        let storage = Arc::new(test_storage_in(&dir).await);
+        let spill = dir.path().join("spill");
+
+        // Another connection takes the table away, so every write fails
+        let other = SqlitePool::connect(&url).await.unwrap();
+        sqlx::query("ALTER TABLE treadmill_samples RENAME TO unavailable")
+            .execute(&other)
+            .await
+            .unwrap();
+
+        let (writer, handle) = SampleWriter::start(Arc::clone(&storage), &config(1), &spill);
+        for t in 0..3 {
+            writer.enqueue(test_sample(1000 + t));
+        }
+        writer.stop();
+        handle.await.unwrap();
+
+        let stats = writer.stats();
+        assert_eq!((stats.written, stats.spilled, stats.dropped), (0, 3, 0));
+        assert!(stats.retries >= 1);
+        assert_eq!(
+            read_spill::<TreadmillSample>(&spill).await.unwrap().len(),
+            3
+        );
+
+        // Back again: the next run replays the spill before anything else
+        sqlx::query("ALTER TABLE unavailable RENAME TO treadmill_samples")
+            .execute(&other)
+            .await
+            .unwrap();
+        let (writer, handle) = SampleWriter::start(Arc::clone(&storage), &config(1), &spill);
+        writer.enqueue(test_sample(1003));
+        writer.stop();
+        handle.await.unwrap();
+
+        assert_eq!(storage.get_total_sample_count().await.unwrap(), 4);
+        assert_eq!(writer.stats().written, 4);
+        assert_eq!(writer.stats().spilled, 0);
+        assert!(!spill.exists());
+    }
+}